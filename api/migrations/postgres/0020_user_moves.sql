@@ -0,0 +1,8 @@
+CREATE TABLE user_moves (
+    from_id TEXT PRIMARY KEY,
+    to_id TEXT NOT NULL,
+    primary_key_fingerprint TEXT NOT NULL,
+    statement TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX idx_user_moves_to_id ON user_moves(to_id);
@@ -0,0 +1,8 @@
+CREATE TABLE user_devices (
+    user_id TEXT NOT NULL,
+    subkey_fingerprint TEXT NOT NULL,
+    name TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ,
+    PRIMARY KEY (user_id, subkey_fingerprint)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE key_transitions (
+    old_fingerprint TEXT PRIMARY KEY,
+    new_fingerprint TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    old_key_statement TEXT NOT NULL,
+    new_key_statement TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX idx_key_transitions_new_fingerprint ON key_transitions(new_fingerprint);
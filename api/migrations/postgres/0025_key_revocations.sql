@@ -0,0 +1,6 @@
+CREATE TABLE key_revocations (
+    fingerprint TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    certificate TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,17 @@
+CREATE TABLE secret_key_backup_recovery_keys (
+    user_id TEXT NOT NULL REFERENCES secret_key_backups(user_id) ON DELETE CASCADE,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, public_key)
+);
+
+CREATE TABLE secret_key_backup_challenges (
+    challenge TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_secret_key_backup_challenges_user ON secret_key_backup_challenges(user_id, created_at);
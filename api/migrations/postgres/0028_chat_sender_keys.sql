@@ -0,0 +1,11 @@
+CREATE TABLE chat_sender_keys (
+    chat_id TEXT NOT NULL REFERENCES chat_groups(id) ON DELETE CASCADE,
+    sender_id TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    recipient_id TEXT NOT NULL,
+    distribution TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (chat_id, sender_id, key_id, recipient_id)
+);
+
+CREATE INDEX idx_chat_sender_keys_recipient ON chat_sender_keys(chat_id, recipient_id, created_at);
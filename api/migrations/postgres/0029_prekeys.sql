@@ -0,0 +1,23 @@
+CREATE TABLE prekey_bundles (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    identity_key TEXT NOT NULL,
+    signed_bundle TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE one_time_prekeys (
+    user_id TEXT NOT NULL REFERENCES prekey_bundles(user_id) ON DELETE CASCADE,
+    prekey_id BIGINT NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, prekey_id)
+);
+
+-- ワンタイムプレキーの取得記録（要求者ごとの取得数の制限に使用）
+CREATE TABLE one_time_prekey_claims (
+    user_id TEXT NOT NULL REFERENCES prekey_bundles(user_id) ON DELETE CASCADE,
+    requester_id TEXT NOT NULL,
+    claimed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_one_time_prekey_claims_requester ON one_time_prekey_claims(user_id, requester_id, claimed_at);
@@ -0,0 +1,12 @@
+CREATE TABLE user_key_history (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    primary_key_fingerprint TEXT NOT NULL,
+    previous_fingerprint TEXT,
+    signing_public_key TEXT NOT NULL,
+    encryption_public_key TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    last_seen_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    UNIQUE (user_id, primary_key_fingerprint)
+);
+CREATE INDEX idx_user_key_history_user_id ON user_key_history(user_id);
@@ -0,0 +1,11 @@
+CREATE TABLE session_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    primary_key_fingerprint TEXT NOT NULL,
+    signing_key_fingerprint TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
+);
+CREATE INDEX idx_session_tokens_user_id ON session_tokens(user_id);
+CREATE INDEX idx_session_tokens_expires_at ON session_tokens(expires_at);
@@ -0,0 +1,8 @@
+CREATE TABLE user_devices (
+    user_id TEXT NOT NULL,
+    subkey_fingerprint TEXT NOT NULL,
+    name TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    revoked_at TEXT,
+    PRIMARY KEY (user_id, subkey_fingerprint)
+);
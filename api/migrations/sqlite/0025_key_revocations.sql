@@ -0,0 +1,6 @@
+CREATE TABLE key_revocations (
+    fingerprint TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    certificate TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
+);
@@ -0,0 +1,17 @@
+CREATE TABLE secret_key_backup_recovery_keys (
+    user_id TEXT NOT NULL REFERENCES secret_key_backups(user_id) ON DELETE CASCADE,
+    public_key TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    PRIMARY KEY (user_id, public_key)
+);
+
+CREATE TABLE secret_key_backup_challenges (
+    challenge TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    failed_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
+);
+
+CREATE INDEX idx_secret_key_backup_challenges_user ON secret_key_backup_challenges(user_id, created_at);
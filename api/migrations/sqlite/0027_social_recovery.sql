@@ -0,0 +1,34 @@
+CREATE TABLE social_recovery_configs (
+    owner_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    payload_armored TEXT NOT NULL,
+    threshold INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
+);
+
+CREATE TABLE social_recovery_shares (
+    owner_id TEXT NOT NULL REFERENCES social_recovery_configs(owner_id) ON DELETE CASCADE,
+    guardian_id TEXT NOT NULL,
+    share_armored TEXT NOT NULL,
+    PRIMARY KEY (owner_id, guardian_id)
+);
+
+CREATE TABLE social_recovery_requests (
+    id TEXT PRIMARY KEY,
+    owner_id TEXT NOT NULL,
+    recovery_public_key TEXT NOT NULL,
+    recovery_key_fingerprint TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    cancelled_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
+);
+
+CREATE INDEX idx_social_recovery_requests_requester ON social_recovery_requests(owner_id, recovery_key_fingerprint, created_at);
+
+CREATE TABLE social_recovery_responses (
+    request_id TEXT NOT NULL REFERENCES social_recovery_requests(id) ON DELETE CASCADE,
+    guardian_id TEXT NOT NULL,
+    share_armored TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    PRIMARY KEY (request_id, guardian_id)
+);
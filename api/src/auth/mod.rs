@@ -2,41 +2,63 @@ use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
+use xrypton_common::error::XryptonError;
+
+pub mod request;
+pub mod session;
 
 use crate::AppState;
-use crate::config::AppConfig;
 use crate::db;
-use crate::db::Db;
 use crate::db::nonces::NonceType;
 use crate::error::AppError;
-use crate::federation::dns::DnsTxtResolver;
 use crate::types::UserId;
 
+use self::request::{RequestBinding, RequestTarget};
+
 /// Authenticated user extracted from the Authorization header.
 ///
 /// The header must contain a base64-encoded PGP-signed message whose plaintext is
-/// `{"nonce":{"random":"<random>","time":"<iso8601>"}}`.
+/// `{"nonce":{"random":"<random>","time":"<iso8601>"},"request":{"method":"POST","path":"/v1/...","host":"<server>","content_digest":"sha-256=:<base64>:"}}`.
+/// `request` はメソッド・パス・宛先ホスト・本文ダイジェストに署名を束縛する。
+/// `request` のない旧形式は非推奨で、`AUTH_ALLOW_UNBOUND` が有効な間のみ受け付ける。
 /// サーバーはnonceのタイムスタンプが現在時刻から前後1時間以内であることを検証する。
 /// The server extracts the signer user ID from the PGP SignersUserID subpacket,
 /// looks up the user, verifies the signature against the user's registered signing key,
 /// then checks the nonce has not been used before.
+///
+/// `Bearer <token>` 形式の場合は `POST /v1/auth/session` で発行したセッショントークンとして、
+/// `Delegation <statement>` 形式の場合はホームサーバが発行した委任ステートメントとして検証する。
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
     pub user_id: UserId,
     pub primary_key_fingerprint: String,
     pub signing_public_key: String,
-    /// 転送用にbase64エンコード済みAuthorizationヘッダーを保持
-    pub raw_auth_header: String,
+    /// セッショントークンで認証した場合のトークンハッシュ
+    pub session_token_hash: Option<String>,
+    /// 認証に使われた署名サブキー（デバイス鍵）のフィンガープリント
+    pub signing_key_fingerprint: Option<String>,
+    /// 委任ステートメントで認証した場合の発行元サーバ
+    pub delegated_by: Option<String>,
 }
 
 /// Authorizationヘッダーを検証し、認証されたユーザ情報を返す。
 /// nonce再利用はリプレイ攻撃として拒否する。
 pub(crate) async fn authenticate(
-    pool: &Db,
-    config: &AppConfig,
-    dns_resolver: &DnsTxtResolver,
+    state: &AppState,
     auth_header_raw: &str,
+    target: &RequestTarget,
 ) -> Result<AuthenticatedUser, AppError> {
+    if let Some(token) = auth_header_raw.strip_prefix("Bearer ") {
+        return session::authenticate_session(state, token.trim()).await;
+    }
+    if let Some(token) = auth_header_raw
+        .strip_prefix(crate::federation::delegation::SCHEME)
+        .and_then(|rest| rest.strip_prefix(' '))
+    {
+        return crate::federation::delegation::authenticate(state, token.trim(), target).await;
+    }
+
+    let pool = &state.pool;
     let auth_decoded = STANDARD
         .decode(auth_header_raw)
         .map_err(|e| AppError::Unauthorized(format!("invalid base64 in authorization: {e}")))?;
@@ -48,41 +70,37 @@ pub(crate) async fn authenticate(
         .map_err(|e| AppError::Unauthorized(format!("failed to extract signer user ID: {e}")))?;
 
     // ローカルユーザとして解決を試みる（ドメイン付きIDでDB検索）
-    let user_id = UserId::resolve_local(&signer_address, &config.server_hostname)
+    let user_id = UserId::resolve_local(&signer_address, &state.config.server_hostname)
         .unwrap_or_else(|_| UserId(signer_address.clone()));
 
     if let Some(user) = db::users::get_user(pool, &user_id).await? {
         let public_keys =
             xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
                 .map_err(|e| AppError::Unauthorized(format!("invalid signing key: {e}")))?;
+        ensure_not_revoked(state, &user.primary_key_fingerprint, &public_keys).await?;
 
-        match public_keys.verify_and_extract(&auth_header) {
-            Ok(payload_bytes) => {
-                let payload: AuthPayload = serde_json::from_slice(&payload_bytes)
-                    .map_err(|e| AppError::Unauthorized(format!("invalid auth payload: {e}")))?;
-                let nonce_time = validate_nonce_timestamp(&payload.nonce)?;
-                let nonce_key = payload.nonce.replay_key();
-                let expires_at = nonce_time + chrono::Duration::hours(1);
-
-                let is_new = db::nonces::try_use_nonce(
-                    pool,
-                    NonceType::Auth,
-                    nonce_key,
-                    user_id.as_str(),
-                    expires_at,
-                )
-                .await?;
-                if !is_new {
-                    return Err(AppError::Unauthorized("nonce already used".into()));
-                }
+        match public_keys.verify_and_extract_with_signer(&auth_header) {
+            Ok((payload_bytes, signer_fingerprint)) => {
+                // キャッシュ済みの外部ユーザもここで解決されるため連合ポリシーを確認
+                crate::federation::policy::ensure_user_permitted(state, &user_id).await?;
+
+                verify_auth_payload(state, &payload_bytes, &user_id, target).await?;
 
                 return Ok(AuthenticatedUser {
                     user_id,
                     primary_key_fingerprint: user.primary_key_fingerprint,
                     signing_public_key: user.signing_public_key,
-                    raw_auth_header: auth_header_raw.to_string(),
+                    session_token_hash: None,
+                    signing_key_fingerprint: Some(signer_fingerprint),
+                    delegated_by: None,
                 });
             }
+            Err(
+                e
+                @ (XryptonError::Expired(_) | XryptonError::Revoked(_) | XryptonError::Unbound(_)),
+            ) => {
+                return Err(AppError::Unauthorized(format!("unusable signing key: {e}")));
+            }
             Err(_) => {
                 // ローカルで検証失敗 → 外部ユーザとして再試行
             }
@@ -90,14 +108,22 @@ pub(crate) async fn authenticate(
     }
 
     // 外部ユーザとして検証（nonce処理は内部で行われる）
-    crate::federation::verify::verify_or_fetch_external_user(
-        pool,
-        config,
-        dns_resolver,
-        auth_header_raw,
-        &auth_header,
-    )
-    .await
+    crate::federation::verify::verify_or_fetch_external_user(state, &auth_header, target).await
+}
+
+/// 鍵自体に失効署名が含まれているか、失効証明書が登録されている鍵での認証を拒否する。
+pub(crate) async fn ensure_not_revoked(
+    state: &AppState,
+    fingerprint: &str,
+    public_keys: &xrypton_common::keys::PublicKeys,
+) -> Result<(), AppError> {
+    if public_keys.is_revoked() || db::key_revocations::is_revoked(&state.pool, fingerprint).await?
+    {
+        return Err(AppError::Unauthorized(
+            "signing key has been revoked".into(),
+        ));
+    }
+    Ok(())
 }
 
 impl FromRequestParts<AppState> for AuthenticatedUser {
@@ -113,19 +139,15 @@ impl FromRequestParts<AppState> for AuthenticatedUser {
             .and_then(|v| v.to_str().ok())
             .ok_or_else(|| AppError::Unauthorized("missing authorization header".into()))?;
 
-        authenticate(
-            &state.pool,
-            &state.config,
-            &state.dns_resolver,
-            auth_header_raw,
-        )
-        .await
+        authenticate(state, auth_header_raw, &RequestTarget::from_parts(parts)).await
     }
 }
 
 #[derive(serde::Deserialize)]
 pub(crate) struct AuthPayload {
     pub(crate) nonce: AuthNonce,
+    #[serde(default)]
+    pub(crate) request: Option<RequestBinding>,
 }
 
 #[derive(serde::Deserialize)]
@@ -167,3 +189,44 @@ pub(crate) fn validate_nonce_timestamp(
     }
     Ok(client_time)
 }
+
+/// 署名から取り出した認証ペイロードを検証し、nonceを消費する。
+pub(crate) async fn verify_auth_payload(
+    state: &AppState,
+    payload_bytes: &[u8],
+    user_id: &UserId,
+    target: &RequestTarget,
+) -> Result<(), AppError> {
+    let payload: AuthPayload = serde_json::from_slice(payload_bytes)
+        .map_err(|e| AppError::Unauthorized(format!("invalid auth payload: {e}")))?;
+    match &payload.request {
+        Some(binding) => binding.verify(&state.config.server_hostname, target)?,
+        None if state.config.auth_allow_unbound => {
+            tracing::warn!(
+                user_id = user_id.as_str(),
+                path = target.path,
+                "accepted deprecated auth payload without request binding"
+            );
+        }
+        None => {
+            return Err(AppError::Unauthorized(
+                "auth payload must be bound to the request".into(),
+            ));
+        }
+    }
+
+    let nonce_time = validate_nonce_timestamp(&payload.nonce)?;
+    let expires_at = nonce_time + chrono::Duration::hours(1);
+    let is_new = db::nonces::try_use_nonce(
+        &state.pool,
+        NonceType::Auth,
+        payload.nonce.replay_key(),
+        user_id.as_str(),
+        expires_at,
+    )
+    .await?;
+    if !is_new {
+        return Err(AppError::Unauthorized("nonce already used".into()));
+    }
+    Ok(())
+}
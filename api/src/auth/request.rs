@@ -0,0 +1,238 @@
+use axum::RequestExt;
+use axum::body::Body;
+use axum::extract::{OriginalUri, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Method, Uri, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+
+/// 署名対象となるリクエスト情報。
+///
+/// `path` はクエリを含むリクエストターゲット（`/v1` プレフィックスを含む）。
+#[derive(Debug, Clone)]
+pub(crate) struct RequestTarget {
+    pub(crate) method: Method,
+    pub(crate) path: String,
+    pub(crate) content_digest: Option<String>,
+    pub(crate) has_body: bool,
+}
+
+impl RequestTarget {
+    pub(crate) fn new(method: &Method, uri: &Uri, headers: &HeaderMap) -> Self {
+        let path = uri
+            .path_and_query()
+            .map(|pq| pq.as_str().to_string())
+            .unwrap_or_else(|| uri.path().to_string());
+        let content_digest = headers
+            .get("content-digest")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let has_body = headers.contains_key(header::TRANSFER_ENCODING)
+            || headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .is_some_and(|len| len > 0);
+        Self {
+            method: method.clone(),
+            path,
+            content_digest,
+            has_body,
+        }
+    }
+
+    /// ネストされたルータでもプレフィックスを含む元のURIを使用する。
+    pub(crate) fn from_parts(parts: &Parts) -> Self {
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|u| &u.0)
+            .unwrap_or(&parts.uri);
+        Self::new(&parts.method, uri, &parts.headers)
+    }
+}
+
+/// 認証ペイロードに含まれるリクエスト束縛情報。
+///
+/// `content_digest` は RFC 9530 の `Content-Digest` ヘッダー値（`sha-256=:<base64>:`）。
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct RequestBinding {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) host: String,
+    #[serde(default)]
+    pub(crate) content_digest: Option<String>,
+}
+
+impl RequestBinding {
+    /// 署名されたメソッド・パス・ホスト・本文ダイジェストが実際のリクエストと一致するか検証する。
+    ///
+    /// ホストは自サーバのホスト名（ポートは無視する）と一致する必要がある。
+    /// リクエストの `Host` はクライアントが指定できるため、他サーバ宛ての署名の再送を防ぐ根拠にしない。
+    /// 他サーバへのプロキシには委任ステートメントを使用するため、転送されたリクエストは受け付けない。
+    pub(crate) fn verify(
+        &self,
+        server_hostname: &str,
+        target: &RequestTarget,
+    ) -> Result<(), AppError> {
+        if !self.method.eq_ignore_ascii_case(target.method.as_str()) {
+            return Err(AppError::Unauthorized(
+                "signed method does not match request".into(),
+            ));
+        }
+        if self.path != target.path {
+            return Err(AppError::Unauthorized(
+                "signed path does not match request".into(),
+            ));
+        }
+
+        if !strip_port(&self.host).eq_ignore_ascii_case(server_hostname) {
+            return Err(AppError::Unauthorized(
+                "signed host does not match this server".into(),
+            ));
+        }
+
+        match (&self.content_digest, &target.content_digest) {
+            (Some(signed), Some(actual)) => {
+                if sha256_digest_value(signed)? != sha256_digest_value(actual)? {
+                    return Err(AppError::Unauthorized(
+                        "signed content digest does not match request".into(),
+                    ));
+                }
+            }
+            (Some(_), None) => {
+                return Err(AppError::Unauthorized(
+                    "content-digest header is required".into(),
+                ));
+            }
+            (None, _) if target.has_body => {
+                return Err(AppError::Unauthorized(
+                    "request body is not covered by the signature".into(),
+                ));
+            }
+            (None, _) => {}
+        }
+        Ok(())
+    }
+}
+
+/// `host[:port]` からポートを取り除く。
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// `Content-Digest` ヘッダー値からsha-256のダイジェストを取り出す。
+fn sha256_digest_value(header_value: &str) -> Result<Vec<u8>, AppError> {
+    header_value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(alg, _)| alg.trim().eq_ignore_ascii_case("sha-256"))
+        .and_then(|(_, value)| {
+            let value = value.trim().strip_prefix(':')?.strip_suffix(':')?;
+            STANDARD.decode(value).ok()
+        })
+        .ok_or_else(|| AppError::BadRequest("invalid content-digest header".into()))
+}
+
+/// `Content-Digest` ヘッダーがある場合に本文のsha-256ダイジェストと一致するか検証する。
+///
+/// 検証済みのヘッダーを認証ペイロードの `content_digest` と照合することで本文を署名に束縛する。
+/// 本文はルートの `DefaultBodyLimit` まで読み込むため、上限を設定するレイヤーより内側に置く。
+pub async fn verify_content_digest(request: Request, next: Next) -> Response {
+    let Some(header_value) = request
+        .headers()
+        .get("content-digest")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+    else {
+        return next.run(request).await;
+    };
+    let expected = match sha256_digest_value(&header_value) {
+        Ok(expected) => expected,
+        Err(e) => return e.into_response(),
+    };
+
+    let (parts, body) = request.with_limited_body().into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return AppError::PayloadTooLarge("request body too large".into()).into_response();
+        }
+    };
+    if Sha256::digest(&bytes).as_slice() != expected.as_slice() {
+        return AppError::BadRequest("content-digest does not match request body".into())
+            .into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sha256_content_digest() {
+        let digest = Sha256::digest(b"{\"hello\":\"world\"}");
+        let header = format!("sha-512=:AAAA:, sha-256=:{}:", STANDARD.encode(digest));
+        assert_eq!(sha256_digest_value(&header).unwrap(), digest.as_slice());
+        assert!(sha256_digest_value("sha-256=abc").is_err());
+        assert!(sha256_digest_value("md5=:AAAA:").is_err());
+    }
+
+    #[test]
+    fn request_target_detects_body() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "example.com".parse().unwrap());
+        headers.insert(header::CONTENT_LENGTH, "0".parse().unwrap());
+        let uri: Uri = "/v1/chat?x=1".parse().unwrap();
+        let target = RequestTarget::new(&Method::GET, &uri, &headers);
+        assert_eq!(target.path, "/v1/chat?x=1");
+        assert!(!target.has_body);
+
+        headers.insert(header::CONTENT_LENGTH, "12".parse().unwrap());
+        assert!(RequestTarget::new(&Method::POST, &uri, &headers).has_body);
+    }
+
+    fn binding(host: &str) -> RequestBinding {
+        RequestBinding {
+            method: "GET".into(),
+            path: "/v1/chat".into(),
+            host: host.into(),
+            content_digest: None,
+        }
+    }
+
+    #[test]
+    fn verifies_signed_host_against_server_hostname() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "other.example".parse().unwrap());
+        let uri: Uri = "/v1/chat".parse().unwrap();
+        let target = RequestTarget::new(&Method::GET, &uri, &headers);
+
+        assert!(
+            binding("example.com")
+                .verify("example.com", &target)
+                .is_ok()
+        );
+        assert!(
+            binding("example.com:8080")
+                .verify("example.com", &target)
+                .is_ok()
+        );
+        // Host ヘッダーを署名済みのホストに合わせても、自サーバ宛てでなければ拒否する
+        assert!(
+            binding("other.example")
+                .verify("example.com", &target)
+                .is_err()
+        );
+    }
+}
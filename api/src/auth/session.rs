@@ -0,0 +1,80 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use super::AuthenticatedUser;
+use crate::AppState;
+use crate::db;
+use crate::error::AppError;
+use crate::types::UserId;
+
+const TOKEN_BYTES: usize = 32;
+
+/// ランダムなセッショントークンを生成する。
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// DB保存・照合用にトークンをハッシュ化する。
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Bearerトークンを検証し、認証されたユーザ情報を返す。
+///
+/// 主鍵が失効している場合、発行時と主鍵が異なる場合や、発行時の署名サブキー（デバイス鍵）が失効・削除されている場合は拒否する。
+pub(crate) async fn authenticate_session(
+    state: &AppState,
+    token: &str,
+) -> Result<AuthenticatedUser, AppError> {
+    let token_hash = hash_token(token);
+    let session = db::sessions::get_active_session(&state.pool, &token_hash)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid or expired session token".into()))?;
+
+    let user_id = UserId(session.user_id);
+    let user = db::users::get_user(&state.pool, &user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("session user not found".into()))?;
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
+        .map_err(|e| AppError::Unauthorized(format!("invalid signing key: {e}")))?;
+    super::ensure_not_revoked(state, &user.primary_key_fingerprint, &public_keys).await?;
+    if user.primary_key_fingerprint != session.primary_key_fingerprint
+        || !public_keys.is_valid_signing_sub_key(&session.signing_key_fingerprint)
+    {
+        return Err(AppError::Unauthorized(
+            "session token was issued for a different key".into(),
+        ));
+    }
+    crate::federation::policy::ensure_user_permitted(state, &user_id).await?;
+
+    Ok(AuthenticatedUser {
+        user_id,
+        primary_key_fingerprint: user.primary_key_fingerprint,
+        signing_public_key: user.signing_public_key,
+        session_token_hash: Some(token_hash),
+        signing_key_fingerprint: Some(session.signing_key_fingerprint),
+        delegated_by: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_unique_and_hash_deterministically() {
+        let a = generate_token();
+        let b = generate_token();
+        assert_ne!(a, b);
+        assert_eq!(hash_token(&a), hash_token(&a));
+        assert_ne!(hash_token(&a), hash_token(&b));
+        assert_eq!(hash_token(&a).len(), 64);
+    }
+}
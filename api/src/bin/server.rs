@@ -6,10 +6,14 @@ use xrypton_api::DidCache;
 use xrypton_api::config::AppConfig;
 use xrypton_api::db;
 use xrypton_api::federation::dns::DnsTxtResolver;
+use xrypton_api::federation::http::OutboundClient;
+use xrypton_api::federation::policy::{FederationPolicyStore, policy_from_config};
+use xrypton_api::federation::server_key::{PeerKeyCache, ServerKey};
 use xrypton_api::routes::build_router;
 use xrypton_api::storage::S3Storage;
 
 const NONCE_CLEANUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const FEDERATION_POLICY_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() {
@@ -51,14 +55,105 @@ async fn main() {
                         );
                     }
                 }
+                match db::sessions::delete_expired_sessions(&cleanup_pool).await {
+                    Ok(deleted) => {
+                        tracing::info!(deleted, "session token cleanup finished");
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            error = %e,
+                            "session token cleanup failed"
+                        );
+                    }
+                }
+                // 取得失敗回数の集計期間（1時間）を過ぎたチャレンジを削除する
+                match db::backups::delete_challenges_before(
+                    &cleanup_pool,
+                    chrono::Utc::now() - chrono::Duration::hours(1),
+                )
+                .await
+                {
+                    Ok(deleted) => {
+                        tracing::info!(deleted, "backup challenge cleanup finished");
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            error = %e,
+                            "backup challenge cleanup failed"
+                        );
+                    }
+                }
+                // 集計期間（1日）を過ぎた復元リクエストは有効期限も切れている
+                match db::social_recovery::delete_requests_before(
+                    &cleanup_pool,
+                    chrono::Utc::now() - chrono::Duration::days(1),
+                )
+                .await
+                {
+                    Ok(deleted) => {
+                        tracing::info!(deleted, "social recovery request cleanup finished");
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            error = %e,
+                            "social recovery request cleanup failed"
+                        );
+                    }
+                }
+                // 集計期間（1時間）を過ぎたワンタイムプレキーの取得記録を削除する
+                match db::prekeys::delete_claims_before(
+                    &cleanup_pool,
+                    chrono::Utc::now() - chrono::Duration::hours(1),
+                )
+                .await
+                {
+                    Ok(deleted) => {
+                        tracing::info!(deleted, "one-time prekey claim cleanup finished");
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            error = %e,
+                            "one-time prekey claim cleanup failed"
+                        );
+                    }
+                }
                 sleep(NONCE_CLEANUP_INTERVAL).await;
             }
         });
     }
 
+    let fallback_policy = policy_from_config(&config);
+    let federation_policy = FederationPolicyStore::new(fallback_policy.clone());
+    federation_policy
+        .reload(&pool, &fallback_policy)
+        .await
+        .expect("failed to load federation policy");
+
+    {
+        // 他インスタンスで変更されたポリシーを取り込む
+        let reload_pool = pool.clone();
+        let reload_policy = federation_policy.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(FEDERATION_POLICY_RELOAD_INTERVAL).await;
+                if let Err(e) = reload_policy.reload(&reload_pool, &fallback_policy).await {
+                    tracing::warn!(
+                        error = %e,
+                        "federation policy reload failed"
+                    );
+                }
+            }
+        });
+    }
+
     let storage = Arc::new(S3Storage::new(&config).await);
     let dns_resolver = DnsTxtResolver::new(Duration::from_secs(3600));
     let did_cache = DidCache::new(Duration::from_secs(86400));
+    let http = OutboundClient::new(&config, federation_policy.clone());
+    let server_key = ServerKey::load_or_create(&pool)
+        .await
+        .expect("failed to load server key");
+    let peer_keys = PeerKeyCache::new(Duration::from_secs(3600));
 
     let state = AppState {
         pool,
@@ -66,6 +161,10 @@ async fn main() {
         storage,
         dns_resolver,
         did_cache,
+        http,
+        federation_policy,
+        server_key,
+        peer_keys,
     };
 
     let app = build_router(state);
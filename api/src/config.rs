@@ -15,6 +15,22 @@ pub struct AppConfig {
     pub server_hostname: String,
     /// 連合通信でHTTPフォールバックを許可するか（開発用）
     pub federation_allow_http: bool,
+    /// 連合・プロキシ通信の接続タイムアウト（秒）
+    pub federation_connect_timeout_secs: u64,
+    /// 連合・プロキシ通信のリクエスト全体のタイムアウト（秒）
+    pub federation_request_timeout_secs: u64,
+    /// 連合・プロキシ通信で受け付ける応答本文の上限（バイト）
+    pub federation_max_response_bytes: usize,
+    /// DBに連合ポリシーが未保存の場合の初期モード（open / allow_list / deny_list）
+    pub federation_policy: String,
+    /// 初期ポリシーの対象ドメイン（カンマ区切り）
+    pub federation_policy_domains: Vec<String>,
+    /// 管理APIを利用できるユーザID
+    pub admin_user_ids: Vec<String>,
+    /// セッショントークンの有効期間（秒）
+    pub session_token_ttl_secs: u64,
+    /// リクエストに束縛されていない旧形式の認証ペイロードを受け付けるか（非推奨）
+    pub auth_allow_unbound: bool,
 }
 
 impl AppConfig {
@@ -32,6 +48,39 @@ impl AppConfig {
             federation_allow_http: env::var("FEDERATION_ALLOW_HTTP")
                 .map(|v| v == "true" || v == "1")
                 .unwrap_or(false),
+            federation_connect_timeout_secs: env::var("FEDERATION_CONNECT_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            federation_request_timeout_secs: env::var("FEDERATION_REQUEST_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            federation_max_response_bytes: env::var("FEDERATION_MAX_RESPONSE_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16 * 1024 * 1024),
+            federation_policy: env::var("FEDERATION_POLICY").unwrap_or_else(|_| "open".into()),
+            federation_policy_domains: split_list(env::var("FEDERATION_POLICY_DOMAINS").ok()),
+            admin_user_ids: split_list(env::var("ADMIN_USER_IDS").ok()),
+            session_token_ttl_secs: env::var("SESSION_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(15 * 60),
+            auth_allow_unbound: env::var("AUTH_ALLOW_UNBOUND")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(true),
         }
     }
 }
+
+fn split_list(value: Option<String>) -> Vec<String> {
+    value
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
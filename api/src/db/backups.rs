@@ -9,12 +9,17 @@ pub async fn upsert_secret_key_backup(
     version: i32,
     webauthn_credential_id_b64: &str,
 ) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now();
     let q = sql(
         "INSERT INTO secret_key_backups (user_id, armor, version, webauthn_credential_id_b64) \
          VALUES (?, ?, ?, ?) \
          ON CONFLICT (user_id) DO UPDATE SET \
-         armor = ?, version = ?, webauthn_credential_id_b64 = ?, updated_at = CURRENT_TIMESTAMP",
+         armor = ?, version = ?, webauthn_credential_id_b64 = ?, updated_at = ?",
     );
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
 
     sqlx::query(&q)
         .bind(user_id)
@@ -24,6 +29,7 @@ pub async fn upsert_secret_key_backup(
         .bind(armor)
         .bind(version)
         .bind(webauthn_credential_id_b64)
+        .bind(now_bind)
         .execute(pool)
         .await?;
     Ok(())
@@ -43,7 +49,176 @@ pub async fn get_secret_key_backup(
 
 #[tracing::instrument(skip(pool), err)]
 pub async fn delete_secret_key_backup(pool: &Db, user_id: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM secret_key_backup_recovery_keys WHERE user_id = ?");
+    sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+
     let q = sql("DELETE FROM secret_key_backups WHERE user_id = ?");
-    let result = sqlx::query(&q).bind(user_id).execute(pool).await?;
+    let result = sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+
+    tx.commit().await?;
     Ok(result.rows_affected() > 0)
 }
+
+/// バックアップ取得時の所持証明に使う回復用公開鍵を置き換える。
+#[tracing::instrument(skip(pool, public_keys), err)]
+pub async fn replace_recovery_keys(
+    pool: &Db,
+    user_id: &str,
+    public_keys: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM secret_key_backup_recovery_keys WHERE user_id = ?");
+    sqlx::query(&q).bind(user_id).execute(&mut *tx).await?;
+
+    let q = sql(
+        "INSERT INTO secret_key_backup_recovery_keys (user_id, public_key) VALUES (?, ?) \
+         ON CONFLICT (user_id, public_key) DO NOTHING",
+    );
+    for public_key in public_keys {
+        sqlx::query(&q)
+            .bind(user_id)
+            .bind(public_key)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool, public_key), err)]
+pub async fn has_recovery_key(
+    pool: &Db,
+    user_id: &str,
+    public_key: &str,
+) -> Result<bool, sqlx::Error> {
+    let q =
+        sql("SELECT 1 FROM secret_key_backup_recovery_keys WHERE user_id = ? AND public_key = ?");
+    let row: Option<(i32,)> = sqlx::query_as(&q)
+        .bind(user_id)
+        .bind(public_key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// `since` 以降に署名検証に失敗した取得の試行回数を返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn count_failed_attempts_since(
+    pool: &Db,
+    user_id: &str,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<i64, sqlx::Error> {
+    let q = sql(
+        "SELECT COUNT(*) FROM secret_key_backup_challenges WHERE user_id = ? AND failed_at > ?",
+    );
+    #[cfg(not(feature = "postgres"))]
+    let since_bind = since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let since_bind = since;
+
+    let (count,): (i64,) = sqlx::query_as(&q)
+        .bind(user_id)
+        .bind(since_bind)
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+#[tracing::instrument(skip(pool, challenge), err)]
+pub async fn create_challenge(
+    pool: &Db,
+    challenge: &str,
+    user_id: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), sqlx::Error> {
+    let q = sql(
+        "INSERT INTO secret_key_backup_challenges (challenge, user_id, expires_at) VALUES (?, ?, ?)",
+    );
+    #[cfg(not(feature = "postgres"))]
+    let expires_at_bind = expires_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let expires_at_bind = expires_at;
+
+    sqlx::query(&q)
+        .bind(challenge)
+        .bind(user_id)
+        .bind(expires_at_bind)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 未使用かつ有効期限内のチャレンジを使用済みにする。使用できた場合は true を返す。
+#[tracing::instrument(skip(pool, challenge), err)]
+pub async fn consume_challenge(
+    pool: &Db,
+    challenge: &str,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now();
+    let q = sql("UPDATE secret_key_backup_challenges SET used_at = ? \
+         WHERE challenge = ? AND user_id = ? AND used_at IS NULL AND expires_at > ?");
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let result = sqlx::query(&q)
+        .bind(&now_bind)
+        .bind(challenge)
+        .bind(user_id)
+        .bind(&now_bind)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 署名検証に失敗した試行として、未使用かつ有効期限内のチャレンジを使用済みにする。
+///
+/// 失敗回数の集計に使うため、失敗したチャレンジは集計期間が過ぎるまで削除しない。
+#[tracing::instrument(skip(pool, challenge), err)]
+pub async fn record_failed_attempt(
+    pool: &Db,
+    challenge: &str,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now();
+    let q = sql(
+        "UPDATE secret_key_backup_challenges SET used_at = ?, failed_at = ? \
+         WHERE challenge = ? AND user_id = ? AND used_at IS NULL AND expires_at > ?",
+    );
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let result = sqlx::query(&q)
+        .bind(&now_bind)
+        .bind(&now_bind)
+        .bind(challenge)
+        .bind(user_id)
+        .bind(&now_bind)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 試行回数の集計期間を過ぎたチャレンジを削除する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_challenges_before(
+    pool: &Db,
+    before: chrono::DateTime<chrono::Utc>,
+) -> Result<u64, sqlx::Error> {
+    let q = sql("DELETE FROM secret_key_backup_challenges WHERE created_at < ?");
+    #[cfg(not(feature = "postgres"))]
+    let before_bind = before.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let before_bind = before;
+
+    let result = sqlx::query(&q).bind(before_bind).execute(pool).await?;
+    Ok(result.rows_affected())
+}
@@ -131,6 +131,36 @@ pub async fn get_chat_members(
         .await
 }
 
+/// 指定ユーザと同じチャットに所属する他のメンバーを返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_co_member_ids(pool: &Db, user_id: &UserId) -> Result<Vec<UserId>, sqlx::Error> {
+    let q = sql("SELECT DISTINCT other.user_id FROM chat_members self_m \
+         JOIN chat_members other ON other.chat_id = self_m.chat_id \
+         WHERE self_m.user_id = ? AND other.user_id != ?");
+    let rows = sqlx::query_as::<_, (String,)>(&q)
+        .bind(user_id.as_str())
+        .bind(user_id.as_str())
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|(id,)| UserId(id)).collect())
+}
+
+/// 指定ユーザが所属する外部ホストのチャットのホームサーバドメインを返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_remote_chat_domains(
+    pool: &Db,
+    user_id: &UserId,
+) -> Result<Vec<String>, sqlx::Error> {
+    let q = sql("SELECT DISTINCT g.server_domain FROM chat_groups g \
+         JOIN chat_members m ON m.chat_id = g.id \
+         WHERE m.user_id = ? AND g.server_domain IS NOT NULL");
+    let rows = sqlx::query_as::<_, (String,)>(&q)
+        .bind(user_id.as_str())
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|(d,)| d).collect())
+}
+
 /// 外部サーバから同期されたチャットの参照を作成する。
 /// server_domain にホームサーバのドメインを設定し、
 /// ローカルメンバーのみ chat_members に追加する。
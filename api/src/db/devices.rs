@@ -0,0 +1,59 @@
+use super::models::UserDeviceRow;
+use super::{Db, sql};
+
+/// デバイスを登録する。登録済みの場合は名前を更新する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn upsert_device(
+    pool: &Db,
+    user_id: &str,
+    subkey_fingerprint: &str,
+    name: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let q = sql(
+        "INSERT INTO user_devices (user_id, subkey_fingerprint, name) VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, subkey_fingerprint) DO UPDATE SET name = excluded.name",
+    );
+    sqlx::query(&q)
+        .bind(user_id)
+        .bind(subkey_fingerprint)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_devices(pool: &Db, user_id: &str) -> Result<Vec<UserDeviceRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM user_devices WHERE user_id = ? ORDER BY created_at");
+    sqlx::query_as::<_, UserDeviceRow>(&q)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// デバイスを失効済みとして記録する。未登録のデバイスは失効済みとして追加する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn revoke_device(
+    pool: &Db,
+    user_id: &str,
+    subkey_fingerprint: &str,
+) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql(
+        "INSERT INTO user_devices (user_id, subkey_fingerprint, revoked_at) \
+         VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, subkey_fingerprint) DO UPDATE SET \
+         revoked_at = COALESCE(user_devices.revoked_at, ?)",
+    );
+    sqlx::query(&q)
+        .bind(user_id)
+        .bind(subkey_fingerprint)
+        .bind(&now_bind)
+        .bind(&now_bind)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use super::{Db, sql};
+
+/// 保存済みの連合ポリシー（モード文字列とドメイン一覧）を取得する。
+pub async fn get_policy(pool: &Db) -> Result<Option<(String, Vec<String>)>, sqlx::Error> {
+    let q = sql("SELECT mode FROM federation_policy WHERE id = 1");
+    let Some((mode,)) = sqlx::query_as::<_, (String,)>(&q)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let q = sql("SELECT domain FROM federation_policy_domains ORDER BY domain");
+    let domains = sqlx::query_as::<_, (String,)>(&q)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(d,)| d)
+        .collect();
+    Ok(Some((mode, domains)))
+}
+
+/// 連合ポリシーを置き換える。
+#[tracing::instrument(skip(pool, domains), err)]
+pub async fn replace_policy(
+    pool: &Db,
+    mode: &str,
+    domains: &[String],
+    updated_by: &str,
+) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let mut tx = pool.begin().await?;
+
+    let q = sql(
+        "INSERT INTO federation_policy (id, mode, updated_by) VALUES (1, ?, ?) \
+         ON CONFLICT (id) DO UPDATE SET \
+         mode = ?, updated_by = ?, updated_at = ?",
+    );
+    sqlx::query(&q)
+        .bind(mode)
+        .bind(updated_by)
+        .bind(mode)
+        .bind(updated_by)
+        .bind(now_bind)
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql("DELETE FROM federation_policy_domains");
+    sqlx::query(&q).execute(&mut *tx).await?;
+
+    let q = sql("INSERT INTO federation_policy_domains (domain) VALUES (?)");
+    for domain in domains {
+        sqlx::query(&q).bind(domain).execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
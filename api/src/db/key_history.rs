@@ -0,0 +1,64 @@
+use super::models::UserKeyHistoryRow;
+use super::{Db, sql};
+
+/// 観測した公開鍵を履歴に記録する。既知の鍵であれば最終観測日時のみ更新する。
+#[tracing::instrument(skip(pool, signing_public_key, encryption_public_key), err)]
+pub async fn record_key(
+    pool: &Db,
+    user_id: &str,
+    primary_key_fingerprint: &str,
+    previous_fingerprint: Option<&str>,
+    signing_public_key: &str,
+    encryption_public_key: &str,
+) -> Result<(), sqlx::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("INSERT INTO user_key_history \
+         (id, user_id, primary_key_fingerprint, previous_fingerprint, signing_public_key, encryption_public_key) \
+         VALUES (?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (user_id, primary_key_fingerprint) DO UPDATE SET last_seen_at = ?");
+    sqlx::query(&q)
+        .bind(&id)
+        .bind(user_id)
+        .bind(primary_key_fingerprint)
+        .bind(previous_fingerprint)
+        .bind(signing_public_key)
+        .bind(encryption_public_key)
+        .bind(now_bind)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// ユーザの鍵履歴を古い順に返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn list_key_history(
+    pool: &Db,
+    user_id: &str,
+) -> Result<Vec<UserKeyHistoryRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM user_key_history WHERE user_id = ? ORDER BY first_seen_at ASC");
+    sqlx::query_as::<_, UserKeyHistoryRow>(&q)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// 指紋に対応する履歴上の鍵を返す（最後に観測されたもの）。
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_key_by_fingerprint(
+    pool: &Db,
+    primary_key_fingerprint: &str,
+) -> Result<Option<UserKeyHistoryRow>, sqlx::Error> {
+    let q = sql(
+        "SELECT * FROM user_key_history WHERE primary_key_fingerprint = ? \
+         ORDER BY last_seen_at DESC LIMIT 1",
+    );
+    sqlx::query_as::<_, UserKeyHistoryRow>(&q)
+        .bind(primary_key_fingerprint)
+        .fetch_optional(pool)
+        .await
+}
@@ -0,0 +1,31 @@
+use super::{Db, sql};
+
+/// 検証済みの失効証明書を保存する。既に失効済みの場合は何もしない。
+#[tracing::instrument(skip(pool, certificate), err)]
+pub async fn insert_revocation(
+    pool: &Db,
+    fingerprint: &str,
+    user_id: &str,
+    certificate: &str,
+) -> Result<bool, sqlx::Error> {
+    let q = sql(
+        "INSERT INTO key_revocations (fingerprint, user_id, certificate) VALUES (?, ?, ?) \
+         ON CONFLICT (fingerprint) DO NOTHING",
+    );
+    let result = sqlx::query(&q)
+        .bind(fingerprint)
+        .bind(user_id)
+        .bind(certificate)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn is_revoked(pool: &Db, fingerprint: &str) -> Result<bool, sqlx::Error> {
+    let q = sql("SELECT 1 FROM key_revocations WHERE fingerprint = ?");
+    let row: Option<(i32,)> = sqlx::query_as(&q)
+        .bind(fingerprint)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
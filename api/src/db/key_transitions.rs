@@ -0,0 +1,82 @@
+use super::models::KeyTransitionRow;
+use super::{Db, sql};
+use crate::types::UserId;
+
+/// 保存する鍵移行宣言
+#[derive(Debug, Clone)]
+pub struct NewKeyTransition<'a> {
+    pub old_fingerprint: &'a str,
+    pub new_fingerprint: &'a str,
+    pub old_key_statement: &'a str,
+    pub new_key_statement: &'a str,
+}
+
+/// 鍵移行宣言を保存し、ユーザの公開鍵を同じトランザクションで更新する。
+///
+/// 旧鍵ごとに移行先は1つのみ。旧鍵から既に移行済みの場合や、ユーザの主鍵が
+/// 旧鍵でなくなっている場合は何も変更せず `false` を返す。
+#[tracing::instrument(skip(pool, transition, encryption_public_key, signing_public_key), err)]
+pub async fn rotate_user_keys(
+    pool: &Db,
+    user_id: &UserId,
+    transition: &NewKeyTransition<'_>,
+    encryption_public_key: &str,
+    signing_public_key: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("INSERT INTO key_transitions \
+         (old_fingerprint, new_fingerprint, user_id, old_key_statement, new_key_statement) \
+         VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT (old_fingerprint) DO NOTHING");
+    let result = sqlx::query(&q)
+        .bind(transition.old_fingerprint)
+        .bind(transition.new_fingerprint)
+        .bind(user_id.as_str())
+        .bind(transition.old_key_statement)
+        .bind(transition.new_key_statement)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("UPDATE users
+         SET encryption_public_key = ?,
+             signing_public_key = ?,
+             primary_key_fingerprint = ?,
+             updated_at = ?
+         WHERE id = ? AND primary_key_fingerprint = ?");
+    let result = sqlx::query(&q)
+        .bind(encryption_public_key)
+        .bind(signing_public_key)
+        .bind(transition.new_fingerprint)
+        .bind(now_bind)
+        .bind(user_id.as_str())
+        .bind(transition.old_fingerprint)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_transition_from(
+    pool: &Db,
+    old_fingerprint: &str,
+) -> Result<Option<KeyTransitionRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM key_transitions WHERE old_fingerprint = ?");
+    sqlx::query_as::<_, KeyTransitionRow>(&q)
+        .bind(old_fingerprint)
+        .fetch_optional(pool)
+        .await
+}
@@ -3,12 +3,23 @@ pub mod backups;
 pub mod chat;
 pub mod contacts;
 pub mod deleted_users;
+pub mod devices;
+pub mod federation_policy;
 pub mod files;
+pub mod key_history;
+pub mod key_revocations;
+pub mod key_transitions;
 pub mod messages;
 pub mod models;
 pub mod nonces;
+pub mod prekeys;
 pub mod push;
+pub mod sender_keys;
+pub mod server_keys;
+pub mod sessions;
+pub mod social_recovery;
 pub mod threads;
+pub mod user_moves;
 pub mod users;
 pub mod wot;
 pub mod x;
@@ -17,6 +17,97 @@ pub struct UserRow {
     pub updated_at: Timestamp,
 }
 
+/// 観測した公開鍵の履歴。`previous_fingerprint` が設定されている行は鍵変更を示す。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct UserKeyHistoryRow {
+    pub id: String,
+    pub user_id: String,
+    pub primary_key_fingerprint: String,
+    pub previous_fingerprint: Option<String>,
+    pub signing_public_key: String,
+    pub encryption_public_key: String,
+    pub first_seen_at: Timestamp,
+    pub last_seen_at: Timestamp,
+}
+
+/// 鍵移行の記録。`old_key_statement` と `new_key_statement` は同じ移行宣言を
+/// それぞれ旧主鍵・新主鍵で署名したもの。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct KeyTransitionRow {
+    pub old_fingerprint: String,
+    pub new_fingerprint: String,
+    pub user_id: String,
+    pub old_key_statement: String,
+    pub new_key_statement: String,
+    pub created_at: Timestamp,
+}
+
+/// ソーシャルリカバリーの設定。`payload_armored` は保護者に分割した鍵で暗号化したバックアップ。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SocialRecoveryConfigRow {
+    pub owner_id: String,
+    pub payload_armored: String,
+    pub threshold: i32,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+/// 保護者の暗号化サブキー宛てに暗号化したシェア
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SocialRecoveryShareRow {
+    pub owner_id: String,
+    pub guardian_id: String,
+    pub share_armored: String,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SocialRecoveryRequestRow {
+    pub id: String,
+    pub owner_id: String,
+    pub recovery_public_key: String,
+    pub recovery_key_fingerprint: String,
+    pub expires_at: Timestamp,
+    pub cancelled_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+}
+
+/// 保護者が復元者の回復鍵宛てに再暗号化したシェア
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SocialRecoveryResponseRow {
+    pub request_id: String,
+    pub guardian_id: String,
+    pub share_armored: String,
+    pub created_at: Timestamp,
+}
+
+/// 前方秘匿セッション用のプレキーバンドル。`signed_bundle` は ID 鍵と署名付きプレキーを
+/// 署名サブキーで署名したもの。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct PrekeyBundleRow {
+    pub user_id: String,
+    pub identity_key: String,
+    pub signed_bundle: String,
+    pub updated_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct OneTimePrekeyRow {
+    pub user_id: String,
+    pub prekey_id: i64,
+    pub public_key: String,
+    pub created_at: Timestamp,
+}
+
+/// アカウント移行の記録。`statement` は主鍵で署名された移行宣言。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct UserMoveRow {
+    pub from_id: String,
+    pub to_id: String,
+    pub primary_key_fingerprint: String,
+    pub statement: String,
+    pub created_at: Timestamp,
+}
+
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
 pub struct ProfileRow {
     pub user_id: String,
@@ -52,6 +143,17 @@ pub struct ChatMemberRow {
     pub joined_at: Timestamp,
 }
 
+/// センダーキーの配布メッセージ。`distribution` は受信者宛てに署名・暗号化したチェーン鍵。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ChatSenderKeyRow {
+    pub chat_id: String,
+    pub sender_id: String,
+    pub key_id: String,
+    pub recipient_id: String,
+    pub distribution: String,
+    pub created_at: Timestamp,
+}
+
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
 pub struct ThreadRow {
     pub id: String,
@@ -106,6 +208,28 @@ pub struct SecretKeyBackupRow {
     pub updated_at: Timestamp,
 }
 
+/// ユーザが登録したデバイス（署名サブキー）
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct UserDeviceRow {
+    pub user_id: String,
+    pub subkey_fingerprint: String,
+    pub name: Option<String>,
+    pub created_at: Timestamp,
+    pub revoked_at: Option<Timestamp>,
+}
+
+/// セッショントークン。トークン本体は保存せず SHA-256 ハッシュのみ保持する。
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SessionTokenRow {
+    pub token_hash: String,
+    pub user_id: String,
+    pub primary_key_fingerprint: String,
+    pub signing_key_fingerprint: String,
+    pub expires_at: Timestamp,
+    pub revoked_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+}
+
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
 pub struct ContactRow {
     pub user_id: String,
@@ -4,6 +4,7 @@ use super::{Db, sql};
 pub enum NonceType {
     Auth,
     Qr,
+    Delegation,
 }
 
 impl NonceType {
@@ -11,6 +12,7 @@ impl NonceType {
         match self {
             Self::Auth => "auth",
             Self::Qr => "qr",
+            Self::Delegation => "delegation",
         }
     }
 }
@@ -0,0 +1,184 @@
+use super::models::{OneTimePrekeyRow, PrekeyBundleRow};
+use super::{Db, sql};
+use crate::types::UserId;
+
+/// プレキーバンドルを置き換え、ワンタイムプレキーを追加する。
+///
+/// ID 鍵が変わった場合、古い ID 鍵に対応するワンタイムプレキーは削除する。
+/// 既存のワンタイムプレキーと ID が同じで公開鍵が異なる場合は何も変更せず `false` を返す。
+#[tracing::instrument(skip(pool, signed_bundle, one_time_prekeys), err)]
+pub async fn upsert_bundle(
+    pool: &Db,
+    user_id: &UserId,
+    identity_key: &str,
+    signed_bundle: &str,
+    one_time_prekeys: &[(i64, String)],
+) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM one_time_prekeys WHERE user_id = ? AND EXISTS \
+         (SELECT 1 FROM prekey_bundles WHERE user_id = ? AND identity_key <> ?)");
+    sqlx::query(&q)
+        .bind(user_id.as_str())
+        .bind(user_id.as_str())
+        .bind(identity_key)
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql(
+        "INSERT INTO prekey_bundles (user_id, identity_key, signed_bundle) VALUES (?, ?, ?) \
+         ON CONFLICT (user_id) DO UPDATE SET \
+         identity_key = ?, signed_bundle = ?, updated_at = ?",
+    );
+    sqlx::query(&q)
+        .bind(user_id.as_str())
+        .bind(identity_key)
+        .bind(signed_bundle)
+        .bind(identity_key)
+        .bind(signed_bundle)
+        .bind(now_bind)
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql(
+        "INSERT INTO one_time_prekeys (user_id, prekey_id, public_key) VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, prekey_id) DO NOTHING",
+    );
+    let existing_q =
+        sql("SELECT public_key FROM one_time_prekeys WHERE user_id = ? AND prekey_id = ?");
+    for (prekey_id, public_key) in one_time_prekeys {
+        let result = sqlx::query(&q)
+            .bind(user_id.as_str())
+            .bind(prekey_id)
+            .bind(public_key)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() > 0 {
+            continue;
+        }
+        // 同じ公開鍵の再送は許可する
+        let (existing,): (String,) = sqlx::query_as(&existing_q)
+            .bind(user_id.as_str())
+            .bind(prekey_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if existing != *public_key {
+            return Ok(false);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_bundle(
+    pool: &Db,
+    user_id: &UserId,
+) -> Result<Option<PrekeyBundleRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM prekey_bundles WHERE user_id = ?");
+    sqlx::query_as::<_, PrekeyBundleRow>(&q)
+        .bind(user_id.as_str())
+        .fetch_optional(pool)
+        .await
+}
+
+/// 最も古いワンタイムプレキーを取り出して削除する。各プレキーは一度しか渡さない。
+///
+/// `since` 以降に同じ要求者が取り出した数が `max_claims` に達している場合は渡さない。
+#[tracing::instrument(skip(pool), err)]
+pub async fn take_one_time_prekey(
+    pool: &Db,
+    user_id: &UserId,
+    requester_id: &UserId,
+    since: chrono::DateTime<chrono::Utc>,
+    max_claims: i64,
+) -> Result<Option<OneTimePrekeyRow>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("SELECT COUNT(*) FROM one_time_prekey_claims \
+         WHERE user_id = ? AND requester_id = ? AND claimed_at > ?");
+    #[cfg(not(feature = "postgres"))]
+    let since_bind = since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let since_bind = since;
+    let (claims,): (i64,) = sqlx::query_as(&q)
+        .bind(user_id.as_str())
+        .bind(requester_id.as_str())
+        .bind(since_bind)
+        .fetch_one(&mut *tx)
+        .await?;
+    if claims >= max_claims {
+        return Ok(None);
+    }
+
+    let q = sql("SELECT * FROM one_time_prekeys WHERE user_id = ? \
+         ORDER BY created_at ASC, prekey_id ASC LIMIT 1");
+    let row = sqlx::query_as::<_, OneTimePrekeyRow>(&q)
+        .bind(user_id.as_str())
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let q = sql("DELETE FROM one_time_prekeys WHERE user_id = ? AND prekey_id = ?");
+    let result = sqlx::query(&q)
+        .bind(user_id.as_str())
+        .bind(row.prekey_id)
+        .execute(&mut *tx)
+        .await?;
+    // 並行リクエストが先に削除した場合は渡さない
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql(
+        "INSERT INTO one_time_prekey_claims (user_id, requester_id, claimed_at) VALUES (?, ?, ?)",
+    );
+    sqlx::query(&q)
+        .bind(user_id.as_str())
+        .bind(requester_id.as_str())
+        .bind(now_bind)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(row))
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn count_one_time_prekeys(pool: &Db, user_id: &UserId) -> Result<i64, sqlx::Error> {
+    let q = sql("SELECT COUNT(*) FROM one_time_prekeys WHERE user_id = ?");
+    let (count,): (i64,) = sqlx::query_as(&q)
+        .bind(user_id.as_str())
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_claims_before(
+    pool: &Db,
+    before: chrono::DateTime<chrono::Utc>,
+) -> Result<u64, sqlx::Error> {
+    let q = sql("DELETE FROM one_time_prekey_claims WHERE claimed_at < ?");
+    #[cfg(not(feature = "postgres"))]
+    let before_bind = before.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let before_bind = before;
+
+    let result = sqlx::query(&q).bind(before_bind).execute(pool).await?;
+    Ok(result.rows_affected())
+}
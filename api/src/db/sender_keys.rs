@@ -0,0 +1,61 @@
+use super::models::ChatSenderKeyRow;
+use super::{Db, sql};
+use crate::types::{ChatId, UserId};
+
+/// 送信者のセンダーキーを各受信者宛ての配布メッセージとともに保存する。
+///
+/// 同じ鍵IDで再配布した場合は配布メッセージを置き換える。
+#[tracing::instrument(skip(pool, distributions), err)]
+pub async fn store_distributions(
+    pool: &Db,
+    chat_id: &ChatId,
+    sender_id: &UserId,
+    key_id: &str,
+    distributions: &[(String, String)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM chat_sender_keys WHERE chat_id = ? AND sender_id = ? AND key_id = ?");
+    sqlx::query(&q)
+        .bind(chat_id.as_str())
+        .bind(sender_id.as_str())
+        .bind(key_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql(
+        "INSERT INTO chat_sender_keys (chat_id, sender_id, key_id, recipient_id, distribution) \
+         VALUES (?, ?, ?, ?, ?)",
+    );
+    for (recipient_id, distribution) in distributions {
+        sqlx::query(&q)
+            .bind(chat_id.as_str())
+            .bind(sender_id.as_str())
+            .bind(key_id)
+            .bind(recipient_id)
+            .bind(distribution)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 指定ユーザ宛ての配布メッセージを古い順に返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn list_for_recipient(
+    pool: &Db,
+    chat_id: &ChatId,
+    recipient_id: &UserId,
+) -> Result<Vec<ChatSenderKeyRow>, sqlx::Error> {
+    let q = sql(
+        "SELECT * FROM chat_sender_keys WHERE chat_id = ? AND recipient_id = ? \
+         ORDER BY created_at ASC",
+    );
+    sqlx::query_as::<_, ChatSenderKeyRow>(&q)
+        .bind(chat_id.as_str())
+        .bind(recipient_id.as_str())
+        .fetch_all(pool)
+        .await
+}
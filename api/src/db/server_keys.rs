@@ -0,0 +1,26 @@
+use super::{Db, sql};
+
+/// サーバ署名鍵を取得する。未保存の場合は渡された鍵を保存して返す。
+///
+/// 複数インスタンスが同時に起動しても、最初に保存された鍵を全インスタンスで共有する。
+#[tracing::instrument(skip_all, err)]
+pub async fn get_or_insert_key(
+    pool: &Db,
+    private_key: &str,
+    public_key: &str,
+) -> Result<(String, String), sqlx::Error> {
+    let q = sql(
+        "INSERT INTO server_keys (id, private_key, public_key) VALUES (1, ?, ?) \
+         ON CONFLICT (id) DO NOTHING",
+    );
+    sqlx::query(&q)
+        .bind(private_key)
+        .bind(public_key)
+        .execute(pool)
+        .await?;
+
+    let q = sql("SELECT private_key, public_key FROM server_keys WHERE id = 1");
+    sqlx::query_as::<_, (String, String)>(&q)
+        .fetch_one(pool)
+        .await
+}
@@ -0,0 +1,125 @@
+use super::models::SessionTokenRow;
+use super::{Db, sql};
+
+#[tracing::instrument(skip(pool, token_hash), err)]
+pub async fn create_session(
+    pool: &Db,
+    token_hash: &str,
+    user_id: &str,
+    primary_key_fingerprint: &str,
+    signing_key_fingerprint: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), sqlx::Error> {
+    let q = sql("INSERT INTO session_tokens \
+         (token_hash, user_id, primary_key_fingerprint, signing_key_fingerprint, expires_at) \
+         VALUES (?, ?, ?, ?, ?)");
+    #[cfg(not(feature = "postgres"))]
+    let expires_at_bind = expires_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let expires_at_bind = expires_at;
+
+    sqlx::query(&q)
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(primary_key_fingerprint)
+        .bind(signing_key_fingerprint)
+        .bind(expires_at_bind)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 有効期限内かつ未失効のセッションを取得する。
+#[tracing::instrument(skip(pool, token_hash), err)]
+pub async fn get_active_session(
+    pool: &Db,
+    token_hash: &str,
+) -> Result<Option<SessionTokenRow>, sqlx::Error> {
+    let now = chrono::Utc::now();
+    let q = sql(
+        "SELECT * FROM session_tokens WHERE token_hash = ? AND revoked_at IS NULL AND expires_at > ?",
+    );
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    sqlx::query_as::<_, SessionTokenRow>(&q)
+        .bind(token_hash)
+        .bind(now_bind)
+        .fetch_optional(pool)
+        .await
+}
+
+#[tracing::instrument(skip(pool, token_hash), err)]
+pub async fn revoke_session(pool: &Db, token_hash: &str) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("UPDATE session_tokens SET revoked_at = ? \
+         WHERE token_hash = ? AND revoked_at IS NULL");
+    let result = sqlx::query(&q)
+        .bind(now_bind)
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// ユーザのすべてのセッションを失効させ、失効件数を返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn revoke_user_sessions(pool: &Db, user_id: &str) -> Result<u64, sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("UPDATE session_tokens SET revoked_at = ? \
+         WHERE user_id = ? AND revoked_at IS NULL");
+    let result = sqlx::query(&q)
+        .bind(now_bind)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// 指定した署名サブキーに紐づくユーザのセッションを失効させ、失効件数を返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn revoke_key_sessions(
+    pool: &Db,
+    user_id: &str,
+    signing_key_fingerprint: &str,
+) -> Result<u64, sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("UPDATE session_tokens SET revoked_at = ? \
+         WHERE user_id = ? AND signing_key_fingerprint = ? AND revoked_at IS NULL");
+    let result = sqlx::query(&q)
+        .bind(now_bind)
+        .bind(user_id)
+        .bind(signing_key_fingerprint)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// 期限切れセッションを削除し、削除件数を返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_expired_sessions(pool: &Db) -> Result<u64, sqlx::Error> {
+    let now = chrono::Utc::now();
+    let q = sql("DELETE FROM session_tokens WHERE expires_at < ?");
+
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let result = sqlx::query(&q).bind(now_bind).execute(pool).await?;
+    Ok(result.rows_affected())
+}
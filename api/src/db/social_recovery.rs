@@ -0,0 +1,263 @@
+use super::models::{
+    SocialRecoveryConfigRow, SocialRecoveryRequestRow, SocialRecoveryResponseRow,
+    SocialRecoveryShareRow,
+};
+use super::{Db, sql};
+
+/// ソーシャルリカバリーの設定とシェアを置き換える。
+#[tracing::instrument(skip(pool, payload_armored, shares), err)]
+pub async fn replace_config(
+    pool: &Db,
+    owner_id: &str,
+    payload_armored: &str,
+    threshold: i32,
+    shares: &[(String, String)],
+) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM social_recovery_shares WHERE owner_id = ?");
+    sqlx::query(&q).bind(owner_id).execute(&mut *tx).await?;
+
+    let q = sql(
+        "INSERT INTO social_recovery_configs (owner_id, payload_armored, threshold) VALUES (?, ?, ?) \
+         ON CONFLICT (owner_id) DO UPDATE SET \
+         payload_armored = ?, threshold = ?, updated_at = ?",
+    );
+    sqlx::query(&q)
+        .bind(owner_id)
+        .bind(payload_armored)
+        .bind(threshold)
+        .bind(payload_armored)
+        .bind(threshold)
+        .bind(now_bind)
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql(
+        "INSERT INTO social_recovery_shares (owner_id, guardian_id, share_armored) VALUES (?, ?, ?)",
+    );
+    for (guardian_id, share_armored) in shares {
+        sqlx::query(&q)
+            .bind(owner_id)
+            .bind(guardian_id)
+            .bind(share_armored)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_config(
+    pool: &Db,
+    owner_id: &str,
+) -> Result<Option<SocialRecoveryConfigRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM social_recovery_configs WHERE owner_id = ?");
+    sqlx::query_as::<_, SocialRecoveryConfigRow>(&q)
+        .bind(owner_id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn list_shares(
+    pool: &Db,
+    owner_id: &str,
+) -> Result<Vec<SocialRecoveryShareRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM social_recovery_shares WHERE owner_id = ? ORDER BY guardian_id");
+    sqlx::query_as::<_, SocialRecoveryShareRow>(&q)
+        .bind(owner_id)
+        .fetch_all(pool)
+        .await
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_share(
+    pool: &Db,
+    owner_id: &str,
+    guardian_id: &str,
+) -> Result<Option<SocialRecoveryShareRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM social_recovery_shares WHERE owner_id = ? AND guardian_id = ?");
+    sqlx::query_as::<_, SocialRecoveryShareRow>(&q)
+        .bind(owner_id)
+        .bind(guardian_id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_config(pool: &Db, owner_id: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM social_recovery_shares WHERE owner_id = ?");
+    sqlx::query(&q).bind(owner_id).execute(&mut *tx).await?;
+
+    let q = sql("DELETE FROM social_recovery_configs WHERE owner_id = ?");
+    let result = sqlx::query(&q).bind(owner_id).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip(pool, recovery_public_key), err)]
+pub async fn create_request(
+    pool: &Db,
+    id: &str,
+    owner_id: &str,
+    recovery_public_key: &str,
+    recovery_key_fingerprint: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), sqlx::Error> {
+    let q = sql("INSERT INTO social_recovery_requests \
+         (id, owner_id, recovery_public_key, recovery_key_fingerprint, expires_at) \
+         VALUES (?, ?, ?, ?, ?)");
+    #[cfg(not(feature = "postgres"))]
+    let expires_at_bind = expires_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let expires_at_bind = expires_at;
+
+    sqlx::query(&q)
+        .bind(id)
+        .bind(owner_id)
+        .bind(recovery_public_key)
+        .bind(recovery_key_fingerprint)
+        .bind(expires_at_bind)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 有効期限内かつ取り消されていない復元リクエストを取得する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_active_request(
+    pool: &Db,
+    id: &str,
+    owner_id: &str,
+) -> Result<Option<SocialRecoveryRequestRow>, sqlx::Error> {
+    let now = chrono::Utc::now();
+    let q = sql("SELECT * FROM social_recovery_requests \
+         WHERE id = ? AND owner_id = ? AND cancelled_at IS NULL AND expires_at > ?");
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+
+    sqlx::query_as::<_, SocialRecoveryRequestRow>(&q)
+        .bind(id)
+        .bind(owner_id)
+        .bind(now_bind)
+        .fetch_optional(pool)
+        .await
+}
+
+/// `since` 以降に同じ回復鍵で作成された復元リクエストの数を返す。
+#[tracing::instrument(skip(pool), err)]
+pub async fn count_requests_since(
+    pool: &Db,
+    owner_id: &str,
+    recovery_key_fingerprint: &str,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<i64, sqlx::Error> {
+    let q = sql("SELECT COUNT(*) FROM social_recovery_requests \
+         WHERE owner_id = ? AND recovery_key_fingerprint = ? AND created_at > ?");
+    #[cfg(not(feature = "postgres"))]
+    let since_bind = since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let since_bind = since;
+
+    let (count,): (i64,) = sqlx::query_as(&q)
+        .bind(owner_id)
+        .bind(recovery_key_fingerprint)
+        .bind(since_bind)
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn cancel_request(pool: &Db, id: &str, owner_id: &str) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now();
+    #[cfg(not(feature = "postgres"))]
+    let now_bind = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let now_bind = now;
+    let q = sql("UPDATE social_recovery_requests SET cancelled_at = ? \
+         WHERE id = ? AND owner_id = ? AND cancelled_at IS NULL");
+    let result = sqlx::query(&q)
+        .bind(now_bind)
+        .bind(id)
+        .bind(owner_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 保護者の返したシェアを保存する。同じ保護者が再送した場合は置き換える。
+#[tracing::instrument(skip(pool, share_armored), err)]
+pub async fn upsert_response(
+    pool: &Db,
+    request_id: &str,
+    guardian_id: &str,
+    share_armored: &str,
+) -> Result<(), sqlx::Error> {
+    let q = sql(
+        "INSERT INTO social_recovery_responses (request_id, guardian_id, share_armored) \
+         VALUES (?, ?, ?) \
+         ON CONFLICT (request_id, guardian_id) DO UPDATE SET share_armored = ?",
+    );
+    sqlx::query(&q)
+        .bind(request_id)
+        .bind(guardian_id)
+        .bind(share_armored)
+        .bind(share_armored)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn list_responses(
+    pool: &Db,
+    request_id: &str,
+) -> Result<Vec<SocialRecoveryResponseRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM social_recovery_responses WHERE request_id = ? ORDER BY created_at");
+    sqlx::query_as::<_, SocialRecoveryResponseRow>(&q)
+        .bind(request_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// `before` より前に作成された復元リクエストを返却済みシェアごと削除する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_requests_before(
+    pool: &Db,
+    before: chrono::DateTime<chrono::Utc>,
+) -> Result<u64, sqlx::Error> {
+    #[cfg(not(feature = "postgres"))]
+    let before_bind = before.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    #[cfg(feature = "postgres")]
+    let before_bind = before;
+
+    let mut tx = pool.begin().await?;
+
+    let q = sql("DELETE FROM social_recovery_responses WHERE request_id IN \
+         (SELECT id FROM social_recovery_requests WHERE created_at < ?)");
+    sqlx::query(&q)
+        .bind(before_bind.clone())
+        .execute(&mut *tx)
+        .await?;
+
+    let q = sql("DELETE FROM social_recovery_requests WHERE created_at < ?");
+    let result = sqlx::query(&q).bind(before_bind).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
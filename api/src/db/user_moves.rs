@@ -0,0 +1,89 @@
+use super::models::UserMoveRow;
+use super::{Db, sql};
+
+/// 移行記録を保存する。同じ移行元の記録があれば置き換える。
+#[tracing::instrument(skip(pool, statement), err)]
+pub async fn upsert_move(
+    pool: &Db,
+    from_id: &str,
+    to_id: &str,
+    primary_key_fingerprint: &str,
+    statement: &str,
+) -> Result<(), sqlx::Error> {
+    let q = sql(
+        "INSERT INTO user_moves (from_id, to_id, primary_key_fingerprint, statement) \
+         VALUES (?, ?, ?, ?) \
+         ON CONFLICT (from_id) DO UPDATE SET \
+         to_id = ?, primary_key_fingerprint = ?, statement = ?",
+    );
+    sqlx::query(&q)
+        .bind(from_id)
+        .bind(to_id)
+        .bind(primary_key_fingerprint)
+        .bind(statement)
+        .bind(to_id)
+        .bind(primary_key_fingerprint)
+        .bind(statement)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn get_move(pool: &Db, from_id: &str) -> Result<Option<UserMoveRow>, sqlx::Error> {
+    let q = sql("SELECT * FROM user_moves WHERE from_id = ?");
+    sqlx::query_as::<_, UserMoveRow>(&q)
+        .bind(from_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// 移行元ユーザIDへの参照を移行先に書き換える。
+///
+/// 移行先のusers行は事前に作成しておくこと（作成者カラムに外部キー制約があるため）。
+/// 移行元のusers行は書き換え後に `users::delete_user` で削除する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn rewrite_user_id(pool: &Db, from_id: &str, to_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // 主キーが衝突しうるテーブルは移行先で挿入してから移行元を削除する
+    let q = sql("INSERT INTO chat_members (chat_id, user_id, joined_at) \
+         SELECT chat_id, ?, joined_at FROM chat_members WHERE user_id = ? \
+         ON CONFLICT (chat_id, user_id) DO NOTHING");
+    sqlx::query(&q)
+        .bind(to_id)
+        .bind(from_id)
+        .execute(&mut *tx)
+        .await?;
+    let q = sql("DELETE FROM chat_members WHERE user_id = ?");
+    sqlx::query(&q).bind(from_id).execute(&mut *tx).await?;
+
+    let q = sql(
+        "INSERT INTO contacts (user_id, contact_user_id, created_at) \
+         SELECT user_id, ?, created_at FROM contacts WHERE contact_user_id = ? \
+         ON CONFLICT (user_id, contact_user_id) DO NOTHING",
+    );
+    sqlx::query(&q)
+        .bind(to_id)
+        .bind(from_id)
+        .execute(&mut *tx)
+        .await?;
+    let q = sql("DELETE FROM contacts WHERE contact_user_id = ?");
+    sqlx::query(&q).bind(from_id).execute(&mut *tx).await?;
+
+    for raw in [
+        "UPDATE messages SET sender_id = ? WHERE sender_id = ?",
+        "UPDATE threads SET created_by = ? WHERE created_by = ?",
+        "UPDATE chat_groups SET created_by = ? WHERE created_by = ?",
+    ] {
+        let q = sql(raw);
+        sqlx::query(&q)
+            .bind(to_id)
+            .bind(from_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
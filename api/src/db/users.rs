@@ -70,7 +70,9 @@ pub async fn delete_user(
 ) -> Result<bool, sqlx::Error> {
     let mut tx = pool.begin().await?;
 
-    let insert_q = sql("INSERT INTO deleted_users (id, primary_key_fingerprint) VALUES (?, ?)");
+    let insert_q = sql(
+        "INSERT INTO deleted_users (id, primary_key_fingerprint) VALUES (?, ?) ON CONFLICT (id) DO NOTHING",
+    );
     sqlx::query(&insert_q)
         .bind(id.as_str())
         .bind(primary_key_fingerprint)
@@ -87,6 +89,41 @@ pub async fn delete_user(
     Ok(result.rows_affected() > 0)
 }
 
+/// 連合先で削除された外部ユーザのキャッシュとチャットメンバーシップを破棄し、tombstoneを記録する。
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_external_user(
+    pool: &Db,
+    id: &UserId,
+    primary_key_fingerprint: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let insert_q = sql(
+        "INSERT INTO deleted_users (id, primary_key_fingerprint) VALUES (?, ?) ON CONFLICT (id) DO NOTHING",
+    );
+    sqlx::query(&insert_q)
+        .bind(id.as_str())
+        .bind(primary_key_fingerprint)
+        .execute(&mut *tx)
+        .await?;
+
+    // chat_members.user_id には外部キー制約がないため明示的に削除する
+    let members_q = sql("DELETE FROM chat_members WHERE user_id = ?");
+    sqlx::query(&members_q)
+        .bind(id.as_str())
+        .execute(&mut *tx)
+        .await?;
+
+    let delete_q = sql("DELETE FROM users WHERE id = ?");
+    sqlx::query(&delete_q)
+        .bind(id.as_str())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
 #[tracing::instrument(
     skip(
         pool,
@@ -198,6 +235,8 @@ pub async fn get_user_case_insensitive(
 }
 
 /// 外部ユーザの公開鍵をupsertする（INSERT ON CONFLICT UPDATE）
+///
+/// 保存済みの鍵が失効している場合は置き換えず `false` を返す。
 #[tracing::instrument(skip(pool, encryption_public_key, signing_public_key), err)]
 pub async fn upsert_external_user(
     pool: &Db,
@@ -205,16 +244,19 @@ pub async fn upsert_external_user(
     encryption_public_key: &str,
     signing_public_key: &str,
     primary_key_fingerprint: &str,
-) -> Result<(), sqlx::Error> {
+) -> Result<bool, sqlx::Error> {
     let q = sql(
         "INSERT INTO users (id, encryption_public_key, signing_public_key, primary_key_fingerprint)
          VALUES (?, ?, ?, ?)
          ON CONFLICT (id) DO UPDATE SET
              encryption_public_key = ?,
              signing_public_key = ?,
-             primary_key_fingerprint = ?",
+             primary_key_fingerprint = ?
+         WHERE NOT EXISTS (
+             SELECT 1 FROM key_revocations WHERE fingerprint = users.primary_key_fingerprint
+         )",
     );
-    sqlx::query(&q)
+    let result = sqlx::query(&q)
         .bind(full_id)
         .bind(encryption_public_key)
         .bind(signing_public_key)
@@ -224,5 +266,5 @@ pub async fn upsert_external_user(
         .bind(primary_key_fingerprint)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
@@ -17,8 +17,13 @@ pub enum AppError {
     PayloadTooLarge(String),
     #[error("gone: {0}")]
     Gone(String),
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
     #[error("bad gateway: {0}")]
     BadGateway(String),
+    /// ユーザが別サーバへ移行済み。署名済みの移行宣言を返す。
+    #[error("moved to {moved_to}")]
+    Moved { moved_to: String, statement: String },
     #[error("internal: {0}")]
     Internal(String),
 }
@@ -40,8 +45,22 @@ impl IntoResponse for AppError {
             AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
             AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg.clone()),
             AppError::Gone(msg) => (StatusCode::GONE, msg.clone()),
+            AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg.clone()),
             AppError::BadGateway(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
+            AppError::Moved {
+                moved_to,
+                statement,
+            } => {
+                // リダイレクトにするとクライアントが移行宣言を検証せずに追従するため、
+                // 410 で移行先を本文に含めて返す
+                let body = serde_json::json!({
+                    "error": "user has moved",
+                    "moved_to": moved_to,
+                    "moved_statement": statement,
+                });
+                return (StatusCode::GONE, axum::Json(body)).into_response();
+            }
         };
         let body = serde_json::json!({ "error": message });
         (status, axum::Json(body)).into_response()
@@ -1,4 +1,5 @@
 use crate::error::AppError;
+use crate::federation::http::OutboundClient;
 use serde::Deserialize;
 
 pub fn base_url(domain: &str, allow_http: bool) -> String {
@@ -17,13 +18,47 @@ pub struct UserKeysResponse {
     pub primary_key_fingerprint: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ServerKeyResponse {
+    pub domain: String,
+    pub algorithm: String,
+    pub public_key: String,
+}
+
+/// 移行チェーン中の鍵移行宣言
+#[derive(Debug, Deserialize)]
+pub struct KeyTransitionResponse {
+    pub old_fingerprint: String,
+    pub new_fingerprint: String,
+    pub old_key_statement: String,
+    pub new_key_statement: String,
+}
+
+/// 指紋による鍵取得の応答。`transitions` はその鍵から最新の鍵までの移行チェーン。
+#[derive(Debug, Deserialize)]
+pub struct KeyResponse {
+    pub fingerprint: String,
+    pub armored_public_key: String,
+    #[serde(default)]
+    pub transitions: Vec<KeyTransitionResponse>,
+}
+
+/// 削除・移行済みユーザの鍵取得時に返される 410 応答。移行済みの場合のみ移行先を含む。
+#[derive(Debug, Deserialize)]
+struct GoneResponse {
+    moved_to: Option<String>,
+    moved_statement: Option<String>,
+}
+
 /// URLパスに含めるユーザIDをパーセントエンコードする。
 pub fn encode_user_id(user_id: &str) -> String {
     urlencoding::encode(user_id).into_owned()
 }
 
 /// 外部サーバからユーザの公開鍵を取得する（認証不要）。
+/// 移行済みユーザの場合は `AppError::Moved` を返す。
 pub async fn fetch_user_keys(
+    http: &OutboundClient,
     domain: &str,
     user_id: &str,
     allow_http: bool,
@@ -33,38 +68,67 @@ pub async fn fetch_user_keys(
     let url = format!("{base}/v1/user/{encoded}/keys");
     tracing::debug!("fetch_user_keys: url={url} domain={domain} user_id={user_id}");
 
-    let client = reqwest::Client::new();
-    let resp = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| AppError::BadGateway(format!("federation request failed: {e}")))?;
+    let resp = http.send(http.get(&url)).await?;
 
     if !resp.status().is_success() {
         let status = resp.status();
         if status == reqwest::StatusCode::GONE {
-            return Err(AppError::Gone("user has been deleted".into()));
+            return match http.json::<GoneResponse>(resp).await {
+                Ok(GoneResponse {
+                    moved_to: Some(moved_to),
+                    moved_statement: Some(statement),
+                }) => Err(AppError::Moved {
+                    moved_to,
+                    statement,
+                }),
+                _ => Err(AppError::Gone("user has been deleted".into())),
+            };
         }
-        let body = resp.text().await.unwrap_or_default();
+        let body = http.error_text(resp).await;
+        return Err(AppError::BadGateway(format!(
+            "federation server returned {status}: {body}"
+        )));
+    }
+
+    http.json::<UserKeysResponse>(resp).await
+}
+
+/// 外部サーバから指紋で公開鍵と移行チェーンを取得する（認証不要）。
+pub async fn fetch_key(
+    http: &OutboundClient,
+    domain: &str,
+    fingerprint: &str,
+    allow_http: bool,
+) -> Result<KeyResponse, AppError> {
+    let base = base_url(domain, allow_http);
+    let url = format!("{base}/v1/keys/{fingerprint}");
+
+    let resp = http.send(http.get(&url)).await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = http.error_text(resp).await;
         return Err(AppError::BadGateway(format!(
             "federation server returned {status}: {body}"
         )));
     }
 
-    resp.json::<UserKeysResponse>()
-        .await
-        .map_err(|e| AppError::BadGateway(format!("invalid federation response: {e}")))
+    let key = http.json::<KeyResponse>(resp).await?;
+    if key.fingerprint != fingerprint {
+        return Err(AppError::BadGateway("unexpected key response".into()));
+    }
+    Ok(key)
 }
 
 /// 外部サーバにチャットグループの参照を同期する。
 /// チャット作成時、外部メンバーのホームサーバにチャット情報を通知し、
 /// リモート側で server_domain 付きの参照を作成させる。
 pub async fn sync_chat_to_remote(
+    http: &OutboundClient,
     domain: &str,
     chat_id: &str,
     chat_name: &str,
     member_ids: &[String],
-    auth_header_raw: &str,
+    authorization: &str,
     allow_http: bool,
 ) -> Result<(), AppError> {
     let base = base_url(domain, allow_http);
@@ -76,18 +140,17 @@ pub async fn sync_chat_to_remote(
         "member_ids": member_ids,
     });
 
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(&url)
-        .header("Authorization", auth_header_raw)
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| AppError::BadGateway(format!("federation chat sync failed: {e}")))?;
+    let resp = http
+        .send(
+            http.post(&url)
+                .header("Authorization", authorization)
+                .json(&body),
+        )
+        .await?;
 
     if !resp.status().is_success() {
         let status = resp.status();
-        let body = resp.text().await.unwrap_or_default();
+        let body = http.error_text(resp).await;
         tracing::warn!("federation chat sync to {domain} returned {status}: {body}");
     }
 
@@ -98,6 +161,7 @@ pub async fn sync_chat_to_remote(
 /// チャットのホームサーバが、外部メンバーのホームサーバにPush通知を依頼する。
 /// 通知はメタデータのみで実データを含まないため、ユーザ認証は不要。
 pub async fn forward_push(
+    http: &OutboundClient,
     domain: &str,
     user_ids: &[String],
     payload: &serde_json::Value,
@@ -111,19 +175,115 @@ pub async fn forward_push(
         "payload": payload,
     });
 
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(&url)
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| AppError::BadGateway(format!("federation push failed: {e}")))?;
+    let resp = http.send(http.post(&url).json(&body)).await?;
 
     if !resp.status().is_success() {
         let status = resp.status();
-        let body = resp.text().await.unwrap_or_default();
+        let body = http.error_text(resp).await;
         tracing::warn!("federation push to {domain} returned {status}: {body}");
     }
 
     Ok(())
 }
+
+/// 外部サーバにアカウント削除を通知する。
+/// 受信側はホームサーバへの鍵取得が `410 Gone` になることを確認してから削除を反映する。
+pub async fn send_tombstone(
+    http: &OutboundClient,
+    domain: &str,
+    user_id: &str,
+    allow_http: bool,
+) -> Result<(), AppError> {
+    let base = base_url(domain, allow_http);
+    let url = format!("{base}/v1/federation/tombstone");
+
+    let body = serde_json::json!({ "user_id": user_id });
+
+    let resp = http.send(http.post(&url).json(&body)).await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = http.error_text(resp).await;
+        tracing::warn!("federation tombstone to {domain} returned {status}: {body}");
+    }
+
+    Ok(())
+}
+
+/// 外部サーバにアカウント移行を通知する。
+/// 受信側は移行宣言の署名と移行先サーバの鍵を検証してから反映する。
+pub async fn send_moved(
+    http: &OutboundClient,
+    domain: &str,
+    user_id: &str,
+    statement: &str,
+    allow_http: bool,
+) -> Result<(), AppError> {
+    let base = base_url(domain, allow_http);
+    let url = format!("{base}/v1/federation/moved");
+
+    let body = serde_json::json!({
+        "user_id": user_id,
+        "statement": statement,
+    });
+
+    let resp = http.send(http.post(&url).json(&body)).await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = http.error_text(resp).await;
+        tracing::warn!("federation move notice to {domain} returned {status}: {body}");
+    }
+
+    Ok(())
+}
+
+/// 外部サーバに鍵の失効を通知する。
+/// 失効証明書は主鍵で署名されているため、受信側はキャッシュ済みの鍵で検証できる。
+pub async fn send_key_revocation(
+    http: &OutboundClient,
+    domain: &str,
+    user_id: &str,
+    certificate: &str,
+    allow_http: bool,
+) -> Result<(), AppError> {
+    let base = base_url(domain, allow_http);
+    let url = format!("{base}/v1/federation/key-revocation");
+
+    let body = serde_json::json!({
+        "user_id": user_id,
+        "certificate": certificate,
+    });
+
+    let resp = http.send(http.post(&url).json(&body)).await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = http.error_text(resp).await;
+        tracing::warn!("federation key revocation to {domain} returned {status}: {body}");
+    }
+
+    Ok(())
+}
+
+/// 外部サーバの委任ステートメント検証用公開鍵を取得する（認証不要）。
+pub async fn fetch_server_key(
+    http: &OutboundClient,
+    domain: &str,
+    allow_http: bool,
+) -> Result<ServerKeyResponse, AppError> {
+    let base = base_url(domain, allow_http);
+    let url = format!("{base}/v1/federation/server-key");
+
+    let resp = http.send(http.get(&url)).await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = http.error_text(resp).await;
+        return Err(AppError::BadGateway(format!(
+            "federation server key from {domain} returned {status}: {body}"
+        )));
+    }
+
+    http.json::<ServerKeyResponse>(resp).await
+}
@@ -0,0 +1,259 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::auth::request::RequestTarget;
+use crate::db;
+use crate::db::nonces::NonceType;
+use crate::error::AppError;
+use crate::federation::dns::ResolvedDomain;
+use crate::types::{ChatId, UserId};
+
+/// 委任ステートメントの `type` フィールドの値
+pub const STATEMENT_TYPE: &str = "delegation";
+
+/// Authorizationヘッダーのスキーム
+pub const SCHEME: &str = "Delegation";
+
+/// 委任ステートメントの有効期間（秒）
+const DELEGATION_TTL_SECS: i64 = 60;
+
+/// サーバ署名付きの委任ステートメント。
+///
+/// ホームサーバが認証済みユーザに代わって、宛先サーバの特定のチャット・操作に限り
+/// 短時間だけ有効な資格情報として発行する。
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DelegationStatement {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub issuer: String,
+    pub audience: String,
+    pub user_id: String,
+    pub chat_id: Option<String>,
+    /// `<METHOD> <path>` 形式の操作
+    pub action: String,
+    pub nonce: String,
+    pub expires_at: String,
+}
+
+/// プロキシ先サーバ向けの委任Authorizationヘッダー値を発行する。
+///
+/// 委任で認証されたリクエストをさらに転送することはできない。
+pub fn authorization(
+    state: &AppState,
+    auth: &AuthenticatedUser,
+    audience: &str,
+    chat_id: Option<&ChatId>,
+    method: &str,
+    path: &str,
+) -> Result<String, AppError> {
+    if auth.delegated_by.is_some() {
+        return Err(AppError::Forbidden(
+            "delegated requests cannot be delegated again".into(),
+        ));
+    }
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(DELEGATION_TTL_SECS);
+    let statement = DelegationStatement {
+        kind: STATEMENT_TYPE.to_string(),
+        issuer: state.config.server_hostname.clone(),
+        audience: audience.to_string(),
+        user_id: auth.user_id.as_str().to_string(),
+        chat_id: chat_id.map(|c| c.as_str().to_string()),
+        action: format!("{} {path}", method.to_ascii_uppercase()),
+        nonce: uuid::Uuid::new_v4().to_string(),
+        expires_at: expires_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    };
+    let payload = serde_json::to_vec(&statement)
+        .map_err(|e| AppError::Internal(format!("failed to encode delegation: {e}")))?;
+    let payload = URL_SAFE_NO_PAD.encode(payload);
+    let signature = state.server_key.sign(payload.as_bytes());
+    Ok(format!("{SCHEME} {payload}.{signature}"))
+}
+
+/// 委任ステートメントを検証し、委任元ユーザとして認証する。
+///
+/// 発行元がユーザのホームサーバであること、宛先が自サーバであること、
+/// 操作がリクエストと一致すること、期限内かつ未使用であることを確認する。
+pub async fn authenticate(
+    state: &AppState,
+    token: &str,
+    target: &RequestTarget,
+) -> Result<AuthenticatedUser, AppError> {
+    let (payload, signature) = token
+        .split_once('.')
+        .ok_or_else(|| AppError::Unauthorized("malformed delegation".into()))?;
+    let statement: DelegationStatement = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| AppError::Unauthorized("malformed delegation".into()))?;
+
+    if statement.kind != STATEMENT_TYPE {
+        return Err(AppError::Unauthorized("not a delegation statement".into()));
+    }
+    if !statement
+        .audience
+        .eq_ignore_ascii_case(&state.config.server_hostname)
+    {
+        return Err(AppError::Unauthorized(
+            "delegation is addressed to another server".into(),
+        ));
+    }
+    let expires_at: chrono::DateTime<chrono::Utc> = statement
+        .expires_at
+        .parse()
+        .map_err(|e| AppError::Unauthorized(format!("invalid delegation expiry: {e}")))?;
+    let now = chrono::Utc::now();
+    if expires_at <= now || expires_at > now + chrono::Duration::seconds(DELEGATION_TTL_SECS * 2) {
+        return Err(AppError::Unauthorized(
+            "delegation expired or expiry out of range".into(),
+        ));
+    }
+    if statement.action != format!("{} {}", target.method.as_str(), target.path) {
+        return Err(AppError::Unauthorized(
+            "delegation action does not match request".into(),
+        ));
+    }
+    if let Some(chat_id) = &statement.chat_id
+        && let Some(rest) = target.path.strip_prefix("/v1/chat/")
+        && rest.split(['/', '?']).next() != Some(chat_id.as_str())
+    {
+        return Err(AppError::Unauthorized(
+            "delegation chat does not match request".into(),
+        ));
+    }
+
+    let issuer = statement.issuer.to_ascii_lowercase();
+    if !state.federation_policy.permits(&issuer).await {
+        return Err(AppError::Forbidden(format!(
+            "federation with {issuer} is not allowed"
+        )));
+    }
+    let user_id = UserId::validate_full(&statement.user_id)
+        .map_err(|e| AppError::Unauthorized(format!("invalid delegated user ID: {e}")))?;
+    if home_server_of(state, &user_id).await.as_deref() != Some(issuer.as_str()) {
+        return Err(AppError::Unauthorized(
+            "delegation issuer is not the user's home server".into(),
+        ));
+    }
+
+    verify_issuer_signature(state, &issuer, payload, signature).await?;
+
+    let is_new = db::nonces::try_use_nonce(
+        &state.pool,
+        NonceType::Delegation,
+        &statement.nonce,
+        user_id.as_str(),
+        expires_at,
+    )
+    .await?;
+    if !is_new {
+        return Err(AppError::Unauthorized("delegation already used".into()));
+    }
+
+    let user = match db::users::get_user(&state.pool, &user_id).await? {
+        Some(user) => user,
+        None => cache_user(state, &issuer, &user_id).await?,
+    };
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
+        .map_err(|e| AppError::Unauthorized(format!("invalid signing key: {e}")))?;
+    crate::auth::ensure_not_revoked(state, &user.primary_key_fingerprint, &public_keys).await?;
+
+    Ok(AuthenticatedUser {
+        user_id,
+        primary_key_fingerprint: user.primary_key_fingerprint,
+        signing_public_key: user.signing_public_key,
+        session_token_hash: None,
+        signing_key_fingerprint: None,
+        delegated_by: Some(issuer),
+    })
+}
+
+/// ユーザIDのドメインをDNS TXT解決した実際のホームサーバを返す。
+async fn home_server_of(state: &AppState, user_id: &UserId) -> Option<String> {
+    let domain = user_id.domain()?;
+    Some(
+        match state
+            .dns_resolver
+            .resolve(domain, user_id.local_part())
+            .await
+        {
+            ResolvedDomain::Mapped { domain, .. } => domain,
+            ResolvedDomain::Original => domain.to_string(),
+        },
+    )
+}
+
+/// 発行元サーバの公開鍵で署名を検証する。キャッシュ済みの鍵で失敗した場合は再取得して再試行する。
+async fn verify_issuer_signature(
+    state: &AppState,
+    issuer: &str,
+    payload: &str,
+    signature: &str,
+) -> Result<(), AppError> {
+    if let Some(public_key) = state.peer_keys.get(issuer).await
+        && super::server_key::verify_signature(&public_key, payload.as_bytes(), signature).is_ok()
+    {
+        return Ok(());
+    }
+
+    let fetched =
+        super::client::fetch_server_key(&state.http, issuer, state.config.federation_allow_http)
+            .await?;
+    if !fetched.domain.eq_ignore_ascii_case(issuer) || fetched.algorithm != "ed25519" {
+        return Err(AppError::Unauthorized(
+            "unexpected server key response".into(),
+        ));
+    }
+    super::server_key::verify_signature(&fetched.public_key, payload.as_bytes(), signature)?;
+    state
+        .peer_keys
+        .set(issuer.to_string(), fetched.public_key)
+        .await;
+    Ok(())
+}
+
+/// 未キャッシュの委任元ユーザの公開鍵をホームサーバから取得して保存する。
+async fn cache_user(
+    state: &AppState,
+    issuer: &str,
+    user_id: &UserId,
+) -> Result<db::models::UserRow, AppError> {
+    let keys = super::client::fetch_user_keys(
+        &state.http,
+        issuer,
+        user_id.as_str(),
+        state.config.federation_allow_http,
+    )
+    .await?;
+    let fingerprint = xrypton_common::keys::PublicKeys::try_from(keys.signing_public_key.as_str())
+        .map_err(|e| AppError::Unauthorized(format!("invalid remote signing key: {e}")))?
+        .get_primary_fingerprint();
+    let stored = db::users::upsert_external_user(
+        &state.pool,
+        user_id.as_str(),
+        &keys.encryption_public_key,
+        &keys.signing_public_key,
+        &fingerprint,
+    )
+    .await?;
+    if !stored {
+        return Err(AppError::Unauthorized(
+            "stored signing key has been revoked".into(),
+        ));
+    }
+    super::key_change::record_keys(
+        state,
+        user_id,
+        None,
+        &keys.encryption_public_key,
+        &keys.signing_public_key,
+        &fingerprint,
+    )
+    .await?;
+    db::users::get_user(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::Internal("cached user not found".into()))
+}
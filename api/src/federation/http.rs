@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::federation::policy::FederationPolicyStore;
+
+const USER_AGENT: &str = concat!("xrypton-api/", env!("CARGO_PKG_VERSION"));
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const POOL_MAX_IDLE_PER_HOST: usize = 16;
+/// エラー応答本文をログ・エラーメッセージに含める際の上限
+const ERROR_BODY_MAX_BYTES: usize = 4 * 1024;
+
+/// 連合先ごとの通信統計。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+/// 連合先ホストごとのレイテンシ・エラー件数を記録する。
+#[derive(Clone, Default)]
+pub struct PeerMetrics {
+    inner: Arc<RwLock<HashMap<String, PeerStats>>>,
+}
+
+impl PeerMetrics {
+    async fn record(&self, peer: &str, latency: Duration, outcome: Result<u16, String>) {
+        let latency_ms = latency.as_millis() as u64;
+        let mut stats = self.inner.write().await;
+        let entry = stats.entry(peer.to_string()).or_default();
+        entry.requests += 1;
+        entry.total_latency_ms += latency_ms;
+        entry.max_latency_ms = entry.max_latency_ms.max(latency_ms);
+        match outcome {
+            Ok(status) => {
+                entry.last_status = Some(status);
+                // 5xx は相手サーバ側の障害としてエラー扱い
+                if status >= 500 {
+                    entry.errors += 1;
+                }
+            }
+            Err(e) => {
+                entry.errors += 1;
+                entry.last_error = Some(e);
+            }
+        }
+    }
+
+    /// 現在の統計のスナップショットを返す。
+    pub async fn snapshot(&self) -> HashMap<String, PeerStats> {
+        self.inner.read().await.clone()
+    }
+}
+
+/// 連合・プロキシ通信で共有するHTTPクライアント。
+///
+/// コネクションプールを再利用し、接続・リクエストタイムアウトと
+/// 応答サイズ上限を全リクエストに適用する。
+/// 連合ポリシーで許可されていないホストへの送信は行わない。
+/// リダイレクト先はポリシーを確認できないため追従しない。
+#[derive(Clone)]
+pub struct OutboundClient {
+    client: reqwest::Client,
+    max_response_bytes: usize,
+    metrics: PeerMetrics,
+    policy: FederationPolicyStore,
+}
+
+impl OutboundClient {
+    pub fn new(config: &AppConfig, policy: FederationPolicyStore) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(Duration::from_secs(config.federation_connect_timeout_secs))
+            .timeout(Duration::from_secs(config.federation_request_timeout_secs))
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build outbound HTTP client");
+        Self {
+            client,
+            max_response_bytes: config.federation_max_response_bytes,
+            metrics: PeerMetrics::default(),
+            policy,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn metrics(&self) -> &PeerMetrics {
+        &self.metrics
+    }
+
+    /// リクエストを送信し、宛先ホストごとのレイテンシとエラーを記録する。
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, AppError> {
+        let request = request
+            .build()
+            .map_err(|e| AppError::Internal(format!("failed to build outbound request: {e}")))?;
+        let peer = request.url().host_str().unwrap_or_default().to_string();
+        if !self.policy.permits(&peer).await {
+            tracing::debug!(peer, "outbound request blocked by federation policy");
+            return Err(AppError::Forbidden(format!(
+                "federation with {peer} is not allowed"
+            )));
+        }
+
+        let start = Instant::now();
+        let result = self.client.execute(request).await;
+        let latency = start.elapsed();
+
+        match result {
+            Ok(resp) => {
+                let status = resp.status().as_u16();
+                tracing::debug!(
+                    peer,
+                    status,
+                    latency_ms = latency.as_millis() as u64,
+                    "outbound request"
+                );
+                self.metrics.record(&peer, latency, Ok(status)).await;
+                Ok(resp)
+            }
+            Err(e) => {
+                tracing::debug!(
+                    peer,
+                    error = %e,
+                    latency_ms = latency.as_millis() as u64,
+                    "outbound request failed"
+                );
+                self.metrics
+                    .record(&peer, latency, Err(e.to_string()))
+                    .await;
+                Err(AppError::BadGateway(format!(
+                    "federation request failed: {e}"
+                )))
+            }
+        }
+    }
+
+    /// 応答本文を上限付きで読み出す。
+    pub async fn bytes(&self, resp: reqwest::Response) -> Result<Vec<u8>, AppError> {
+        read_limited(resp, self.max_response_bytes).await
+    }
+
+    /// 応答本文を上限付きで読み出し、JSONとしてデコードする。
+    pub async fn json<T: DeserializeOwned>(&self, resp: reqwest::Response) -> Result<T, AppError> {
+        let body = self.bytes(resp).await?;
+        serde_json::from_slice(&body)
+            .map_err(|e| AppError::BadGateway(format!("invalid federation response: {e}")))
+    }
+
+    /// エラー応答の本文を文字列で返す（読み出し失敗時は空文字列）。
+    pub async fn error_text(&self, resp: reqwest::Response) -> String {
+        read_limited(resp, ERROR_BODY_MAX_BYTES)
+            .await
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+            .unwrap_or_default()
+    }
+}
+
+async fn read_limited(mut resp: reqwest::Response, max: usize) -> Result<Vec<u8>, AppError> {
+    if resp.content_length().is_some_and(|len| len as usize > max) {
+        return Err(AppError::BadGateway("federation response too large".into()));
+    }
+    let mut out = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| AppError::BadGateway(format!("failed to read federation response: {e}")))?
+    {
+        out.extend_from_slice(&chunk);
+        if out.len() > max {
+            return Err(AppError::BadGateway("federation response too large".into()));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_aggregate_per_peer() {
+        let metrics = PeerMetrics::default();
+        metrics
+            .record("a.example.com", Duration::from_millis(10), Ok(200))
+            .await;
+        metrics
+            .record("a.example.com", Duration::from_millis(30), Ok(502))
+            .await;
+        metrics
+            .record(
+                "b.example.com",
+                Duration::from_millis(5),
+                Err("timeout".into()),
+            )
+            .await;
+
+        let snapshot = metrics.snapshot().await;
+        let a = &snapshot["a.example.com"];
+        assert_eq!(a.requests, 2);
+        assert_eq!(a.errors, 1);
+        assert_eq!(a.total_latency_ms, 40);
+        assert_eq!(a.max_latency_ms, 30);
+        assert_eq!(a.last_status, Some(502));
+
+        let b = &snapshot["b.example.com"];
+        assert_eq!(b.requests, 1);
+        assert_eq!(b.errors, 1);
+        assert_eq!(b.last_error.as_deref(), Some("timeout"));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::AppState;
+use crate::db;
+use crate::db::models::UserRow;
+use crate::error::AppError;
+use crate::types::UserId;
+
+/// 公開鍵を鍵履歴に記録し、指紋が変わっていれば鍵変更として扱う。
+///
+/// `previous` は更新前のusers行。指紋が異なる場合は旧鍵も履歴に残したうえで
+/// 新しい鍵に `previous_fingerprint` を付け、共通チャットのメンバーにPush通知する。
+pub async fn record_keys(
+    state: &AppState,
+    user_id: &UserId,
+    previous: Option<&UserRow>,
+    encryption_public_key: &str,
+    signing_public_key: &str,
+    fingerprint: &str,
+) -> Result<(), AppError> {
+    let pool = &state.pool;
+    let previous = previous.filter(|p| p.primary_key_fingerprint != fingerprint);
+
+    if let Some(prev) = previous {
+        db::key_history::record_key(
+            pool,
+            user_id.as_str(),
+            &prev.primary_key_fingerprint,
+            None,
+            &prev.signing_public_key,
+            &prev.encryption_public_key,
+        )
+        .await?;
+    }
+    db::key_history::record_key(
+        pool,
+        user_id.as_str(),
+        fingerprint,
+        previous.map(|p| p.primary_key_fingerprint.as_str()),
+        signing_public_key,
+        encryption_public_key,
+    )
+    .await?;
+
+    if let Some(prev) = previous {
+        tracing::warn!(
+            user_id = user_id.as_str(),
+            old_fingerprint = prev.primary_key_fingerprint,
+            new_fingerprint = fingerprint,
+            "primary key fingerprint changed"
+        );
+        notify_key_change(state, user_id, &prev.primary_key_fingerprint, fingerprint);
+    }
+
+    Ok(())
+}
+
+/// 鍵が変わったユーザと同じチャットに所属するローカルメンバーへ通知する。
+fn notify_key_change(
+    state: &AppState,
+    user_id: &UserId,
+    old_fingerprint: &str,
+    new_fingerprint: &str,
+) {
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let user_id = user_id.clone();
+    let payload = serde_json::json!({
+        "type": "key_changed",
+        "user_id": user_id.as_str(),
+        "old_fingerprint": old_fingerprint,
+        "new_fingerprint": new_fingerprint,
+    });
+    tokio::spawn(async move {
+        let members = match db::chat::get_co_member_ids(&pool, &user_id).await {
+            Ok(members) => members,
+            Err(e) => {
+                tracing::warn!("failed to load co-members for key change: {e}");
+                return;
+            }
+        };
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &members, &payload).await {
+            tracing::warn!("key change push failed: {e}");
+        }
+    });
+}
@@ -0,0 +1,202 @@
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::db;
+use crate::db::key_transitions::NewKeyTransition;
+use crate::db::models::{KeyTransitionRow, UserRow};
+use crate::error::AppError;
+use crate::types::UserId;
+
+/// 鍵移行宣言の `type` フィールドの値
+pub const STATEMENT_TYPE: &str = "key_transition";
+
+/// 移行チェーンを辿る最大段数
+const MAX_CHAIN_LENGTH: usize = 32;
+
+/// 旧主鍵と新主鍵の両方で署名された鍵移行宣言。
+#[derive(Debug, Deserialize)]
+pub struct KeyTransitionStatement {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub user_id: String,
+    pub old_fingerprint: String,
+    pub new_fingerprint: String,
+    pub created_at: String,
+}
+
+/// クライアントが主鍵の変更時に提示する移行宣言
+#[derive(Debug, Deserialize)]
+pub struct KeyTransitionBody {
+    pub old_key_statement: String,
+    pub new_key_statement: String,
+}
+
+/// 鍵移行宣言の署名と内容を検証する。
+///
+/// `old_key_statement` は旧主鍵、`new_key_statement` は新主鍵で同じ宣言に署名したもの。
+pub fn verify_statement(
+    old_key_statement: &str,
+    new_key_statement: &str,
+    old_signing_public_key: &str,
+    new_signing_public_key: &str,
+) -> Result<KeyTransitionStatement, AppError> {
+    let old_keys = xrypton_common::keys::PublicKeys::try_from(old_signing_public_key)
+        .map_err(|e| AppError::Internal(format!("invalid stored signing key: {e}")))?;
+    let new_keys = xrypton_common::keys::PublicKeys::try_from(new_signing_public_key)
+        .map_err(|e| AppError::BadRequest(format!("invalid signing public key: {e}")))?;
+
+    let old_payload = old_keys
+        .verify_primary_and_extract(old_key_statement)
+        .map_err(|e| AppError::BadRequest(format!("invalid old key signature: {e}")))?;
+    let new_payload = new_keys
+        .verify_primary_and_extract(new_key_statement)
+        .map_err(|e| AppError::BadRequest(format!("invalid new key signature: {e}")))?;
+    if old_payload != new_payload {
+        return Err(AppError::BadRequest(
+            "old and new key statements differ".into(),
+        ));
+    }
+
+    let statement: KeyTransitionStatement = serde_json::from_slice(&old_payload)
+        .map_err(|e| AppError::BadRequest(format!("invalid key transition statement: {e}")))?;
+    if statement.kind != STATEMENT_TYPE {
+        return Err(AppError::BadRequest(
+            "not a key transition statement".into(),
+        ));
+    }
+    if statement.old_fingerprint != old_keys.get_primary_fingerprint()
+        || statement.new_fingerprint != new_keys.get_primary_fingerprint()
+    {
+        return Err(AppError::BadRequest(
+            "key transition fingerprints do not match keys".into(),
+        ));
+    }
+    Ok(statement)
+}
+
+/// 主鍵の変更を検証し、移行宣言の保存と公開鍵の更新を同じトランザクションで行う。
+///
+/// 旧鍵から既に別の鍵へ移行済みの場合は分岐を防ぐため拒否する。
+pub async fn record(
+    state: &AppState,
+    user_id: &UserId,
+    previous: &UserRow,
+    encryption_public_key: &str,
+    signing_public_key: &str,
+    transition: &KeyTransitionBody,
+) -> Result<KeyTransitionStatement, AppError> {
+    let statement = verify_statement(
+        &transition.old_key_statement,
+        &transition.new_key_statement,
+        &previous.signing_public_key,
+        signing_public_key,
+    )?;
+    let statement_user =
+        UserId::resolve_local(&statement.user_id, &state.config.server_hostname)
+            .map_err(|e| AppError::BadRequest(format!("invalid key transition user: {e}")))?;
+    if statement_user != *user_id {
+        return Err(AppError::BadRequest(
+            "key transition statement is for another user".into(),
+        ));
+    }
+
+    let rotated = db::key_transitions::rotate_user_keys(
+        &state.pool,
+        user_id,
+        &NewKeyTransition {
+            old_fingerprint: &statement.old_fingerprint,
+            new_fingerprint: &statement.new_fingerprint,
+            old_key_statement: &transition.old_key_statement,
+            new_key_statement: &transition.new_key_statement,
+        },
+        encryption_public_key,
+        signing_public_key,
+    )
+    .await?;
+    if !rotated {
+        return Err(AppError::Conflict("key has already been rotated".into()));
+    }
+    Ok(statement)
+}
+
+/// 外部ユーザの主鍵が変わった場合に、ホームサーバが公開する移行チェーンを検証する。
+///
+/// キャッシュ済みの旧主鍵から `new_signing_public_key` まで、各移行宣言が旧主鍵・新主鍵の
+/// 両方で署名されている必要がある。途中の鍵はホームサーバから取得する。
+pub async fn verify_remote_chain(
+    state: &AppState,
+    domain: &str,
+    user_id: &UserId,
+    previous: &UserRow,
+    new_signing_public_key: &str,
+    new_fingerprint: &str,
+) -> Result<(), AppError> {
+    let invalid =
+        || AppError::Unauthorized("primary key changed without a valid key transition".into());
+    let allow_http = state.config.federation_allow_http;
+    let chain = super::client::fetch_key(
+        &state.http,
+        domain,
+        &previous.primary_key_fingerprint,
+        allow_http,
+    )
+    .await?
+    .transitions;
+
+    let mut current_key = previous.signing_public_key.clone();
+    let mut current_fingerprint = previous.primary_key_fingerprint.clone();
+    for transition in chain.iter().take(MAX_CHAIN_LENGTH) {
+        if transition.old_fingerprint != current_fingerprint {
+            return Err(invalid());
+        }
+        let next_key = if transition.new_fingerprint == new_fingerprint {
+            new_signing_public_key.to_string()
+        } else {
+            super::client::fetch_key(&state.http, domain, &transition.new_fingerprint, allow_http)
+                .await?
+                .armored_public_key
+        };
+        let statement = verify_statement(
+            &transition.old_key_statement,
+            &transition.new_key_statement,
+            &current_key,
+            &next_key,
+        )
+        .map_err(|_| invalid())?;
+        let statement_user = UserId::resolve(&statement.user_id, domain).map_err(|_| invalid())?;
+        if statement_user.local_part() != user_id.local_part() {
+            return Err(invalid());
+        }
+        if statement.new_fingerprint == new_fingerprint {
+            return Ok(());
+        }
+        current_key = next_key;
+        current_fingerprint = statement.new_fingerprint;
+    }
+    Err(invalid())
+}
+
+/// 指紋から移行チェーンを辿り、現在の鍵に至るまでの移行記録を返す。
+pub async fn chain_from(
+    state: &AppState,
+    fingerprint: &str,
+) -> Result<Vec<KeyTransitionRow>, AppError> {
+    let mut chain: Vec<KeyTransitionRow> = Vec::new();
+    let mut current = fingerprint.to_string();
+    while chain.len() < MAX_CHAIN_LENGTH {
+        let Some(transition) =
+            db::key_transitions::get_transition_from(&state.pool, &current).await?
+        else {
+            break;
+        };
+        if chain
+            .iter()
+            .any(|t| t.old_fingerprint == transition.new_fingerprint)
+        {
+            break;
+        }
+        current = transition.new_fingerprint.clone();
+        chain.push(transition);
+    }
+    Ok(chain)
+}
@@ -1,3 +1,12 @@
 pub mod client;
+pub mod delegation;
 pub mod dns;
+pub mod http;
+pub mod key_change;
+pub mod key_transition;
+pub mod moved;
+pub mod policy;
+pub mod revocation;
+pub mod server_key;
+pub mod tombstone;
 pub mod verify;
@@ -0,0 +1,204 @@
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::db;
+use crate::error::AppError;
+use crate::federation::dns::ResolvedDomain;
+use crate::types::UserId;
+
+/// 移行宣言の `type` フィールドの値
+pub const STATEMENT_TYPE: &str = "moved";
+
+/// 主鍵で署名されたアカウント移行宣言。
+#[derive(Debug, Deserialize)]
+pub struct MovedStatement {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub from: String,
+    pub to: String,
+    pub primary_key_fingerprint: String,
+    pub created_at: String,
+}
+
+/// 移行宣言の主鍵署名と内容を検証する。
+///
+/// `signing_public_key` の主鍵で署名されており、宣言中の指紋がその主鍵と一致することを確認する。
+pub fn verify_statement(
+    statement: &str,
+    signing_public_key: &str,
+) -> Result<MovedStatement, AppError> {
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(signing_public_key)
+        .map_err(|e| AppError::BadRequest(format!("invalid signing public key: {e}")))?;
+    let payload = public_keys
+        .verify_primary_and_extract(statement)
+        .map_err(|e| AppError::BadRequest(format!("invalid moved statement signature: {e}")))?;
+    let moved: MovedStatement = serde_json::from_slice(&payload)
+        .map_err(|e| AppError::BadRequest(format!("invalid moved statement: {e}")))?;
+
+    if moved.kind != STATEMENT_TYPE {
+        return Err(AppError::BadRequest("not a moved statement".into()));
+    }
+    if moved.primary_key_fingerprint != public_keys.get_primary_fingerprint() {
+        return Err(AppError::BadRequest(
+            "moved statement fingerprint does not match key".into(),
+        ));
+    }
+    UserId::validate_full(&moved.from)
+        .map_err(|e| AppError::BadRequest(format!("invalid moved statement source: {e}")))?;
+    UserId::validate_full(&moved.to)
+        .map_err(|e| AppError::BadRequest(format!("invalid moved statement destination: {e}")))?;
+    if moved.from == moved.to {
+        return Err(AppError::BadRequest(
+            "moved statement source and destination are identical".into(),
+        ));
+    }
+    Ok(moved)
+}
+
+/// 移行宣言を検証し、キャッシュ済みのユーザIDとチャットメンバーシップを移行先に書き換える。
+///
+/// 移行先のホームサーバが同じ主鍵でユーザを登録済みであることを確認してから反映する。
+/// 反映後、移行元と同じチャットに所属していたローカルメンバーにPush通知する。
+pub async fn follow(
+    state: &AppState,
+    from: &UserId,
+    moved_to: &str,
+    statement: &str,
+) -> Result<UserId, AppError> {
+    let pool = &state.pool;
+    let hostname = &state.config.server_hostname;
+    let to = UserId::validate_full(moved_to)
+        .map_err(|e| AppError::BadGateway(format!("invalid move destination: {e}")))?;
+    super::policy::ensure_user_permitted(state, &to).await?;
+
+    // 移行先の鍵を取得（自サーバに解決される場合はローカルのusers行）
+    let to_domain = to.domain().unwrap_or_default();
+    let home = match state.dns_resolver.resolve(to_domain, to.local_part()).await {
+        ResolvedDomain::Mapped { domain, .. } => domain,
+        ResolvedDomain::Original => to_domain.to_string(),
+    };
+    let (encryption_public_key, signing_public_key, is_local) = if home == *hostname {
+        let user = db::users::get_user(pool, &to)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("move destination is not registered".into()))?;
+        (user.encryption_public_key, user.signing_public_key, true)
+    } else {
+        let keys = super::client::fetch_user_keys(
+            &state.http,
+            &home,
+            to.as_str(),
+            state.config.federation_allow_http,
+        )
+        .await
+        .map_err(|e| match e {
+            AppError::Moved { .. } => AppError::BadGateway("chained moves are not followed".into()),
+            e => e,
+        })?;
+        (keys.encryption_public_key, keys.signing_public_key, false)
+    };
+
+    let moved = verify_statement(statement, &signing_public_key)?;
+    if moved.from != from.as_str() || moved.to != to.as_str() {
+        return Err(AppError::BadRequest(
+            "moved statement does not match the moved user".into(),
+        ));
+    }
+
+    // キャッシュ済みの鍵と主鍵が異なる場合は乗っ取りの可能性があるため拒否
+    let cached = db::users::get_user(pool, from).await?;
+    if let Some(cached) = &cached {
+        if cached.primary_key_fingerprint != moved.primary_key_fingerprint {
+            return Err(AppError::Forbidden(
+                "moved statement key does not match known key".into(),
+            ));
+        }
+    }
+
+    db::user_moves::upsert_move(
+        pool,
+        from.as_str(),
+        to.as_str(),
+        &moved.primary_key_fingerprint,
+        statement,
+    )
+    .await?;
+
+    if !is_local {
+        let previous = db::users::get_user(pool, &to).await?;
+        let stored = db::users::upsert_external_user(
+            pool,
+            to.as_str(),
+            &encryption_public_key,
+            &signing_public_key,
+            &moved.primary_key_fingerprint,
+        )
+        .await?;
+        if !stored {
+            return Err(AppError::Forbidden(
+                "moved user's stored key has been revoked".into(),
+            ));
+        }
+        super::key_change::record_keys(
+            state,
+            &to,
+            previous.as_ref(),
+            &encryption_public_key,
+            &signing_public_key,
+            &moved.primary_key_fingerprint,
+        )
+        .await?;
+    }
+
+    let members = db::chat::get_co_member_ids(pool, from).await?;
+    db::user_moves::rewrite_user_id(pool, from.as_str(), to.as_str()).await?;
+    // 鍵は移行先で使われ続けるため、tombstoneには指紋を記録しない
+    db::users::delete_user(pool, from, None).await?;
+    tracing::info!(from = from.as_str(), to = to.as_str(), "followed user move");
+
+    notify_members(state, from, &to, members);
+    Ok(to)
+}
+
+/// 移行したユーザと同じチャットに所属するローカルメンバーへ通知する。
+fn notify_members(state: &AppState, from: &UserId, to: &UserId, members: Vec<UserId>) {
+    if members.is_empty() {
+        return;
+    }
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let payload = serde_json::json!({
+        "type": "user_moved",
+        "from": from.as_str(),
+        "to": to.as_str(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &members, &payload).await {
+            tracing::warn!("user move push failed: {e}");
+        }
+    });
+}
+
+/// 外部サーバへアカウント移行をバックグラウンドで通知する。
+pub fn announce(
+    state: &AppState,
+    from: &UserId,
+    statement: &str,
+    domains: std::collections::BTreeSet<String>,
+) {
+    if domains.is_empty() {
+        return;
+    }
+    let http = state.http.clone();
+    let allow_http = state.config.federation_allow_http;
+    let from = from.as_str().to_string();
+    let statement = statement.to_string();
+    tokio::spawn(async move {
+        for domain in &domains {
+            if let Err(e) =
+                super::client::send_moved(&http, domain, &from, &statement, allow_http).await
+            {
+                tracing::warn!("federation move notice to {domain} failed: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::AppState;
+use crate::config::AppConfig;
+use crate::db;
+use crate::db::Db;
+use crate::error::AppError;
+use crate::federation::dns::ResolvedDomain;
+use crate::types::UserId;
+
+/// 連合ポリシーの動作モード。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyMode {
+    /// すべてのドメインと連合する。
+    Open,
+    /// リストにあるドメインとのみ連合する。
+    AllowList,
+    /// リストにあるドメイン以外と連合する。
+    DenyList,
+}
+
+impl PolicyMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::AllowList => "allow_list",
+            Self::DenyList => "deny_list",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "open" => Some(Self::Open),
+            "allow_list" => Some(Self::AllowList),
+            "deny_list" => Some(Self::DenyList),
+            _ => None,
+        }
+    }
+}
+
+/// 連合ポリシー。
+///
+/// ドメインは小文字で保持する。`*.example.com` 形式のエントリは
+/// `example.com` のサブドメインすべてに一致する（`example.com` 自体には一致しない）。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederationPolicy {
+    pub mode: PolicyMode,
+    pub domains: BTreeSet<String>,
+}
+
+impl FederationPolicy {
+    pub fn open() -> Self {
+        Self {
+            mode: PolicyMode::Open,
+            domains: BTreeSet::new(),
+        }
+    }
+
+    pub fn new(mode: PolicyMode, domains: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            mode,
+            domains: domains
+                .into_iter()
+                .map(|d| d.trim().to_ascii_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+        }
+    }
+
+    fn listed(&self, domain: &str) -> bool {
+        let domain = domain.to_ascii_lowercase();
+        self.domains
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(suffix) => domain
+                    .strip_suffix(suffix)
+                    .is_some_and(|head| head.ends_with('.')),
+                None => *entry == domain,
+            })
+    }
+
+    /// 指定ドメインのサーバとの連合を許可するか判定する。
+    pub fn permits(&self, domain: &str) -> bool {
+        match self.mode {
+            PolicyMode::Open => true,
+            PolicyMode::AllowList => self.listed(domain),
+            PolicyMode::DenyList => !self.listed(domain),
+        }
+    }
+
+    /// 外部ユーザとの連合を許可するか判定する。
+    ///
+    /// `home_domain` は DNS TXT 解決後の実際のホームサーバ。
+    /// 許可リストではホームサーバが載っていれば許可し、
+    /// 拒否リストではユーザIDのドメイン・ホームサーバのどちらかが載っていれば拒否する。
+    pub fn permits_user(&self, user_domain: &str, home_domain: &str) -> bool {
+        match self.mode {
+            PolicyMode::Open => true,
+            PolicyMode::AllowList => self.listed(home_domain),
+            PolicyMode::DenyList => !self.listed(user_domain) && !self.listed(home_domain),
+        }
+    }
+}
+
+/// 実行時に変更可能な連合ポリシーの保持領域。
+///
+/// 管理者による変更はDBに保存したうえで即座に反映し、
+/// 他インスタンスでの変更は定期的な `reload` で取り込む。
+#[derive(Clone)]
+pub struct FederationPolicyStore {
+    inner: Arc<RwLock<FederationPolicy>>,
+}
+
+impl FederationPolicyStore {
+    pub fn new(policy: FederationPolicy) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(policy)),
+        }
+    }
+
+    pub async fn get(&self) -> FederationPolicy {
+        self.inner.read().await.clone()
+    }
+
+    pub async fn set(&self, policy: FederationPolicy) {
+        *self.inner.write().await = policy;
+    }
+
+    pub async fn permits(&self, domain: &str) -> bool {
+        self.inner.read().await.permits(domain)
+    }
+
+    pub async fn permits_user(&self, user_domain: &str, home_domain: &str) -> bool {
+        self.inner
+            .read()
+            .await
+            .permits_user(user_domain, home_domain)
+    }
+
+    /// DBに保存されたポリシーを読み込む。未保存の場合は `fallback` を使用する。
+    pub async fn reload(&self, pool: &Db, fallback: &FederationPolicy) -> Result<(), sqlx::Error> {
+        let policy = match db::federation_policy::get_policy(pool).await? {
+            Some((mode, domains)) => match PolicyMode::parse(&mode) {
+                Some(mode) => FederationPolicy::new(mode, domains),
+                None => {
+                    tracing::warn!(mode, "unknown federation policy mode in database, ignoring");
+                    fallback.clone()
+                }
+            },
+            None => fallback.clone(),
+        };
+        self.set(policy).await;
+        Ok(())
+    }
+}
+
+/// 環境変数で指定された初期ポリシーを返す。
+pub fn policy_from_config(config: &AppConfig) -> FederationPolicy {
+    let mode = match PolicyMode::parse(&config.federation_policy) {
+        Some(mode) => mode,
+        None => {
+            tracing::warn!(
+                value = config.federation_policy,
+                "unknown FEDERATION_POLICY, falling back to open"
+            );
+            PolicyMode::Open
+        }
+    };
+    FederationPolicy::new(mode, config.federation_policy_domains.iter().cloned())
+}
+
+/// ユーザのホームサーバとの連合がポリシーで許可されているか検証する。
+///
+/// 自サーバに解決されるユーザ（カスタムドメインを含む）は常に許可する。
+pub async fn ensure_user_permitted(state: &AppState, user_id: &UserId) -> Result<(), AppError> {
+    let Some(domain) = user_id.domain() else {
+        return Ok(());
+    };
+    let policy = state.federation_policy.get().await;
+    if policy.mode == PolicyMode::Open || domain == state.config.server_hostname {
+        return Ok(());
+    }
+
+    let home = match state
+        .dns_resolver
+        .resolve(domain, user_id.local_part())
+        .await
+    {
+        ResolvedDomain::Mapped { domain, .. } => domain,
+        ResolvedDomain::Original => domain.to_string(),
+    };
+    if home == state.config.server_hostname || policy.permits_user(domain, &home) {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(format!(
+            "federation with {home} is not allowed"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: PolicyMode, domains: &[&str]) -> FederationPolicy {
+        FederationPolicy::new(mode, domains.iter().map(|d| d.to_string()))
+    }
+
+    #[test]
+    fn open_permits_everything() {
+        let p = FederationPolicy::open();
+        assert!(p.permits("any.example.com"));
+        assert!(p.permits_user("a.example.com", "b.example.com"));
+    }
+
+    #[test]
+    fn allow_list_exact_and_case_insensitive() {
+        let p = policy(PolicyMode::AllowList, &["Friend.Example.com"]);
+        assert!(p.permits("friend.example.com"));
+        assert!(p.permits("FRIEND.example.com"));
+        assert!(!p.permits("stranger.example.com"));
+    }
+
+    #[test]
+    fn wildcard_matches_subdomains_only() {
+        let p = policy(PolicyMode::AllowList, &["*.example.com"]);
+        assert!(p.permits("a.example.com"));
+        assert!(p.permits("a.b.example.com"));
+        assert!(!p.permits("example.com"));
+        assert!(!p.permits("badexample.com"));
+    }
+
+    #[test]
+    fn deny_list_blocks_listed() {
+        let p = policy(PolicyMode::DenyList, &["evil.example.com"]);
+        assert!(!p.permits("evil.example.com"));
+        assert!(p.permits("good.example.com"));
+    }
+
+    #[test]
+    fn permits_user_checks_home_server() {
+        let allow = policy(PolicyMode::AllowList, &["home.example.com"]);
+        assert!(allow.permits_user("custom.example.org", "home.example.com"));
+        assert!(!allow.permits_user("home.example.com", "other.example.com"));
+
+        let deny = policy(PolicyMode::DenyList, &["custom.example.org"]);
+        assert!(!deny.permits_user("custom.example.org", "home.example.com"));
+        assert!(deny.permits_user("other.example.org", "home.example.com"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use crate::AppState;
+use crate::db;
+use crate::db::models::UserRow;
+use crate::error::AppError;
+use crate::types::UserId;
+
+/// 失効証明書を検証し、ユーザの鍵に取り込んで保存する。
+///
+/// 鍵自体に失効署名を含めることで、鍵を取得したクライアントや連合先にも失効が伝わる。
+/// 新たに失効した場合は `true` を返し、セッションを無効化して共通チャットのメンバーに通知する。
+pub async fn apply(state: &AppState, user: &UserRow, certificate: &str) -> Result<bool, AppError> {
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
+        .map_err(|e| AppError::Internal(format!("invalid stored signing key: {e}")))?;
+    let revoked_key = public_keys
+        .with_revocation_certificate(certificate)
+        .map_err(|e| AppError::BadRequest(format!("invalid revocation certificate: {e}")))?;
+
+    let user_id = UserId(user.id.clone());
+    let inserted = db::key_revocations::insert_revocation(
+        &state.pool,
+        &user.primary_key_fingerprint,
+        &user.id,
+        certificate,
+    )
+    .await?;
+    db::users::update_user_keys(
+        &state.pool,
+        &user_id,
+        &user.encryption_public_key,
+        &revoked_key,
+        &user.primary_key_fingerprint,
+    )
+    .await?;
+    if !inserted {
+        return Ok(false);
+    }
+
+    tracing::warn!(
+        user_id = user.id,
+        fingerprint = user.primary_key_fingerprint,
+        "primary key revoked"
+    );
+    db::sessions::revoke_user_sessions(&state.pool, &user.id).await?;
+    notify_members(state, &user_id, &user.primary_key_fingerprint);
+    Ok(true)
+}
+
+/// 外部サーバへ鍵の失効をバックグラウンドで通知する。
+pub fn announce(state: &AppState, user_id: &UserId, certificate: &str, domains: BTreeSet<String>) {
+    if domains.is_empty() {
+        return;
+    }
+    let http = state.http.clone();
+    let allow_http = state.config.federation_allow_http;
+    // ローカルユーザはドメインなしで保存されているため完全修飾IDで通知する
+    let user_id = match user_id.domain() {
+        Some(_) => user_id.as_str().to_string(),
+        None => user_id
+            .with_domain(&state.config.server_hostname)
+            .as_str()
+            .to_string(),
+    };
+    let certificate = certificate.to_string();
+    tokio::spawn(async move {
+        for domain in &domains {
+            if let Err(e) = super::client::send_key_revocation(
+                &http,
+                domain,
+                &user_id,
+                &certificate,
+                allow_http,
+            )
+            .await
+            {
+                tracing::warn!("federation key revocation to {domain} failed: {e}");
+            }
+        }
+    });
+}
+
+/// 鍵が失効したユーザと同じチャットに所属するローカルメンバーへ通知する。
+fn notify_members(state: &AppState, user_id: &UserId, fingerprint: &str) {
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let user_id = user_id.clone();
+    let payload = serde_json::json!({
+        "type": "key_revoked",
+        "user_id": user_id.as_str(),
+        "fingerprint": fingerprint,
+    });
+    tokio::spawn(async move {
+        let members = match db::chat::get_co_member_ids(&pool, &user_id).await {
+            Ok(members) => members,
+            Err(e) => {
+                tracing::warn!("failed to load co-members for key revocation: {e}");
+                return;
+            }
+        };
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &members, &payload).await {
+            tracing::warn!("key revocation push failed: {e}");
+        }
+    });
+}
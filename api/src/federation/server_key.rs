@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+
+use crate::db;
+use crate::db::Db;
+use crate::error::AppError;
+
+/// サーバ間の委任ステートメントに署名するEd25519鍵。
+#[derive(Clone)]
+pub struct ServerKey {
+    signing_key: Arc<SigningKey>,
+}
+
+impl ServerKey {
+    /// DBに保存された鍵を読み込む。未保存の場合は新しく生成して保存する。
+    pub async fn load_or_create(pool: &Db) -> Result<Self, sqlx::Error> {
+        let mut seed = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        let generated = SigningKey::from_bytes(&seed);
+        let (private_key, _) = db::server_keys::get_or_insert_key(
+            pool,
+            &STANDARD.encode(generated.to_bytes()),
+            &STANDARD.encode(generated.verifying_key().to_bytes()),
+        )
+        .await?;
+
+        let seed: [u8; 32] = STANDARD
+            .decode(private_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| sqlx::Error::Decode("invalid stored server key".into()))?;
+        Ok(Self {
+            signing_key: Arc::new(SigningKey::from_bytes(&seed)),
+        })
+    }
+
+    /// base64エンコードした公開鍵
+    pub fn public_key(&self) -> String {
+        STANDARD.encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// base64url（パディングなし）エンコードした署名を返す。
+    pub fn sign(&self, data: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.sign(data).to_bytes())
+    }
+}
+
+/// base64公開鍵でbase64url署名を検証する。
+pub fn verify_signature(public_key: &str, data: &[u8], signature: &str) -> Result<(), AppError> {
+    let key_bytes: [u8; 32] = STANDARD
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| AppError::Unauthorized("invalid server public key".into()))?;
+    let key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|e| AppError::Unauthorized(format!("invalid server public key: {e}")))?;
+    let sig_bytes: [u8; 64] = URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| AppError::Unauthorized("invalid server signature encoding".into()))?;
+    key.verify(data, &Signature::from_bytes(&sig_bytes))
+        .map_err(|_| AppError::Unauthorized("server signature verification failed".into()))
+}
+
+/// 外部サーバの公開鍵キャッシュ。
+#[derive(Clone)]
+pub struct PeerKeyCache {
+    inner: Arc<RwLock<HashMap<String, (String, Instant)>>>,
+    ttl: Duration,
+}
+
+impl PeerKeyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub async fn get(&self, domain: &str) -> Option<String> {
+        let cache = self.inner.read().await;
+        cache
+            .get(domain)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(key, _)| key.clone())
+    }
+
+    pub async fn set(&self, domain: String, public_key: String) {
+        let mut cache = self.inner.write().await;
+        cache.insert(domain, (public_key, Instant::now() + self.ttl));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_roundtrip() {
+        let key = ServerKey {
+            signing_key: Arc::new(SigningKey::from_bytes(&[7u8; 32])),
+        };
+        let signature = key.sign(b"delegation");
+        assert!(verify_signature(&key.public_key(), b"delegation", &signature).is_ok());
+        assert!(verify_signature(&key.public_key(), b"tampered", &signature).is_err());
+    }
+}
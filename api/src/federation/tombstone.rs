@@ -0,0 +1,83 @@
+use std::collections::BTreeSet;
+
+use crate::AppState;
+use crate::db;
+use crate::error::AppError;
+use crate::federation::dns::ResolvedDomain;
+use crate::types::UserId;
+
+/// ユーザとチャットを共有している外部サーバのドメインを返す。
+///
+/// 自サーバがホストするチャットの外部メンバーのホームサーバと、
+/// 外部サーバがホストするチャットのホームサーバを対象とする。
+/// 削除でメンバーシップが消える前に呼び出すこと。
+pub async fn peer_domains(
+    state: &AppState,
+    user_id: &UserId,
+) -> Result<BTreeSet<String>, AppError> {
+    let hostname = &state.config.server_hostname;
+    let mut domains: BTreeSet<String> = db::chat::get_remote_chat_domains(&state.pool, user_id)
+        .await?
+        .into_iter()
+        .collect();
+
+    for member in db::chat::get_co_member_ids(&state.pool, user_id).await? {
+        let Some(domain) = member.domain() else {
+            continue;
+        };
+        if domain == hostname {
+            continue;
+        }
+        let home = match state
+            .dns_resolver
+            .resolve(domain, member.local_part())
+            .await
+        {
+            ResolvedDomain::Mapped { domain, .. } => domain,
+            ResolvedDomain::Original => domain.to_string(),
+        };
+        if home != *hostname {
+            domains.insert(home);
+        }
+    }
+
+    domains.remove(hostname);
+    Ok(domains)
+}
+
+/// 外部サーバへアカウント削除をバックグラウンドで通知する。
+pub fn announce(state: &AppState, user_id: &UserId, domains: BTreeSet<String>) {
+    if domains.is_empty() {
+        return;
+    }
+    let http = state.http.clone();
+    let allow_http = state.config.federation_allow_http;
+    let user_id = user_id.as_str().to_string();
+    tokio::spawn(async move {
+        for domain in &domains {
+            if let Err(e) = super::client::send_tombstone(&http, domain, &user_id, allow_http).await
+            {
+                tracing::warn!("federation tombstone to {domain} failed: {e}");
+            }
+        }
+    });
+}
+
+/// 削除されたユーザと同じチャットに所属していたローカルメンバーへ通知する。
+/// メンバーの一覧は削除前に取得しておく。
+pub fn notify_members(state: &AppState, user_id: &UserId, members: Vec<UserId>) {
+    if members.is_empty() {
+        return;
+    }
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let payload = serde_json::json!({
+        "type": "user_deleted",
+        "user_id": user_id.as_str(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &members, &payload).await {
+            tracing::warn!("user deletion push failed: {e}");
+        }
+    });
+}
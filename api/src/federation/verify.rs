@@ -1,32 +1,49 @@
-use crate::auth::{AuthPayload, AuthenticatedUser, validate_nonce_timestamp};
-use crate::config::AppConfig;
+use crate::AppState;
+use crate::auth::request::RequestTarget;
+use crate::auth::{AuthenticatedUser, ensure_not_revoked, verify_auth_payload};
 use crate::db;
-use crate::db::Db;
-use crate::db::nonces::NonceType;
 use crate::error::AppError;
-use crate::federation::dns::{DnsTxtResolver, ResolvedDomain};
+use crate::federation::dns::ResolvedDomain;
 use crate::types::UserId;
 
 /// 外部ユーザの署名を検証し、AuthenticatedUserを返す。
 ///
 /// 1. SignersUserIDサブパケットからuser_id@domainを抽出
 /// 2. ローカルDBで外部ユーザとして検索（キャッシュ済みの場合あり）
-/// 3. 見つかった → 公開鍵で署名検証を試行、成功すれば返却
+/// 3. 見つかった → 失効済みなら拒否し、公開鍵で署名検証を試行、成功すれば返却
 /// 4. ドメインの鍵取得エンドポイントにリクエスト（認証不要）
-/// 5. 取得した公開鍵をローカルusersテーブルにupsert
-/// 6. 公開鍵で署名検証 → AuthenticatedUser返却
+/// 5. 取得した公開鍵で署名検証し、主鍵が変わっていれば移行チェーンを検証
+/// 6. 検証できた公開鍵をローカルusersテーブルにupsertし、鍵履歴を記録 → AuthenticatedUser返却
+///
+/// 移行済みユーザの場合は移行先のIDで検証する。鍵取得時に移行が判明した場合は
+/// 移行宣言を検証・反映したうえで一度だけ再試行する。
 pub async fn verify_or_fetch_external_user(
-    pool: &Db,
-    config: &AppConfig,
-    dns_resolver: &DnsTxtResolver,
-    auth_header_raw: &str,
+    state: &AppState,
+    auth_header_decoded: &str,
+    target: &RequestTarget,
+) -> Result<AuthenticatedUser, AppError> {
+    verify_or_fetch(state, auth_header_decoded, target, true).await
+}
+
+async fn verify_or_fetch(
+    state: &AppState,
     auth_header_decoded: &str,
+    target: &RequestTarget,
+    follow_moves: bool,
 ) -> Result<AuthenticatedUser, AppError> {
+    let pool = &state.pool;
+    let config = &state.config;
     // 1. SignersUserIDサブパケットからuser_id@domainを抽出
     let signer_user_id = xrypton_common::keys::extract_signer_user_id(auth_header_decoded)
         .map_err(|e| AppError::Unauthorized(format!("failed to extract signer user ID: {e}")))?;
     tracing::debug!("extracted SignersUserID: {:?}", signer_user_id);
 
+    // 移行済みのユーザは鍵のUser IDが旧IDのままなので移行先IDに読み替える
+    let signer_user_id = match db::user_moves::get_move(pool, &signer_user_id).await? {
+        Some(moved) => moved.to_id,
+        None => signer_user_id,
+    };
+
     // ドメイン部分を解析
     let (orig_local, orig_domain) = signer_user_id
         .split_once('@')
@@ -34,43 +51,34 @@ pub async fn verify_or_fetch_external_user(
 
     // 2. ローカルDBで外部ユーザとして検索（キャッシュ済み）
     let cached_user_id = UserId(signer_user_id.clone());
+    super::policy::ensure_user_permitted(state, &cached_user_id).await?;
     if let Some(user) = db::users::get_user(pool, &cached_user_id).await? {
         let public_keys =
             xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
                 .map_err(|e| AppError::Unauthorized(format!("invalid signing key: {e}")))?;
+        // 失効済みの鍵は再取得で上書きさせない
+        ensure_not_revoked(state, &user.primary_key_fingerprint, &public_keys).await?;
 
         // 署名検証を試行
-        if let Ok(payload_bytes) = public_keys.verify_and_extract(auth_header_decoded) {
-            let payload: AuthPayload = serde_json::from_slice(&payload_bytes)
-                .map_err(|e| AppError::Unauthorized(format!("invalid auth payload: {e}")))?;
-            let nonce_time = validate_nonce_timestamp(&payload.nonce)?;
-            let nonce_key = payload.nonce.replay_key();
-            let expires_at = nonce_time + chrono::Duration::hours(1);
-
-            let is_new = db::nonces::try_use_nonce(
-                pool,
-                NonceType::Auth,
-                nonce_key,
-                cached_user_id.as_str(),
-                expires_at,
-            )
-            .await?;
-            if !is_new {
-                return Err(AppError::Unauthorized("nonce already used".into()));
-            }
+        if let Ok((payload_bytes, signer_fingerprint)) =
+            public_keys.verify_and_extract_with_signer(auth_header_decoded)
+        {
+            verify_auth_payload(state, &payload_bytes, &cached_user_id, target).await?;
 
             return Ok(AuthenticatedUser {
                 user_id: cached_user_id,
                 primary_key_fingerprint: user.primary_key_fingerprint,
                 signing_public_key: user.signing_public_key,
-                raw_auth_header: auth_header_raw.to_string(),
+                session_token_hash: None,
+                signing_key_fingerprint: Some(signer_fingerprint),
+                delegated_by: None,
             });
         }
         // 署名検証失敗 → 鍵更新の可能性、下のフローで再取得
     }
 
     // DNS TXTレコードによるドメイン解決
-    let (local_part, domain) = match dns_resolver.resolve(orig_domain, orig_local).await {
+    let (local_part, domain) = match state.dns_resolver.resolve(orig_domain, orig_local).await {
         ResolvedDomain::Mapped {
             local_part: resolved_local,
             domain: resolved_domain,
@@ -102,85 +110,106 @@ pub async fn verify_or_fetch_external_user(
         let public_keys =
             xrypton_common::keys::PublicKeys::try_from(user.signing_public_key.as_str())
                 .map_err(|e| AppError::Unauthorized(format!("invalid signing key: {e}")))?;
+        ensure_not_revoked(state, &user.primary_key_fingerprint, &public_keys).await?;
 
-        let payload_bytes = public_keys
-            .verify_and_extract(auth_header_decoded)
+        let (payload_bytes, signer_fingerprint) = public_keys
+            .verify_and_extract_with_signer(auth_header_decoded)
             .map_err(|e| AppError::Unauthorized(format!("signature verification failed: {e}")))?;
 
-        let payload: AuthPayload = serde_json::from_slice(&payload_bytes)
-            .map_err(|e| AppError::Unauthorized(format!("invalid auth payload: {e}")))?;
-        let nonce_time = validate_nonce_timestamp(&payload.nonce)?;
-        let nonce_key = payload.nonce.replay_key();
-        let expires_at = nonce_time + chrono::Duration::hours(1);
-
-        let is_new = db::nonces::try_use_nonce(
-            pool,
-            NonceType::Auth,
-            nonce_key,
-            user_id.as_str(),
-            expires_at,
-        )
-        .await?;
-        if !is_new {
-            return Err(AppError::Unauthorized("nonce already used".into()));
-        }
+        verify_auth_payload(state, &payload_bytes, &user_id, target).await?;
 
         return Ok(AuthenticatedUser {
             user_id,
             primary_key_fingerprint: user.primary_key_fingerprint,
             signing_public_key: user.signing_public_key,
-            raw_auth_header: auth_header_raw.to_string(),
+            session_token_hash: None,
+            signing_key_fingerprint: Some(signer_fingerprint),
+            delegated_by: None,
         });
     }
 
     // 4. リモートサーバから公開鍵を取得（DNS解決後のドメインを使用）
-    let remote_keys =
-        super::client::fetch_user_keys(&domain, &local_part, config.federation_allow_http).await?;
+    let remote_keys = match super::client::fetch_user_keys(
+        &state.http,
+        &domain,
+        &local_part,
+        config.federation_allow_http,
+    )
+    .await
+    {
+        Ok(keys) => keys,
+        Err(AppError::Moved {
+            moved_to,
+            statement,
+        }) if follow_moves => {
+            let from = UserId(format!("{orig_local}@{orig_domain}"));
+            super::moved::follow(state, &from, &moved_to, &statement).await?;
+            return Box::pin(verify_or_fetch(state, auth_header_decoded, target, false)).await;
+        }
+        Err(e) => return Err(e),
+    };
 
-    // 5. ローカルDBにupsert（元のIDを保持）
-    let full_id = format!("{orig_local}@{orig_domain}");
+    // 元のIDを保持して保存する
+    let user_id = UserId(format!("{orig_local}@{orig_domain}"));
     let public_keys =
         xrypton_common::keys::PublicKeys::try_from(remote_keys.signing_public_key.as_str())
             .map_err(|e| AppError::Unauthorized(format!("invalid remote signing key: {e}")))?;
     let fingerprint = public_keys.get_primary_fingerprint();
+    ensure_not_revoked(state, &fingerprint, &public_keys).await?;
+
+    // 5. 取得した公開鍵で署名検証（検証できない鍵は保存しない）
+    let (payload_bytes, signer_fingerprint) = public_keys
+        .verify_and_extract_with_signer(auth_header_decoded)
+        .map_err(|e| AppError::Unauthorized(format!("signature verification failed: {e}")))?;
+    verify_auth_payload(state, &payload_bytes, &user_id, target).await?;
+
+    // キャッシュ済みの主鍵から変わっている場合は有効な移行チェーンが必要
+    let previous = db::users::get_user(pool, &user_id).await?;
+    if let Some(prev) = previous
+        .as_ref()
+        .filter(|p| p.primary_key_fingerprint != fingerprint)
+    {
+        super::key_transition::verify_remote_chain(
+            state,
+            &domain,
+            &user_id,
+            prev,
+            &remote_keys.signing_public_key,
+            &fingerprint,
+        )
+        .await?;
+    }
 
-    db::users::upsert_external_user(
+    // 6. ローカルDBにupsertし、鍵履歴を記録
+    let stored = db::users::upsert_external_user(
         pool,
-        &full_id,
+        user_id.as_str(),
         &remote_keys.encryption_public_key,
         &remote_keys.signing_public_key,
         &fingerprint,
     )
     .await?;
-
-    // 6. 取得した公開鍵で署名検証
-    let payload_bytes = public_keys
-        .verify_and_extract(auth_header_decoded)
-        .map_err(|e| AppError::Unauthorized(format!("signature verification failed: {e}")))?;
-
-    let payload: AuthPayload = serde_json::from_slice(&payload_bytes)
-        .map_err(|e| AppError::Unauthorized(format!("invalid auth payload: {e}")))?;
-    let nonce_time = validate_nonce_timestamp(&payload.nonce)?;
-    let nonce_key = payload.nonce.replay_key();
-    let expires_at = nonce_time + chrono::Duration::hours(1);
-
-    let user_id = UserId(full_id);
-    let is_new = db::nonces::try_use_nonce(
-        pool,
-        NonceType::Auth,
-        nonce_key,
-        user_id.as_str(),
-        expires_at,
+    if !stored {
+        return Err(AppError::Unauthorized(
+            "stored signing key has been revoked".into(),
+        ));
+    }
+    super::key_change::record_keys(
+        state,
+        &user_id,
+        previous.as_ref(),
+        &remote_keys.encryption_public_key,
+        &remote_keys.signing_public_key,
+        &fingerprint,
     )
     .await?;
-    if !is_new {
-        return Err(AppError::Unauthorized("nonce already used".into()));
-    }
 
     Ok(AuthenticatedUser {
         user_id,
         primary_key_fingerprint: fingerprint,
         signing_public_key: remote_keys.signing_public_key,
-        raw_auth_header: auth_header_raw.to_string(),
+        session_token_hash: None,
+        signing_key_fingerprint: Some(signer_fingerprint),
+        delegated_by: None,
     })
 }
@@ -14,6 +14,9 @@ use std::time::Duration;
 
 use config::AppConfig;
 use federation::dns::DnsTxtResolver;
+use federation::http::OutboundClient;
+use federation::policy::FederationPolicyStore;
+use federation::server_key::{PeerKeyCache, ServerKey};
 use storage::S3Storage;
 use tokio::sync::RwLock;
 use tokio::time::Instant;
@@ -55,4 +58,12 @@ pub struct AppState {
     pub storage: Arc<S3Storage>,
     pub dns_resolver: DnsTxtResolver,
     pub did_cache: DidCache,
+    /// 連合・プロキシ通信用の共有HTTPクライアント
+    pub http: OutboundClient,
+    /// 実行時に変更可能な連合ポリシー
+    pub federation_policy: FederationPolicyStore,
+    /// 委任ステートメントに署名するサーバ鍵
+    pub server_key: ServerKey,
+    /// 外部サーバの公開鍵キャッシュ
+    pub peer_keys: PeerKeyCache,
 }
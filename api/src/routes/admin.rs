@@ -0,0 +1,104 @@
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::error::AppError;
+use crate::federation::policy::{FederationPolicy, PolicyMode};
+use crate::types::UserId;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/federation/policy",
+            get(get_federation_policy).put(put_federation_policy),
+        )
+        .route("/admin/federation/peers", get(get_federation_peers))
+}
+
+/// `ADMIN_USER_IDS` に含まれるユーザか検証する。ベアIDは自サーバのユーザとして扱う。
+fn ensure_admin(state: &AppState, auth: &AuthenticatedUser) -> Result<(), AppError> {
+    let hostname = &state.config.server_hostname;
+    let is_admin = state
+        .config
+        .admin_user_ids
+        .iter()
+        .filter_map(|id| UserId::resolve_local(id, hostname).ok())
+        .any(|id| id == auth.user_id);
+    if !is_admin {
+        return Err(AppError::Forbidden("admin only".into()));
+    }
+    Ok(())
+}
+
+async fn get_federation_policy(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    ensure_admin(&state, &auth)?;
+    let policy = state.federation_policy.get().await;
+    Ok(Json(serde_json::json!({
+        "mode": policy.mode,
+        "domains": policy.domains,
+    })))
+}
+
+#[derive(Deserialize)]
+struct PutFederationPolicyBody {
+    mode: PolicyMode,
+    #[serde(default)]
+    domains: Vec<String>,
+}
+
+/// 連合ポリシーを変更する。DBに保存し、このインスタンスには即座に反映する。
+async fn put_federation_policy(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+    Json(body): Json<PutFederationPolicyBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    ensure_admin(&state, &auth)?;
+
+    let policy = FederationPolicy::new(body.mode, body.domains);
+    if policy
+        .domains
+        .iter()
+        .any(|d| d.contains(['/', ':', '@', ' ']) || d.trim_start_matches("*.").contains('*'))
+    {
+        return Err(AppError::BadRequest("invalid domain in policy".into()));
+    }
+
+    let domains: Vec<String> = policy.domains.iter().cloned().collect();
+    db::federation_policy::replace_policy(
+        &state.pool,
+        policy.mode.as_str(),
+        &domains,
+        auth.user_id.as_str(),
+    )
+    .await?;
+    state.federation_policy.set(policy.clone()).await;
+
+    tracing::info!(
+        admin = auth.user_id.as_str(),
+        mode = policy.mode.as_str(),
+        domains = domains.len(),
+        "federation policy updated"
+    );
+
+    Ok(Json(serde_json::json!({
+        "mode": policy.mode,
+        "domains": policy.domains,
+    })))
+}
+
+/// 連合先ごとの通信統計を返す。
+async fn get_federation_peers(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    ensure_admin(&state, &auth)?;
+    let peers = state.http.metrics().snapshot().await;
+    Ok(Json(serde_json::json!({ "peers": peers })))
+}
@@ -1,7 +1,10 @@
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
-use axum::routing::put;
+use axum::routing::{post, put};
 use axum::{Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use serde::{Deserialize, Serialize};
 
 use crate::AppState;
@@ -12,21 +15,76 @@ use crate::types::UserId;
 
 const MAX_BACKUP_ARMOR_SIZE: usize = 256 * 1024;
 const MAX_CREDENTIAL_ID_B64_SIZE: usize = 1024;
+const MAX_BACKUP_METHODS: usize = 16;
+const BACKUP_V2_ALG: &str = "xrypton_backup_v2";
+/// 取得チャレンジの有効期間（秒）
+const RETRIEVAL_CHALLENGE_TTL_SECS: i64 = 300;
+/// 1時間あたりに失敗理由を返す署名検証の失敗回数（ユーザごと）
+const MAX_FAILED_RETRIEVALS_PER_HOUR: i64 = 5;
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route(
-        "/user/{id}/secret-key-backup",
-        put(put_secret_key_backup)
-            .get(get_secret_key_backup)
-            .delete(delete_secret_key_backup),
-    )
+    Router::new()
+        .route(
+            "/user/{id}/secret-key-backup",
+            put(put_secret_key_backup)
+                .get(get_secret_key_backup)
+                .delete(delete_secret_key_backup),
+        )
+        .route(
+            "/user/{id}/secret-key-backup/challenge",
+            post(create_retrieval_challenge),
+        )
+        .route(
+            "/user/{id}/secret-key-backup/retrieve",
+            post(retrieve_secret_key_backup),
+        )
 }
 
 #[derive(Debug, Deserialize)]
 struct PutSecretKeyBackupBody {
     armor: String,
     version: i32,
+    /// v1 のみ必須。v2 ではエンベロープ内の解除方法から決まる
+    #[serde(default)]
     webauthn_credential_id_b64: String,
+    /// 端末を失った場合の取得に使う回復用 Ed25519 公開鍵（base64）。
+    /// v2 ではエンベロープの解除方法に含まれるものを使う
+    #[serde(default)]
+    recovery_public_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RetrieveSecretKeyBackupBody {
+    challenge: String,
+    recovery_public_key: String,
+    /// `retrieval_message` に対する base64url 署名
+    signature: String,
+}
+
+/// v2 エンベロープのうちサーバが検証する部分
+#[derive(Debug, Deserialize)]
+struct BackupEnvelopeV2 {
+    version: i32,
+    alg: String,
+    methods: Vec<BackupMethod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BackupMethod {
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
+    /// パスキーとリカバリーコードから導出した回復用公開鍵（base64）
+    #[serde(default)]
+    recovery_public_key: Option<String>,
+}
+
+/// 応答で返す解除方法。回復用公開鍵は公開しない
+#[derive(Debug, Serialize)]
+struct BackupMethodSummary {
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -34,6 +92,9 @@ struct SecretKeyBackupResponse {
     armor: String,
     version: i32,
     webauthn_credential_id_b64: String,
+    /// v2 で登録されている解除方法
+    #[serde(skip_serializing_if = "Option::is_none")]
+    methods: Option<Vec<BackupMethodSummary>>,
     created_at: db::models::Timestamp,
     updated_at: db::models::Timestamp,
 }
@@ -58,6 +119,157 @@ fn resolve_backup_user_id(path_id: &str, hostname: &str) -> Result<UserId, AppEr
         .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))
 }
 
+/// 回復用公開鍵で署名するチャレンジのメッセージ。WASM 側と同じ形式でなければならない。
+fn retrieval_message(user_id: &UserId, challenge: &str) -> String {
+    format!(
+        "xrypton-backup-retrieval-v1\n{}\n{challenge}",
+        user_id.as_str()
+    )
+}
+
+fn parse_recovery_public_key(public_key: &str) -> Result<VerifyingKey, AppError> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| AppError::BadRequest("invalid recovery public key".into()))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| AppError::BadRequest(format!("invalid recovery public key: {e}")))
+}
+
+fn verify_retrieval_signature(
+    user_id: &UserId,
+    body: &RetrieveSecretKeyBackupBody,
+) -> Result<(), AppError> {
+    let key = parse_recovery_public_key(&body.recovery_public_key)
+        .map_err(|_| AppError::Unauthorized("invalid recovery public key".into()))?;
+    let sig_bytes: [u8; 64] = URL_SAFE_NO_PAD
+        .decode(&body.signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| AppError::Unauthorized("invalid signature encoding".into()))?;
+    key.verify(
+        retrieval_message(user_id, &body.challenge).as_bytes(),
+        &Signature::from_bytes(&sig_bytes),
+    )
+    .map_err(|_| AppError::Unauthorized("recovery signature verification failed".into()))
+}
+
+/// 直近1時間の署名検証の失敗回数を返す。
+async fn recent_failed_retrievals(state: &AppState, user_id: &UserId) -> Result<i64, AppError> {
+    let window_start = chrono::Utc::now() - chrono::Duration::hours(1);
+    Ok(
+        db::backups::count_failed_attempts_since(&state.pool, user_id.as_str(), window_start)
+            .await?,
+    )
+}
+
+/// 所持証明の検証結果と直近の失敗回数から取得の可否を決める。
+///
+/// 失敗は誰でも重ねられるため、正しい署名は失敗回数に関わらず受け付けて所有者を締め出さない。
+/// 上限に達した後の失敗は理由を区別せずに拒否し、登録済みの回復用公開鍵を推測させない。
+fn retrieval_decision(verified: Result<(), AppError>, failures: i64) -> Result<(), AppError> {
+    match verified {
+        Ok(()) => Ok(()),
+        Err(_) if failures >= MAX_FAILED_RETRIEVALS_PER_HOUR => Err(AppError::TooManyRequests(
+            "too many failed backup retrieval attempts".into(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// バックアップが取得されたことを所有者の全デバイスに通知する。
+fn notify_retrieval(state: &AppState, user_id: &UserId, method: &'static str) {
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let owner = vec![user_id.clone()];
+    let payload = serde_json::json!({
+        "type": "secret_key_backup_retrieved",
+        "method": method,
+    });
+    tokio::spawn(async move {
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &owner, &payload).await {
+            tracing::warn!("backup retrieval push failed: {e}");
+        }
+    });
+}
+
+/// v2 で登録されている解除方法の種類とIDを返す。
+fn method_summaries(row: &db::models::SecretKeyBackupRow) -> Option<Vec<BackupMethodSummary>> {
+    if row.version != 2 {
+        return None;
+    }
+    let methods = parse_envelope_v2(&row.armor).ok()?;
+    Some(
+        methods
+            .into_iter()
+            .map(|m| BackupMethodSummary {
+                kind: m.kind,
+                id: m.id,
+            })
+            .collect(),
+    )
+}
+
+fn backup_response(row: db::models::SecretKeyBackupRow) -> SecretKeyBackupResponse {
+    SecretKeyBackupResponse {
+        methods: method_summaries(&row),
+        armor: row.armor,
+        version: row.version,
+        webauthn_credential_id_b64: row.webauthn_credential_id_b64,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    }
+}
+
+/// v2 エンベロープの形式と解除方法を検証し、解除方法の一覧を返す。
+///
+/// 鍵素材は暗号化されているため、サーバは解除方法の種類とIDのみ確認する。
+fn parse_envelope_v2(armor: &str) -> Result<Vec<BackupMethod>, AppError> {
+    let envelope: BackupEnvelopeV2 = serde_json::from_str(armor)
+        .map_err(|e| AppError::BadRequest(format!("invalid backup envelope: {e}")))?;
+    if envelope.version != 2 || envelope.alg != BACKUP_V2_ALG {
+        return Err(AppError::BadRequest(
+            "backup envelope does not match version".into(),
+        ));
+    }
+    if envelope.methods.is_empty() || envelope.methods.len() > MAX_BACKUP_METHODS {
+        return Err(AppError::BadRequest(
+            "invalid number of unlock methods".into(),
+        ));
+    }
+    let mut seen = std::collections::HashSet::new();
+    for method in &envelope.methods {
+        if !matches!(
+            method.kind.as_str(),
+            "passkey" | "recovery_code" | "passphrase"
+        ) {
+            return Err(AppError::BadRequest(format!(
+                "unknown unlock method: {}",
+                method.kind
+            )));
+        }
+        if method.id.is_empty() || method.id.len() > MAX_CREDENTIAL_ID_B64_SIZE {
+            return Err(AppError::BadRequest("invalid unlock method id".into()));
+        }
+        if !seen.insert((method.kind.as_str(), method.id.as_str())) {
+            return Err(AppError::BadRequest("duplicate unlock method".into()));
+        }
+        match (method.kind.as_str(), &method.recovery_public_key) {
+            ("passphrase", Some(_)) => {
+                return Err(AppError::BadRequest(
+                    "passphrase cannot have a recovery public key".into(),
+                ));
+            }
+            (_, Some(public_key)) => {
+                parse_recovery_public_key(public_key)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(envelope.methods)
+}
+
 async fn put_secret_key_backup(
     State(state): State<AppState>,
     Path(id): Path<String>,
@@ -66,26 +278,53 @@ async fn put_secret_key_backup(
 ) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
     let user_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
 
-    if body.version != 1 {
-        return Err(AppError::BadRequest("unsupported backup version".into()));
-    }
     if body.armor.is_empty() || body.armor.len() > MAX_BACKUP_ARMOR_SIZE {
         return Err(AppError::BadRequest("invalid backup armor size".into()));
     }
-    if body.webauthn_credential_id_b64.is_empty()
-        || body.webauthn_credential_id_b64.len() > MAX_CREDENTIAL_ID_B64_SIZE
+    // v2 では回復用公開鍵を解除方法に紐付け、削除された解除方法の鍵が残らないようにする
+    let (credential_id, recovery_public_keys) = match body.version {
+        1 => (
+            body.webauthn_credential_id_b64.clone(),
+            body.recovery_public_keys.clone(),
+        ),
+        2 => {
+            let methods = parse_envelope_v2(&body.armor)?;
+            // v2 は最初のパスキーのクレデンシャルIDを v1 互換の列に保存する
+            let credential_id = methods
+                .iter()
+                .find(|m| m.kind == "passkey")
+                .map(|m| m.id.clone())
+                .unwrap_or_default();
+            let recovery_public_keys = methods
+                .into_iter()
+                .filter_map(|m| m.recovery_public_key)
+                .collect();
+            (credential_id, recovery_public_keys)
+        }
+        _ => return Err(AppError::BadRequest("unsupported backup version".into())),
+    };
+    if (body.version == 1 && credential_id.is_empty())
+        || credential_id.len() > MAX_CREDENTIAL_ID_B64_SIZE
     {
         return Err(AppError::BadRequest("invalid credential id size".into()));
     }
+    if recovery_public_keys.len() > MAX_BACKUP_METHODS {
+        return Err(AppError::BadRequest("too many recovery public keys".into()));
+    }
+    for public_key in &recovery_public_keys {
+        parse_recovery_public_key(public_key)?;
+    }
 
     db::backups::upsert_secret_key_backup(
         &state.pool,
         user_id.as_str(),
         &body.armor,
         body.version,
-        &body.webauthn_credential_id_b64,
+        &credential_id,
     )
     .await?;
+    db::backups::replace_recovery_keys(&state.pool, user_id.as_str(), &recovery_public_keys)
+        .await?;
 
     Ok((
         StatusCode::OK,
@@ -93,23 +332,87 @@ async fn put_secret_key_backup(
     ))
 }
 
+/// 認証済みの本人にバックアップを返す。
 async fn get_secret_key_backup(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<SecretKeyBackupResponse>, AppError> {
+    let user_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    let row = db::backups::get_secret_key_backup(&state.pool, user_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("secret key backup not found".into()))?;
+
+    notify_retrieval(&state, &user_id, "authenticated");
+    Ok(Json(backup_response(row)))
+}
+
+/// 端末を失ったユーザのために、回復用公開鍵で署名するチャレンジを発行する。
+async fn create_retrieval_challenge(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = resolve_backup_user_id(&id, &state.config.server_hostname)?;
+    let row = db::backups::get_secret_key_backup(&state.pool, user_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("secret key backup not found".into()))?;
+
+    let now = chrono::Utc::now();
+    let challenge = crate::auth::session::generate_token();
+    let expires_at = now + chrono::Duration::seconds(RETRIEVAL_CHALLENGE_TTL_SECS);
+    db::backups::create_challenge(&state.pool, &challenge, user_id.as_str(), expires_at).await?;
+
+    // どのパスキーで署名すればよいかクライアントが判断できるよう解除方法の種類とIDのみ返す
+    let methods = method_summaries(&row);
+    Ok(Json(serde_json::json!({
+        "challenge": challenge,
+        "expires_at": expires_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "version": row.version,
+        "webauthn_credential_id_b64": row.webauthn_credential_id_b64,
+        "methods": methods,
+    })))
+}
+
+/// 署名済みチャレンジと引き換えにバックアップを返す。
+async fn retrieve_secret_key_backup(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<RetrieveSecretKeyBackupBody>,
 ) -> Result<Json<SecretKeyBackupResponse>, AppError> {
     let user_id = resolve_backup_user_id(&id, &state.config.server_hostname)?;
 
+    // 所持証明を確認してからチャレンジを消費する
+    let verified =
+        if db::backups::has_recovery_key(&state.pool, user_id.as_str(), &body.recovery_public_key)
+            .await?
+        {
+            verify_retrieval_signature(&user_id, &body)
+        } else {
+            Err(AppError::Unauthorized(
+                "recovery public key is not registered".into(),
+            ))
+        };
+    let failures = if verified.is_err() {
+        // 失敗したチャレンジは使用済みにして集計に含める
+        db::backups::record_failed_attempt(&state.pool, &body.challenge, user_id.as_str()).await?;
+        recent_failed_retrievals(&state, &user_id).await?
+    } else {
+        0
+    };
+    retrieval_decision(verified, failures)?;
+    if !db::backups::consume_challenge(&state.pool, &body.challenge, user_id.as_str()).await? {
+        return Err(AppError::Unauthorized(
+            "invalid or expired challenge".into(),
+        ));
+    }
+
     let row = db::backups::get_secret_key_backup(&state.pool, user_id.as_str())
         .await?
         .ok_or_else(|| AppError::NotFound("secret key backup not found".into()))?;
 
-    Ok(Json(SecretKeyBackupResponse {
-        armor: row.armor,
-        version: row.version,
-        webauthn_credential_id_b64: row.webauthn_credential_id_b64,
-        created_at: row.created_at,
-        updated_at: row.updated_at,
-    }))
+    notify_retrieval(&state, &user_id, "recovery_key");
+    Ok(Json(backup_response(row)))
 }
 
 async fn delete_secret_key_backup(
@@ -125,3 +428,44 @@ async fn delete_secret_key_backup(
     }
     Ok(Json(serde_json::json!({ "deleted": true })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn failed_attempts_do_not_lock_out_owner() {
+        let user_id = UserId("alice@example.com".into());
+        let owner_key = SigningKey::from_bytes(&[7; 32]);
+        let challenge = "challenge".to_string();
+        let signed = |key: &SigningKey| RetrieveSecretKeyBackupBody {
+            challenge: challenge.clone(),
+            recovery_public_key: STANDARD.encode(owner_key.verifying_key().as_bytes()),
+            signature: URL_SAFE_NO_PAD.encode(
+                key.sign(retrieval_message(&user_id, &challenge).as_bytes())
+                    .to_bytes(),
+            ),
+        };
+
+        // 第三者が登録済みの公開鍵と不正な署名で失敗回数を上限まで増やしても
+        let forged = signed(&SigningKey::from_bytes(&[9; 32]));
+        let failures = MAX_FAILED_RETRIEVALS_PER_HOUR + 10;
+        assert!(matches!(
+            retrieval_decision(verify_retrieval_signature(&user_id, &forged), failures),
+            Err(AppError::TooManyRequests(_))
+        ));
+
+        // 所有者の正しい署名は受け付ける
+        let genuine = signed(&owner_key);
+        assert!(
+            retrieval_decision(verify_retrieval_signature(&user_id, &genuine), failures).is_ok()
+        );
+
+        // 上限未満の失敗は理由を返す
+        assert!(matches!(
+            retrieval_decision(verify_retrieval_signature(&user_id, &forged), 0),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+}
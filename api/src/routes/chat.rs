@@ -44,6 +44,11 @@ async fn create_chat(
         })
         .collect::<Result<_, _>>()?;
 
+    // 連合ポリシーで許可されていないサーバのユーザはメンバーにできない
+    for id in &resolved_member_ids {
+        crate::federation::policy::ensure_user_permitted(&state, &UserId(id.clone())).await?;
+    }
+
     db::chat::create_chat_group(
         &state.pool,
         &chat_id,
@@ -70,18 +75,33 @@ async fn create_chat(
         });
     if !external_domains.is_empty() {
         let allow_http = state.config.federation_allow_http;
-        let auth_header = auth.raw_auth_header.clone();
+        let http = state.http.clone();
+        let authorizations = external_domains
+            .keys()
+            .map(|domain| {
+                crate::federation::delegation::authorization(
+                    &state,
+                    &auth,
+                    domain,
+                    Some(&chat_id),
+                    "POST",
+                    "/v1/federation/chat",
+                )
+                .map(|authorization| (domain.clone(), authorization))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
         let sync_chat_id = chat_id.as_str().to_string();
         let sync_name = body.name.clone();
         let all_member_ids = resolved_member_ids.clone();
         tokio::spawn(async move {
-            for domain in external_domains.keys() {
+            for (domain, authorization) in &authorizations {
                 if let Err(e) = crate::federation::client::sync_chat_to_remote(
+                    &http,
                     domain,
                     &sync_chat_id,
                     &sync_name,
                     &all_member_ids,
-                    &auth_header,
+                    authorization,
                     allow_http,
                 )
                 .await
@@ -221,25 +241,32 @@ async fn get_chat(
     if let Some(ref server_domain) = group.server_domain {
         let base =
             crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
-        let url = format!("{base}/v1/chat/{}", chat_id.as_str());
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&url)
-            .header("Authorization", &auth.raw_auth_header)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let path = format!("/v1/chat/{}", chat_id.as_str());
+        let authorization = crate::federation::delegation::authorization(
+            &state,
+            &auth,
+            server_domain,
+            Some(&chat_id),
+            "GET",
+            &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .get(format!("{base}{path}"))
+                    .header("Authorization", authorization),
+            )
+            .await?;
         if !resp.status().is_success() {
             let status = resp.status();
-            let resp_body = resp.text().await.unwrap_or_default();
+            let resp_body = state.http.error_text(resp).await;
             return Err(AppError::BadGateway(format!(
                 "home server returned {status}: {resp_body}"
             )));
         }
-        let mut body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let mut body: serde_json::Value = state.http.json(resp).await?;
 
         // ホームサーバのローカルユーザIDにドメインを付与して、
         // リモート側のフロントエンドが鍵を正しく取得できるようにする
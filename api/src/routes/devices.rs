@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use serde::Deserialize;
+use xrypton_common::keys::PublicKeys;
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::error::AppError;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/user/devices", get(list_devices).post(add_device))
+        .route("/user/devices/{fingerprint}", delete(revoke_device))
+}
+
+/// 署名サブキー（デバイス鍵）の一覧を返す。
+///
+/// 鍵に含まれる主鍵で認証された署名サブキーを、登録時の名前とともに返す。
+async fn list_devices(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let public_keys = PublicKeys::try_from(auth.signing_public_key.as_str())
+        .map_err(|e| AppError::Internal(format!("invalid stored signing key: {e}")))?;
+    let mut rows: HashMap<String, db::models::UserDeviceRow> =
+        db::devices::list_devices(&state.pool, auth.user_id.as_str())
+            .await?
+            .into_iter()
+            .map(|row| (row.subkey_fingerprint.clone(), row))
+            .collect();
+
+    let devices: Vec<serde_json::Value> = public_keys
+        .signing_sub_keys()
+        .into_iter()
+        .map(|subkey| {
+            let row = rows.remove(&subkey.fingerprint);
+            serde_json::json!({
+                "fingerprint": subkey.fingerprint,
+                "key_id": subkey.key_id,
+                "name": row.as_ref().and_then(|r| r.name.clone()),
+                "created_at": row.as_ref().map(|r| r.created_at.clone()),
+                "revoked": subkey.revoked || row.as_ref().is_some_and(|r| r.revoked_at.is_some()),
+                "expired": subkey.expired,
+                "expires_at": subkey.expires_at,
+                "current": auth.signing_key_fingerprint.as_deref() == Some(subkey.fingerprint.as_str()),
+            })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({ "devices": devices })))
+}
+
+#[derive(Deserialize)]
+struct DeviceKeyBody {
+    /// デバイス鍵を追加・失効させた後の署名用公開鍵
+    signing_public_key: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// 更新後の鍵を検証し、現在の鍵と新しい鍵の有効なデバイス鍵を返す。
+///
+/// 主鍵が変わっていないこと、既存のデバイス鍵が黙って削除されていないことを確認する。
+/// 失効させるデバイス鍵は削除ではなく、主鍵によるサブキー失効署名を含めて残す必要がある。
+fn diff_device_keys(
+    current: &str,
+    updated: &str,
+    revoking: Option<&str>,
+) -> Result<(Vec<String>, Vec<String>), AppError> {
+    let current = PublicKeys::try_from(current)
+        .map_err(|e| AppError::Internal(format!("invalid stored signing key: {e}")))?;
+    let updated = PublicKeys::try_from(updated)
+        .map_err(|e| AppError::BadRequest(format!("invalid signing public key: {e}")))?;
+    updated
+        .validate()
+        .map_err(|e| AppError::BadRequest(format!("unusable signing public key: {e}")))?;
+    if current.get_primary_fingerprint() != updated.get_primary_fingerprint() {
+        return Err(AppError::BadRequest(
+            "device keys must be certified by the current primary key".into(),
+        ));
+    }
+
+    let before = current.get_signing_sub_key_fingerprints();
+    let after = updated.get_signing_sub_key_fingerprints();
+    if before
+        .iter()
+        .any(|fp| Some(fp.as_str()) != revoking && !after.contains(fp))
+    {
+        return Err(AppError::BadRequest(
+            "existing device keys must be kept or explicitly revoked".into(),
+        ));
+    }
+    if let Some(revoking) = revoking.filter(|fp| before.iter().any(|b| b == fp)) {
+        // 削除しただけの鍵では連合先やキャッシュに失効が伝わらない
+        let revoked = updated
+            .signing_sub_keys()
+            .iter()
+            .any(|k| k.fingerprint == revoking && k.revoked);
+        if !revoked {
+            return Err(AppError::BadRequest(
+                "signing public key must keep the device key with a revocation signature".into(),
+            ));
+        }
+    }
+    Ok((before, after))
+}
+
+/// 主鍵で認証された署名サブキーを追加した鍵を受け取り、新しいデバイスとして登録する。
+async fn add_device(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+    Json(body): Json<DeviceKeyBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user = local_user(&state, &auth).await?;
+    let (before, after) =
+        diff_device_keys(&user.signing_public_key, &body.signing_public_key, None)?;
+    let added: Vec<String> = after
+        .into_iter()
+        .filter(|fp| !before.contains(fp))
+        .collect();
+    let [fingerprint] = added.as_slice() else {
+        return Err(AppError::BadRequest(
+            "exactly one new device key must be added".into(),
+        ));
+    };
+
+    update_signing_key(&state, &user, &body.signing_public_key).await?;
+    db::devices::upsert_device(
+        &state.pool,
+        auth.user_id.as_str(),
+        fingerprint,
+        body.name.as_deref(),
+    )
+    .await?;
+
+    // 心当たりのないデバイス追加に気付けるよう所有者の全デバイスに通知する
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let owner = vec![auth.user_id.clone()];
+    let payload = serde_json::json!({
+        "type": "device_added",
+        "fingerprint": fingerprint,
+        "name": body.name,
+    });
+    tokio::spawn(async move {
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &owner, &payload).await {
+            tracing::warn!("device added push failed: {e}");
+        }
+    });
+
+    Ok(Json(serde_json::json!({ "fingerprint": fingerprint })))
+}
+
+/// デバイスを失効させる。
+///
+/// 主鍵によるサブキー失効署名を含む鍵を受け取り、連合先にも失効が伝わるようにする。
+async fn revoke_device(
+    State(state): State<AppState>,
+    Path(fingerprint): Path<String>,
+    auth: AuthenticatedUser,
+    Json(body): Json<DeviceKeyBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let fingerprint = fingerprint.to_ascii_uppercase();
+    let user = local_user(&state, &auth).await?;
+    let (before, after) = diff_device_keys(
+        &user.signing_public_key,
+        &body.signing_public_key,
+        Some(&fingerprint),
+    )?;
+    if !before.contains(&fingerprint) {
+        return Err(AppError::NotFound("device not found".into()));
+    }
+    if after.is_empty() {
+        return Err(AppError::BadRequest("cannot revoke the last device".into()));
+    }
+
+    update_signing_key(&state, &user, &body.signing_public_key).await?;
+    db::devices::revoke_device(&state.pool, auth.user_id.as_str(), &fingerprint).await?;
+    db::sessions::revoke_key_sessions(&state.pool, auth.user_id.as_str(), &fingerprint).await?;
+
+    Ok(Json(
+        serde_json::json!({ "fingerprint": fingerprint, "revoked": true }),
+    ))
+}
+
+/// デバイスを管理できるのは自サーバのユーザ本人のみ。
+async fn local_user(
+    state: &AppState,
+    auth: &AuthenticatedUser,
+) -> Result<db::models::UserRow, AppError> {
+    if auth.delegated_by.is_some() || !auth.user_id.is_local(&state.config.server_hostname) {
+        return Err(AppError::Forbidden(
+            "devices can only be managed on the home server".into(),
+        ));
+    }
+    db::users::get_user(&state.pool, &auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))
+}
+
+/// 暗号化鍵はそのままに署名用公開鍵を更新し、鍵履歴に記録する。
+async fn update_signing_key(
+    state: &AppState,
+    user: &db::models::UserRow,
+    signing_public_key: &str,
+) -> Result<(), AppError> {
+    let user_id = crate::types::UserId(user.id.clone());
+    db::users::update_user_keys(
+        &state.pool,
+        &user_id,
+        &user.encryption_public_key,
+        signing_public_key,
+        &user.primary_key_fingerprint,
+    )
+    .await?;
+    crate::federation::key_change::record_keys(
+        state,
+        &user_id,
+        Some(user),
+        &user.encryption_public_key,
+        signing_public_key,
+        &user.primary_key_fingerprint,
+    )
+    .await
+}
@@ -1,5 +1,5 @@
 use axum::extract::State;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::{Json, Router};
 use serde::Deserialize;
 
@@ -7,12 +7,27 @@ use crate::AppState;
 use crate::auth::AuthenticatedUser;
 use crate::db;
 use crate::error::AppError;
+use crate::federation::dns::ResolvedDomain;
+use crate::federation::policy::PolicyMode;
 use crate::types::{ChatId, UserId};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/federation/notify", post(receive_notify))
         .route("/federation/chat", post(receive_chat_sync))
+        .route("/federation/tombstone", post(receive_tombstone))
+        .route("/federation/moved", post(receive_moved))
+        .route("/federation/key-revocation", post(receive_key_revocation))
+        .route("/federation/server-key", get(get_server_key))
+}
+
+/// 委任ステートメントの検証に使うサーバ公開鍵を返す。
+async fn get_server_key(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "domain": state.config.server_hostname,
+        "algorithm": "ed25519",
+        "public_key": state.server_key.public_key(),
+    }))
 }
 
 #[derive(Deserialize)]
@@ -24,10 +39,14 @@ struct NotifyBody {
 /// 外部サーバからのPush通知転送リクエストを受け付ける。
 /// 指定されたローカルユーザにPush通知を送信する。
 /// ペイロードはメタデータのみで実データは含まないため、認証不要。
+/// 送信元を認証できないため、対象チャットのホームサーバで連合ポリシーを判定する。
+/// チャットを伴わない通知は、ペイロードに含まれる送信元ユーザのホームサーバで判定する。
 async fn receive_notify(
     State(state): State<AppState>,
     Json(body): Json<NotifyBody>,
 ) -> Result<Json<serde_json::Value>, AppError> {
+    ensure_notify_permitted(&state, &body.payload).await?;
+
     let user_ids: Vec<UserId> = body.user_ids.into_iter().map(UserId).collect();
 
     let pool = state.pool.clone();
@@ -43,6 +62,38 @@ async fn receive_notify(
     Ok(Json(serde_json::json!({ "ok": true })))
 }
 
+/// 通知ペイロードの送信元が連合ポリシーで許可されているか検証する。
+async fn ensure_notify_permitted(
+    state: &AppState,
+    payload: &serde_json::Value,
+) -> Result<(), AppError> {
+    if let Some(chat_id) = payload.get("chat_id").and_then(|v| v.as_str()) {
+        let chat = db::chat::get_chat_group(&state.pool, &ChatId(chat_id.to_string())).await?;
+        if let Some(server_domain) = chat.and_then(|c| c.server_domain) {
+            if !state.federation_policy.permits(&server_domain).await {
+                return Err(AppError::Forbidden(format!(
+                    "federation with {server_domain} is not allowed"
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    // 送信元はホームサーバを判定できるようドメイン付きのIDに限る
+    let origin = ["sender_id", "owner_id"]
+        .iter()
+        .find_map(|key| payload.get(key).and_then(|v| v.as_str()))
+        .map(|id| UserId(id.to_string()))
+        .filter(|id| id.domain().is_some());
+    match origin {
+        Some(origin) => crate::federation::policy::ensure_user_permitted(state, &origin).await,
+        None if state.federation_policy.get().await.mode == PolicyMode::Open => Ok(()),
+        None => Err(AppError::Forbidden(
+            "notification without a chat or sender is not allowed".into(),
+        )),
+    }
+}
+
 #[derive(Deserialize)]
 struct ChatSyncBody {
     chat_id: String,
@@ -58,12 +109,22 @@ async fn receive_chat_sync(
     auth: AuthenticatedUser,
     Json(body): Json<ChatSyncBody>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    // 送信元のドメインを特定（外部ユーザのドメイン部分がホームサーバ）
-    let server_domain = auth
-        .user_id
-        .domain()
-        .ok_or_else(|| AppError::BadRequest("chat sync requires external user with domain".into()))?
-        .to_string();
+    // 送信元のドメインを特定（委任の発行元、なければ外部ユーザのドメイン部分がホームサーバ）
+    let server_domain = match auth.delegated_by.clone() {
+        Some(issuer) => issuer,
+        None => auth
+            .user_id
+            .domain()
+            .ok_or_else(|| {
+                AppError::BadRequest("chat sync requires external user with domain".into())
+            })?
+            .to_string(),
+    };
+    if !state.federation_policy.permits(&server_domain).await {
+        return Err(AppError::Forbidden(format!(
+            "federation with {server_domain} is not allowed"
+        )));
+    }
 
     let chat_id = ChatId(body.chat_id);
 
@@ -120,3 +181,130 @@ async fn receive_chat_sync(
 
     Ok(Json(serde_json::json!({ "ok": true })))
 }
+
+#[derive(Deserialize)]
+struct TombstoneBody {
+    user_id: String,
+}
+
+/// 外部サーバからのアカウント削除通知を受け付ける。
+/// 通知自体は信用せず、ホームサーバへの鍵取得が `410 Gone` を返す場合のみ
+/// キャッシュ済みの鍵とチャットメンバーシップを破棄する。
+async fn receive_tombstone(
+    State(state): State<AppState>,
+    Json(body): Json<TombstoneBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = UserId::validate_full(&body.user_id)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    let (local_part, domain) = body
+        .user_id
+        .split_once('@')
+        .ok_or_else(|| AppError::BadRequest("user ID has no domain".into()))?;
+    if domain == state.config.server_hostname {
+        return Err(AppError::BadRequest("cannot tombstone a local user".into()));
+    }
+
+    // 未キャッシュのユーザについては何もしない（任意ユーザの鍵取得を誘発させない）
+    let Some(cached) = db::users::get_user(&state.pool, &user_id).await? else {
+        return Ok(Json(serde_json::json!({ "ok": true })));
+    };
+
+    let home = match state.dns_resolver.resolve(domain, local_part).await {
+        ResolvedDomain::Mapped { domain, .. } => domain,
+        ResolvedDomain::Original => domain.to_string(),
+    };
+    if home == state.config.server_hostname {
+        return Err(AppError::BadRequest("cannot tombstone a local user".into()));
+    }
+
+    match crate::federation::client::fetch_user_keys(
+        &state.http,
+        &home,
+        user_id.as_str(),
+        state.config.federation_allow_http,
+    )
+    .await
+    {
+        Err(AppError::Gone(_)) => {}
+        Ok(_) => return Err(AppError::Conflict("user has not been deleted".into())),
+        Err(e) => return Err(e),
+    }
+
+    let members = db::chat::get_co_member_ids(&state.pool, &user_id).await?;
+    db::users::purge_external_user(
+        &state.pool,
+        &user_id,
+        Some(cached.primary_key_fingerprint.as_str()),
+    )
+    .await?;
+    tracing::info!(user_id = user_id.as_str(), "purged deleted external user");
+
+    crate::federation::tombstone::notify_members(&state, &user_id, members);
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+#[derive(Deserialize)]
+struct KeyRevocationBody {
+    user_id: String,
+    certificate: String,
+}
+
+/// 外部サーバからの鍵失効通知を受け付ける。
+/// 失効証明書をキャッシュ済みの鍵の主鍵で検証し、鍵に取り込む。
+async fn receive_key_revocation(
+    State(state): State<AppState>,
+    Json(body): Json<KeyRevocationBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = UserId::validate_full(&body.user_id)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    if user_id.domain() == Some(state.config.server_hostname.as_str()) {
+        return Err(AppError::BadRequest(
+            "cannot revoke a local user's key".into(),
+        ));
+    }
+
+    // 未キャッシュのユーザについては何もしない
+    let Some(cached) = db::users::get_user(&state.pool, &user_id).await? else {
+        return Ok(Json(serde_json::json!({ "ok": true })));
+    };
+    crate::federation::revocation::apply(&state, &cached, &body.certificate).await?;
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+#[derive(Deserialize)]
+struct MovedBody {
+    user_id: String,
+    statement: String,
+}
+
+/// 外部サーバからのアカウント移行通知を受け付ける。
+/// キャッシュ済みの鍵で移行宣言を検証し、移行先サーバの鍵と一致する場合のみ
+/// キャッシュ済みのユーザIDとチャットメンバーシップを書き換える。
+async fn receive_moved(
+    State(state): State<AppState>,
+    Json(body): Json<MovedBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = UserId::validate_full(&body.user_id)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    if user_id.domain() == Some(state.config.server_hostname.as_str()) {
+        return Err(AppError::BadRequest("cannot move a local user".into()));
+    }
+
+    // 未キャッシュのユーザについては何もしない
+    let Some(cached) = db::users::get_user(&state.pool, &user_id).await? else {
+        return Ok(Json(serde_json::json!({ "ok": true })));
+    };
+
+    let moved =
+        crate::federation::moved::verify_statement(&body.statement, &cached.signing_public_key)?;
+    if moved.from != user_id.as_str() {
+        return Err(AppError::BadRequest(
+            "moved statement source does not match user".into(),
+        ));
+    }
+    crate::federation::moved::follow(&state, &user_id, &moved.to, &body.statement).await?;
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
@@ -15,12 +15,19 @@ use crate::types::{ChatId, FileId, MessageId, ThreadId};
 const MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
 
 pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route(
-            "/chat/{chat_id}/{thread_id}/file",
-            axum::routing::post(upload_file).layer(DefaultBodyLimit::max(15 * 1024 * 1024)),
-        )
-        .route("/file/{file_id}", get(download_file))
+    Router::new().route("/file/{file_id}", get(download_file))
+}
+
+/// アップロード用ルート。本文ダイジェストの検証はこのルートの上限で行う。
+pub fn upload_routes() -> Router<AppState> {
+    Router::new().route(
+        "/chat/{chat_id}/{thread_id}/file",
+        axum::routing::post(upload_file)
+            .layer(axum::middleware::from_fn(
+                crate::auth::request::verify_content_digest,
+            ))
+            .layer(DefaultBodyLimit::max(15 * 1024 * 1024)),
+    )
 }
 
 /// ファイルアップロード（multipart: metadata + file）
@@ -43,11 +50,8 @@ async fn upload_file(
     {
         let base =
             crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
-        let url = format!(
-            "{base}/v1/chat/{}/{}/file",
-            chat_id.as_str(),
-            thread_id.as_str(),
-        );
+        let path = format!("/v1/chat/{}/{}/file", chat_id.as_str(), thread_id.as_str());
+        let url = format!("{base}{path}");
 
         // multipartを再構築してプロキシ
         let mut form = reqwest::multipart::Form::new();
@@ -64,19 +68,27 @@ async fn upload_file(
             form = form.part(name, reqwest::multipart::Part::bytes(data.to_vec()));
         }
 
-        let client = reqwest::Client::new();
-        let resp = client
-            .post(&url)
-            .header("Authorization", &auth.raw_auth_header)
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        // 委任の有効期間は短いため、クライアントからの受信後に発行する
+        let authorization = crate::federation::delegation::authorization(
+            &state,
+            &auth,
+            server_domain,
+            Some(&chat_id),
+            "POST",
+            &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .post(&url)
+                    .header("Authorization", authorization)
+                    .multipart(form),
+            )
+            .await?;
         let status = resp.status();
-        let resp_body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let resp_body: serde_json::Value = state.http.json(resp).await?;
         if !status.is_success() {
             return Err(AppError::BadGateway(format!(
                 "home server returned {status}: {resp_body}"
@@ -90,16 +102,19 @@ async fn upload_file(
         let content_public_keys =
             xrypton_common::keys::PublicKeys::try_from(auth.signing_public_key.as_str())
                 .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
-        let content_fingerprint = xrypton_common::keys::extract_issuer_fingerprint(content)
+        // いずれかの有効なデバイス鍵で署名されていればよい
+        let (_, report) = content_public_keys
+            .verify_with_report(content)
             .map_err(|e| AppError::BadRequest(format!("invalid message format: {e}")))?;
-        let expected_fingerprint = content_public_keys
-            .get_signing_sub_key_fingerprint()
-            .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
-        if content_fingerprint != expected_fingerprint {
-            return Err(AppError::BadRequest("content signer mismatch".into()));
+        if let xrypton_common::keys::VerificationOutcome::SignerUnusable(e)
+        | xrypton_common::keys::VerificationOutcome::VerifiedHistorical(e) = &report.outcome
+        {
+            return Err(AppError::BadRequest(format!(
+                "content signer mismatch: {e}"
+            )));
         }
-        content_public_keys
-            .verify_and_extract(content)
+        report
+            .ensure_verified()
             .map_err(|e| AppError::BadRequest(format!("content signature invalid: {e}")))?;
         Ok(())
     };
@@ -179,6 +194,7 @@ async fn upload_file(
     // 外部メンバーへのPush通知転送
     let members = db::chat::get_chat_members(&state.pool, &chat_id).await?;
     let allow_http = state.config.federation_allow_http;
+    let http = state.http.clone();
     let fwd_chat_id = chat_id.as_str().to_string();
     let fwd_thread_id = thread_id.as_str().to_string();
     let fwd_message_id = message_id.as_str().to_string();
@@ -202,9 +218,10 @@ async fn upload_file(
             "message_id": fwd_message_id,
         });
         for (domain, user_ids) in &domains {
-            if let Err(e) =
-                crate::federation::client::forward_push(domain, user_ids, &payload, allow_http)
-                    .await
+            if let Err(e) = crate::federation::client::forward_push(
+                &http, domain, user_ids, &payload, allow_http,
+            )
+            .await
             {
                 tracing::warn!("federation push to {domain} failed: {e}");
             }
@@ -11,7 +11,7 @@ use sha2::{Digest, Sha256};
 use crate::AppState;
 use crate::auth::AuthenticatedUser;
 use crate::db;
-use crate::db::models::WotSignatureRow;
+use crate::db::models::{KeyTransitionRow, WotSignatureRow};
 use crate::db::nonces::NonceType;
 use crate::db::wot::EdgeDirection;
 use crate::error::AppError;
@@ -29,6 +29,10 @@ const TIME_BUDGET_MS: u64 = 1200;
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/keys/{fingerprint}", axum::routing::get(get_key))
+        .route(
+            "/keys/{fingerprint}/revocation",
+            axum::routing::post(post_revocation),
+        )
         .route(
             "/keys/{fingerprint}/signature",
             axum::routing::post(post_signature),
@@ -39,13 +43,9 @@ pub fn routes() -> Router<AppState> {
         )
 }
 
+/// v4（40文字）または v6（64文字）の大文字16進フィンガープリントか検証する。
 fn validate_fingerprint(fingerprint: &str) -> Result<(), AppError> {
-    let valid = (40..=128).contains(&fingerprint.len())
-        && fingerprint.len().is_multiple_of(2)
-        && fingerprint
-            .bytes()
-            .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_lowercase());
-    if valid {
+    if xrypton_common::keys::fingerprint_version(fingerprint).is_some() {
         Ok(())
     } else {
         Err(AppError::BadRequest("invalid fingerprint format".into()))
@@ -58,6 +58,10 @@ struct GetKeyResponse {
     armored_public_key: String,
     user_id: String,
     revoked: bool,
+    /// 鍵移行により置き換えられている場合の最新の指紋
+    superseded_by: Option<String>,
+    /// この鍵から最新の鍵までの移行宣言チェーン
+    transitions: Vec<KeyTransitionRow>,
     fetched_at: String,
 }
 
@@ -66,19 +70,88 @@ async fn get_key(
     Path(fingerprint): Path<String>,
 ) -> Result<Json<GetKeyResponse>, AppError> {
     validate_fingerprint(&fingerprint)?;
-    let user = db::users::get_user_by_fingerprint(&state.pool, &fingerprint)
-        .await?
-        .ok_or_else(|| AppError::NotFound("key not found".into()))?;
+    let (user_id, armored_public_key) =
+        match db::users::get_user_by_fingerprint(&state.pool, &fingerprint).await? {
+            Some(user) => (user.id, user.signing_public_key),
+            // 移行済みの旧鍵は鍵履歴から返す
+            None => db::key_history::get_key_by_fingerprint(&state.pool, &fingerprint)
+                .await?
+                .map(|row| (row.user_id, row.signing_public_key))
+                .ok_or_else(|| AppError::NotFound("key not found".into()))?,
+        };
+    let transitions = crate::federation::key_transition::chain_from(&state, &fingerprint).await?;
+    let revoked = db::key_revocations::is_revoked(&state.pool, &fingerprint).await?
+        || xrypton_common::keys::PublicKeys::try_from(armored_public_key.as_str())
+            .is_ok_and(|keys| keys.is_revoked());
 
     Ok(Json(GetKeyResponse {
-        fingerprint: user.primary_key_fingerprint,
-        armored_public_key: user.signing_public_key,
-        user_id: user.id,
-        revoked: false,
+        fingerprint,
+        armored_public_key,
+        user_id,
+        revoked,
+        superseded_by: transitions.last().map(|t| t.new_fingerprint.clone()),
+        transitions,
         fetched_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
     }))
 }
 
+#[derive(Deserialize)]
+struct PostRevocationBody {
+    /// armored 形式の鍵失効証明書
+    certificate: String,
+}
+
+/// 鍵失効証明書を受け付ける（認証不要）。
+///
+/// 鍵を紛失した場合でも失効できるよう、失効証明書自体の主鍵署名のみで検証する。
+/// 自サーバのユーザの鍵であれば、鍵をキャッシュしている連合先にも通知する。
+async fn post_revocation(
+    State(state): State<AppState>,
+    Path(fingerprint): Path<String>,
+    Json(body): Json<PostRevocationBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    validate_fingerprint(&fingerprint)?;
+    if body.certificate.len() > SIGNATURE_MAX_BYTES {
+        return Err(AppError::PayloadTooLarge("certificate too large".into()));
+    }
+
+    let Some(user) = db::users::get_user_by_fingerprint(&state.pool, &fingerprint).await? else {
+        // 移行済みの旧鍵は履歴上の鍵で検証して記録のみ行う
+        let history = db::key_history::get_key_by_fingerprint(&state.pool, &fingerprint)
+            .await?
+            .ok_or_else(|| AppError::NotFound("key not found".into()))?;
+        xrypton_common::keys::PublicKeys::try_from(history.signing_public_key.as_str())
+            .map_err(|e| AppError::Internal(format!("invalid stored signing key: {e}")))?
+            .verify_revocation_certificate(&body.certificate)
+            .map_err(|e| AppError::BadRequest(format!("invalid revocation certificate: {e}")))?;
+        db::key_revocations::insert_revocation(
+            &state.pool,
+            &fingerprint,
+            &history.user_id,
+            &body.certificate,
+        )
+        .await?;
+        return Ok(Json(
+            serde_json::json!({ "fingerprint": fingerprint, "revoked": true }),
+        ));
+    };
+
+    let user_id = crate::types::UserId(user.id.clone());
+    // メンバーシップから通知先を確定してから失効を反映する
+    let peer_domains = if user_id.is_local(&state.config.server_hostname) {
+        crate::federation::tombstone::peer_domains(&state, &user_id).await?
+    } else {
+        Default::default()
+    };
+    if crate::federation::revocation::apply(&state, &user, &body.certificate).await? {
+        crate::federation::revocation::announce(&state, &user_id, &body.certificate, peer_domains);
+    }
+
+    Ok(Json(
+        serde_json::json!({ "fingerprint": fingerprint, "revoked": true }),
+    ))
+}
+
 #[derive(Deserialize)]
 struct NoncePayload {
     random: String,
@@ -289,7 +362,7 @@ async fn proxy_get_signatures(
     domain: &str,
     fingerprint: &str,
     query: &SignatureQuery,
-    auth_header: &str,
+    auth: &AuthenticatedUser,
 ) -> Result<Json<SignatureGraphResponse>, AppError> {
     let base = crate::federation::client::base_url(domain, state.config.federation_allow_http);
     let mut params = Vec::new();
@@ -310,27 +383,33 @@ async fn proxy_get_signatures(
     } else {
         format!("?{}", params.join("&"))
     };
-    let url = format!(
-        "{base}/v1/keys/{}/signatures{qs}",
+    let path = format!(
+        "/v1/keys/{}/signatures{qs}",
         urlencoding::encode(fingerprint),
     );
-
-    let resp = reqwest::Client::new()
-        .get(&url)
-        .header("Authorization", auth_header)
-        .send()
-        .await
-        .map_err(|e| AppError::BadGateway(format!("federation signatures proxy failed: {e}")))?;
+    let authorization =
+        crate::federation::delegation::authorization(state, auth, domain, None, "GET", &path)?;
+
+    let resp = state
+        .http
+        .send(
+            state
+                .http
+                .get(format!("{base}{path}"))
+                .header("Authorization", authorization),
+        )
+        .await?;
     if !resp.status().is_success() {
         let status = resp.status();
-        let body = resp.text().await.unwrap_or_default();
+        let body = state.http.error_text(resp).await;
         return Err(AppError::BadGateway(format!(
             "federation signatures proxy returned {status}: {body}"
         )));
     }
-    resp.json::<SignatureGraphResponse>()
+    state
+        .http
+        .json::<SignatureGraphResponse>(resp)
         .await
-        .map_err(|e| AppError::BadGateway(format!("invalid federation signatures response: {e}")))
         .map(Json)
 }
 
@@ -350,8 +429,7 @@ async fn get_signatures(
     if let Some((_local, domain)) = user.id.split_once('@')
         && domain != state.config.server_hostname
     {
-        return proxy_get_signatures(&state, domain, &fingerprint, &query, &auth.raw_auth_header)
-            .await;
+        return proxy_get_signatures(&state, domain, &fingerprint, &query, &auth).await;
     }
 
     let direction = match query.direction.as_deref().unwrap_or("inbound") {
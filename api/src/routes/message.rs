@@ -58,31 +58,38 @@ async fn get_messages(
     {
         let base =
             crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
-        let url = format!(
-            "{base}/v1/chat/{}/{}/message?from={}&until={}",
+        let path = format!(
+            "/v1/chat/{}/{}/message?from={}&until={}",
             chat_id.as_str(),
             thread_id.as_str(),
             query.from,
             query.until
         );
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&url)
-            .header("Authorization", &auth.raw_auth_header)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let authorization = crate::federation::delegation::authorization(
+            &state,
+            &auth,
+            server_domain,
+            Some(&chat_id),
+            "GET",
+            &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .get(format!("{base}{path}"))
+                    .header("Authorization", authorization),
+            )
+            .await?;
         if !resp.status().is_success() {
             let status = resp.status();
-            let resp_body = resp.text().await.unwrap_or_default();
+            let resp_body = state.http.error_text(resp).await;
             return Err(AppError::BadGateway(format!(
                 "home server returned {status}: {resp_body}"
             )));
         }
-        let mut body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let mut body: serde_json::Value = state.http.json(resp).await?;
 
         // ホームサーバのローカルユーザIDにドメインを付与
         qualify_sender_ids_in_messages(&mut body, server_domain);
@@ -143,24 +150,31 @@ async fn post_message(
     {
         let base =
             crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
-        let url = format!(
-            "{base}/v1/chat/{}/{}/message",
+        let path = format!(
+            "/v1/chat/{}/{}/message",
             chat_id.as_str(),
             thread_id.as_str(),
         );
-        let client = reqwest::Client::new();
-        let resp = client
-            .post(&url)
-            .header("Authorization", &auth.raw_auth_header)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let authorization = crate::federation::delegation::authorization(
+            &state,
+            &auth,
+            server_domain,
+            Some(&chat_id),
+            "POST",
+            &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .post(format!("{base}{path}"))
+                    .header("Authorization", authorization)
+                    .json(&body),
+            )
+            .await?;
         let status = resp.status();
-        let resp_body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let resp_body: serde_json::Value = state.http.json(resp).await?;
         if !status.is_success() {
             return Err(AppError::BadGateway(format!(
                 "home server returned {status}: {resp_body}"
@@ -173,16 +187,19 @@ async fn post_message(
     let content_public_keys =
         xrypton_common::keys::PublicKeys::try_from(auth.signing_public_key.as_str())
             .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
-    let content_fingerprint = xrypton_common::keys::extract_issuer_fingerprint(&body.content)
+    // いずれかの有効なデバイス鍵で署名されていればよい
+    let (_, report) = content_public_keys
+        .verify_with_report(&body.content)
         .map_err(|e| AppError::BadRequest(format!("invalid message format: {e}")))?;
-    let expected_fingerprint = content_public_keys
-        .get_signing_sub_key_fingerprint()
-        .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
-    if content_fingerprint != expected_fingerprint {
-        return Err(AppError::BadRequest("content signer mismatch".into()));
+    if let xrypton_common::keys::VerificationOutcome::SignerUnusable(e)
+    | xrypton_common::keys::VerificationOutcome::VerifiedHistorical(e) = &report.outcome
+    {
+        return Err(AppError::BadRequest(format!(
+            "content signer mismatch: {e}"
+        )));
     }
-    content_public_keys
-        .verify_and_extract(&body.content)
+    report
+        .ensure_verified()
         .map_err(|e| AppError::BadRequest(format!("content signature invalid: {e}")))?;
 
     let message_id = MessageId::new_v4();
@@ -199,6 +216,7 @@ async fn post_message(
     // 外部メンバーへのPush通知転送
     let members = db::chat::get_chat_members(&state.pool, &chat_id).await?;
     let allow_http = state.config.federation_allow_http;
+    let http = state.http.clone();
     let fwd_chat_id = chat_id.as_str().to_string();
     let fwd_thread_id = thread_id.as_str().to_string();
     let fwd_message_id = message_id.as_str().to_string();
@@ -223,9 +241,10 @@ async fn post_message(
             "message_id": fwd_message_id,
         });
         for (domain, user_ids) in &domains {
-            if let Err(e) =
-                crate::federation::client::forward_push(domain, user_ids, &payload, allow_http)
-                    .await
+            if let Err(e) = crate::federation::client::forward_push(
+                &http, domain, user_ids, &payload, allow_http,
+            )
+            .await
             {
                 tracing::warn!("federation push to {domain} failed: {e}");
             }
@@ -282,20 +301,27 @@ async fn create_thread(
     {
         let base =
             crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
-        let url = format!("{base}/v1/chat/{}", chat_id.as_str());
-        let client = reqwest::Client::new();
-        let resp = client
-            .post(&url)
-            .header("Authorization", &auth.raw_auth_header)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let path = format!("/v1/chat/{}", chat_id.as_str());
+        let authorization = crate::federation::delegation::authorization(
+            &state,
+            &auth,
+            server_domain,
+            Some(&chat_id),
+            "POST",
+            &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .post(format!("{base}{path}"))
+                    .header("Authorization", authorization)
+                    .json(&body),
+            )
+            .await?;
         let status = resp.status();
-        let resp_body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let resp_body: serde_json::Value = state.http.json(resp).await?;
         if !status.is_success() {
             return Err(AppError::BadGateway(format!(
                 "home server returned {status}: {resp_body}"
@@ -1,13 +1,19 @@
+mod admin;
 mod atproto;
 mod backup;
 mod chat;
 mod contacts;
+mod devices;
 mod federation;
 mod file;
 mod keys;
 mod message;
 mod notification;
+mod prekeys;
 mod realtime;
+mod sender_keys;
+mod session;
+mod social_recovery;
 mod thread;
 mod user;
 mod x;
@@ -27,13 +33,25 @@ pub fn build_router(state: AppState) -> Router {
         .merge(message::thread_create_routes())
         .merge(file::routes())
         .merge(keys::routes())
+        .merge(prekeys::routes())
         .merge(contacts::routes())
         .merge(notification::routes())
         .merge(federation::routes())
         .merge(atproto::routes())
         .merge(x::routes())
         .merge(backup::routes())
-        .merge(realtime::routes());
+        .merge(realtime::routes())
+        .merge(devices::routes())
+        .merge(session::routes())
+        .merge(social_recovery::routes())
+        .merge(sender_keys::routes())
+        .merge(admin::routes())
+        .route_layer(axum::middleware::from_fn(
+            crate::auth::request::verify_content_digest,
+        ))
+        // 本文の上限が大きいルートは上限の内側で本文ダイジェストを検証する
+        .merge(file::upload_routes())
+        .merge(user::upload_routes());
 
     Router::new()
         .nest("/v1", api)
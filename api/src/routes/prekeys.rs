@@ -0,0 +1,231 @@
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::error::AppError;
+use crate::types::UserId;
+
+/// WASM 側の署名付きバンドルの `type`
+const BUNDLE_TYPE: &str = "xrypton_prekey_bundle";
+const MAX_SIGNED_BUNDLE_SIZE: usize = 16 * 1024;
+/// 1回のアップロードで追加できるワンタイムプレキーの数（WASM 側の生成上限と同じ）
+const MAX_ONE_TIME_PREKEYS_PER_UPLOAD: usize = 100;
+/// ユーザごとに保持するワンタイムプレキーの上限
+const MAX_STORED_ONE_TIME_PREKEYS: i64 = 200;
+/// 1時間に同じ要求者へ渡すワンタイムプレキーの上限
+const MAX_ONE_TIME_PREKEY_CLAIMS_PER_HOUR: i64 = 10;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/user/{id}/prekeys", get(get_bundle).put(put_bundle))
+        .route("/user/{id}/prekeys/status", get(get_status))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PublicPrekey {
+    id: u32,
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct PutBundleBody {
+    signed_bundle: String,
+    #[serde(default)]
+    one_time_prekeys: Vec<PublicPrekey>,
+}
+
+/// 署名付きバンドルの内容（サーバでは形式のみ検証する）
+#[derive(Deserialize)]
+struct BundleStatement {
+    #[serde(rename = "type")]
+    kind: String,
+    identity_key: String,
+    signed_prekey: PublicPrekey,
+}
+
+/// X25519 公開鍵（Base64 で 32 バイト）か検証する。
+fn ensure_x25519_key(key: &str) -> Result<(), AppError> {
+    match STANDARD.decode(key) {
+        Ok(bytes) if bytes.len() == 32 => Ok(()),
+        _ => Err(AppError::BadRequest("invalid prekey".into())),
+    }
+}
+
+/// プレキーを管理できるのは自サーバのユーザ本人のみ。
+fn ensure_owner(
+    path_id: &str,
+    auth: &AuthenticatedUser,
+    hostname: &str,
+) -> Result<UserId, AppError> {
+    let owner_id = UserId::resolve_local(path_id, hostname)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    if auth.delegated_by.is_some() || owner_id != auth.user_id {
+        return Err(AppError::Forbidden("can only manage own prekeys".into()));
+    }
+    Ok(owner_id)
+}
+
+/// プレキーバンドルを公開する。
+///
+/// ID 鍵と署名付きプレキーは認証済みの署名サブキーで署名されていなければならない。
+/// ワンタイムプレキーは既存のものに追加される。
+async fn put_bundle(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+    Json(body): Json<PutBundleBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    if body.signed_bundle.is_empty() || body.signed_bundle.len() > MAX_SIGNED_BUNDLE_SIZE {
+        return Err(AppError::BadRequest("invalid signed bundle size".into()));
+    }
+    if body.one_time_prekeys.len() > MAX_ONE_TIME_PREKEYS_PER_UPLOAD {
+        return Err(AppError::BadRequest(format!(
+            "at most {MAX_ONE_TIME_PREKEYS_PER_UPLOAD} one-time prekeys per upload"
+        )));
+    }
+
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(auth.signing_public_key.as_str())
+        .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
+    let statement = public_keys
+        .verify_and_extract(&body.signed_bundle)
+        .map_err(|_| AppError::BadRequest("signed bundle signature invalid".into()))?;
+    let statement: BundleStatement = serde_json::from_slice(&statement)
+        .map_err(|e| AppError::BadRequest(format!("invalid signed bundle: {e}")))?;
+    if statement.kind != BUNDLE_TYPE {
+        return Err(AppError::BadRequest("invalid signed bundle type".into()));
+    }
+    ensure_x25519_key(&statement.identity_key)?;
+    ensure_x25519_key(&statement.signed_prekey.key)?;
+
+    let mut one_time_prekeys = Vec::with_capacity(body.one_time_prekeys.len());
+    for prekey in &body.one_time_prekeys {
+        ensure_x25519_key(&prekey.key)?;
+        one_time_prekeys.push((i64::from(prekey.id), prekey.key.clone()));
+    }
+
+    // ID 鍵が変わらない場合のみ既存のワンタイムプレキーが残る
+    let existing = match db::prekeys::get_bundle(&state.pool, &user_id).await? {
+        Some(bundle) if bundle.identity_key == statement.identity_key => {
+            db::prekeys::count_one_time_prekeys(&state.pool, &user_id).await?
+        }
+        _ => 0,
+    };
+    if existing + one_time_prekeys.len() as i64 > MAX_STORED_ONE_TIME_PREKEYS {
+        return Err(AppError::BadRequest(format!(
+            "at most {MAX_STORED_ONE_TIME_PREKEYS} one-time prekeys can be stored"
+        )));
+    }
+
+    let stored = db::prekeys::upsert_bundle(
+        &state.pool,
+        &user_id,
+        &statement.identity_key,
+        &body.signed_bundle,
+        &one_time_prekeys,
+    )
+    .await?;
+    if !stored {
+        return Err(AppError::BadRequest(
+            "one-time prekey id is already in use with another key".into(),
+        ));
+    }
+    let count = db::prekeys::count_one_time_prekeys(&state.pool, &user_id).await?;
+
+    Ok(Json(serde_json::json!({ "one_time_prekey_count": count })))
+}
+
+/// 相手とのセッションを開始するためのプレキーバンドルを返す。
+///
+/// ワンタイムプレキーは一度だけ渡され、残っていないか要求者ごとの上限に達した場合は `null` になる。
+/// 外部サーバのユーザの場合はホームサーバから取得する。
+async fn get_bundle(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hostname = &state.config.server_hostname;
+    let user_id = UserId::resolve(&id, hostname)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+
+    if !user_id.is_local(hostname)
+        && let Some(domain) = user_id.domain()
+    {
+        let base = crate::federation::client::base_url(domain, state.config.federation_allow_http);
+        let path = format!(
+            "/v1/user/{}/prekeys",
+            crate::federation::client::encode_user_id(user_id.as_str())
+        );
+        let authorization = crate::federation::delegation::authorization(
+            &state, &auth, domain, None, "GET", &path,
+        )?;
+        let resp = state
+            .http
+            .send(
+                state
+                    .http
+                    .get(&format!("{base}{path}"))
+                    .header("Authorization", authorization),
+            )
+            .await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let resp_body = state.http.error_text(resp).await;
+            return Err(AppError::BadGateway(format!(
+                "home server returned {status}: {resp_body}"
+            )));
+        }
+        let body: serde_json::Value = state.http.json(resp).await?;
+        return Ok(Json(body));
+    }
+
+    let bundle = db::prekeys::get_bundle(&state.pool, &user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("prekey bundle not found".into()))?;
+    let one_time_prekey = db::prekeys::take_one_time_prekey(
+        &state.pool,
+        &user_id,
+        &auth.user_id,
+        chrono::Utc::now() - chrono::Duration::hours(1),
+        MAX_ONE_TIME_PREKEY_CLAIMS_PER_HOUR,
+    )
+    .await?
+    .and_then(|row| {
+        Some(PublicPrekey {
+            id: u32::try_from(row.prekey_id).ok()?,
+            key: row.public_key,
+        })
+    });
+
+    Ok(Json(serde_json::json!({
+        "signed_bundle": bundle.signed_bundle,
+        "one_time_prekey": one_time_prekey,
+    })))
+}
+
+/// 残りのワンタイムプレキー数を返す。クライアントは少なくなったら補充する。
+async fn get_status(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    let bundle = db::prekeys::get_bundle(&state.pool, &user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("prekey bundle not found".into()))?;
+    let count = db::prekeys::count_one_time_prekeys(&state.pool, &user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "identity_key": bundle.identity_key,
+        "updated_at": bundle.updated_at,
+        "one_time_prekey_count": count,
+    })))
+}
@@ -0,0 +1,266 @@
+use std::collections::{BTreeSet, HashMap};
+
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::error::AppError;
+use crate::types::{ChatId, UserId};
+
+/// 配布メッセージ1件あたりの最大サイズ
+const MAX_DISTRIBUTION_SIZE: usize = 16 * 1024;
+const MAX_KEY_ID_LEN: usize = 64;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route(
+        "/chat/{chat_id}/sender-keys",
+        get(list_sender_keys).post(distribute_sender_key),
+    )
+}
+
+#[derive(Deserialize, Serialize)]
+struct DistributionBody {
+    recipient_id: String,
+    distribution: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DistributeBody {
+    key_id: String,
+    distributions: Vec<DistributionBody>,
+}
+
+/// チャットの現在のメンバーを完全修飾IDで返す。
+async fn current_member_ids(state: &AppState, chat_id: &ChatId) -> Result<Vec<UserId>, AppError> {
+    let hostname = &state.config.server_hostname;
+    db::chat::get_chat_members(&state.pool, chat_id)
+        .await?
+        .into_iter()
+        .map(|m| {
+            UserId::resolve(&m.user_id, hostname)
+                .map_err(|e| AppError::Internal(format!("invalid member ID: {e}")))
+        })
+        .collect()
+}
+
+/// リモートチャットの場合はホームサーバへプロキシする。
+async fn proxy_to_home_server(
+    state: &AppState,
+    auth: &AuthenticatedUser,
+    chat_id: &ChatId,
+    body: Option<&DistributeBody>,
+) -> Result<Option<serde_json::Value>, AppError> {
+    let Some(group) = db::chat::get_chat_group(&state.pool, chat_id).await? else {
+        return Err(AppError::NotFound("chat group not found".into()));
+    };
+    let Some(ref server_domain) = group.server_domain else {
+        return Ok(None);
+    };
+
+    let base =
+        crate::federation::client::base_url(server_domain, state.config.federation_allow_http);
+    let path = format!("/v1/chat/{}/sender-keys", chat_id.as_str());
+    let method = if body.is_some() { "POST" } else { "GET" };
+    let authorization = crate::federation::delegation::authorization(
+        state,
+        auth,
+        server_domain,
+        Some(chat_id),
+        method,
+        &path,
+    )?;
+    let url = format!("{base}{path}");
+    let request = match body {
+        Some(body) => state.http.post(&url).json(body),
+        None => state.http.get(&url),
+    };
+    let resp = state
+        .http
+        .send(request.header("Authorization", authorization))
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let resp_body = state.http.error_text(resp).await;
+        return Err(AppError::BadGateway(format!(
+            "home server returned {status}: {resp_body}"
+        )));
+    }
+    let mut resp_body: serde_json::Value = state.http.json(resp).await?;
+    qualify_user_ids(&mut resp_body, server_domain);
+    Ok(Some(resp_body))
+}
+
+/// 自分のセンダーキーを現在のメンバー全員に配布する。
+///
+/// 配布先は送信者を除く現在のメンバーと完全に一致していなければならない。
+/// メンバーが変わった場合、クライアントは新しい鍵IDで配布し直す。
+async fn distribute_sender_key(
+    State(state): State<AppState>,
+    Path(chat_id): Path<String>,
+    auth: AuthenticatedUser,
+    Json(body): Json<DistributeBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let chat_id = ChatId(chat_id);
+
+    if !db::chat::is_member(&state.pool, &chat_id, &auth.user_id).await? {
+        return Err(AppError::Forbidden("not a member of this chat".into()));
+    }
+    if let Some(resp) = proxy_to_home_server(&state, &auth, &chat_id, Some(&body)).await? {
+        return Ok(Json(resp));
+    }
+
+    if body.key_id.is_empty()
+        || body.key_id.len() > MAX_KEY_ID_LEN
+        || !body
+            .key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(AppError::BadRequest("invalid key_id".into()));
+    }
+
+    let hostname = &state.config.server_hostname;
+    let expected: BTreeSet<String> = current_member_ids(&state, &chat_id)
+        .await?
+        .into_iter()
+        .filter(|id| *id != auth.user_id)
+        .map(|id| id.as_str().to_string())
+        .collect();
+    let mut distributions = Vec::with_capacity(body.distributions.len());
+    for d in &body.distributions {
+        let recipient_id = UserId::resolve(&d.recipient_id, hostname)
+            .map_err(|e| AppError::BadRequest(format!("invalid recipient ID: {e}")))?;
+        if d.distribution.is_empty() || d.distribution.len() > MAX_DISTRIBUTION_SIZE {
+            return Err(AppError::BadRequest("invalid distribution size".into()));
+        }
+        distributions.push((recipient_id.as_str().to_string(), d.distribution.clone()));
+    }
+    let recipients: BTreeSet<String> = distributions.iter().map(|(id, _)| id.clone()).collect();
+    if recipients.len() != distributions.len() || recipients != expected {
+        return Err(AppError::Conflict(
+            "distributions must cover exactly the current members".into(),
+        ));
+    }
+
+    // 外側の署名が認証済みの送信者によるものか検証する
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(auth.signing_public_key.as_str())
+        .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
+    for (_, distribution) in &distributions {
+        public_keys
+            .verify_and_extract(distribution)
+            .map_err(|_| AppError::BadRequest("distribution signature invalid".into()))?;
+    }
+
+    db::sender_keys::store_distributions(
+        &state.pool,
+        &chat_id,
+        &auth.user_id,
+        &body.key_id,
+        &distributions,
+    )
+    .await?;
+
+    // 受信者に新しいセンダーキーを通知する
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let http = state.http.clone();
+    let allow_http = state.config.federation_allow_http;
+    let recipient_ids: Vec<UserId> = recipients.into_iter().map(UserId).collect();
+    let payload = serde_json::json!({
+        "type": "sender_key",
+        "chat_id": chat_id.as_str(),
+        "sender_id": auth.user_id.as_str(),
+        "key_id": body.key_id,
+    });
+    let hostname = hostname.clone();
+    tokio::spawn(async move {
+        let (local, remote): (Vec<UserId>, Vec<UserId>) = recipient_ids
+            .into_iter()
+            .partition(|id| id.is_local(&hostname));
+        if let Err(e) = crate::push::send_event_to_users(&pool, &config, &local, &payload).await {
+            tracing::warn!("push notification failed for sender key: {e}");
+        }
+
+        let mut domains: HashMap<String, Vec<String>> = HashMap::new();
+        for id in &remote {
+            if let Some(domain) = id.domain() {
+                domains
+                    .entry(domain.to_string())
+                    .or_default()
+                    .push(id.local_part().to_string());
+            }
+        }
+        for (domain, user_ids) in &domains {
+            if let Err(e) = crate::federation::client::forward_push(
+                &http, domain, user_ids, &payload, allow_http,
+            )
+            .await
+            {
+                tracing::warn!("federation push to {domain} failed: {e}");
+            }
+        }
+    });
+
+    Ok(Json(serde_json::json!({ "distributed": true })))
+}
+
+/// 自分宛てに配布されたセンダーキーと現在のメンバーを返す。
+///
+/// クライアントは `members` と自分のセンダーキーの配布先を比べてローテーションを判断する。
+async fn list_sender_keys(
+    State(state): State<AppState>,
+    Path(chat_id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let chat_id = ChatId(chat_id);
+
+    if !db::chat::is_member(&state.pool, &chat_id, &auth.user_id).await? {
+        return Err(AppError::Forbidden("not a member of this chat".into()));
+    }
+    if let Some(resp) = proxy_to_home_server(&state, &auth, &chat_id, None).await? {
+        return Ok(Json(resp));
+    }
+
+    let members: Vec<String> = current_member_ids(&state, &chat_id)
+        .await?
+        .into_iter()
+        .map(|id| id.0)
+        .collect();
+    let sender_keys =
+        db::sender_keys::list_for_recipient(&state.pool, &chat_id, &auth.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "members": members,
+        "sender_keys": sender_keys,
+    })))
+}
+
+/// プロキシ応答内のベアユーザIDに `@domain` を付与する。
+fn qualify_user_ids(body: &mut serde_json::Value, server_domain: &str) {
+    fn qualify(value: &mut serde_json::Value, domain: &str) {
+        if let Some(id) = value.as_str()
+            && !id.contains('@')
+        {
+            *value = serde_json::Value::String(format!("{id}@{domain}"));
+        }
+    }
+
+    if let Some(members) = body.get_mut("members").and_then(|v| v.as_array_mut()) {
+        for member in members {
+            qualify(member, server_domain);
+        }
+    }
+    if let Some(keys) = body.get_mut("sender_keys").and_then(|v| v.as_array_mut()) {
+        for key in keys {
+            for field in ["sender_id", "recipient_id"] {
+                if let Some(value) = key.get_mut(field) {
+                    qualify(value, server_domain);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use axum::extract::State;
+use axum::routing::{delete, post};
+use axum::{Json, Router};
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::auth::session;
+use crate::db;
+use crate::error::AppError;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/session", post(create_session).delete(revoke_session))
+        .route("/auth/sessions", delete(revoke_all_sessions))
+}
+
+/// 署名付きnonceと引き換えに短命なセッショントークンを発行する。
+///
+/// トークンはユーザと署名に使われた署名サブキー（デバイス鍵）に紐づき、以降は
+/// `Authorization: Bearer <token>` として署名付きヘッダーの代わりに使用できる。
+async fn create_session(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    // セッションからの再発行を許すと失効まで無期限に延長できるため、署名のみ受け付ける
+    let signing_key_fingerprint = match (&auth.session_token_hash, &auth.signing_key_fingerprint) {
+        (None, Some(fingerprint)) => fingerprint.clone(),
+        _ => {
+            return Err(AppError::Unauthorized(
+                "a signed authorization header is required to create a session".into(),
+            ));
+        }
+    };
+    let token = session::generate_token();
+    let expires_at =
+        chrono::Utc::now() + chrono::Duration::seconds(state.config.session_token_ttl_secs as i64);
+    db::sessions::create_session(
+        &state.pool,
+        &session::hash_token(&token),
+        auth.user_id.as_str(),
+        &auth.primary_key_fingerprint,
+        &signing_key_fingerprint,
+        expires_at,
+    )
+    .await?;
+
+    Ok(Json(serde_json::json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_at": expires_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    })))
+}
+
+/// 現在のセッショントークンを失効させる。
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let token_hash = auth
+        .session_token_hash
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("not authenticated with a session token".into()))?;
+    db::sessions::revoke_session(&state.pool, token_hash).await?;
+    Ok(Json(serde_json::json!({ "revoked": true })))
+}
+
+/// ユーザのすべてのセッショントークンを失効させる。
+async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let revoked = db::sessions::revoke_user_sessions(&state.pool, auth.user_id.as_str()).await?;
+    Ok(Json(serde_json::json!({ "revoked": revoked })))
+}
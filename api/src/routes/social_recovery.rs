@@ -0,0 +1,447 @@
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::auth::AuthenticatedUser;
+use crate::db;
+use crate::error::AppError;
+use crate::types::UserId;
+
+const MAX_PAYLOAD_ARMOR_SIZE: usize = 256 * 1024;
+const MAX_SHARE_ARMOR_SIZE: usize = 16 * 1024;
+const MAX_RECOVERY_PUBLIC_KEY_SIZE: usize = 16 * 1024;
+/// 設定できる保護者の最大数（WASM 側の分割上限と同じ）
+const MAX_GUARDIANS: usize = 16;
+/// 復元リクエストの有効期間（時間）
+const RECOVERY_REQUEST_TTL_HOURS: i64 = 24;
+/// 1日あたりに作成できる復元リクエストの数（回復鍵ごと）
+const MAX_RECOVERY_REQUESTS_PER_DAY: i64 = 3;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/user/{id}/social-recovery",
+            get(get_config).put(put_config).delete(delete_config),
+        )
+        .route("/user/{id}/social-recovery/requests", post(create_request))
+        .route(
+            "/user/{id}/social-recovery/requests/{request_id}",
+            get(get_request_for_guardian).delete(cancel_request),
+        )
+        .route(
+            "/user/{id}/social-recovery/requests/{request_id}/shares",
+            get(list_returned_shares).put(return_share),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+struct GuardianShare {
+    guardian_id: String,
+    share_armored: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PutConfigBody {
+    payload_armored: String,
+    threshold: i32,
+    shares: Vec<GuardianShare>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequestBody {
+    /// 復元者が一時的に生成した回復鍵（保護者がシェアを再暗号化する宛先）
+    recovery_public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReturnShareBody {
+    /// 保護者の署名サブキーで署名し、回復鍵宛てに暗号化したシェア
+    share_armored: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigResponse {
+    threshold: i32,
+    guardians: Vec<String>,
+    created_at: db::models::Timestamp,
+    updated_at: db::models::Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+struct ReturnedShare {
+    guardian_id: String,
+    share_armored: String,
+    created_at: db::models::Timestamp,
+}
+
+fn resolve_owner_id(path_id: &str, hostname: &str) -> Result<UserId, AppError> {
+    UserId::resolve_local(path_id, hostname)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))
+}
+
+/// 設定を変更できるのは自サーバのユーザ本人のみ。
+fn ensure_owner(
+    path_id: &str,
+    auth: &AuthenticatedUser,
+    hostname: &str,
+) -> Result<UserId, AppError> {
+    let owner_id = resolve_owner_id(path_id, hostname)?;
+    if auth.delegated_by.is_some() || owner_id != auth.user_id {
+        return Err(AppError::Forbidden(
+            "can only manage own social recovery".into(),
+        ));
+    }
+    Ok(owner_id)
+}
+
+async fn get_active_request(
+    state: &AppState,
+    request_id: &str,
+    owner_id: &UserId,
+) -> Result<db::models::SocialRecoveryRequestRow, AppError> {
+    db::social_recovery::get_active_request(&state.pool, request_id, owner_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("recovery request not found".into()))
+}
+
+/// 保護者と所有者に復元リクエストを通知する。外部サーバの保護者には転送する。
+///
+/// 保護者が所有者本人のリクエストか別経路で確認できるよう、回復鍵の指紋を含める。
+fn notify_request(
+    state: &AppState,
+    owner_id: &UserId,
+    request_id: &str,
+    recovery_key_fingerprint: &str,
+    guardians: Vec<UserId>,
+) {
+    let hostname = state.config.server_hostname.clone();
+    let (local, remote): (Vec<UserId>, Vec<UserId>) =
+        guardians.into_iter().partition(|g| g.is_local(&hostname));
+
+    let payload = serde_json::json!({
+        "type": "social_recovery_requested",
+        "owner_id": owner_id.as_str(),
+        "request_id": request_id,
+        "recovery_key_fingerprint": recovery_key_fingerprint,
+    });
+
+    let pool = state.pool.clone();
+    let config = state.config.clone();
+    let mut recipients = local;
+    // 所有者本人の端末にも通知し、身に覚えのないリクエストを取り消せるようにする
+    recipients.push(owner_id.clone());
+    let push_payload = payload.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            crate::push::send_event_to_users(&pool, &config, &recipients, &push_payload).await
+        {
+            tracing::warn!("social recovery push failed: {e}");
+        }
+    });
+
+    let allow_http = state.config.federation_allow_http;
+    let http = state.http.clone();
+    tokio::spawn(async move {
+        // 外部の保護者をドメインごとにグループ化
+        let mut domains: std::collections::HashMap<String, Vec<String>> =
+            std::collections::HashMap::new();
+        for guardian in &remote {
+            if let Some((local, domain)) = guardian.as_str().split_once('@') {
+                domains
+                    .entry(domain.to_string())
+                    .or_default()
+                    .push(local.to_string());
+            }
+        }
+        for (domain, user_ids) in &domains {
+            if let Err(e) = crate::federation::client::forward_push(
+                &http, domain, user_ids, &payload, allow_http,
+            )
+            .await
+            {
+                tracing::warn!("federation push to {domain} failed: {e}");
+            }
+        }
+    });
+}
+
+/// 保護者に分割したシェアとバックアップペイロードを登録する。
+///
+/// シェアは各保護者の暗号化サブキー宛てに暗号化済みで、サーバは中身を検証できない。
+async fn put_config(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+    Json(body): Json<PutConfigBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    if body.payload_armored.is_empty() || body.payload_armored.len() > MAX_PAYLOAD_ARMOR_SIZE {
+        return Err(AppError::BadRequest("invalid payload size".into()));
+    }
+    if body.shares.is_empty() || body.shares.len() > MAX_GUARDIANS {
+        return Err(AppError::BadRequest("invalid number of guardians".into()));
+    }
+    if body.threshold < 2 || body.threshold as usize > body.shares.len() {
+        return Err(AppError::BadRequest("invalid threshold".into()));
+    }
+
+    let mut shares = Vec::with_capacity(body.shares.len());
+    for share in body.shares {
+        let guardian_id = UserId::resolve(&share.guardian_id, &state.config.server_hostname)
+            .map_err(|e| AppError::BadRequest(format!("invalid guardian ID: {e}")))?;
+        if guardian_id == owner_id {
+            return Err(AppError::BadRequest("cannot be your own guardian".into()));
+        }
+        if share.share_armored.is_empty() || share.share_armored.len() > MAX_SHARE_ARMOR_SIZE {
+            return Err(AppError::BadRequest("invalid share size".into()));
+        }
+        if shares
+            .iter()
+            .any(|(id, _): &(String, String)| id == guardian_id.as_str())
+        {
+            return Err(AppError::BadRequest("duplicate guardian".into()));
+        }
+        shares.push((guardian_id.0, share.share_armored));
+    }
+
+    db::social_recovery::replace_config(
+        &state.pool,
+        owner_id.as_str(),
+        &body.payload_armored,
+        body.threshold,
+        &shares,
+    )
+    .await?;
+
+    Ok(Json(serde_json::json!({
+        "saved": true,
+        "threshold": body.threshold,
+        "guardians": shares.len(),
+    })))
+}
+
+async fn get_config(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<ConfigResponse>, AppError> {
+    let owner_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    let config = db::social_recovery::get_config(&state.pool, owner_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("social recovery is not configured".into()))?;
+    let guardians = db::social_recovery::list_shares(&state.pool, owner_id.as_str())
+        .await?
+        .into_iter()
+        .map(|share| share.guardian_id)
+        .collect();
+
+    Ok(Json(ConfigResponse {
+        threshold: config.threshold,
+        guardians,
+        created_at: config.created_at,
+        updated_at: config.updated_at,
+    }))
+}
+
+async fn delete_config(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    let deleted = db::social_recovery::delete_config(&state.pool, owner_id.as_str()).await?;
+    if !deleted {
+        return Err(AppError::NotFound(
+            "social recovery is not configured".into(),
+        ));
+    }
+    Ok(Json(serde_json::json!({ "deleted": true })))
+}
+
+/// 端末を失ったユーザが復元リクエストを作成し、保護者に通知する。
+///
+/// リクエストIDは推測できないトークンで、返却されたシェアの取得に使う。
+/// 認証できないため作成数は回復鍵ごとに制限し、第三者が所有者を締め出せないようにする。
+async fn create_request(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<CreateRequestBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = resolve_owner_id(&id, &state.config.server_hostname)?;
+
+    if body.recovery_public_key.is_empty()
+        || body.recovery_public_key.len() > MAX_RECOVERY_PUBLIC_KEY_SIZE
+    {
+        return Err(AppError::BadRequest(
+            "invalid recovery public key size".into(),
+        ));
+    }
+    let recovery_key_fingerprint =
+        xrypton_common::keys::PublicKeys::try_from(body.recovery_public_key.as_str())
+            .map_err(|e| AppError::BadRequest(format!("invalid recovery public key: {e}")))?
+            .get_primary_fingerprint();
+
+    let config = db::social_recovery::get_config(&state.pool, owner_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("social recovery is not configured".into()))?;
+
+    let now = chrono::Utc::now();
+    let requests = db::social_recovery::count_requests_since(
+        &state.pool,
+        owner_id.as_str(),
+        &recovery_key_fingerprint,
+        now - chrono::Duration::days(1),
+    )
+    .await?;
+    if requests >= MAX_RECOVERY_REQUESTS_PER_DAY {
+        return Err(AppError::TooManyRequests(
+            "too many recovery requests".into(),
+        ));
+    }
+
+    let request_id = crate::auth::session::generate_token();
+    let expires_at = now + chrono::Duration::hours(RECOVERY_REQUEST_TTL_HOURS);
+    db::social_recovery::create_request(
+        &state.pool,
+        &request_id,
+        owner_id.as_str(),
+        &body.recovery_public_key,
+        &recovery_key_fingerprint,
+        expires_at,
+    )
+    .await?;
+
+    let guardians: Vec<UserId> = db::social_recovery::list_shares(&state.pool, owner_id.as_str())
+        .await?
+        .into_iter()
+        .map(|share| UserId(share.guardian_id))
+        .collect();
+    let guardian_ids: Vec<String> = guardians.iter().map(|g| g.0.clone()).collect();
+    notify_request(
+        &state,
+        &owner_id,
+        &request_id,
+        &recovery_key_fingerprint,
+        guardians,
+    );
+
+    Ok(Json(serde_json::json!({
+        "request_id": request_id,
+        "expires_at": expires_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "recovery_key_fingerprint": recovery_key_fingerprint,
+        "threshold": config.threshold,
+        "guardians": guardian_ids,
+    })))
+}
+
+/// 保護者に自分宛てのシェアと再暗号化先の回復鍵を返す。
+///
+/// 保護者は回復鍵の指紋を所有者本人と別経路で照合してからシェアを返却する。
+async fn get_request_for_guardian(
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(String, String)>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = resolve_owner_id(&id, &state.config.server_hostname)?;
+    let request = get_active_request(&state, &request_id, &owner_id).await?;
+    let share =
+        db::social_recovery::get_share(&state.pool, owner_id.as_str(), auth.user_id.as_str())
+            .await?
+            .ok_or_else(|| AppError::Forbidden("not a guardian of this user".into()))?;
+
+    Ok(Json(serde_json::json!({
+        "owner_id": owner_id.as_str(),
+        "request_id": request.id,
+        "recovery_public_key": request.recovery_public_key,
+        "recovery_key_fingerprint": request.recovery_key_fingerprint,
+        "share_armored": share.share_armored,
+        "expires_at": request.expires_at,
+    })))
+}
+
+/// 保護者が回復鍵宛てに再暗号化したシェアを返却する。
+async fn return_share(
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(String, String)>,
+    auth: AuthenticatedUser,
+    Json(body): Json<ReturnShareBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = resolve_owner_id(&id, &state.config.server_hostname)?;
+    let request = get_active_request(&state, &request_id, &owner_id).await?;
+    db::social_recovery::get_share(&state.pool, owner_id.as_str(), auth.user_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::Forbidden("not a guardian of this user".into()))?;
+
+    if body.share_armored.is_empty() || body.share_armored.len() > MAX_SHARE_ARMOR_SIZE {
+        return Err(AppError::BadRequest("invalid share size".into()));
+    }
+    // 外側の署名が認証済みの保護者によるものか検証する
+    let public_keys = xrypton_common::keys::PublicKeys::try_from(auth.signing_public_key.as_str())
+        .map_err(|e| AppError::BadRequest(format!("invalid signing key: {e}")))?;
+    public_keys
+        .verify_and_extract(&body.share_armored)
+        .map_err(|_| AppError::BadRequest("share signature invalid".into()))?;
+
+    db::social_recovery::upsert_response(
+        &state.pool,
+        &request.id,
+        auth.user_id.as_str(),
+        &body.share_armored,
+    )
+    .await?;
+
+    Ok(Json(serde_json::json!({ "returned": true })))
+}
+
+/// 復元者に返却済みのシェアとバックアップペイロードを返す。
+///
+/// シェアは回復鍵宛てに暗号化されているため、リクエストIDを知っていても
+/// 回復鍵を持たなければ復元できない。
+async fn list_returned_shares(
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = resolve_owner_id(&id, &state.config.server_hostname)?;
+    let request = get_active_request(&state, &request_id, &owner_id).await?;
+    let config = db::social_recovery::get_config(&state.pool, owner_id.as_str())
+        .await?
+        .ok_or_else(|| AppError::NotFound("social recovery is not configured".into()))?;
+
+    let shares: Vec<ReturnedShare> = db::social_recovery::list_responses(&state.pool, &request.id)
+        .await?
+        .into_iter()
+        .map(|response| ReturnedShare {
+            guardian_id: response.guardian_id,
+            share_armored: response.share_armored,
+            created_at: response.created_at,
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "payload_armored": config.payload_armored,
+        "threshold": config.threshold,
+        "shares": shares,
+        "expires_at": request.expires_at,
+    })))
+}
+
+/// 所有者が身に覚えのない復元リクエストを取り消す。
+async fn cancel_request(
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(String, String)>,
+    auth: AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let owner_id = ensure_owner(&id, &auth, &state.config.server_hostname)?;
+
+    let cancelled =
+        db::social_recovery::cancel_request(&state.pool, &request_id, owner_id.as_str()).await?;
+    if !cancelled {
+        return Err(AppError::NotFound("recovery request not found".into()));
+    }
+    Ok(Json(serde_json::json!({ "cancelled": true })))
+}
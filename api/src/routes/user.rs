@@ -1,8 +1,8 @@
 use axum::body::Body;
-use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
-use axum::http::{HeaderMap, header};
+use axum::extract::{DefaultBodyLimit, Multipart, OriginalUri, Path, State};
+use axum::http::{HeaderMap, Method, header};
 use axum::response::Response;
-use axum::routing::get;
+use axum::routing::{get, put};
 use axum::{Json, Router};
 use serde::Deserialize;
 
@@ -11,6 +11,7 @@ use crate::auth::AuthenticatedUser;
 use crate::db;
 use crate::db::models::{EmbeddedAtprotoSignature, ExternalAccount};
 use crate::error::AppError;
+use crate::federation::key_transition::KeyTransitionBody;
 use crate::types::UserId;
 
 pub fn routes() -> Router<AppState> {
@@ -22,19 +23,34 @@ pub fn routes() -> Router<AppState> {
                 .put(update_keys)
                 .delete(delete_user),
         )
+        .route("/user/{id}/keys/history", get(get_key_history))
+        .route("/user/{id}/moved", put(put_moved))
         .route("/user/{id}/profile", get(get_profile).post(update_profile))
-        .route(
-            "/user/{id}/icon",
-            get(get_icon)
-                .post(upload_icon)
-                .layer(DefaultBodyLimit::max(6 * 1024 * 1024)),
-        )
+        .route("/user/{id}/icon", get(get_icon))
+}
+
+/// アイコンのアップロード用ルート。本文ダイジェストの検証はこのルートの上限で行う。
+pub fn upload_routes() -> Router<AppState> {
+    Router::new().route(
+        "/user/{id}/icon",
+        axum::routing::post(upload_icon)
+            .layer(axum::middleware::from_fn(
+                crate::auth::request::verify_content_digest,
+            ))
+            .layer(DefaultBodyLimit::max(6 * 1024 * 1024)),
+    )
 }
 
 #[derive(Deserialize)]
 struct PostKeysBody {
     encryption_public_key: String,
     signing_public_key: String,
+    /// 他サーバからの移行時に提示する、主鍵で署名された移行宣言
+    #[serde(default)]
+    moved_statement: Option<String>,
+    /// 主鍵を変更する際に提示する、旧主鍵・新主鍵の両方で署名された鍵移行宣言
+    #[serde(default)]
+    transition: Option<KeyTransitionBody>,
 }
 
 /// ユーザ登録（認証不要）
@@ -86,13 +102,34 @@ async fn post_keys(
     // 主鍵フィンガープリントを抽出
     let public_keys = xrypton_common::keys::PublicKeys::try_from(body.signing_public_key.as_str())
         .map_err(|e| AppError::BadRequest(format!("invalid signing public key: {e}")))?;
+    // 期限切れ・失効済みの鍵は登録できない
+    public_keys
+        .validate()
+        .map_err(|e| AppError::BadRequest(format!("unusable signing public key: {e}")))?;
     let fingerprint = public_keys.get_primary_fingerprint();
 
     // PGP公開鍵のユーザIDが登録IDと一致するか検証（ドメインだけでなく名前も確認）
     let key_address = public_keys
         .get_primary_user_address()
         .map_err(|e| AppError::BadRequest(format!("invalid signing key user ID: {e}")))?;
-    if key_address != user_id.as_str() {
+    // 移行時は鍵のユーザIDが移行元のIDのままなので、移行宣言で対応を確認する
+    if let Some(statement) = body.moved_statement.as_deref() {
+        let moved =
+            crate::federation::moved::verify_statement(statement, &body.signing_public_key)?;
+        if moved.to != user_id.as_str() {
+            return Err(AppError::BadRequest(format!(
+                "moved statement destination ({}) does not match registration ID ({})",
+                moved.to,
+                user_id.as_str()
+            )));
+        }
+        if moved.from != key_address {
+            return Err(AppError::BadRequest(format!(
+                "moved statement source ({}) does not match signing key user ID ({key_address})",
+                moved.from
+            )));
+        }
+    } else if key_address != user_id.as_str() {
         return Err(AppError::BadRequest(format!(
             "signing key user ID ({key_address}) does not match registration ID ({})",
             user_id.as_str()
@@ -112,6 +149,26 @@ async fn post_keys(
         &fingerprint,
     )
     .await?;
+    crate::federation::key_change::record_keys(
+        &state,
+        &user_id,
+        None,
+        &body.encryption_public_key,
+        &body.signing_public_key,
+        &fingerprint,
+    )
+    .await?;
+
+    if let Some(statement) = body.moved_statement.as_deref() {
+        db::user_moves::upsert_move(
+            &state.pool,
+            &key_address,
+            user_id.as_str(),
+            &fingerprint,
+            statement,
+        )
+        .await?;
+    }
 
     Ok(Json(serde_json::json!({ "id": user_id.as_str() })))
 }
@@ -132,21 +189,52 @@ async fn update_keys(
 
     let public_keys = xrypton_common::keys::PublicKeys::try_from(body.signing_public_key.as_str())
         .map_err(|e| AppError::BadRequest(format!("invalid signing public key: {e}")))?;
+    // 期限切れ・失効済みの鍵は登録できない
+    public_keys
+        .validate()
+        .map_err(|e| AppError::BadRequest(format!("unusable signing public key: {e}")))?;
     let fingerprint = public_keys.get_primary_fingerprint();
 
-    let updated = db::users::update_user_keys(
+    let previous = db::users::get_user(&state.pool, &user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+
+    // 主鍵の変更には旧主鍵・新主鍵の両方による移行宣言が必要
+    if previous.primary_key_fingerprint != fingerprint {
+        let transition = body.transition.as_ref().ok_or_else(|| {
+            AppError::BadRequest("key transition statement is required to rotate keys".into())
+        })?;
+        crate::federation::key_transition::record(
+            &state,
+            &user_id,
+            &previous,
+            &body.encryption_public_key,
+            &body.signing_public_key,
+            transition,
+        )
+        .await?;
+    } else if !db::users::update_user_keys(
         &state.pool,
         &user_id,
         &body.encryption_public_key,
         &body.signing_public_key,
         &fingerprint,
     )
-    .await?;
-
-    if !updated {
+    .await?
+    {
         return Err(AppError::NotFound("user not found".into()));
     }
 
+    crate::federation::key_change::record_keys(
+        &state,
+        &user_id,
+        Some(&previous),
+        &body.encryption_public_key,
+        &body.signing_public_key,
+        &fingerprint,
+    )
+    .await?;
+
     Ok(Json(serde_json::json!({ "id": user_id.as_str() })))
 }
 
@@ -164,6 +252,13 @@ async fn fetch_local_user_keys(
         })));
     }
 
+    if let Some(moved) = db::user_moves::get_move(&state.pool, user_id.as_str()).await? {
+        return Err(AppError::Moved {
+            moved_to: moved.to_id,
+            statement: moved.statement,
+        });
+    }
+
     if db::deleted_users::is_deleted(&state.pool, user_id.as_str()).await? {
         return Err(AppError::Gone("user has been deleted".into()));
     }
@@ -175,6 +270,8 @@ async fn fetch_local_user_keys(
 async fn get_keys(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
     headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, AppError> {
     // CASE A: リクエストIDに@あり
@@ -194,15 +291,8 @@ async fn get_keys(
                 let auth_header_raw = v
                     .to_str()
                     .map_err(|_| AppError::Unauthorized("invalid authorization header".into()))?;
-                Some(
-                    crate::auth::authenticate(
-                        &state.pool,
-                        &state.config,
-                        &state.dns_resolver,
-                        auth_header_raw,
-                    )
-                    .await?,
-                )
+                let target = crate::auth::request::RequestTarget::new(&method, &uri, &headers);
+                Some(crate::auth::authenticate(&state, auth_header_raw, &target).await?)
             }
             None => None,
         };
@@ -236,7 +326,9 @@ async fn get_keys(
 
         // 外部サーバのユーザ → 連合リクエスト（ドメイン込みIDで取得）
         let remote_id = format!("{resolved_local}@{resolved_domain}");
+        // 移行済みの場合はクライアントに移行先を伝えるのみで、移行の反映は行わない
         let remote_keys = crate::federation::client::fetch_user_keys(
+            &state.http,
             &resolved_domain,
             &remote_id,
             state.config.federation_allow_http,
@@ -244,19 +336,48 @@ async fn get_keys(
         .await?;
 
         // キャッシュとしてローカルに保存（元のIDを維持）
-        let full_id = format!("{local_part}@{domain}");
+        let full_id = UserId(format!("{local_part}@{domain}"));
         let public_keys =
             xrypton_common::keys::PublicKeys::try_from(remote_keys.signing_public_key.as_str())
                 .map_err(|e| AppError::BadGateway(format!("invalid remote signing key: {e}")))?;
         let fingerprint = public_keys.get_primary_fingerprint();
-        db::users::upsert_external_user(
+        let previous = db::users::get_user(&state.pool, &full_id).await?;
+        // キャッシュ済みの主鍵から変わっている場合は有効な移行チェーンが必要
+        if let Some(prev) = previous
+            .as_ref()
+            .filter(|p| p.primary_key_fingerprint != fingerprint)
+        {
+            crate::federation::key_transition::verify_remote_chain(
+                &state,
+                &resolved_domain,
+                &full_id,
+                prev,
+                &remote_keys.signing_public_key,
+                &fingerprint,
+            )
+            .await
+            .map_err(|e| AppError::BadGateway(format!("unverified key change: {e}")))?;
+        }
+        // 失効済みの鍵を保存している場合は置き換えない
+        if db::users::upsert_external_user(
             &state.pool,
-            &full_id,
+            full_id.as_str(),
             &remote_keys.encryption_public_key,
             &remote_keys.signing_public_key,
             &fingerprint,
         )
-        .await?;
+        .await?
+        {
+            crate::federation::key_change::record_keys(
+                &state,
+                &full_id,
+                previous.as_ref(),
+                &remote_keys.encryption_public_key,
+                &remote_keys.signing_public_key,
+                &fingerprint,
+            )
+            .await?;
+        }
 
         return Ok(Json(serde_json::json!({
             "id": remote_keys.id,
@@ -272,6 +393,25 @@ async fn get_keys(
     fetch_local_user_keys(&state, &user_id).await
 }
 
+/// 鍵履歴取得（認証不要）
+///
+/// このサーバが観測した公開鍵の一覧を返す。外部ユーザの場合はキャッシュ時に記録された履歴。
+async fn get_key_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = UserId::resolve(&id, &state.config.server_hostname)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    let history = db::key_history::list_key_history(&state.pool, user_id.as_str()).await?;
+    if history.is_empty() {
+        return Err(AppError::NotFound("no key history for user".into()));
+    }
+    Ok(Json(serde_json::json!({
+        "id": user_id.as_str(),
+        "history": history,
+    })))
+}
+
 async fn delete_user(
     State(state): State<AppState>,
     Path(id): Path<String>,
@@ -285,10 +425,57 @@ async fn delete_user(
     let fingerprint = db::users::get_user(&state.pool, &user_id)
         .await?
         .map(|u| u.primary_key_fingerprint);
+
+    // メンバーシップが消える前に通知先を確定する
+    let peer_domains = crate::federation::tombstone::peer_domains(&state, &user_id).await?;
+    let members = db::chat::get_co_member_ids(&state.pool, &user_id).await?;
+
     db::users::delete_user(&state.pool, &user_id, fingerprint.as_deref()).await?;
+
+    crate::federation::tombstone::notify_members(&state, &user_id, members);
+    crate::federation::tombstone::announce(&state, &user_id, peer_domains);
+
     Ok(Json(serde_json::json!({ "deleted": true })))
 }
 
+#[derive(Deserialize)]
+struct PutMovedBody {
+    statement: String,
+}
+
+/// 別サーバへの移行を宣言する（認証必要）
+///
+/// 移行先サーバで同じ主鍵による登録が済んでいることを確認したうえで、
+/// このサーバ上のユーザを移行先に置き換え、以降の鍵取得には移行宣言を返す。
+/// チャットを共有している外部サーバにも移行を通知する。
+async fn put_moved(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: AuthenticatedUser,
+    Json(body): Json<PutMovedBody>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = UserId::resolve_local(&id, &state.config.server_hostname)
+        .map_err(|e| AppError::BadRequest(format!("invalid user ID: {e}")))?;
+    if auth.user_id != user_id {
+        return Err(AppError::Forbidden("can only move own account".into()));
+    }
+
+    let moved =
+        crate::federation::moved::verify_statement(&body.statement, &auth.signing_public_key)?;
+    if moved.from != user_id.as_str() {
+        return Err(AppError::BadRequest(
+            "moved statement source does not match user".into(),
+        ));
+    }
+
+    // メンバーシップが書き換わる前に通知先を確定する
+    let peer_domains = crate::federation::tombstone::peer_domains(&state, &user_id).await?;
+    let to = crate::federation::moved::follow(&state, &user_id, &moved.to, &body.statement).await?;
+    crate::federation::moved::announce(&state, &user_id, &body.statement, peer_domains);
+
+    Ok(Json(serde_json::json!({ "moved_to": to.as_str() })))
+}
+
 /// ATProto・Xアカウント等から外部アカウント情報を構築する。
 /// ATProtoアカウントの pubkey_post_uri に対応する署名を一括取得し埋め込む。
 async fn build_external_accounts(state: &AppState, user_id: &str) -> Vec<ExternalAccount> {
@@ -441,25 +628,18 @@ pub(crate) async fn fetch_profile(
         );
         let encoded_id = crate::federation::client::encode_user_id(&remote_id);
         let url = format!("{base}/v1/user/{encoded_id}/profile");
-        let resp = reqwest::Client::new()
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let resp = state.http.send(state.http.get(&url)).await?;
         if !resp.status().is_success() {
             let status = resp.status();
             if status == reqwest::StatusCode::GONE {
                 return Err(AppError::Gone("user has been deleted".into()));
             }
-            let body = resp.text().await.unwrap_or_default();
+            let body = state.http.error_text(resp).await;
             return Err(AppError::BadGateway(format!(
                 "federation profile proxy returned {status}: {body}"
             )));
         }
-        let body: serde_json::Value = resp
-            .json()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("invalid proxy response: {e}")))?;
+        let body: serde_json::Value = state.http.json(resp).await?;
         return Ok(Json(body));
     }
 
@@ -728,14 +908,10 @@ async fn get_icon(
         );
         let encoded_id = crate::federation::client::encode_user_id(&remote_id);
         let url = format!("{base}/v1/user/{encoded_id}/icon");
-        let resp = reqwest::Client::new()
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy request failed: {e}")))?;
+        let resp = state.http.send(state.http.get(&url)).await?;
         if !resp.status().is_success() {
             let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
+            let body = state.http.error_text(resp).await;
             return Err(AppError::BadGateway(format!(
                 "federation icon proxy returned {status}: {body}"
             )));
@@ -746,10 +922,7 @@ async fn get_icon(
             .and_then(|v| v.to_str().ok())
             .unwrap_or("application/octet-stream")
             .to_string();
-        let bytes = resp
-            .bytes()
-            .await
-            .map_err(|e| AppError::BadGateway(format!("proxy response failed: {e}")))?;
+        let bytes = state.http.bytes(resp).await?;
         return Ok(Response::builder()
             .header(header::CONTENT_TYPE, content_type)
             .header(header::CACHE_CONTROL, "no-store")
@@ -1,4 +1,4 @@
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum XryptonError {
     #[error("key format error: {0}")]
     KeyFormat(String),
@@ -6,4 +6,12 @@ pub enum XryptonError {
     Verification(String),
     #[error("invalid payload: {0}")]
     InvalidPayload(String),
+    #[error("key expired: {0}")]
+    Expired(String),
+    #[error("key revoked: {0}")]
+    Revoked(String),
+    #[error("unbound key: {0}")]
+    Unbound(String),
+    #[error("unsuitable key: {0}")]
+    Unsuitable(String),
 }
@@ -1,7 +1,9 @@
 use pgp::composed::*;
-use pgp::packet::{Packet, PacketParser, Signature};
+use pgp::packet::{Packet, PacketParser, Signature, SignatureType};
 use pgp::ser::Serialize;
-use pgp::types::{KeyDetails, PublicKeyTrait, SignedUser, SignedUserAttribute, Tag};
+use pgp::types::{
+    KeyDetails, PublicKeyTrait, RevocationCode, SignedUser, SignedUserAttribute, Tag,
+};
 
 use crate::error::XryptonError;
 
@@ -36,12 +38,8 @@ pub fn extract_address_from_uid(uid: &str) -> Result<&str, XryptonError> {
     ))
 }
 
-/// PGP署名メッセージからSignersUserIDサブパケットの値を抽出し、
-/// `[ID]@[domain]` 形式に正規化して返す。
-///
-/// `Real Name <user@domain>` 形式の場合はアドレス部分のみ返す。
-/// 連合フローで署名者のホームサーバを特定するために使用する。
-pub fn extract_signer_user_id(armored: &str) -> Result<String, XryptonError> {
+/// armored PGP データをバイト列に変換する。
+pub fn dearmor(armored: &str) -> Result<Vec<u8>, XryptonError> {
     use pgp::armor::Dearmor;
     use std::io::{BufReader, Read};
 
@@ -50,139 +48,249 @@ pub fn extract_signer_user_id(armored: &str) -> Result<String, XryptonError> {
     dearmor
         .read_to_end(&mut bytes)
         .map_err(|e| XryptonError::Verification(format!("dearmor failed: {e}")))?;
-
-    extract_signer_user_id_from_bytes(&bytes)
+    Ok(bytes)
 }
 
-/// raw PGP バイト列から SignersUserID を抽出する。
+/// PGP署名メッセージからSignersUserIDサブパケットの値を抽出し、
+/// `[ID]@[domain]` 形式に正規化して返す。
 ///
-/// CompressedData パケットがあれば展開してから内部パケットを走査する。
-fn extract_signer_user_id_from_bytes(data: &[u8]) -> Result<String, XryptonError> {
-    use pgp::packet::{Packet, PacketParser};
-    use std::io::{BufReader, Read};
-
-    let parser = PacketParser::new(BufReader::new(data));
-
-    for result in parser.flatten() {
-        match result {
-            Packet::Signature(ref sig) if sig.signers_userid().is_some() => {
-                let uid = sig.signers_userid().unwrap();
-                let raw = String::from_utf8(uid.to_vec()).map_err(|e| {
-                    XryptonError::Verification(format!("invalid UTF-8 in SignersUserID: {e}"))
-                })?;
-                return extract_address_from_uid(&raw)
-                    .map(str::to_owned)
-                    .map_err(|e| {
-                        XryptonError::Verification(format!("invalid SignersUserID format: {e}"))
-                    });
-            }
-            Packet::CompressedData(ref cd) => {
-                let mut decompressed = Vec::new();
-                cd.decompress()
-                    .map_err(|e| XryptonError::Verification(format!("decompress failed: {e}")))?
-                    .read_to_end(&mut decompressed)
-                    .map_err(|e| {
-                        XryptonError::Verification(format!("decompress read failed: {e}"))
-                    })?;
-                return extract_signer_user_id_from_bytes(&decompressed);
-            }
-            _ => continue,
-        }
+/// `Real Name <user@domain>` 形式の場合はアドレス部分のみ返す。
+/// 連合フローで署名者のホームサーバを特定するために使用する。
+pub fn extract_signer_user_id(armored: &str) -> Result<String, XryptonError> {
+    let report = inspect_message(armored)?;
+    match report.signer_user_id {
+        Some(uid) => extract_address_from_uid(&uid)
+            .map(str::to_owned)
+            .map_err(|e| XryptonError::Verification(format!("invalid SignersUserID format: {e}"))),
+        None => Err(XryptonError::Verification(
+            "no SignersUserID subpacket found in message".into(),
+        )),
     }
+}
 
-    Err(XryptonError::Verification(
-        "no SignersUserID subpacket found in message".into(),
-    ))
+/// 16進フィンガープリントの形式から鍵のバージョンを判定する。
+///
+/// v4 鍵は40文字（SHA-1）、v6 鍵は64文字（SHA-256）の大文字16進文字列。
+pub fn fingerprint_version(fingerprint: &str) -> Option<u8> {
+    if !fingerprint
+        .bytes()
+        .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_lowercase())
+    {
+        return None;
+    }
+    match fingerprint.len() {
+        40 => Some(4),
+        64 => Some(6),
+        _ => None,
+    }
 }
 
 /// PGP署名メッセージから署名者の鍵IDを検証なしで抽出する。
 ///
 /// OnePassSignature パケットまたは Signature パケットの issuer 情報を使用する。
 pub fn extract_issuer_key_id(armored: &str) -> Result<String, XryptonError> {
-    use pgp::armor::Dearmor;
-    use std::io::{BufReader, Read};
-
-    let mut dearmor = Dearmor::new(BufReader::new(armored.as_bytes()));
-    let mut bytes = Vec::new();
-    dearmor
-        .read_to_end(&mut bytes)
-        .map_err(|e| XryptonError::Verification(format!("dearmor failed: {e}")))?;
-
-    extract_issuer_key_id_from_bytes(&bytes)
+    extract_issuer_key_id_from_bytes(&dearmor(armored)?)
 }
 
 /// PGP署名メッセージから署名者のフィンガープリントを検証なしで抽出する。
 ///
 /// Signature パケットの issuer_fingerprint 情報を使用する。
 pub fn extract_issuer_fingerprint(armored: &str) -> Result<String, XryptonError> {
-    use pgp::armor::Dearmor;
-    use std::io::{BufReader, Read};
+    extract_issuer_fingerprint_from_bytes(&dearmor(armored)?)
+}
 
-    let mut dearmor = Dearmor::new(BufReader::new(armored.as_bytes()));
-    let mut bytes = Vec::new();
-    dearmor
-        .read_to_end(&mut bytes)
-        .map_err(|e| XryptonError::Verification(format!("dearmor failed: {e}")))?;
+/// raw PGP バイト列から署名者の鍵IDを検証なしで抽出する。
+pub fn extract_issuer_key_id_from_bytes(data: &[u8]) -> Result<String, XryptonError> {
+    inspect_message_bytes(data)?
+        .signer_key_id
+        .ok_or_else(|| XryptonError::Verification("no issuer key ID found in message".into()))
+}
+
+/// raw PGP バイト列から署名者のフィンガープリントを検証なしで抽出する。
+pub fn extract_issuer_fingerprint_from_bytes(data: &[u8]) -> Result<String, XryptonError> {
+    inspect_message_bytes(data)?
+        .signer_fingerprint
+        .ok_or_else(|| XryptonError::Verification("no issuer fingerprint found in message".into()))
+}
 
-    extract_issuer_fingerprint_from_bytes(&bytes)
+/// メッセージを構成するパケットの層（外側から順）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageLayer {
+    /// 署名（OnePassSignature または Signature パケット）
+    Signed,
+    /// 圧縮（アルゴリズム名）
+    Compressed(String),
+    /// 暗号化データ。内側は復号するまで走査できない
+    Encrypted,
+    /// リテラルデータ
+    Literal,
 }
 
-/// raw PGP バイト列から署名者の鍵IDを検証なしで抽出する。
+/// 署名検証の結果
+#[derive(Debug, Clone)]
+pub enum VerificationOutcome {
+    /// 検証していない（[`inspect_message`] の結果）
+    Unverified,
+    /// 有効な署名サブキーによる正しい署名
+    Verified,
+    /// 署名時点では有効だった署名サブキーによる正しい署名。現在は期限切れまたは失効している
+    VerifiedHistorical(XryptonError),
+    /// 署名者が有効な署名サブキーではない（不明・期限切れ・失効・未束縛）
+    SignerUnusable(XryptonError),
+    /// 署名が一致しない
+    BadSignature(String),
+}
+
+impl VerificationOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unverified => "unverified",
+            Self::Verified => "verified",
+            Self::VerifiedHistorical(_) => "verified_historical",
+            Self::SignerUnusable(_) => "signer_unusable",
+            Self::BadSignature(_) => "bad_signature",
+        }
+    }
+}
+
+/// 署名メッセージの検証レポート。
 ///
-/// CompressedData パケットがあれば展開してから内部パケットを走査する。
-pub fn extract_issuer_key_id_from_bytes(data: &[u8]) -> Result<String, XryptonError> {
-    use pgp::packet::{OpsVersionSpecific, Packet, PacketParser};
-    use std::io::{BufReader, Read};
+/// 署名者の情報はメッセージ中の最初の署名から取得する。
+/// 暗号化層の内側の署名は含まない。
+#[derive(Debug, Clone)]
+pub struct SignatureReport {
+    /// 署名者のフィンガープリント（大文字16進）
+    pub signer_fingerprint: Option<String>,
+    /// 署名者の鍵ID
+    pub signer_key_id: Option<String>,
+    /// SignersUserID サブパケットの値（正規化前）
+    pub signer_user_id: Option<String>,
+    /// 署名作成時刻
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 署名のハッシュアルゴリズム
+    pub hash_algorithm: Option<String>,
+    pub layers: Vec<MessageLayer>,
+    pub outcome: VerificationOutcome,
+}
 
-    let parser = PacketParser::new(BufReader::new(data));
+impl SignatureReport {
+    pub fn is_verified(&self) -> bool {
+        matches!(self.outcome, VerificationOutcome::Verified)
+    }
 
-    for result in parser.flatten() {
-        match result {
-            Packet::OnePassSignature(ref ops) => {
-                if let OpsVersionSpecific::V3 { key_id } = ops.version_specific() {
-                    return Ok(key_id.to_string());
-                }
-            }
-            Packet::Signature(ref sig) => {
-                if let Some(key_id) = sig.issuer().first() {
-                    return Ok(key_id.to_string());
-                }
-            }
-            Packet::CompressedData(ref cd) => {
-                let mut decompressed = Vec::new();
-                cd.decompress()
-                    .map_err(|e| XryptonError::Verification(format!("decompress failed: {e}")))?
-                    .read_to_end(&mut decompressed)
-                    .map_err(|e| {
-                        XryptonError::Verification(format!("decompress read failed: {e}"))
-                    })?;
-                return extract_issuer_key_id_from_bytes(&decompressed);
-            }
-            _ => continue,
+    /// 署名時点で有効だったサブキーによる正しい署名か。受信済みメッセージの表示などに使う。
+    pub fn is_valid_when_signed(&self) -> bool {
+        matches!(
+            self.outcome,
+            VerificationOutcome::Verified | VerificationOutcome::VerifiedHistorical(_)
+        )
+    }
+
+    /// 検証に成功していなければ理由をエラーとして返す。
+    ///
+    /// 現在も有効な署名サブキーによる署名のみ受け付ける。
+    pub fn ensure_verified(&self) -> Result<(), XryptonError> {
+        match &self.outcome {
+            VerificationOutcome::VerifiedHistorical(e) => Err(e.clone()),
+            _ => self.ensure_valid_when_signed(),
         }
     }
 
-    Err(XryptonError::Verification(
-        "no issuer key ID found in message".into(),
-    ))
+    /// 署名時点で有効だったサブキーによる正しい署名でなければ理由をエラーとして返す。
+    pub fn ensure_valid_when_signed(&self) -> Result<(), XryptonError> {
+        match &self.outcome {
+            VerificationOutcome::Verified | VerificationOutcome::VerifiedHistorical(_) => Ok(()),
+            VerificationOutcome::Unverified => Err(XryptonError::Verification(
+                "message has not been verified".into(),
+            )),
+            VerificationOutcome::SignerUnusable(e) => Err(e.clone()),
+            VerificationOutcome::BadSignature(reason) => Err(XryptonError::Verification(format!(
+                "signature verification failed: {reason}"
+            ))),
+        }
+    }
 }
 
-/// raw PGP バイト列から署名者のフィンガープリントを検証なしで抽出する。
-///
-/// CompressedData パケットがあれば展開してから内部パケットを走査する。
-pub fn extract_issuer_fingerprint_from_bytes(data: &[u8]) -> Result<String, XryptonError> {
+/// armored PGP メッセージの署名情報と層構造を検証なしで取得する。
+pub fn inspect_message(armored: &str) -> Result<SignatureReport, XryptonError> {
+    inspect_message_bytes(&dearmor(armored)?)
+}
+
+/// raw PGP バイト列の署名情報と層構造を検証なしで取得する。
+pub fn inspect_message_bytes(data: &[u8]) -> Result<SignatureReport, XryptonError> {
+    let mut report = SignatureReport {
+        signer_fingerprint: None,
+        signer_key_id: None,
+        signer_user_id: None,
+        created_at: None,
+        hash_algorithm: None,
+        layers: Vec::new(),
+        outcome: VerificationOutcome::Unverified,
+    };
+    walk_packets(data, &mut report)?;
+    Ok(report)
+}
+
+/// パケットを走査してレポートを埋める。CompressedData パケットは展開して再帰的に走査する。
+fn walk_packets(data: &[u8], report: &mut SignatureReport) -> Result<(), XryptonError> {
+    use pgp::packet::OpsVersionSpecific;
     use std::io::{BufReader, Read};
 
     let parser = PacketParser::new(BufReader::new(data));
+    // OnePassSignature に対応する末尾の Signature を別の層として数えないため
+    let mut pending_ops = 0usize;
 
-    for result in parser.flatten() {
-        match result {
+    for packet in parser.flatten() {
+        match packet {
+            Packet::OnePassSignature(ref ops) => {
+                pending_ops += 1;
+                report.layers.push(MessageLayer::Signed);
+                match ops.version_specific() {
+                    OpsVersionSpecific::V3 { key_id } => {
+                        report
+                            .signer_key_id
+                            .get_or_insert_with(|| key_id.to_string());
+                    }
+                    // v6 の OnePassSignature は署名者のフィンガープリントを含む
+                    OpsVersionSpecific::V6 { fingerprint, .. } => {
+                        report
+                            .signer_fingerprint
+                            .get_or_insert_with(|| format!("{fingerprint:X}"));
+                    }
+                }
+            }
             Packet::Signature(ref sig) => {
-                if let Some(fingerprint) = sig.issuer_fingerprint().first() {
-                    return Ok(format!("{fingerprint:X}"));
+                if pending_ops > 0 {
+                    pending_ops -= 1;
+                } else {
+                    report.layers.push(MessageLayer::Signed);
+                }
+                if report.signer_fingerprint.is_none() {
+                    report.signer_fingerprint =
+                        sig.issuer_fingerprint().first().map(|fp| format!("{fp:X}"));
+                }
+                if report.signer_key_id.is_none() {
+                    report.signer_key_id = sig.issuer().first().map(|id| id.to_string());
+                }
+                if report.signer_user_id.is_none()
+                    && let Some(uid) = sig.signers_userid()
+                {
+                    report.signer_user_id = Some(String::from_utf8(uid.to_vec()).map_err(|e| {
+                        XryptonError::Verification(format!("invalid UTF-8 in SignersUserID: {e}"))
+                    })?);
+                }
+                if report.created_at.is_none() {
+                    report.created_at = sig.created().map(|t| t.to_owned());
+                }
+                if report.hash_algorithm.is_none() {
+                    report.hash_algorithm = sig.hash_alg().map(|alg| format!("{alg:?}"));
                 }
             }
             Packet::CompressedData(ref cd) => {
+                report.layers.push(MessageLayer::Compressed(format!(
+                    "{:?}",
+                    cd.compression_algorithm()
+                )));
                 let mut decompressed = Vec::new();
                 cd.decompress()
                     .map_err(|e| XryptonError::Verification(format!("decompress failed: {e}")))?
@@ -190,15 +298,18 @@ pub fn extract_issuer_fingerprint_from_bytes(data: &[u8]) -> Result<String, Xryp
                     .map_err(|e| {
                         XryptonError::Verification(format!("decompress read failed: {e}"))
                     })?;
-                return extract_issuer_fingerprint_from_bytes(&decompressed);
+                walk_packets(&decompressed, report)?;
+            }
+            Packet::SymEncryptedProtectedData(_) | Packet::SymEncryptedData(_) => {
+                report.layers.push(MessageLayer::Encrypted);
+            }
+            Packet::LiteralData(_) => {
+                report.layers.push(MessageLayer::Literal);
             }
             _ => continue,
         }
     }
-
-    Err(XryptonError::Verification(
-        "no issuer fingerprint found in message".into(),
-    ))
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -279,10 +390,8 @@ fn verify_against_signer_candidates(
     // 署名がサブキーで作成されるケースもあるため、
     // 主鍵に加えて署名可能サブキーも検証候補に含める。
     verify_against_users(sig, target_key, signer_key)
-        || signer_key
-            .public_subkeys
-            .iter()
-            .filter(|subkey| subkey.key.is_signing_key())
+        || valid_signing_subkeys(signer_key)
+            .into_iter()
             .any(|subkey| verify_against_users(sig, target_key, subkey))
 }
 
@@ -308,6 +417,375 @@ pub fn verify_certification_signature_for_target(
     ))
 }
 
+/// サブキーに主鍵による失効署名があるか判定する。
+fn is_subkey_revoked(key: &SignedPublicKey, subkey: &SignedPublicSubKey) -> bool {
+    subkey.signatures.iter().any(|sig| {
+        matches!(sig.typ(), Some(SignatureType::SubkeyRevocation))
+            && sig
+                .verify_key_binding(&key.primary_key, &subkey.key)
+                .is_ok()
+    })
+}
+
+/// 鍵の漏洩による失効や理由のない失効か判定する。これらは失効前の署名も無効にする。
+fn is_hard_revocation(sig: &Signature) -> bool {
+    !matches!(
+        sig.revocation_reason_code(),
+        Some(RevocationCode::KeySuperseded | RevocationCode::KeyRetired)
+    )
+}
+
+/// サブキーの有効性。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubkeyStatus {
+    Valid,
+    /// 主鍵による有効なバインディング署名がない
+    Unbound,
+    /// 鍵またはバインディング署名の有効期限切れ
+    Expired,
+    Revoked,
+}
+
+/// 主鍵による最新の有効なバインディング署名を返す。
+fn latest_subkey_binding<'a>(
+    key: &SignedPublicKey,
+    subkey: &'a SignedPublicSubKey,
+) -> Option<&'a Signature> {
+    subkey
+        .signatures
+        .iter()
+        .filter(|sig| {
+            matches!(sig.typ(), Some(SignatureType::SubkeyBinding))
+                && sig
+                    .verify_key_binding(&key.primary_key, &subkey.key)
+                    .is_ok()
+        })
+        .max_by_key(|sig| sig.created().copied())
+}
+
+/// 署名の Key Expiration Time から鍵の有効期限を求める。0 は無期限を表す。
+fn key_expires_at(
+    created_at: &chrono::DateTime<chrono::Utc>,
+    sig: &Signature,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    sig.key_expiration_time()
+        .filter(|d| !d.is_zero())
+        .map(|d| *created_at + *d)
+}
+
+/// 署名自体の有効期限が切れているか判定する。
+fn is_signature_expired(sig: &Signature, now: chrono::DateTime<chrono::Utc>) -> bool {
+    match (sig.created(), sig.signature_expiration_time()) {
+        (Some(created), Some(d)) if !d.is_zero() => *created + *d <= now,
+        _ => false,
+    }
+}
+
+/// 主鍵の最新の自己署名（Direct Key 署名または User ID の自己認証）から有効期限を求める。
+fn primary_expires_at(key: &SignedPublicKey) -> Option<chrono::DateTime<chrono::Utc>> {
+    let direct = key
+        .details
+        .direct_signatures
+        .iter()
+        .filter(|sig| sig.verify_key(&key.primary_key).is_ok());
+    let certifications = key.details.users.iter().flat_map(|u| {
+        u.signatures.iter().filter(move |sig| {
+            sig.verify_third_party_certification(key, &key.primary_key, Tag::UserId, &u.id)
+                .is_ok()
+        })
+    });
+    direct
+        .chain(certifications)
+        .max_by_key(|sig| sig.created().copied())
+        .and_then(|sig| key_expires_at(key.primary_key.created_at(), sig))
+}
+
+/// 主鍵が失効・期限切れでないか検証する。
+fn check_primary(
+    key: &SignedPublicKey,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(), XryptonError> {
+    if is_key_revoked(key) {
+        return Err(XryptonError::Revoked("primary key is revoked".into()));
+    }
+    if let Some(expires_at) = primary_expires_at(key)
+        && expires_at <= now
+    {
+        return Err(XryptonError::Expired(format!(
+            "primary key expired at {expires_at}"
+        )));
+    }
+    Ok(())
+}
+
+/// サブキーの失効・バインディング・有効期限を判定する。
+pub fn subkey_status(
+    key: &SignedPublicKey,
+    subkey: &SignedPublicSubKey,
+    now: chrono::DateTime<chrono::Utc>,
+) -> SubkeyStatus {
+    if is_subkey_revoked(key, subkey) {
+        return SubkeyStatus::Revoked;
+    }
+    let Some(binding) = latest_subkey_binding(key, subkey) else {
+        return SubkeyStatus::Unbound;
+    };
+    let expired = is_signature_expired(binding, now)
+        || key_expires_at(subkey.key.created_at(), binding).is_some_and(|at| at <= now);
+    if expired {
+        SubkeyStatus::Expired
+    } else {
+        SubkeyStatus::Valid
+    }
+}
+
+/// 署名作成時刻 `at` の時点でのサブキーの有効性を判定する。
+///
+/// 置き換え・廃止による失効は失効署名の作成以降のみ無効とし、
+/// それ以外の失効は作成時刻にかかわらず無効とする。
+fn subkey_status_at(
+    key: &SignedPublicKey,
+    subkey: &SignedPublicSubKey,
+    at: chrono::DateTime<chrono::Utc>,
+) -> SubkeyStatus {
+    let revoked = subkey.signatures.iter().any(|sig| {
+        matches!(sig.typ(), Some(SignatureType::SubkeyRevocation))
+            && sig
+                .verify_key_binding(&key.primary_key, &subkey.key)
+                .is_ok()
+            && (is_hard_revocation(sig) || sig.created().is_some_and(|created| *created <= at))
+    });
+    if revoked {
+        return SubkeyStatus::Revoked;
+    }
+    if *subkey.key.created_at() > at {
+        return SubkeyStatus::Unbound;
+    }
+    let binding = subkey
+        .signatures
+        .iter()
+        .filter(|sig| {
+            matches!(sig.typ(), Some(SignatureType::SubkeyBinding))
+                && sig.created().is_some_and(|created| *created <= at)
+                && sig
+                    .verify_key_binding(&key.primary_key, &subkey.key)
+                    .is_ok()
+        })
+        .max_by_key(|sig| sig.created().copied());
+    let Some(binding) = binding else {
+        return SubkeyStatus::Unbound;
+    };
+    let expired = is_signature_expired(binding, at)
+        || key_expires_at(subkey.key.created_at(), binding).is_some_and(|exp| exp <= at);
+    if expired {
+        SubkeyStatus::Expired
+    } else {
+        SubkeyStatus::Valid
+    }
+}
+
+/// RSA 鍵として受け付ける最小の鍵長（ビット）
+const MIN_RSA_BITS: u16 = 2048;
+
+/// 十分な強度を持ち、サポートしている公開鍵アルゴリズムか検証する。
+fn check_algorithm(
+    algorithm: pgp::crypto::public_key::PublicKeyAlgorithm,
+    params: &pgp::types::PublicParams,
+) -> Result<(), XryptonError> {
+    use pgp::crypto::public_key::PublicKeyAlgorithm as Alg;
+
+    match algorithm {
+        Alg::RSA | Alg::RSASign | Alg::RSAEncrypt => {
+            let mut buf = Vec::new();
+            params
+                .to_writer(&mut buf)
+                .map_err(|e| XryptonError::KeyFormat(e.to_string()))?;
+            // RSA の公開パラメータは法 n の MPI（先頭2バイトがビット長）から始まる
+            let bits = buf
+                .get(..2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .unwrap_or(0);
+            if bits < MIN_RSA_BITS {
+                return Err(XryptonError::Unsuitable(format!(
+                    "RSA key is too short ({bits} bits, at least {MIN_RSA_BITS} required)"
+                )));
+            }
+            Ok(())
+        }
+        Alg::EdDSALegacy
+        | Alg::Ed25519
+        | Alg::Ed448
+        | Alg::ECDSA
+        | Alg::ECDH
+        | Alg::X25519
+        | Alg::X448 => Ok(()),
+        other => Err(XryptonError::Unsuitable(format!(
+            "{other:?} keys are not supported"
+        ))),
+    }
+}
+
+/// サブキーのアルゴリズムが使用可能か判定する。
+fn is_suitable_subkey(subkey: &SignedPublicSubKey) -> bool {
+    check_algorithm(subkey.key.algorithm(), subkey.key.public_params()).is_ok()
+}
+
+/// インポートする鍵の構成。
+///
+/// 有効な署名・暗号化サブキーがない場合は、インポート時に主鍵で認証したサブキーを追加する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyLayout {
+    pub has_signing_subkey: bool,
+    pub has_encryption_subkey: bool,
+}
+
+impl KeyLayout {
+    /// サブキーを追加せずにそのまま使用できるか
+    pub fn is_complete(&self) -> bool {
+        self.has_signing_subkey && self.has_encryption_subkey
+    }
+}
+
+/// 既存の OpenPGP 鍵をインポートできるか検査し、鍵の構成を返す。
+///
+/// 主鍵が失効・期限切れ、または強度不足・非対応のアルゴリズムの場合は理由を示すエラーを返す。
+pub fn inspect_key_layout(key: &SignedPublicKey) -> Result<KeyLayout, XryptonError> {
+    use pgp::crypto::public_key::PublicKeyAlgorithm as Alg;
+
+    check_primary(key, chrono::Utc::now())?;
+    let algorithm = key.primary_key.algorithm();
+    check_algorithm(algorithm, key.primary_key.public_params()).map_err(|e| match e {
+        XryptonError::Unsuitable(reason) => {
+            XryptonError::Unsuitable(format!("primary key: {reason}"))
+        }
+        e => e,
+    })?;
+    // 主鍵は追加するサブキーや他の鍵の認証に使うため署名可能である必要がある
+    if !matches!(
+        algorithm,
+        Alg::RSA | Alg::RSASign | Alg::EdDSALegacy | Alg::Ed25519 | Alg::Ed448 | Alg::ECDSA
+    ) {
+        return Err(XryptonError::Unsuitable(
+            "primary key cannot certify subkeys".into(),
+        ));
+    }
+
+    Ok(KeyLayout {
+        has_signing_subkey: !valid_signing_subkeys(key).is_empty(),
+        has_encryption_subkey: !valid_encryption_subkeys(key).is_empty(),
+    })
+}
+
+/// 条件に合う有効なサブキーを新しい順に返す。主鍵が無効な場合は空。
+fn valid_subkeys(
+    key: &SignedPublicKey,
+    is_target: impl Fn(&SignedPublicSubKey) -> bool,
+) -> Vec<&SignedPublicSubKey> {
+    let now = chrono::Utc::now();
+    if check_primary(key, now).is_err() {
+        return Vec::new();
+    }
+    // 作成日時が同じ場合は後から追加したサブキーを優先する
+    let mut subkeys: Vec<&SignedPublicSubKey> = key
+        .public_subkeys
+        .iter()
+        .rev()
+        .filter(|k| {
+            is_target(k)
+                && is_suitable_subkey(k)
+                && subkey_status(key, k, now) == SubkeyStatus::Valid
+        })
+        .collect();
+    subkeys.sort_by_key(|k| std::cmp::Reverse(*k.key.created_at()));
+    subkeys
+}
+
+/// 条件に合う有効なサブキーがない理由を表すエラーを返す。
+///
+/// 条件に合うサブキー自体がない場合は `None`。
+fn invalid_subkey_reason(
+    key: &SignedPublicKey,
+    is_target: impl Fn(&SignedPublicSubKey) -> bool,
+    kind: &str,
+) -> Option<XryptonError> {
+    let now = chrono::Utc::now();
+    if let Err(e) = check_primary(key, now) {
+        return Some(e);
+    }
+    let targets: Vec<&SignedPublicSubKey> =
+        key.public_subkeys.iter().filter(|k| is_target(k)).collect();
+    if let Some(subkey) = targets.first()
+        && targets.iter().all(|k| !is_suitable_subkey(k))
+        && let Err(e) = check_algorithm(subkey.key.algorithm(), subkey.key.public_params())
+    {
+        return Some(e);
+    }
+    let statuses: Vec<SubkeyStatus> = targets
+        .into_iter()
+        .map(|k| subkey_status(key, k, now))
+        .collect();
+    if statuses.contains(&SubkeyStatus::Expired) {
+        Some(XryptonError::Expired(format!("{kind} subkey has expired")))
+    } else if statuses.contains(&SubkeyStatus::Revoked) {
+        Some(XryptonError::Revoked(format!("{kind} subkey is revoked")))
+    } else if statuses.contains(&SubkeyStatus::Unbound) {
+        Some(XryptonError::Unbound(format!(
+            "{kind} subkey is not bound to the primary key"
+        )))
+    } else {
+        None
+    }
+}
+
+/// 主鍵自身による鍵失効署名か判定する。
+fn is_key_revocation(key: &SignedPublicKey, sig: &Signature) -> bool {
+    matches!(sig.typ(), Some(SignatureType::KeyRevocation))
+        && sig.verify_key(&key.primary_key).is_ok()
+}
+
+/// 主鍵が失効しているか判定する。
+fn is_key_revoked(key: &SignedPublicKey) -> bool {
+    key.details
+        .revocation_signatures
+        .iter()
+        .any(|sig| is_key_revocation(key, sig))
+}
+
+/// armored 形式の失効証明書から署名パケットを取り出す。
+fn parse_revocation_certificate(armored: &str) -> Result<Signature, XryptonError> {
+    use pgp::armor::Dearmor;
+    use std::io::{BufReader, Read};
+
+    let mut dearmor = Dearmor::new(BufReader::new(armored.as_bytes()));
+    let mut bytes = Vec::new();
+    dearmor
+        .read_to_end(&mut bytes)
+        .map_err(|e| XryptonError::KeyFormat(format!("dearmor failed: {e}")))?;
+    first_signature_from_bytes(&bytes)
+}
+
+/// 主鍵で認証され、失効・期限切れでない署名サブキー（デバイス鍵）を新しい順に返す。
+///
+/// 主鍵が失効・期限切れの場合は有効なサブキーはない。
+pub fn valid_signing_subkeys(key: &SignedPublicKey) -> Vec<&SignedPublicSubKey> {
+    valid_subkeys(key, |k| k.key.is_signing_key())
+}
+
+/// 主鍵で認証され、失効・期限切れでない暗号化サブキーを新しい順に返す。
+pub fn valid_encryption_subkeys(key: &SignedPublicKey) -> Vec<&SignedPublicSubKey> {
+    valid_subkeys(key, |k| k.key.is_encryption_key())
+}
+
+/// 署名サブキー（デバイス鍵）の状態。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningSubkeyInfo {
+    pub fingerprint: String,
+    pub key_id: String,
+    pub revoked: bool,
+    pub expired: bool,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
 /// Server-side public key holder for signature verification.
 #[derive(Debug)]
 pub struct PublicKeys {
@@ -315,12 +793,172 @@ pub struct PublicKeys {
 }
 
 impl PublicKeys {
+    /// 最初の有効な署名サブキー
     fn signing_public(&self) -> Result<&SignedPublicSubKey, XryptonError> {
+        valid_signing_subkeys(&self.keys)
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                invalid_subkey_reason(&self.keys, |k| k.key.is_signing_key(), "signing")
+                    .unwrap_or_else(|| XryptonError::KeyFormat("no signing subkey found".into()))
+            })
+    }
+
+    /// 最新の有効な暗号化サブキー
+    fn encryption_public(&self) -> Result<&SignedPublicSubKey, XryptonError> {
+        valid_encryption_subkeys(&self.keys)
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                invalid_subkey_reason(&self.keys, |k| k.key.is_encryption_key(), "encryption")
+                    .unwrap_or_else(|| XryptonError::KeyFormat("no encryption subkey found".into()))
+            })
+    }
+
+    /// 署名者の Issuer 情報に一致する有効な署名サブキーを返す。
+    ///
+    /// Issuer 情報がない場合は最新の有効な署名サブキーを返す。
+    /// 現在は無効でも署名作成時刻 `signed_at` の時点で有効だったサブキーは、
+    /// 現在無効な理由とともに返す。
+    fn signer_subkey(
+        &self,
+        issuer_fingerprint: Option<String>,
+        issuer_key_id: Option<String>,
+        signed_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(&SignedPublicSubKey, Option<XryptonError>), XryptonError> {
+        let is_issuer = |k: &SignedPublicSubKey| match (&issuer_fingerprint, &issuer_key_id) {
+            (Some(fp), _) => format!("{:X}", k.fingerprint()) == *fp,
+            (None, Some(id)) => k.key_id().to_string() == *id,
+            (None, None) => true,
+        };
+        if let Some(subkey) = valid_signing_subkeys(&self.keys)
+            .into_iter()
+            .find(|k| is_issuer(k))
+        {
+            return Ok((subkey, None));
+        }
+        let reason = invalid_subkey_reason(
+            &self.keys,
+            |k| k.key.is_signing_key() && is_issuer(k),
+            "signing",
+        )
+        .unwrap_or_else(|| {
+            XryptonError::Verification("signer is not a valid signing subkey".into())
+        });
+
+        // 主鍵の失効や未束縛のサブキーは署名時点にかかわらず受け付けない
+        let historical = signed_at
+            .filter(|_| matches!(reason, XryptonError::Expired(_) | XryptonError::Revoked(_)))
+            .filter(|at| check_primary(&self.keys, *at).is_ok())
+            .and_then(|at| {
+                self.keys.public_subkeys.iter().find(|k| {
+                    k.key.is_signing_key()
+                        && is_issuer(k)
+                        && is_suitable_subkey(k)
+                        && subkey_status_at(&self.keys, k, at) == SubkeyStatus::Valid
+                })
+            });
+        match historical {
+            Some(subkey) => Ok((subkey, Some(reason))),
+            None => Err(reason),
+        }
+    }
+
+    /// 有効な署名サブキーのフィンガープリントを大文字16進文字列で返す。
+    pub fn get_signing_sub_key_fingerprints(&self) -> Vec<String> {
+        valid_signing_subkeys(&self.keys)
+            .into_iter()
+            .map(|k| format!("{:X}", k.fingerprint()))
+            .collect()
+    }
+
+    /// 指定したフィンガープリントが有効な署名サブキーか判定する。
+    pub fn is_valid_signing_sub_key(&self, fingerprint: &str) -> bool {
+        self.get_signing_sub_key_fingerprints()
+            .iter()
+            .any(|fp| fp.eq_ignore_ascii_case(fingerprint))
+    }
+
+    /// 主鍵で認証されたすべての署名サブキーと失効・有効期限の状態を返す。
+    pub fn signing_sub_keys(&self) -> Vec<SigningSubkeyInfo> {
+        let now = chrono::Utc::now();
         self.keys
             .public_subkeys
             .iter()
-            .find(|k| k.key.is_signing_key())
-            .ok_or_else(|| XryptonError::KeyFormat("no signing subkey found".into()))
+            .filter_map(|k| {
+                let binding = latest_subkey_binding(&self.keys, k);
+                (k.key.is_signing_key() && binding.is_some()).then(|| {
+                    let status = subkey_status(&self.keys, k, now);
+                    SigningSubkeyInfo {
+                        fingerprint: format!("{:X}", k.fingerprint()),
+                        key_id: k.key_id().to_string(),
+                        revoked: status == SubkeyStatus::Revoked,
+                        expired: status == SubkeyStatus::Expired,
+                        expires_at: binding.and_then(|sig| key_expires_at(k.key.created_at(), sig)),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// 主鍵が失効しているか判定する。
+    pub fn is_revoked(&self) -> bool {
+        is_key_revoked(&self.keys)
+    }
+
+    /// 失効証明書がこの鍵の主鍵による鍵失効署名であることを検証する。
+    pub fn verify_revocation_certificate(&self, armored: &str) -> Result<(), XryptonError> {
+        let sig = parse_revocation_certificate(armored)?;
+        if !matches!(sig.typ(), Some(SignatureType::KeyRevocation)) {
+            return Err(XryptonError::InvalidPayload(
+                "not a key revocation signature".into(),
+            ));
+        }
+        sig.verify_key(&self.keys.primary_key)
+            .map_err(|e| XryptonError::Verification(e.to_string()))
+    }
+
+    /// 失効証明書を取り込んだ armored 公開鍵を返す。
+    ///
+    /// 鍵を取得した他のクライアントやサーバにも失効が伝わるよう、鍵自体に失効署名を含める。
+    pub fn with_revocation_certificate(&self, armored: &str) -> Result<String, XryptonError> {
+        self.verify_revocation_certificate(armored)?;
+        let sig = parse_revocation_certificate(armored)?;
+        let mut keys = self.keys.clone();
+        if !keys.details.revocation_signatures.contains(&sig) {
+            keys.details.revocation_signatures.push(sig);
+        }
+        keys.to_armored_string(ArmorOptions::default())
+            .map_err(|e| XryptonError::KeyFormat(e.to_string()))
+    }
+
+    /// サブキー失効署名を対象のサブキーに取り込んだ armored 公開鍵を返す。
+    ///
+    /// 署名が主鍵によるいずれかのサブキーの失効署名でなければエラーを返す。
+    pub fn with_subkey_revocation_certificate(
+        &self,
+        armored: &str,
+    ) -> Result<String, XryptonError> {
+        let sig = parse_revocation_certificate(armored)?;
+        if !matches!(sig.typ(), Some(SignatureType::SubkeyRevocation)) {
+            return Err(XryptonError::InvalidPayload(
+                "not a subkey revocation signature".into(),
+            ));
+        }
+        let mut keys = self.keys.clone();
+        let primary = keys.primary_key.clone();
+        let subkey = keys
+            .public_subkeys
+            .iter_mut()
+            .find(|k| sig.verify_key_binding(&primary, &k.key).is_ok())
+            .ok_or_else(|| {
+                XryptonError::Verification("revocation does not match any subkey".into())
+            })?;
+        if !subkey.signatures.contains(&sig) {
+            subkey.signatures.push(sig);
+        }
+        keys.to_armored_string(ArmorOptions::default())
+            .map_err(|e| XryptonError::KeyFormat(e.to_string()))
     }
 
     /// Returns the key ID of the signing subkey (hex string).
@@ -338,6 +976,21 @@ impl PublicKeys {
         Ok(format!("{:X}", self.signing_public()?.fingerprint()))
     }
 
+    /// 最新の有効な暗号化サブキーのフィンガープリントを大文字16進文字列で返す。
+    pub fn get_encryption_sub_key_fingerprint(&self) -> Result<String, XryptonError> {
+        Ok(format!("{:X}", self.encryption_public()?.fingerprint()))
+    }
+
+    /// 主鍵が有効で、有効な署名サブキーと暗号化サブキーを持つことを検証する。
+    ///
+    /// 鍵の登録・更新時など、現時点で使用可能な鍵であることを要求する場合に使う。
+    pub fn validate(&self) -> Result<(), XryptonError> {
+        check_primary(&self.keys, chrono::Utc::now())?;
+        self.signing_public()?;
+        self.encryption_public()?;
+        Ok(())
+    }
+
     /// PGP公開鍵のプライマリユーザIDからアドレス（`user@domain`）を抽出する。
     pub fn get_primary_user_address(&self) -> Result<String, XryptonError> {
         let uid_str = self
@@ -350,67 +1003,119 @@ impl PublicKeys {
         extract_address_from_uid(uid_str).map(str::to_owned)
     }
 
-    /// Verifies a PGP signed message and returns the verified plaintext.
+    /// 署名メッセージを検証し、ペイロードと検証レポートを返す。
     ///
+    /// パース失敗時のみ Err を返し、署名者や署名の問題はレポートの `outcome` で報告する。
+    pub fn verify_with_report(
+        &self,
+        armored: &str,
+    ) -> Result<(Vec<u8>, SignatureReport), XryptonError> {
+        let (msg, _) =
+            Message::from_string(armored).map_err(|e| XryptonError::Verification(e.to_string()))?;
+        self.verify_message(msg, inspect_message(armored)?)
+    }
+
+    /// raw PGP バイト列の署名を検証し、ペイロードと検証レポートを返す。
+    pub fn verify_bytes_with_report(
+        &self,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, SignatureReport), XryptonError> {
+        let msg = Message::from_bytes(std::io::Cursor::new(data))
+            .map_err(|e| XryptonError::Verification(e.to_string()))?;
+        self.verify_message(msg, inspect_message_bytes(data)?)
+    }
+
     /// `verify_read()` は内部で `drain()` を呼びストリームを消費するため、
     /// 先にデータを読み出してからでないとペイロードが空になる。
     /// そのため `as_data_vec()` → `verify_read()` の順で呼ぶ。
-    pub fn verify_and_extract(&self, armored: &str) -> Result<Vec<u8>, XryptonError> {
-        let (data, verified) = self.extract_and_verify(armored)?;
-        if verified {
-            Ok(data)
-        } else {
-            Err(XryptonError::Verification(
-                "signature verification failed".to_string(),
-            ))
-        }
-    }
-
-    /// armored PGP メッセージからデータを抽出し、署名検証結果とともに返す。
-    /// パース失敗時のみ Err を返し、署名不一致ではデータを返しつつ verified=false とする。
-    pub fn extract_and_verify(&self, armored: &str) -> Result<(Vec<u8>, bool), XryptonError> {
-        let (msg, _) =
-            Message::from_string(armored).map_err(|e| XryptonError::Verification(e.to_string()))?;
+    fn verify_message(
+        &self,
+        msg: Message,
+        mut report: SignatureReport,
+    ) -> Result<(Vec<u8>, SignatureReport), XryptonError> {
         let mut msg = msg
             .decompress()
             .map_err(|e| XryptonError::Verification(e.to_string()))?;
-        let signing_key = self.signing_public()?;
-        // データを先に読み出す（ハッシュ計算もこの段階で行われる）
         let data = msg
             .as_data_vec()
             .map_err(|e| XryptonError::Verification(e.to_string()))?;
-        // 読み出し後に署名を検証する（drain は 0 バイト読み出し → verify のみ実行）
-        let verified = msg.verify_read(signing_key).is_ok();
-        Ok((data, verified))
+        // 期限切れ・未束縛の署名鍵を区別して報告するため、先に署名者を解決する
+        report.outcome = match self.signer_subkey(
+            report.signer_fingerprint.clone(),
+            report.signer_key_id.clone(),
+            report.created_at,
+        ) {
+            Ok((signing_key, invalid_now)) => {
+                // 鍵IDのみの署名でも解決したサブキーのフィンガープリントを報告する
+                report.signer_fingerprint = Some(format!("{:X}", signing_key.fingerprint()));
+                report.signer_key_id = Some(signing_key.key_id().to_string());
+                match (msg.verify_read(signing_key), invalid_now) {
+                    (Ok(_), None) => VerificationOutcome::Verified,
+                    (Ok(_), Some(e)) => VerificationOutcome::VerifiedHistorical(e),
+                    (Err(e), _) => VerificationOutcome::BadSignature(e.to_string()),
+                }
+            }
+            Err(e) => VerificationOutcome::SignerUnusable(e),
+        };
+        Ok((data, report))
+    }
+
+    /// Verifies a PGP signed message and returns the verified plaintext.
+    pub fn verify_and_extract(&self, armored: &str) -> Result<Vec<u8>, XryptonError> {
+        let (data, report) = self.verify_with_report(armored)?;
+        report.ensure_verified()?;
+        Ok(data)
+    }
+
+    /// armored PGP メッセージからデータを抽出し、署名検証結果とともに返す。
+    /// パース失敗時のみ Err を返し、署名不一致ではデータを返しつつ verified=false とする。
+    /// 署名時点で有効だったサブキーによる署名は verified=true とする。
+    pub fn extract_and_verify(&self, armored: &str) -> Result<(Vec<u8>, bool), XryptonError> {
+        let (data, report) = self.verify_with_report(armored)?;
+        Ok((data, report.is_valid_when_signed()))
+    }
+
+    /// 署名を検証してペイロードと署名サブキーのフィンガープリントを返す。
+    pub fn verify_and_extract_with_signer(
+        &self,
+        armored: &str,
+    ) -> Result<(Vec<u8>, String), XryptonError> {
+        let (data, report) = self.verify_with_report(armored)?;
+        report.ensure_verified()?;
+        let signer = report
+            .signer_fingerprint
+            .ok_or_else(|| XryptonError::Verification("no issuer found in message".into()))?;
+        Ok((data, signer))
     }
 
     /// raw PGP バイト列の署名を検証してペイロードを取り出す。
     pub fn verify_and_extract_from_bytes(&self, data: &[u8]) -> Result<Vec<u8>, XryptonError> {
-        let msg = Message::from_bytes(std::io::Cursor::new(data))
-            .map_err(|e| XryptonError::Verification(e.to_string()))?;
+        let (payload, report) = self.verify_bytes_with_report(data)?;
+        report.ensure_verified()?;
+        Ok(payload)
+    }
+
+    /// 主鍵で署名された armored メッセージを検証してペイロードを取り出す。
+    ///
+    /// 移行宣言など、サブキーではなく主鍵の署名を要求する用途に使う。
+    pub fn verify_primary_and_extract(&self, armored: &str) -> Result<Vec<u8>, XryptonError> {
+        check_primary(&self.keys, chrono::Utc::now())?;
+        let (msg, _) =
+            Message::from_string(armored).map_err(|e| XryptonError::Verification(e.to_string()))?;
         let mut msg = msg
             .decompress()
             .map_err(|e| XryptonError::Verification(e.to_string()))?;
-        let signing_key = self.signing_public()?;
         let payload = msg
             .as_data_vec()
             .map_err(|e| XryptonError::Verification(e.to_string()))?;
-        msg.verify_read(signing_key)
+        msg.verify_read(&self.keys.primary_key)
             .map_err(|e| XryptonError::Verification(e.to_string()))?;
         Ok(payload)
     }
 
     /// Verifies a PGP signed message without extracting data.
     pub fn verify(&self, armored: &str) -> Result<(), XryptonError> {
-        let (msg, _) =
-            Message::from_string(armored).map_err(|e| XryptonError::Verification(e.to_string()))?;
-        let mut msg = msg
-            .decompress()
-            .map_err(|e| XryptonError::Verification(e.to_string()))?;
-        let signing_key = self.signing_public()?;
-        msg.verify_read(signing_key)
-            .map(|_| ())
-            .map_err(|e| XryptonError::Verification(e.to_string()))
+        self.verify_with_report(armored)?.1.ensure_verified()
     }
 }
 
@@ -420,11 +1125,27 @@ impl TryFrom<&str> for PublicKeys {
         let (keys, _) = SignedPublicKey::from_string(value)
             .map_err(|e| XryptonError::KeyFormat(e.to_string()))?;
         let subkeys = &keys.public_subkeys;
-        if !subkeys.iter().any(|k| k.is_signing_key())
-            || !subkeys.iter().any(|k| k.is_encryption_key())
-        {
+        // 主鍵のみで署名する鍵などはインポート時にサブキーを追加する必要がある
+        if !subkeys.iter().any(|k| k.is_signing_key()) {
+            return Err(XryptonError::KeyFormat(
+                "key has no signing subkey; import the key to add one".into(),
+            ));
+        }
+        if !subkeys.iter().any(|k| k.is_encryption_key()) {
             return Err(XryptonError::KeyFormat(
-                "both signing and encryption subkeys are required".into(),
+                "key has no encryption subkey; import the key to add one".into(),
+            ));
+        }
+        // 主鍵に束縛されていないサブキーしかない鍵は受け付けない
+        // （期限切れ・失効したサブキーは署名時点での検証のため許容）
+        let bound = |is_target: fn(&SignedPublicSubKey) -> bool| {
+            subkeys
+                .iter()
+                .any(|k| is_target(k) && latest_subkey_binding(&keys, k).is_some())
+        };
+        if !bound(|k| k.is_signing_key()) || !bound(|k| k.is_encryption_key()) {
+            return Err(XryptonError::Unbound(
+                "signing and encryption subkeys must be bound to the primary key".into(),
             ));
         }
         Ok(PublicKeys { keys })
@@ -476,42 +1197,110 @@ mod tests {
         assert!(extract_address_from_uid("Name <user@example.com").is_err());
     }
 
-    /// 鍵ペアを生成し、公開鍵からPublicKeysを構築してsigning key IDを取得できることを確認
-    #[test]
-    fn parse_public_key_and_get_signing_key_id() {
-        let signing_sub = SubkeyParamsBuilder::default()
-            .version(KeyVersion::V4)
-            .key_type(KeyType::Ed25519Legacy)
-            .can_sign(true)
-            .can_encrypt(false)
-            .passphrase(Some("pass".into()))
-            .build()
-            .unwrap();
+    /// テスト用の鍵の生成オプション
+    #[derive(Default)]
+    struct TestKeyOptions {
+        /// RFC 9580 (v6) 鍵を生成する
+        v6: bool,
+        /// 追加する署名サブキー（デバイス鍵）の数
+        extra_signing_subkeys: usize,
+        /// 主鍵の有効期間
+        expires_in: Option<chrono::Duration>,
+        /// 失効証明書を取り込んだ公開鍵を返す
+        revoked: bool,
+    }
+
+    /// テスト用の鍵を生成し、秘密鍵と armored 形式の公開鍵を返す。
+    ///
+    /// 主鍵のパスフレーズは `main`、サブキーは `sub`。
+    fn test_key(options: TestKeyOptions) -> (SignedSecretKey, String) {
+        let (version, sign_type, encrypt_type) = if options.v6 {
+            (KeyVersion::V6, KeyType::Ed25519, KeyType::X25519)
+        } else {
+            (
+                KeyVersion::V4,
+                KeyType::Ed25519Legacy,
+                KeyType::ECDH(crypto::ecc_curve::ECCCurve::Curve25519),
+            )
+        };
+        let signing_sub = || {
+            SubkeyParamsBuilder::default()
+                .version(version)
+                .key_type(sign_type.clone())
+                .can_sign(true)
+                .can_encrypt(false)
+                .passphrase(Some("sub".into()))
+                .build()
+                .unwrap()
+        };
         let encryption_sub = SubkeyParamsBuilder::default()
-            .version(KeyVersion::V4)
-            .key_type(KeyType::ECDH(crypto::ecc_curve::ECCCurve::Curve25519))
+            .version(version)
+            .key_type(encrypt_type)
             .can_sign(false)
             .can_encrypt(true)
-            .passphrase(Some("pass".into()))
+            .passphrase(Some("sub".into()))
             .build()
             .unwrap();
-        let params = SecretKeyParamsBuilder::default()
-            .version(KeyVersion::V4)
-            .key_type(KeyType::Ed25519Legacy)
+        let mut subkeys = vec![signing_sub(), encryption_sub];
+        subkeys.extend((0..options.extra_signing_subkeys).map(|_| signing_sub()));
+
+        let signed_key = SecretKeyParamsBuilder::default()
+            .version(version)
+            .key_type(sign_type)
             .can_sign(true)
             .can_encrypt(false)
-            .passphrase(Some("pass".into()))
-            .subkeys(vec![signing_sub, encryption_sub])
+            .passphrase(Some("main".into()))
+            .expiration(options.expires_in)
+            .subkeys(subkeys)
             .primary_user_id("test <test@example.com>".into())
             .build()
+            .unwrap()
+            .generate(OsRng)
+            .unwrap()
+            .sign(OsRng, &"main".into())
             .unwrap();
-
-        let secret = params.generate(OsRng).unwrap();
-        let signed = secret.sign(OsRng, &"pass".into()).unwrap();
-        let pub_armored = signed
+        let mut public = signed_key
             .signed_public_key()
             .to_armored_string(ArmorOptions::default())
             .unwrap();
+        if options.revoked {
+            public = PublicKeys::try_from(public.as_str())
+                .unwrap()
+                .with_revocation_certificate(&revocation_certificate(&signed_key))
+                .unwrap();
+        }
+        (signed_key, public)
+    }
+
+    /// 主鍵で署名した失効証明書を armored 形式で返す（v4 鍵のみ）。
+    fn revocation_certificate(signed_key: &SignedSecretKey) -> String {
+        use pgp::packet::SignatureVersionSpecific;
+        use pgp::types::Password;
+
+        let cfg = pgp::packet::SignatureConfig {
+            typ: SignatureType::KeyRevocation,
+            pub_alg: signed_key.primary_key.algorithm(),
+            hash_alg: pgp::crypto::hash::HashAlgorithm::Sha512,
+            hashed_subpackets: vec![],
+            unhashed_subpackets: vec![],
+            version_specific: SignatureVersionSpecific::V4,
+        };
+        let sig = cfg
+            .sign_key(
+                &signed_key.primary_key,
+                &Password::from("main"),
+                &signed_key.signed_public_key().primary_key,
+            )
+            .unwrap();
+        DetachedSignature::new(sig)
+            .to_armored_string(ArmorOptions::default())
+            .unwrap()
+    }
+
+    /// 鍵ペアを生成し、公開鍵からPublicKeysを構築してsigning key IDを取得できることを確認
+    #[test]
+    fn parse_public_key_and_get_signing_key_id() {
+        let (_, pub_armored) = test_key(TestKeyOptions::default());
 
         let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
         let key_id = pk.get_signing_sub_key_id().unwrap();
@@ -588,6 +1377,136 @@ mod tests {
         assert_eq!(payload, b"test payload");
     }
 
+    /// 検証レポートに署名者・ハッシュ・層構造・検証結果が含まれることを確認
+    #[test]
+    fn verification_report_describes_message() {
+        use pgp::crypto::hash::HashAlgorithm;
+        use pgp::types::{Password, PublicKeyTrait};
+
+        let (signed_key, pub_armored) = test_key(TestKeyOptions::default());
+        let (_, other_armored) = test_key(TestKeyOptions::default());
+        let signing_subkey = signed_key
+            .secret_subkeys
+            .iter()
+            .find(|k| k.public_key().is_signing_key())
+            .expect("signing subkey");
+
+        let mut builder = MessageBuilder::from_bytes("", b"test payload".to_vec());
+        builder.compression(CompressionAlgorithm::ZLIB);
+        builder.sign(
+            &signing_subkey.key,
+            Password::from("sub"),
+            HashAlgorithm::Sha512,
+        );
+        let armored = builder
+            .to_armored_string(OsRng, ArmorOptions::default())
+            .unwrap();
+
+        let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
+        let (payload, report) = pk.verify_with_report(&armored).unwrap();
+        assert_eq!(payload, b"test payload");
+        assert!(report.is_verified());
+        assert_eq!(
+            report.signer_fingerprint,
+            Some(format!("{:X}", signing_subkey.fingerprint()))
+        );
+        assert_eq!(
+            report.signer_key_id,
+            Some(signing_subkey.key_id().to_string())
+        );
+        assert!(report.created_at.is_some());
+        assert_eq!(report.hash_algorithm.as_deref(), Some("Sha512"));
+        assert!(report.layers.contains(&MessageLayer::Signed));
+        assert!(report.layers.contains(&MessageLayer::Literal));
+        assert!(
+            report
+                .layers
+                .iter()
+                .any(|layer| matches!(layer, MessageLayer::Compressed(_)))
+        );
+
+        // 別の鍵では署名者を解決できず、データは返すが未検証になる
+        let other = PublicKeys::try_from(other_armored.as_str()).unwrap();
+        let (payload, report) = other.verify_with_report(&armored).unwrap();
+        assert_eq!(payload, b"test payload");
+        assert!(matches!(
+            report.outcome,
+            VerificationOutcome::SignerUnusable(_)
+        ));
+        assert!(report.ensure_verified().is_err());
+    }
+
+    /// 主鍵署名は verify_primary_and_extract でのみ受理され、サブキー署名は拒否されることを確認
+    #[test]
+    fn verify_primary_signature() {
+        use pgp::crypto::hash::HashAlgorithm;
+        use pgp::types::{Password, PublicKeyTrait};
+
+        let (signed_key, pub_armored) = test_key(TestKeyOptions::default());
+        let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
+
+        let mut builder = MessageBuilder::from_bytes("", b"moved".to_vec());
+        builder.sign(
+            &signed_key.primary_key,
+            Password::from("main"),
+            HashAlgorithm::Sha512,
+        );
+        let by_primary = builder
+            .to_armored_string(OsRng, ArmorOptions::default())
+            .unwrap();
+        assert_eq!(
+            pk.verify_primary_and_extract(&by_primary).unwrap(),
+            b"moved"
+        );
+
+        let signing_subkey = signed_key
+            .secret_subkeys
+            .iter()
+            .find(|k| k.public_key().is_signing_key())
+            .expect("signing subkey");
+        let mut builder = MessageBuilder::from_bytes("", b"moved".to_vec());
+        builder.sign(
+            &signing_subkey.key,
+            Password::from("sub"),
+            HashAlgorithm::Sha512,
+        );
+        let by_subkey = builder
+            .to_armored_string(OsRng, ArmorOptions::default())
+            .unwrap();
+        assert!(pk.verify_primary_and_extract(&by_subkey).is_err());
+    }
+
+    /// 複数の署名サブキー（デバイス鍵）のどれで署名しても検証できることを確認
+    #[test]
+    fn verify_with_any_device_subkey() {
+        use pgp::crypto::hash::HashAlgorithm;
+        use pgp::types::Password;
+
+        let (signed_key, pub_armored) = test_key(TestKeyOptions {
+            extra_signing_subkeys: 1,
+            ..Default::default()
+        });
+        let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
+        let fingerprints = pk.get_signing_sub_key_fingerprints();
+        assert_eq!(fingerprints.len(), 2);
+        assert!(pk.signing_sub_keys().iter().all(|k| !k.revoked));
+
+        for device in signed_key
+            .secret_subkeys
+            .iter()
+            .filter(|k| k.public_key().is_signing_key())
+        {
+            let mut builder = MessageBuilder::from_bytes("", b"hello".to_vec());
+            builder.sign(&device.key, Password::from("sub"), HashAlgorithm::Sha512);
+            let armored = builder
+                .to_armored_string(OsRng, ArmorOptions::default())
+                .unwrap();
+            let (payload, signer) = pk.verify_and_extract_with_signer(&armored).unwrap();
+            assert_eq!(payload, b"hello");
+            assert!(pk.is_valid_signing_sub_key(&signer));
+        }
+    }
+
     /// サブキーで作成された certification 署名も検証できることを確認。
     #[test]
     fn verify_certification_with_signing_subkey() {
@@ -676,4 +1595,112 @@ mod tests {
             .unwrap()
         );
     }
+
+    /// 失効証明書を取り込んだ鍵は失効扱いとなり、署名サブキーも無効になることを確認。
+    #[test]
+    fn revocation_certificate_revokes_key() {
+        let (signed_key, pub_armored) = test_key(TestKeyOptions::default());
+        let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
+        assert!(!pk.is_revoked());
+
+        let certificate = revocation_certificate(&signed_key);
+        pk.verify_revocation_certificate(&certificate).unwrap();
+        let revoked_armored = pk.with_revocation_certificate(&certificate).unwrap();
+        let revoked = PublicKeys::try_from(revoked_armored.as_str()).unwrap();
+        assert!(revoked.is_revoked());
+        assert!(matches!(revoked.validate(), Err(XryptonError::Revoked(_))));
+        assert!(revoked.get_signing_sub_key_fingerprints().is_empty());
+        assert_eq!(
+            revoked.get_primary_fingerprint(),
+            pk.get_primary_fingerprint()
+        );
+
+        let (_, revoked_armored) = test_key(TestKeyOptions {
+            revoked: true,
+            ..Default::default()
+        });
+        assert!(
+            PublicKeys::try_from(revoked_armored.as_str())
+                .unwrap()
+                .is_revoked()
+        );
+    }
+
+    /// 主鍵のバインディング署名がないサブキーは Unbound として拒否されることを確認。
+    #[test]
+    fn rejects_unbound_subkeys() {
+        let (signed_key, bound) = test_key(TestKeyOptions::default());
+        let pk = PublicKeys::try_from(bound.as_str()).unwrap();
+        pk.validate().unwrap();
+        assert!(pk.get_encryption_sub_key_fingerprint().is_ok());
+        assert!(pk.signing_sub_keys().iter().all(|k| !k.expired));
+
+        let mut unbound = signed_key.signed_public_key();
+        for subkey in &mut unbound.public_subkeys {
+            subkey.signatures.clear();
+        }
+        let unbound = unbound.to_armored_string(ArmorOptions::default()).unwrap();
+        assert!(matches!(
+            PublicKeys::try_from(unbound.as_str()),
+            Err(XryptonError::Unbound(_))
+        ));
+    }
+
+    /// 主鍵の有効期限を過ぎると Expired として扱われることを確認。
+    #[test]
+    fn rejects_expired_primary_key() {
+        let (signed_key, _) = test_key(TestKeyOptions {
+            expires_in: Some(chrono::Duration::days(1)),
+            ..Default::default()
+        });
+        let public = signed_key.signed_public_key();
+        let now = chrono::Utc::now();
+        check_primary(&public, now).unwrap();
+        assert!(matches!(
+            check_primary(&public, now + chrono::Duration::days(2)),
+            Err(XryptonError::Expired(_))
+        ));
+    }
+
+    /// RFC 9580 (v6) 鍵でも署名検証とフィンガープリント判定ができることを確認。
+    #[test]
+    fn verify_with_v6_key() {
+        use pgp::crypto::hash::HashAlgorithm;
+        use pgp::types::Password;
+
+        let (signed_key, pub_armored) = test_key(TestKeyOptions {
+            v6: true,
+            ..Default::default()
+        });
+        let pk = PublicKeys::try_from(pub_armored.as_str()).unwrap();
+        assert_eq!(fingerprint_version(&pk.get_primary_fingerprint()), Some(6));
+
+        let signing_subkey = signed_key
+            .secret_subkeys
+            .iter()
+            .find(|k| k.public_key().is_signing_key())
+            .expect("signing subkey");
+        let mut builder = MessageBuilder::from_bytes("", b"hello".to_vec());
+        builder.sign(
+            &signing_subkey.key,
+            Password::from("sub"),
+            HashAlgorithm::Sha512,
+        );
+        let armored = builder
+            .to_armored_string(OsRng, ArmorOptions::default())
+            .unwrap();
+        assert_eq!(
+            extract_issuer_fingerprint(&armored).unwrap(),
+            pk.get_signing_sub_key_fingerprint().unwrap()
+        );
+        assert_eq!(pk.verify_and_extract(&armored).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn fingerprint_versions() {
+        assert_eq!(fingerprint_version(&"A".repeat(40)), Some(4));
+        assert_eq!(fingerprint_version(&"0".repeat(64)), Some(6));
+        assert_eq!(fingerprint_version(&"a".repeat(40)), None);
+        assert_eq!(fingerprint_version(&"A".repeat(42)), None);
+    }
 }
@@ -3,6 +3,7 @@ use base64::engine::general_purpose::STANDARD;
 use pgp::composed::{ArmorOptions, Message, MessageBuilder};
 use pgp::crypto::sym::SymmetricKeyAlgorithm;
 use pgp::types::{Password, StringToKey};
+use rand::RngCore;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +15,14 @@ const ARGON2_T_COST: u8 = 3;
 const ARGON2_P_COST: u8 = 1;
 const ARGON2_M_ENC: u8 = 16;
 
+const VERSION_V2: u8 = 2;
+const ALG_V2: &str = "xrypton_backup_v2";
+/// バックアップ鍵（ペイロードを暗号化する共通鍵）のバイト数
+const BACKUP_KEY_LEN: usize = 32;
+/// リカバリーコードのバイト数（Base32 で32文字）
+const RECOVERY_CODE_LEN: usize = 20;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
 #[derive(Debug, Deserialize)]
 pub struct BackupPayload {
     pub subpassphrase: String,
@@ -28,7 +37,7 @@ pub struct BackupEnvelopeV1 {
     pub inner_armored: String,
 }
 
-fn parse_payload(payload_json: &str) -> Result<BackupPayload, Error> {
+pub(crate) fn parse_payload(payload_json: &str) -> Result<BackupPayload, Error> {
     let payload: BackupPayload =
         serde_json::from_str(payload_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
     if payload.subpassphrase.is_empty() {
@@ -40,7 +49,7 @@ fn parse_payload(payload_json: &str) -> Result<BackupPayload, Error> {
     Ok(payload)
 }
 
-fn pgp_encrypt_with_password(plain: Vec<u8>, password: &str) -> Result<String, Error> {
+pub(crate) fn pgp_encrypt_with_password(plain: Vec<u8>, password: &str) -> Result<String, Error> {
     let mut builder =
         MessageBuilder::from_bytes("", plain).seipd_v1(OsRng, SymmetricKeyAlgorithm::AES256);
     builder
@@ -55,7 +64,7 @@ fn pgp_encrypt_with_password(plain: Vec<u8>, password: &str) -> Result<String, E
         .map_err(|e| Error::EncryptionError(e.to_string()))
 }
 
-fn pgp_decrypt_with_password(armored: &str, password: &str) -> Result<Vec<u8>, Error> {
+pub(crate) fn pgp_decrypt_with_password(armored: &str, password: &str) -> Result<Vec<u8>, Error> {
     let (msg, _) =
         Message::from_string(armored).map_err(|e| Error::DecryptionError(e.to_string()))?;
     let mut msg = msg
@@ -138,3 +147,425 @@ pub fn backup_decrypt(
     parse_payload(&payload_json)?;
     Ok((payload_json, envelope.webauthn_credential_id_b64))
 }
+
+/// v2 エンベロープの解除方法の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupMethodKind {
+    Passkey,
+    RecoveryCode,
+    Passphrase,
+}
+
+/// バックアップ鍵を解除方法ごとに暗号化したもの。
+///
+/// `id` はパスキーならクレデンシャルID、リカバリーコードならランダムな識別子、
+/// パスフレーズなら `"passphrase"`。
+/// `recovery_public_key` はパスキーとリカバリーコードから導出した回復用公開鍵で、
+/// サーバはエンベロープに含まれるものだけを取得の所持証明に使う。
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupMethod {
+    #[serde(rename = "type")]
+    pub kind: BackupMethodKind,
+    pub id: String,
+    pub wrapped_key_armored: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_public_key: Option<String>,
+}
+
+/// 複数の解除方法に対応したバックアップ。
+///
+/// ペイロードはランダムなバックアップ鍵で暗号化し、バックアップ鍵を解除方法ごとに暗号化して持つ。
+/// 解除方法の追加はいずれか1つの方法で、削除は解除なしで行える。
+///
+/// どの解除方法でも単独でバックアップを開けるため、安全性は最も弱い解除方法（通常はパスフレーズ）で
+/// 決まる。エンベロープ自体は暗号化されていない JSON なので、取得した者は全ての解除方法を
+/// オフラインで試せる。削除した解除方法は以後のエンベロープから開けなくなるが、削除前の
+/// エンベロープの複製は引き続き開けるため、漏洩が疑われる場合はバックアップを作り直す。
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupEnvelopeV2 {
+    pub version: u8,
+    pub alg: String,
+    pub payload_armored: String,
+    pub methods: Vec<BackupMethod>,
+}
+
+/// バックアップを開く方法
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UnlockMethod {
+    Passkey {
+        credential_id_b64: String,
+        prf_output_b64: String,
+    },
+    RecoveryCode {
+        code: String,
+    },
+    Passphrase {
+        passphrase: String,
+    },
+}
+
+/// 追加する解除方法。リカバリーコードは生成して返す
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NewMethod {
+    Passkey {
+        credential_id_b64: String,
+        prf_output_b64: String,
+    },
+    RecoveryCode,
+    Passphrase {
+        passphrase: String,
+    },
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// 印刷して保管するリカバリーコードを生成する（`XXXX-XXXX-...` 形式）。
+fn generate_recovery_code() -> String {
+    base32_encode(&random_bytes(RECOVERY_CODE_LEN))
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// 入力されたリカバリーコードから区切り文字を除き大文字に揃える。
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn build_recovery_code_password(code: &str) -> String {
+    format!("xrypton-recovery-v2:{}", normalize_recovery_code(code))
+}
+
+fn build_backup_key_password(backup_key_b64: &str) -> String {
+    format!("xrypton-backup-key-v2:{backup_key_b64}")
+}
+
+fn validate_prf_output(prf_output_b64: &str) -> Result<(), Error> {
+    let prf_output = STANDARD
+        .decode(prf_output_b64)
+        .map_err(|e| Error::InvalidPayload(format!("invalid prf output: {e}")))?;
+    if prf_output.is_empty() {
+        return Err(Error::InvalidPayload("prf output is empty".into()));
+    }
+    Ok(())
+}
+
+fn parse_envelope_v2(envelope_json: &str) -> Result<BackupEnvelopeV2, Error> {
+    let envelope: BackupEnvelopeV2 =
+        serde_json::from_str(envelope_json).map_err(|e| Error::DecryptionError(e.to_string()))?;
+    if envelope.version != VERSION_V2 {
+        return Err(Error::DecryptionError("unsupported backup version".into()));
+    }
+    if envelope.alg != ALG_V2 {
+        return Err(Error::DecryptionError(
+            "unsupported backup algorithm".into(),
+        ));
+    }
+    Ok(envelope)
+}
+
+fn serialize_envelope_v2(envelope: &BackupEnvelopeV2) -> Result<String, Error> {
+    if envelope.methods.is_empty() {
+        return Err(Error::InvalidPayload(
+            "at least one unlock method is required".into(),
+        ));
+    }
+    serde_json::to_string(envelope).map_err(|e| Error::EncryptionError(e.to_string()))
+}
+
+/// バックアップ鍵を新しい解除方法で暗号化する。
+/// 生成したリカバリーコードがあれば一緒に返す。
+fn wrap_backup_key(
+    backup_key_b64: &str,
+    method: NewMethod,
+) -> Result<(BackupMethod, Option<String>), Error> {
+    let (kind, id, password, recovery_code) = match method {
+        NewMethod::Passkey {
+            credential_id_b64,
+            prf_output_b64,
+        } => {
+            if credential_id_b64.is_empty() {
+                return Err(Error::InvalidPayload("credential id is required".into()));
+            }
+            validate_prf_output(&prf_output_b64)?;
+            (
+                BackupMethodKind::Passkey,
+                credential_id_b64,
+                build_prf_password(&prf_output_b64),
+                None,
+            )
+        }
+        NewMethod::RecoveryCode => {
+            let code = generate_recovery_code();
+            (
+                BackupMethodKind::RecoveryCode,
+                random_bytes(8).iter().map(|b| format!("{b:02x}")).collect(),
+                build_recovery_code_password(&code),
+                Some(code),
+            )
+        }
+        NewMethod::Passphrase { passphrase } => {
+            if passphrase.is_empty() {
+                return Err(Error::InvalidPayload("passphrase is required".into()));
+            }
+            (
+                BackupMethodKind::Passphrase,
+                "passphrase".to_string(),
+                passphrase,
+                None,
+            )
+        }
+    };
+    let wrapped_key_armored =
+        pgp_encrypt_with_password(backup_key_b64.as_bytes().to_vec(), &password)?;
+    let recovery_public_key = match kind {
+        BackupMethodKind::Passphrase => None,
+        _ => Some(
+            STANDARD.encode(
+                recovery_signing_key_from_secret(&password)
+                    .verifying_key()
+                    .to_bytes(),
+            ),
+        ),
+    };
+    Ok((
+        BackupMethod {
+            kind,
+            id,
+            wrapped_key_armored,
+            recovery_public_key,
+        },
+        recovery_code,
+    ))
+}
+
+/// いずれかの解除方法でバックアップ鍵を取り出す。
+fn unwrap_backup_key(envelope: &BackupEnvelopeV2, unlock: &UnlockMethod) -> Result<String, Error> {
+    let (candidates, password): (Vec<&BackupMethod>, String) = match unlock {
+        UnlockMethod::Passkey {
+            credential_id_b64,
+            prf_output_b64,
+        } => {
+            validate_prf_output(prf_output_b64)?;
+            (
+                envelope
+                    .methods
+                    .iter()
+                    .filter(|m| m.kind == BackupMethodKind::Passkey && m.id == *credential_id_b64)
+                    .collect(),
+                build_prf_password(prf_output_b64),
+            )
+        }
+        UnlockMethod::RecoveryCode { code } => (
+            envelope
+                .methods
+                .iter()
+                .filter(|m| m.kind == BackupMethodKind::RecoveryCode)
+                .collect(),
+            build_recovery_code_password(code),
+        ),
+        UnlockMethod::Passphrase { passphrase } => (
+            envelope
+                .methods
+                .iter()
+                .filter(|m| m.kind == BackupMethodKind::Passphrase)
+                .collect(),
+            passphrase.clone(),
+        ),
+    };
+    if candidates.is_empty() {
+        return Err(Error::DecryptionError(
+            "unlock method is not registered for this backup".into(),
+        ));
+    }
+    // リカバリーコードは複数登録できるため、一致するものが見つかるまで試す
+    for method in candidates {
+        if let Ok(plain) = pgp_decrypt_with_password(&method.wrapped_key_armored, &password) {
+            return String::from_utf8(plain).map_err(|e| Error::DecryptionError(e.to_string()));
+        }
+    }
+    Err(Error::DecryptionError(
+        "failed to unlock backup with the given method".into(),
+    ))
+}
+
+/// ペイロードを複数の解除方法で開けるバックアップ（v2）を作成する。
+///
+/// `methods_json` は [`NewMethod`] の配列。返り値は (エンベロープJSON, 生成したリカバリーコード)。
+pub fn backup_v2_create(
+    payload_json: &str,
+    methods_json: &str,
+) -> Result<(String, Vec<String>), Error> {
+    parse_payload(payload_json)?;
+    let methods: Vec<NewMethod> =
+        serde_json::from_str(methods_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+
+    let backup_key_b64 = STANDARD.encode(random_bytes(BACKUP_KEY_LEN));
+    let payload_armored = pgp_encrypt_with_password(
+        payload_json.as_bytes().to_vec(),
+        &build_backup_key_password(&backup_key_b64),
+    )?;
+    let mut envelope = BackupEnvelopeV2 {
+        version: VERSION_V2,
+        alg: ALG_V2.to_string(),
+        payload_armored,
+        methods: Vec::new(),
+    };
+    let mut recovery_codes = Vec::new();
+    for method in methods {
+        let (method, recovery_code) = wrap_backup_key(&backup_key_b64, method)?;
+        add_method(&mut envelope, method);
+        recovery_codes.extend(recovery_code);
+    }
+    Ok((serialize_envelope_v2(&envelope)?, recovery_codes))
+}
+
+/// 同じ ID の解除方法は置き換える（パスフレーズの変更、パスキーの再登録）。
+fn add_method(envelope: &mut BackupEnvelopeV2, method: BackupMethod) {
+    envelope
+        .methods
+        .retain(|m| !(m.kind == method.kind && m.id == method.id));
+    envelope.methods.push(method);
+}
+
+/// v2 バックアップを開き、ペイロードJSONを返す。
+pub fn backup_v2_decrypt(envelope_json: &str, unlock_json: &str) -> Result<String, Error> {
+    let envelope = parse_envelope_v2(envelope_json)?;
+    let unlock: UnlockMethod =
+        serde_json::from_str(unlock_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let backup_key_b64 = unwrap_backup_key(&envelope, &unlock)?;
+    let plain = pgp_decrypt_with_password(
+        &envelope.payload_armored,
+        &build_backup_key_password(&backup_key_b64),
+    )?;
+    let payload_json =
+        String::from_utf8(plain).map_err(|e| Error::DecryptionError(e.to_string()))?;
+    parse_payload(&payload_json)?;
+    Ok(payload_json)
+}
+
+/// 既存の解除方法の1つで開き、解除方法を追加する。
+///
+/// 返り値は (エンベロープJSON, 生成したリカバリーコード)。
+pub fn backup_v2_add_method(
+    envelope_json: &str,
+    unlock_json: &str,
+    method_json: &str,
+) -> Result<(String, Option<String>), Error> {
+    let mut envelope = parse_envelope_v2(envelope_json)?;
+    let unlock: UnlockMethod =
+        serde_json::from_str(unlock_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let method: NewMethod =
+        serde_json::from_str(method_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let backup_key_b64 = unwrap_backup_key(&envelope, &unlock)?;
+    let (method, recovery_code) = wrap_backup_key(&backup_key_b64, method)?;
+    add_method(&mut envelope, method);
+    Ok((serialize_envelope_v2(&envelope)?, recovery_code))
+}
+
+/// 解除方法を削除する。バックアップを開く必要はないが、最後の1つは削除できない。
+///
+/// 削除した解除方法の暗号化済みバックアップ鍵と回復用公開鍵はエンベロープから消える。
+/// 更新したエンベロープをサーバに保存すると、サーバ側の回復用公開鍵も削除される。
+pub fn backup_v2_remove_method(envelope_json: &str, method_id: &str) -> Result<String, Error> {
+    let mut envelope = parse_envelope_v2(envelope_json)?;
+    let before = envelope.methods.len();
+    envelope.methods.retain(|m| m.id != method_id);
+    if envelope.methods.len() == before {
+        return Err(Error::InvalidPayload("unlock method not found".into()));
+    }
+    serialize_envelope_v2(&envelope)
+}
+
+/// サーバがバックアップを渡す前に署名を求めるチャレンジのメッセージ。
+/// サーバ側（`routes/backup.rs`）と同じ形式でなければならない。
+fn retrieval_message(user_id: &str, challenge: &str) -> String {
+    format!("xrypton-backup-retrieval-v1\n{user_id}\n{challenge}")
+}
+
+/// 解除方法から回復用の Ed25519 署名鍵を導出する。
+///
+/// パスフレーズは推測可能なため、所持の証明に使えるのはパスキーとリカバリーコードのみ。
+fn recovery_signing_key(unlock: &UnlockMethod) -> Result<ed25519_dalek::SigningKey, Error> {
+    let secret = match unlock {
+        UnlockMethod::Passkey { prf_output_b64, .. } => {
+            validate_prf_output(prf_output_b64)?;
+            build_prf_password(prf_output_b64)
+        }
+        UnlockMethod::RecoveryCode { code } => build_recovery_code_password(code),
+        UnlockMethod::Passphrase { .. } => {
+            return Err(Error::InvalidPayload(
+                "passphrase cannot be used to retrieve a backup".into(),
+            ));
+        }
+    };
+    Ok(recovery_signing_key_from_secret(&secret))
+}
+
+fn recovery_signing_key_from_secret(secret: &str) -> ed25519_dalek::SigningKey {
+    use sha2::{Digest, Sha256};
+
+    let seed: [u8; 32] = Sha256::new()
+        .chain_update(b"xrypton-backup-recovery-key-v1:")
+        .chain_update(secret.as_bytes())
+        .finalize()
+        .into();
+    ed25519_dalek::SigningKey::from_bytes(&seed)
+}
+
+/// バックアップ登録時にサーバへ渡す回復用公開鍵（base64）を返す。
+pub fn backup_recovery_public_key(unlock_json: &str) -> Result<String, Error> {
+    let unlock: UnlockMethod =
+        serde_json::from_str(unlock_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let key = recovery_signing_key(&unlock)?;
+    Ok(STANDARD.encode(key.verifying_key().to_bytes()))
+}
+
+/// サーバが発行したチャレンジに署名する。返り値は (回復用公開鍵, base64url 署名)。
+pub fn backup_sign_retrieval_challenge(
+    unlock_json: &str,
+    user_id: &str,
+    challenge: &str,
+) -> Result<(String, String), Error> {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use ed25519_dalek::Signer;
+
+    let unlock: UnlockMethod =
+        serde_json::from_str(unlock_json).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let key = recovery_signing_key(&unlock)?;
+    let signature = key.sign(retrieval_message(user_id, challenge).as_bytes());
+    Ok((
+        STANDARD.encode(key.verifying_key().to_bytes()),
+        URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+    ))
+}
@@ -6,21 +6,85 @@ use pgp::{
     packet::{PacketTrait, SignatureConfig, SignatureType, Subpacket, SubpacketData},
     types::{CompressionAlgorithm, KeyDetails, *},
 };
+use rand::RngCore;
 use rand::rngs::OsRng;
 
 /// (plaintext, detached_signature, issuer_fingerprints)
 pub type DecryptResult = (Vec<u8>, Option<String>, Vec<String>);
 
+/// 鍵束の形式バージョン
+const KEYRING_VERSION: u8 = 1;
+
+/// 暗号化の内側でパディングした署名済みメッセージの識別子
+const PADDING_MAGIC: &[u8; 8] = b"XRYPAD1\0";
+/// 識別子と長さを含むパディング形式のヘッダ長
+const PADDING_HEADER_LEN: usize = PADDING_MAGIC.len() + 8;
+/// パディング後の最小サイズ（短いメッセージの長さを区別できないようにする）
+const MIN_PADDED_LEN: usize = 512;
+
+/// Padmé 方式で長さを丸める。オーバーヘッドは最大で約 12%。
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// `[識別子][長さ u64 BE][署名済みメッセージ][0 埋め]` の形式でパディングする。
+fn pad_payload(signed: Vec<u8>) -> Vec<u8> {
+    let padded_len = padme(PADDING_HEADER_LEN + signed.len()).max(MIN_PADDED_LEN);
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(PADDING_MAGIC);
+    padded.extend_from_slice(&(signed.len() as u64).to_be_bytes());
+    padded.extend_from_slice(&signed);
+    padded.resize(padded_len, 0);
+    padded
+}
+
+/// パディングを除去して署名済みメッセージを返す。パディング形式でなければ `None`。
+fn unpad_payload(data: &[u8]) -> Option<&[u8]> {
+    let rest = data.strip_prefix(PADDING_MAGIC.as_slice())?;
+    let (len, rest) = rest.split_first_chunk::<8>()?;
+    let len = usize::try_from(u64::from_be_bytes(*len)).ok()?;
+    rest.get(..len)
+}
+
+/// 主鍵の移行前など、現在の秘密鍵に含まれない過去の秘密鍵を保持する鍵束。
+///
+/// 各秘密鍵はサブ鍵のパスフレーズで保護されたまま格納する。
+/// サブ鍵のパスフレーズを変更したら [`change_keyring_sub_passphrase`] で鍵束も更新する。
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Keyring {
+    version: u8,
+    keys: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct PrivateKeys {
     keys: SignedSecretKey,
+    /// 過去の秘密鍵（復号にのみ使う）
+    history: Vec<SignedSecretKey>,
 }
 impl PrivateKeys {
+    /// 最新の有効な署名サブキー。ローテーション後は新しいサブキーで署名する。
     pub fn signing_secret(&self) -> &SignedSecretSubKey {
+        let public = self.keys.signed_public_key();
+        let latest = xrypton_common::keys::valid_signing_subkeys(&public)
+            .first()
+            .map(|k| k.fingerprint());
         self.keys
             .secret_subkeys
             .iter()
-            .find(|k| k.signed_public_key().key.is_signing_key())
+            .find(|k| latest.as_ref().is_some_and(|fp| k.key.fingerprint() == *fp))
+            .or_else(|| {
+                self.keys
+                    .secret_subkeys
+                    .iter()
+                    .find(|k| k.signed_public_key().key.is_signing_key())
+            })
             .unwrap()
     }
     pub fn get_user_ids(&self) -> Vec<String> {
@@ -39,8 +103,40 @@ impl PrivateKeys {
             .unwrap()
     }
 
+    /// RFC 9580 (v6) 鍵か判定する。
+    fn is_v6(&self) -> bool {
+        self.keys.primary_key.version() == KeyVersion::V6
+    }
+
+    /// 鍵のバージョンに応じた署名のバージョン固有情報を返す。
+    /// v6 鍵ではハッシュアルゴリズムに応じた長さの salt を付けた v6 署名になる。
+    fn signature_version(
+        &self,
+        hash_alg: crypto::hash::HashAlgorithm,
+    ) -> Result<pgp::packet::SignatureVersionSpecific, Error> {
+        if !self.is_v6() {
+            return Ok(pgp::packet::SignatureVersionSpecific::V4);
+        }
+        let salt_len = hash_alg
+            .salt_len()
+            .ok_or_else(|| Error::SigningError(format!("unsupported hash for v6: {hash_alg:?}")))?;
+        let mut salt = vec![0u8; salt_len];
+        OsRng.fill_bytes(&mut salt);
+        Ok(pgp::packet::SignatureVersionSpecific::V6 { salt })
+    }
+
     /// SignersUserIDサブパケットを含む署名用SubpacketConfigを生成する。
     fn sign_subpacket_config(&self) -> Result<SubpacketConfig, Error> {
+        let signing_key = &self.signing_secret().key;
+        self.subpacket_config_for(signing_key.fingerprint(), signing_key.key_id())
+    }
+
+    /// 指定した署名鍵の Issuer 情報と SignersUserID を含む SubpacketConfig を生成する。
+    fn subpacket_config_for(
+        &self,
+        fingerprint: Fingerprint,
+        key_id: KeyId,
+    ) -> Result<SubpacketConfig, Error> {
         // UserId::to_string()はデバッグ用フォーマット("User ID: ...")を返すため、
         // as_str()で生のユーザID文字列を取得する
         let user_id = self
@@ -51,9 +147,8 @@ impl PrivateKeys {
             .first()
             .and_then(|u| u.id.as_str().map(str::to_owned))
             .unwrap_or_default();
-        let signing_key = &self.signing_secret().key;
         let hashed = vec![
-            Subpacket::regular(SubpacketData::IssuerFingerprint(signing_key.fingerprint()))
+            Subpacket::regular(SubpacketData::IssuerFingerprint(fingerprint))
                 .map_err(|e| Error::SigningError(e.to_string()))?,
             Subpacket::regular(SubpacketData::SignatureCreationTime(
                 chrono::Utc::now().with_nanosecond(0).unwrap(),
@@ -62,33 +157,77 @@ impl PrivateKeys {
             Subpacket::regular(SubpacketData::SignersUserID(user_id.into()))
                 .map_err(|e| Error::SigningError(e.to_string()))?,
         ];
-        let unhashed = vec![
-            Subpacket::regular(SubpacketData::Issuer(signing_key.key_id()))
-                .map_err(|e| Error::SigningError(e.to_string()))?,
-        ];
+        // v6 署名では Issuer Key ID を使わず Issuer Fingerprint のみで署名者を示す
+        let unhashed = if self.is_v6() {
+            Vec::new()
+        } else {
+            vec![
+                Subpacket::regular(SubpacketData::Issuer(key_id))
+                    .map_err(|e| Error::SigningError(e.to_string()))?,
+            ]
+        };
         Ok(SubpacketConfig::UserDefined { hashed, unhashed })
     }
 
+    /// armored の鍵束に含まれる過去の秘密鍵を復号に使えるようにする。
+    pub fn with_keyring(mut self, keyring: &str) -> Result<Self, Error> {
+        self.history = parse_keyring(keyring)?;
+        Ok(self)
+    }
+
     /// returns (data, signature, issuer_fingerprints)
     #[tracing::instrument]
     pub fn decrypt(&self, passphrase: &str, armor: &str) -> Result<DecryptResult, Error> {
-        let (msg, _) =
-            Message::from_string(armor).map_err(|e| Error::DecryptionError(e.to_string()))?;
-        let msg = msg
-            .decompress()
-            .map_err(|e| Error::DecryptionError(e.to_string()))?;
-        Self::decrypt_message(msg, passphrase, &self.keys)
+        self.decrypt_and_report(passphrase, armor)
+            .map(|(result, _)| result)
     }
 
     /// raw PGP bytes から復号する。
     #[tracing::instrument]
     pub fn decrypt_from_bytes(&self, passphrase: &str, raw: &[u8]) -> Result<DecryptResult, Error> {
-        let msg = Message::from_bytes(std::io::Cursor::new(raw))
-            .map_err(|e| Error::DecryptionError(e.to_string()))?;
-        let msg = msg
-            .decompress()
+        self.decrypt_bytes_and_report(passphrase, raw)
+            .map(|(result, _)| result)
+    }
+
+    /// armored メッセージを復号し、復号に使ったサブキーのフィンガープリントも返す。
+    pub fn decrypt_and_report(
+        &self,
+        passphrase: &str,
+        armor: &str,
+    ) -> Result<(DecryptResult, String), Error> {
+        let raw = xrypton_common::keys::dearmor(armor)
             .map_err(|e| Error::DecryptionError(e.to_string()))?;
-        Self::decrypt_message(msg, passphrase, &self.keys)
+        self.decrypt_bytes_and_report(passphrase, &raw)
+    }
+
+    /// raw PGP bytes を復号し、復号に使ったサブキーのフィンガープリントも返す。
+    ///
+    /// PKESK の宛先 Key ID（v6 ではフィンガープリント）から、現在の秘密鍵と鍵束の
+    /// 中でローテーション前のものも含めて一致する暗号化サブキーを選ぶ。
+    pub fn decrypt_bytes_and_report(
+        &self,
+        passphrase: &str,
+        raw: &[u8],
+    ) -> Result<(DecryptResult, String), Error> {
+        let recipients = pkesk_recipients(raw)?;
+        let mut last_error = None;
+        for keys in std::iter::once(&self.keys).chain(&self.history) {
+            let Some(fingerprint) = matching_decryption_key(keys, &recipients) else {
+                continue;
+            };
+            let msg = Message::from_bytes(std::io::Cursor::new(raw))
+                .map_err(|e| Error::DecryptionError(e.to_string()))?;
+            let msg = msg
+                .decompress()
+                .map_err(|e| Error::DecryptionError(e.to_string()))?;
+            match Self::decrypt_message(msg, passphrase, keys) {
+                Ok(result) => return Ok((result, format!("{fingerprint:X}"))),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            Error::DecryptionError("no secret key matches the message recipients".into())
+        }))
     }
 
     fn decrypt_message(
@@ -96,9 +235,26 @@ impl PrivateKeys {
         passphrase: &str,
         keys: &SignedSecretKey,
     ) -> Result<DecryptResult, Error> {
-        let mut decrypted = msg
+        let decrypted = msg
             .decrypt(&Password::from(passphrase), keys)
             .map_err(|e| Error::DecryptionError(e.to_string()))?;
+        let (data, signature, issuer_fingerprints) = Self::read_message(decrypted)?;
+        // パディングされたメッセージは内側の署名済みメッセージを取り出す。
+        // パディング導入前のメッセージは暗号化の直下が署名済みなのでそのまま返す
+        if signature.is_none()
+            && let Some(signed) = unpad_payload(&data)
+        {
+            let msg = Message::from_bytes(std::io::Cursor::new(signed))
+                .map_err(|e| Error::DecryptionError(e.to_string()))?
+                .decompress()
+                .map_err(|e| Error::DecryptionError(e.to_string()))?;
+            return Self::read_message(msg);
+        }
+        Ok((data, signature, issuer_fingerprints))
+    }
+
+    /// 復号済みメッセージからデータと署名を読み出す。
+    fn read_message(mut decrypted: Message) -> Result<DecryptResult, Error> {
         let data = decrypted
             .as_data_vec()
             .map_err(|e| Error::DecryptionError(e.to_string()))?;
@@ -156,6 +312,94 @@ impl PrivateKeys {
         self.build_sign(passphrase, data, |b| b.to_vec(OsRng))
     }
 
+    /// 主鍵で署名した armored メッセージを生成する。
+    #[tracing::instrument]
+    pub fn sign_with_primary(&self, main_passphrase: &str, data: Vec<u8>) -> Result<String, Error> {
+        let primary = &self.keys.primary_key;
+        let mut builder = MessageBuilder::from_bytes("", data);
+        builder.compression(CompressionAlgorithm::ZLIB);
+        builder.sign_with_subpackets(
+            primary,
+            Password::from(main_passphrase),
+            crypto::hash::HashAlgorithm::Sha512,
+            self.subpacket_config_for(primary.fingerprint(), primary.key_id())?,
+        );
+        builder
+            .to_armored_string(OsRng, ArmorOptions::default())
+            .map_err(|e| Error::SigningError(e.to_string()))
+    }
+
+    /// 別サーバへのアカウント移行宣言を主鍵で署名して生成する。
+    ///
+    /// `from` は鍵の最初のUser IDのアドレス、`to` は移行先のユーザID。
+    #[tracing::instrument]
+    pub fn create_moved_statement(&self, main_passphrase: &str, to: &str) -> Result<String, Error> {
+        let from = self
+            .get_user_ids()
+            .first()
+            .ok_or_else(|| Error::KeyFormatError("no user ID in key".into()))
+            .and_then(|uid| {
+                xrypton_common::keys::extract_address_from_uid(uid)
+                    .map(str::to_owned)
+                    .map_err(|e| Error::KeyFormatError(e.to_string()))
+            })?;
+        if from == to {
+            return Err(Error::InvalidPayload(
+                "destination must differ from current user ID".into(),
+            ));
+        }
+        let statement = serde_json::json!({
+            "type": "moved",
+            "from": from,
+            "to": to,
+            "primary_key_fingerprint": format!("{:X}", self.keys.fingerprint()),
+            "created_at": chrono::Utc::now().to_rfc3339(),
+        });
+        self.sign_with_primary(main_passphrase, statement.to_string().into_bytes())
+    }
+
+    /// 新しい鍵への鍵移行宣言を生成する。
+    ///
+    /// 同じ宣言を旧主鍵（`self`）と新主鍵（`new_keys`）でそれぞれ署名し、
+    /// `(旧主鍵の署名, 新主鍵の署名)` を返す。
+    #[tracing::instrument(skip(new_keys))]
+    pub fn create_key_transition_statement(
+        &self,
+        main_passphrase: &str,
+        new_keys: &PrivateKeys,
+        new_main_passphrase: &str,
+    ) -> Result<(String, String), Error> {
+        let user_id = self
+            .get_user_ids()
+            .first()
+            .ok_or_else(|| Error::KeyFormatError("no user ID in key".into()))
+            .and_then(|uid| {
+                xrypton_common::keys::extract_address_from_uid(uid)
+                    .map(str::to_owned)
+                    .map_err(|e| Error::KeyFormatError(e.to_string()))
+            })?;
+        let old_fingerprint = format!("{:X}", self.keys.fingerprint());
+        let new_fingerprint = format!("{:X}", new_keys.keys.fingerprint());
+        if old_fingerprint == new_fingerprint {
+            return Err(Error::InvalidPayload(
+                "new key must differ from current key".into(),
+            ));
+        }
+        let statement = serde_json::json!({
+            "type": "key_transition",
+            "user_id": user_id,
+            "old_fingerprint": old_fingerprint,
+            "new_fingerprint": new_fingerprint,
+            "created_at": chrono::Utc::now().to_rfc3339(),
+        })
+        .to_string()
+        .into_bytes();
+        Ok((
+            self.sign_with_primary(main_passphrase, statement.clone())?,
+            new_keys.sign_with_primary(new_main_passphrase, statement)?,
+        ))
+    }
+
     /// detached signature（armored）を生成する。
     #[tracing::instrument]
     pub fn sign_detached(&self, passphrase: &str, data: Vec<u8>) -> Result<String, Error> {
@@ -171,7 +415,7 @@ impl PrivateKeys {
                 SubpacketConfig::UserDefined { unhashed, .. } => unhashed,
                 _ => Vec::new(),
             },
-            version_specific: pgp::packet::SignatureVersionSpecific::V4,
+            version_specific: self.signature_version(crypto::hash::HashAlgorithm::Sha512)?,
         };
         let sig = cfg
             .sign(
@@ -213,7 +457,7 @@ impl PrivateKeys {
             hash_alg: crypto::hash::HashAlgorithm::Sha512,
             hashed_subpackets,
             unhashed_subpackets,
-            version_specific: pgp::packet::SignatureVersionSpecific::V4,
+            version_specific: self.signature_version(crypto::hash::HashAlgorithm::Sha512)?,
         };
         let sig = cfg
             .sign_certification_third_party(
@@ -246,6 +490,155 @@ impl PrivateKeys {
             .map_err(|e: pgp::errors::Error| Error::KeyFormatError(e.to_string()))
     }
 
+    /// 失効署名の SignatureConfig を生成する。
+    fn revocation_config(
+        &self,
+        typ: SignatureType,
+        reason: RevocationReason,
+        description: &str,
+    ) -> Result<SignatureConfig, Error> {
+        let primary = &self.keys.primary_key;
+        let hashed_subpackets = vec![
+            Subpacket::regular(SubpacketData::SignatureCreationTime(
+                chrono::Utc::now().with_nanosecond(0).unwrap(),
+            ))
+            .map_err(|e| Error::SigningError(e.to_string()))?,
+            Subpacket::regular(SubpacketData::IssuerFingerprint(primary.fingerprint()))
+                .map_err(|e| Error::SigningError(e.to_string()))?,
+            Subpacket::regular(SubpacketData::RevocationReason(
+                reason.code(),
+                description.as_bytes().to_vec().into(),
+            ))
+            .map_err(|e| Error::SigningError(e.to_string()))?,
+        ];
+        // v6 署名では Issuer Key ID サブパケットを使わない
+        let mut unhashed_subpackets = Vec::new();
+        if !self.is_v6() {
+            unhashed_subpackets.push(
+                Subpacket::regular(SubpacketData::Issuer(primary.key_id()))
+                    .map_err(|e| Error::SigningError(e.to_string()))?,
+            );
+        }
+        Ok(SignatureConfig {
+            typ,
+            pub_alg: primary.algorithm(),
+            hash_alg: crypto::hash::HashAlgorithm::Sha512,
+            hashed_subpackets,
+            unhashed_subpackets,
+            version_specific: self.signature_version(crypto::hash::HashAlgorithm::Sha512)?,
+        })
+    }
+
+    /// 主鍵の失効証明書（armored 署名）を生成する。
+    ///
+    /// 鍵を紛失した場合に備えてオフラインで保管し、必要になったら公開する。
+    pub fn create_revocation_certificate(
+        &self,
+        main_passphrase: &str,
+        reason: RevocationReason,
+        description: &str,
+    ) -> Result<String, Error> {
+        let cfg = self.revocation_config(SignatureType::KeyRevocation, reason, description)?;
+        let sig = cfg
+            .sign_key(
+                &self.keys.primary_key,
+                &Password::from(main_passphrase),
+                &self.keys.primary_key.public_key(),
+            )
+            .map_err(|e| Error::SigningError(e.to_string()))?;
+        DetachedSignature::new(sig)
+            .to_armored_string(ArmorOptions::default())
+            .map_err(|e| Error::SigningError(e.to_string()))
+    }
+
+    /// 指定したサブキーの失効署名（armored 署名）を生成する。
+    pub fn create_subkey_revocation(
+        &self,
+        main_passphrase: &str,
+        subkey_fingerprint: &str,
+        reason: RevocationReason,
+        description: &str,
+    ) -> Result<String, Error> {
+        let subkey = self
+            .keys
+            .secret_subkeys
+            .iter()
+            .find(|k| format!("{:X}", k.key.fingerprint()) == subkey_fingerprint)
+            .ok_or_else(|| {
+                Error::KeyFormatError(format!("subkey not found: {subkey_fingerprint}"))
+            })?;
+        let cfg = self.revocation_config(SignatureType::SubkeyRevocation, reason, description)?;
+        let sig = cfg
+            .sign_subkey_binding(
+                &self.keys.primary_key,
+                &self.keys.primary_key.public_key(),
+                &Password::from(main_passphrase),
+                &subkey.key.public_key(),
+            )
+            .map_err(|e| Error::SigningError(e.to_string()))?;
+        DetachedSignature::new(sig)
+            .to_armored_string(ArmorOptions::default())
+            .map_err(|e| Error::SigningError(e.to_string()))
+    }
+
+    /// 主鍵のパスフレーズを変更した armored 秘密鍵を返す。
+    pub fn change_main_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<String, Error> {
+        self.validate_main_passphrase(old_passphrase)?;
+        let mut keys = self.keys.clone();
+        keys.primary_key
+            .remove_password(&Password::from(old_passphrase))
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+        keys.primary_key
+            .set_password(OsRng, &Password::from(new_passphrase))
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+        keys.to_armored_string(ArmorOptions::default())
+            .map_err(|e| Error::KeyFormatError(e.to_string()))
+    }
+
+    /// すべてのサブキーのパスフレーズを変更した armored 秘密鍵を返す。
+    pub fn change_sub_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<String, Error> {
+        self.validate_sub_passphrase(old_passphrase)?;
+        let mut keys = self.keys.clone();
+        change_subkeys_password(
+            &mut keys,
+            &Password::from(old_passphrase),
+            &Password::from(new_passphrase),
+        )?;
+        keys.to_armored_string(ArmorOptions::default())
+            .map_err(|e| Error::KeyFormatError(e.to_string()))
+    }
+
+    /// 主鍵で認証した新しいサブキーを追加した armored 秘密鍵を返す。
+    ///
+    /// 古いサブキーは過去のメッセージを復号・検証できるよう残す。
+    /// 新しいサブキーの方が作成日時が新しいため、以降の署名・暗号化に使われる。
+    pub fn rotate_subkey(
+        &self,
+        main_passphrase: &str,
+        sub_passphrase: &str,
+        kind: SubkeyKind,
+    ) -> Result<String, Error> {
+        self.validate_main_passphrase(main_passphrase)?;
+        self.validate_sub_passphrase(sub_passphrase)?;
+        let mut keys = self.keys.clone();
+        add_bound_subkeys(
+            &mut keys,
+            &[kind],
+            &Password::from(main_passphrase),
+            &Password::from(sub_passphrase),
+        )?;
+        keys.to_armored_string(ArmorOptions::default())
+            .map_err(|e| Error::KeyFormatError(e.to_string()))
+    }
+
     /// `Signed(Encrypted(Signed(Data)))` の外側署名済みメッセージビルダーを構築し、
     /// `finish` クロージャで最終出力形式を決定する。
     fn build_sign_encrypt_sign<T>(
@@ -255,23 +648,45 @@ impl PrivateKeys {
         data: Vec<u8>,
         finish: impl FnOnce(MessageBuilder<'_>) -> Result<T, pgp::errors::Error>,
     ) -> Result<T, Error> {
-        // inner: sign + encrypt → raw PGP bytes
-        let mut inner = MessageBuilder::from_bytes("", data)
-            .seipd_v1(OsRng, crypto::sym::SymmetricKeyAlgorithm::AES256);
-        inner.sign_with_subpackets(
+        // inner: sign → pad → encrypt → raw PGP bytes
+        // 署名はパディング前のデータに対して行い、暗号化の内側でサイズを丸める
+        let mut signed = MessageBuilder::from_bytes("", data);
+        signed.sign_with_subpackets(
             &self.signing_secret().key,
             Password::from(passphrase),
             crypto::hash::HashAlgorithm::Sha512,
             self.sign_subpacket_config()?,
         );
-        for recipient in recipients {
-            inner
-                .encrypt_to_key(OsRng, recipient.encryption_public())
-                .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        let padded = pad_payload(
+            signed
+                .to_vec(OsRng)
+                .map_err(|e| Error::SigningError(e.to_string()))?,
+        );
+        // 全受信者が v6 鍵の場合のみ SEIPDv2 (AEAD) を使う（v4 鍵のクライアントとの互換性のため）
+        let inner_bytes = if recipients.iter().all(|r| r.is_v6()) {
+            let mut inner = MessageBuilder::from_bytes("", padded).seipd_v2(
+                OsRng,
+                crypto::sym::SymmetricKeyAlgorithm::AES256,
+                crypto::aead::AeadAlgorithm::Ocb,
+                crypto::aead::ChunkSize::default(),
+            );
+            for recipient in recipients {
+                inner
+                    .encrypt_to_key(OsRng, recipient.encryption_public()?)
+                    .map_err(|e| Error::EncryptionError(e.to_string()))?;
+            }
+            inner.to_vec(OsRng)
+        } else {
+            let mut inner = MessageBuilder::from_bytes("", padded)
+                .seipd_v1(OsRng, crypto::sym::SymmetricKeyAlgorithm::AES256);
+            for recipient in recipients {
+                inner
+                    .encrypt_to_key(OsRng, recipient.encryption_public()?)
+                    .map_err(|e| Error::EncryptionError(e.to_string()))?;
+            }
+            inner.to_vec(OsRng)
         }
-        let inner_bytes = inner
-            .to_vec(OsRng)
-            .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
 
         // outer: sign（サーバが検証可能）— 圧縮は最外層のみ
         let mut outer = MessageBuilder::from_bytes("", inner_bytes);
@@ -309,6 +724,167 @@ impl PrivateKeys {
         self.build_sign_encrypt_sign(passphrase, recipients, data, |b| b.to_vec(OsRng))
     }
 }
+/// PKESK の宛先。匿名の宛先（ワイルドカード）の場合はどちらも `None`。
+#[derive(Debug)]
+struct PkeskRecipient {
+    key_id: Option<KeyId>,
+    fingerprint: Option<Fingerprint>,
+}
+
+/// メッセージの PKESK パケットから宛先を取り出す。
+fn pkesk_recipients(raw: &[u8]) -> Result<Vec<PkeskRecipient>, Error> {
+    use pgp::packet::{Packet, PacketParser};
+    use std::io::{BufReader, Read};
+
+    let mut recipients = Vec::new();
+    for packet in PacketParser::new(BufReader::new(raw)).flatten() {
+        match packet {
+            Packet::PublicKeyEncryptedSessionKey(pkesk) => {
+                let key_id = pkesk
+                    .id()
+                    .ok()
+                    // 全ビットが 0 の Key ID は匿名の宛先
+                    .filter(|id| id.as_ref() != [0u8; 8])
+                    .cloned();
+                let fingerprint = pkesk.fingerprint().ok().flatten().cloned();
+                recipients.push(PkeskRecipient {
+                    key_id,
+                    fingerprint,
+                });
+            }
+            // 圧縮されたメッセージの内側にある暗号化パケットも対象にする
+            Packet::CompressedData(cd) => {
+                let mut decompressed = Vec::new();
+                cd.decompress()
+                    .map_err(|e| Error::DecryptionError(e.to_string()))?
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| Error::DecryptionError(e.to_string()))?;
+                recipients.extend(pkesk_recipients(&decompressed)?);
+            }
+            Packet::SymEncryptedProtectedData(_) | Packet::SymEncryptedData(_) => break,
+            _ => continue,
+        }
+    }
+    Ok(recipients)
+}
+
+/// 宛先に一致する暗号化サブキーのフィンガープリントを返す。
+///
+/// 匿名の宛先がある場合は最初の暗号化サブキーを候補とする。
+fn matching_decryption_key(
+    keys: &SignedSecretKey,
+    recipients: &[PkeskRecipient],
+) -> Option<Fingerprint> {
+    let subkeys = || {
+        keys.secret_subkeys
+            .iter()
+            .filter(|k| k.key.public_key().is_encryption_key())
+    };
+    recipients.iter().find_map(|recipient| {
+        if recipient.key_id.is_none() && recipient.fingerprint.is_none() {
+            return subkeys().next().map(|k| k.key.fingerprint());
+        }
+        subkeys()
+            .find(|k| {
+                recipient.key_id.as_ref() == Some(&k.key.key_id())
+                    || recipient.fingerprint.as_ref() == Some(&k.key.fingerprint())
+            })
+            .map(|k| k.key.fingerprint())
+    })
+}
+
+/// 鍵束を読み込む。空文字列は空の鍵束として扱う。
+fn parse_keyring(keyring: &str) -> Result<Vec<SignedSecretKey>, Error> {
+    if keyring.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let keyring: Keyring = serde_json::from_str(keyring)
+        .map_err(|e| Error::KeyFormatError(format!("invalid keyring: {e}")))?;
+    if keyring.version != KEYRING_VERSION {
+        return Err(Error::KeyFormatError("unsupported keyring version".into()));
+    }
+    keyring
+        .keys
+        .iter()
+        .map(|armored| {
+            SignedSecretKey::from_string(armored)
+                .map(|(keys, _)| keys)
+                .map_err(|e| Error::KeyFormatError(e.to_string()))
+        })
+        .collect()
+}
+
+/// 秘密鍵を鍵束に追加した armored 鍵束を返す。
+///
+/// 主鍵の移行時などに古い秘密鍵を追加しておくと、古い鍵宛てのメッセージも復号できる。
+/// 同じ主鍵の秘密鍵が既にある場合は置き換える。
+pub fn add_to_keyring(keyring: &str, private_key: &str) -> Result<String, Error> {
+    let (added, _) = SignedSecretKey::from_string(private_key)
+        .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    let mut keys: Vec<SignedSecretKey> = parse_keyring(keyring)?
+        .into_iter()
+        .filter(|k| k.fingerprint() != added.fingerprint())
+        .collect();
+    keys.push(added);
+    serialize_keyring(&keys)
+}
+
+/// 鍵束内のすべての秘密鍵のサブ鍵パスフレーズを変更した鍵束を返す。
+///
+/// 鍵束の秘密鍵は変更前のパスフレーズで保護されているため、
+/// [`PrivateKeys::change_sub_passphrase`] と合わせて呼び出す。
+pub fn change_keyring_sub_passphrase(
+    keyring: &str,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<String, Error> {
+    let old_password = Password::from(old_passphrase);
+    let new_password = Password::from(new_passphrase);
+    let mut keys = parse_keyring(keyring)?;
+    for key in &mut keys {
+        change_subkeys_password(key, &old_password, &new_password)?;
+    }
+    serialize_keyring(&keys)
+}
+
+fn serialize_keyring(keys: &[SignedSecretKey]) -> Result<String, Error> {
+    let keys = keys
+        .iter()
+        .map(|k| {
+            k.to_armored_string(ArmorOptions::default())
+                .map_err(|e| Error::KeyFormatError(e.to_string()))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    serde_json::to_string(&Keyring {
+        version: KEYRING_VERSION,
+        keys,
+    })
+    .map_err(|e| Error::KeyFormatError(e.to_string()))
+}
+
+/// すべてのサブキーのパスフレーズを変更する。
+fn change_subkeys_password(
+    keys: &mut SignedSecretKey,
+    old_password: &Password,
+    new_password: &Password,
+) -> Result<(), Error> {
+    for subkey in &mut keys.secret_subkeys {
+        if subkey.key.secret_params().is_encrypted() {
+            subkey.key.remove_password(old_password).map_err(|e| {
+                Error::KeyFormatError(format!(
+                    "subkey {:X} is protected by another passphrase: {e}",
+                    subkey.key.fingerprint()
+                ))
+            })?;
+        }
+        subkey
+            .key
+            .set_password(OsRng, new_password)
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    }
+    Ok(())
+}
+
 impl TryFrom<&str> for PrivateKeys {
     type Error = Error;
     fn try_from(value: &str) -> Result<Self, Error> {
@@ -319,10 +895,14 @@ impl TryFrom<&str> for PrivateKeys {
             || !subkeys.iter().any(|k| k.public_key().is_encryption_key())
         {
             Err(Error::KeyFormatError(
-                "both of signing sub key and encryption sub key required".to_string(),
+                "both of signing sub key and encryption sub key required; use import to add them"
+                    .to_string(),
             ))
         } else {
-            Ok(PrivateKeys { keys })
+            Ok(PrivateKeys {
+                keys,
+                history: Vec::new(),
+            })
         }
     }
 }
@@ -332,30 +912,34 @@ pub struct PublicKeys {
     keys: SignedPublicKey,
 }
 impl PublicKeys {
-    pub fn encryption_public(&self) -> &SignedPublicSubKey {
-        self.keys
-            .public_subkeys
-            .iter()
-            .find(|k| k.key.is_encryption_key())
-            .unwrap()
+    /// 最新の有効な暗号化サブキー
+    pub fn encryption_public(&self) -> Result<&SignedPublicSubKey, Error> {
+        xrypton_common::keys::valid_encryption_subkeys(&self.keys)
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::KeyFormatError("no valid encryption subkey".into()))
     }
-    pub fn signing_public(&self) -> &SignedPublicSubKey {
-        self.keys
-            .public_subkeys
-            .iter()
-            .find(|k| k.key.is_signing_key())
-            .unwrap()
+    /// 最新の有効な署名サブキー
+    pub fn signing_public(&self) -> Result<&SignedPublicSubKey, Error> {
+        xrypton_common::keys::valid_signing_subkeys(&self.keys)
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::KeyFormatError("no valid signing subkey".into()))
     }
 
-    pub fn get_signing_sub_key_id(&self) -> String {
-        self.signing_public().key_id().to_string()
+    pub fn get_signing_sub_key_id(&self) -> Result<String, Error> {
+        Ok(self.signing_public()?.key_id().to_string())
     }
-    pub fn get_signing_sub_key_fingerprint(&self) -> String {
-        format!("{:X}", self.signing_public().fingerprint())
+    pub fn get_signing_sub_key_fingerprint(&self) -> Result<String, Error> {
+        Ok(format!("{:X}", self.signing_public()?.fingerprint()))
     }
     pub fn get_primary_fingerprint(&self) -> String {
         format!("{:X}", self.keys.fingerprint())
     }
+    /// RFC 9580 (v6) 鍵か判定する。
+    pub fn is_v6(&self) -> bool {
+        self.keys.primary_key.version() == KeyVersion::V6
+    }
     pub fn get_user_ids(&self) -> Vec<String> {
         self.keys
             .details
@@ -365,6 +949,22 @@ impl PublicKeys {
             .collect()
     }
 
+    /// 指定したフィンガープリントの有効な署名サブキー（デバイス鍵）を返す。
+    /// フィンガープリントがない場合は最新の有効な署名サブキーを返す。
+    fn device_signing_public(
+        &self,
+        fingerprint: Option<String>,
+    ) -> Result<&SignedPublicSubKey, Error> {
+        xrypton_common::keys::valid_signing_subkeys(&self.keys)
+            .into_iter()
+            .find(|k| {
+                fingerprint
+                    .as_ref()
+                    .is_none_or(|fp| format!("{:X}", k.fingerprint()) == *fp)
+            })
+            .ok_or_else(|| Error::VerificationError("signer is not a valid signing subkey".into()))
+    }
+
     #[tracing::instrument]
     pub fn verify(&self, armored: &str) -> Result<(), Error> {
         let (msg, _) =
@@ -372,7 +972,10 @@ impl PublicKeys {
         let mut msg = msg
             .decompress()
             .map_err(|e| Error::VerificationError(e.to_string()))?;
-        msg.verify_read(self.signing_public())
+        let signing_key = self.device_signing_public(
+            xrypton_common::keys::extract_issuer_fingerprint(armored).ok(),
+        )?;
+        msg.verify_read(signing_key)
             .map(|_| ())
             .map_err(|e| Error::VerificationError(e.to_string()))
     }
@@ -380,7 +983,13 @@ impl PublicKeys {
     pub fn verify_detached_signature(&self, armored: &str, data: &[u8]) -> Result<(), Error> {
         let (sig, _) = DetachedSignature::from_string(armored)
             .map_err(|e| Error::VerificationError(e.to_string()))?;
-        sig.verify(self.signing_public(), data)
+        let signing_key = self.device_signing_public(
+            sig.signature
+                .issuer_fingerprint()
+                .first()
+                .map(|fp| format!("{fp:X}")),
+        )?;
+        sig.verify(signing_key, data)
             .map_err(|e| Error::VerificationError(e.to_string()))
     }
 }
@@ -402,15 +1011,113 @@ impl TryFrom<&str> for PublicKeys {
     }
 }
 
-// (main, subkeys)
+/// 生成する鍵のプロファイル。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyProfile {
+    /// v4 鍵（Ed25519Legacy + ECDH Curve25519、SEIPDv1）
+    #[default]
+    V4,
+    /// RFC 9580 v6 鍵（Ed25519 + X25519、SEIPDv2）
+    V6,
+}
+
+impl std::str::FromStr for KeyProfile {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "v4" => Ok(Self::V4),
+            "v6" => Ok(Self::V6),
+            _ => Err(Error::KeyGenerationError(format!(
+                "unknown key profile: {value}"
+            ))),
+        }
+    }
+}
+
+impl KeyProfile {
+    /// (鍵のバージョン, 署名鍵の種類, 暗号化鍵の種類)
+    fn key_types(self) -> (KeyVersion, KeyType, KeyType) {
+        match self {
+            Self::V4 => (
+                KeyVersion::V4,
+                KeyType::Ed25519Legacy,
+                KeyType::ECDH(crypto::ecc_curve::ECCCurve::Curve25519),
+            ),
+            Self::V6 => (KeyVersion::V6, KeyType::Ed25519, KeyType::X25519),
+        }
+    }
+}
+
+/// 失効の理由（RFC 9580 Reason for Revocation）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RevocationReason {
+    #[default]
+    NoReason,
+    /// 新しい鍵に置き換えた
+    Superseded,
+    /// 秘密鍵が漏洩した
+    Compromised,
+    /// 鍵を使用しなくなった
+    Retired,
+}
+
+impl std::str::FromStr for RevocationReason {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "no_reason" => Ok(Self::NoReason),
+            "superseded" => Ok(Self::Superseded),
+            "compromised" => Ok(Self::Compromised),
+            "retired" => Ok(Self::Retired),
+            _ => Err(Error::SigningError(format!(
+                "unknown revocation reason: {value}"
+            ))),
+        }
+    }
+}
+
+impl RevocationReason {
+    fn code(self) -> RevocationCode {
+        match self {
+            Self::NoReason => RevocationCode::NoReason,
+            Self::Superseded => RevocationCode::KeySuperseded,
+            Self::Compromised => RevocationCode::KeyCompromised,
+            Self::Retired => RevocationCode::KeyRetired,
+        }
+    }
+}
+
+/// 追加・ローテーションするサブキーの種類。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubkeyKind {
+    Signing,
+    Encryption,
+}
+
+impl std::str::FromStr for SubkeyKind {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "signing" => Ok(Self::Signing),
+            "encryption" => Ok(Self::Encryption),
+            _ => Err(Error::KeyGenerationError(format!(
+                "unknown subkey kind: {value}"
+            ))),
+        }
+    }
+}
+
+/// 鍵を生成し、(armored 秘密鍵, 主鍵の失効証明書) を返す。
 pub fn generate_keys(
     user_id: String,
     main_passphrase: String,
     sub_passphrase: String,
+    profile: KeyProfile,
 ) -> Result<(String, String), Error> {
+    let (version, signing_type, encryption_type) = profile.key_types();
     let signing_key_param = SubkeyParamsBuilder::default()
-        .version(KeyVersion::V4)
-        .key_type(KeyType::Ed25519Legacy)
+        .version(version)
+        .key_type(signing_type.clone())
         .can_sign(true)
         .can_encrypt(false)
         .can_authenticate(false)
@@ -418,8 +1125,8 @@ pub fn generate_keys(
         .build()
         .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
     let encryption_key_param = SubkeyParamsBuilder::default()
-        .version(KeyVersion::V4)
-        .key_type(KeyType::ECDH(crypto::ecc_curve::ECCCurve::Curve25519))
+        .version(version)
+        .key_type(encryption_type)
         .can_sign(false)
         .can_encrypt(true)
         .can_authenticate(false)
@@ -427,16 +1134,27 @@ pub fn generate_keys(
         .build()
         .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
 
-    let params = SecretKeyParamsBuilder::default()
-        .version(KeyVersion::V4)
-        .key_type(KeyType::Ed25519Legacy)
+    let mut params = SecretKeyParamsBuilder::default();
+    params
+        .version(version)
+        .key_type(signing_type)
         .feature_seipd_v1(true)
         .can_sign(true)
         .can_encrypt(false)
         .can_authenticate(false)
         .passphrase(Some(main_passphrase.clone()))
         .subkeys(vec![signing_key_param, encryption_key_param])
-        .primary_user_id(user_id.clone())
+        .primary_user_id(user_id.clone());
+    if profile == KeyProfile::V6 {
+        params.feature_seipd_v2(true).preferred_aead_algorithms(
+            vec![(
+                crypto::sym::SymmetricKeyAlgorithm::AES256,
+                crypto::aead::AeadAlgorithm::Ocb,
+            )]
+            .into(),
+        );
+    }
+    let params = params
         .build()
         .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
 
@@ -450,11 +1168,166 @@ pub fn generate_keys(
         .to_armored_string(ArmorOptions::default())
         .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
 
-    let subkeys = signed
-        .to_armored_string(ArmorOptions::default())
+    // 鍵を紛失しても失効できるよう、生成時に失効証明書も作成する
+    let revocation = PrivateKeys {
+        keys: signed,
+        history: Vec::new(),
+    }
+    .create_revocation_certificate(
+        &main_passphrase,
+        RevocationReason::NoReason,
+        "revocation certificate generated at key creation",
+    )
+    .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
+
+    Ok((main, revocation))
+}
+
+/// サブキーをサブ鍵のパスフレーズで保護し直す。
+///
+/// GnuPG の鍵はサブキーも主鍵と同じパスフレーズで保護されているため、
+/// 主鍵のパスフレーズで解除してからサブ鍵のパスフレーズを設定する。
+fn reprotect_subkey(
+    subkey: &mut SignedSecretSubKey,
+    main_passphrase: &Password,
+    sub_passphrase: &Password,
+) -> Result<(), Error> {
+    if subkey.key.secret_params().is_encrypted() {
+        if subkey
+            .unlock(sub_passphrase, |_, _| Ok(()))
+            .is_ok_and(|r| r.is_ok())
+        {
+            return Ok(());
+        }
+        subkey.key.remove_password(main_passphrase).map_err(|e| {
+            Error::KeyFormatError(format!(
+                "subkey {:X} is protected by an unknown passphrase: {e}",
+                subkey.key.fingerprint()
+            ))
+        })?;
+    }
+    subkey
+        .key
+        .set_password(OsRng, sub_passphrase)
+        .map_err(|e| Error::KeyFormatError(e.to_string()))
+}
+
+/// 主鍵で認証したサブキーを生成して鍵に追加する。
+///
+/// 追加したサブキーはサブ鍵のパスフレーズで保護する。
+fn add_bound_subkeys(
+    keys: &mut SignedSecretKey,
+    kinds: &[SubkeyKind],
+    main_password: &Password,
+    sub_password: &Password,
+) -> Result<(), Error> {
+    if kinds.is_empty() {
+        return Ok(());
+    }
+    let profile = if keys.primary_key.version() == KeyVersion::V6 {
+        KeyProfile::V6
+    } else {
+        KeyProfile::V4
+    };
+    let (version, signing_type, encryption_type) = profile.key_types();
+    let subkeys = kinds
+        .iter()
+        .map(|kind| {
+            let is_signing = *kind == SubkeyKind::Signing;
+            SubkeyParamsBuilder::default()
+                .version(version)
+                .key_type(if is_signing {
+                    signing_type.clone()
+                } else {
+                    encryption_type.clone()
+                })
+                .can_sign(is_signing)
+                .can_encrypt(!is_signing)
+                .can_authenticate(false)
+                .build()
+                .map_err(|e| Error::KeyGenerationError(e.to_string()))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    // サブキーだけを取り出すための一時的な鍵。主鍵は使わずに破棄する
+    let generated = SecretKeyParamsBuilder::default()
+        .version(version)
+        .key_type(signing_type)
+        .can_sign(true)
+        .subkeys(subkeys)
+        .primary_user_id("subkey".into())
+        .build()
+        .map_err(|e| Error::KeyGenerationError(e.to_string()))?
+        .generate(OsRng)
         .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
 
-    Ok((main, subkeys))
+    let primary_public = keys.primary_key.public_key();
+    for subkey in generated.secret_subkeys {
+        let mut signed = subkey
+            .sign(OsRng, &keys.primary_key, &primary_public, main_password)
+            .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
+        signed
+            .key
+            .set_password(OsRng, sub_password)
+            .map_err(|e| Error::KeyGenerationError(e.to_string()))?;
+        keys.secret_subkeys.push(signed);
+    }
+    Ok(())
+}
+
+/// 既存の OpenPGP 秘密鍵（GnuPG でエクスポートした鍵など）をインポートする。
+///
+/// RSA 鍵や主鍵で署名する鍵など、署名・暗号化サブキーが揃っていない鍵には
+/// 主鍵で認証したサブキーを追加する。使用できない鍵の場合は理由を示すエラーを返す。
+/// サブキーはサブ鍵のパスフレーズで保護し直した armored 秘密鍵を返す。
+pub fn import_keys(
+    armored: &str,
+    main_passphrase: &str,
+    sub_passphrase: &str,
+) -> Result<String, Error> {
+    let (mut keys, _) =
+        SignedSecretKey::from_string(armored).map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    let layout = xrypton_common::keys::inspect_key_layout(&keys.signed_public_key())
+        .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+
+    let main_password = Password::from(main_passphrase);
+    let sub_password = Password::from(sub_passphrase);
+    keys.unlock(&main_password, |_, _| Ok(()))
+        .map_err(|e| Error::KeyFormatError(e.to_string()))?
+        .map_err(|e: pgp::errors::Error| {
+            Error::KeyFormatError(format!("invalid main passphrase: {e}"))
+        })?;
+    if !keys.primary_key.secret_params().is_encrypted() {
+        keys.primary_key
+            .set_password(OsRng, &main_password)
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    }
+    for subkey in &mut keys.secret_subkeys {
+        reprotect_subkey(subkey, &main_password, &sub_password)?;
+    }
+
+    let mut missing = Vec::new();
+    if !layout.has_signing_subkey {
+        missing.push(SubkeyKind::Signing);
+    }
+    if !layout.has_encryption_subkey {
+        missing.push(SubkeyKind::Encryption);
+    }
+    add_bound_subkeys(&mut keys, &missing, &main_password, &sub_password)?;
+
+    // 追加後の鍵が通常の鍵と同様に使用できることを確認する
+    xrypton_common::keys::inspect_key_layout(&keys.signed_public_key())
+        .map_err(|e| Error::KeyFormatError(e.to_string()))
+        .and_then(|layout| {
+            if layout.is_complete() {
+                Ok(())
+            } else {
+                Err(Error::KeyFormatError(
+                    "failed to add missing subkeys".into(),
+                ))
+            }
+        })?;
+    keys.to_armored_string(ArmorOptions::default())
+        .map_err(|e| Error::KeyFormatError(e.to_string()))
 }
 
 #[cfg(test)]
@@ -487,4 +1360,315 @@ QV3hL3V6GgI=
         let subkeys = PrivateKeys::try_from(data).unwrap();
         dbg!(subkeys.keys);
     }
+
+    #[test]
+    fn import_adds_missing_subkeys() {
+        // 主鍵で署名し、暗号化サブキーのみを持つ GnuPG 形式の鍵
+        let encryption = SubkeyParamsBuilder::default()
+            .key_type(KeyType::ECDH(crypto::ecc_curve::ECCCurve::Curve25519))
+            .can_sign(false)
+            .can_encrypt(true)
+            .passphrase(Some("main".into()))
+            .build()
+            .unwrap();
+        let key = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::Ed25519Legacy)
+            .can_sign(true)
+            .passphrase(Some("main".into()))
+            .subkeys(vec![encryption])
+            .primary_user_id("Alice <alice@example.com>".into())
+            .build()
+            .unwrap()
+            .generate(OsRng)
+            .unwrap()
+            .sign(OsRng, &"main".into())
+            .unwrap()
+            .to_armored_string(ArmorOptions::default())
+            .unwrap();
+        assert!(PrivateKeys::try_from(key.as_str()).is_err());
+        assert!(import_keys(&key, "wrong", "sub").is_err());
+
+        let imported = import_keys(&key, "main", "sub").unwrap();
+        let private_keys = PrivateKeys::try_from(imported.as_str()).unwrap();
+        private_keys.validate_main_passphrase("main").unwrap();
+        private_keys.validate_sub_passphrase("sub").unwrap();
+        let public_keys = PublicKeys::try_from(private_keys.public_keys().as_str()).unwrap();
+        let signed = private_keys.sign("sub", b"hello".to_vec()).unwrap();
+        public_keys.verify(&signed).unwrap();
+    }
+
+    #[test]
+    fn change_passphrases_and_rotate_subkeys() {
+        let (armored, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let keys = PrivateKeys::try_from(armored.as_str()).unwrap();
+        assert!(keys.change_main_passphrase("wrong", "main2").is_err());
+
+        let changed = keys.change_main_passphrase("main", "main2").unwrap();
+        let keys = PrivateKeys::try_from(changed.as_str()).unwrap();
+        keys.validate_main_passphrase("main2").unwrap();
+        keys.validate_sub_passphrase("sub").unwrap();
+
+        let changed = keys.change_sub_passphrase("sub", "sub2").unwrap();
+        let keys = PrivateKeys::try_from(changed.as_str()).unwrap();
+        keys.validate_sub_passphrase("sub2").unwrap();
+        assert!(keys.validate_sub_passphrase("sub").is_err());
+
+        let before_armored = keys.public_keys();
+        let before = PublicKeys::try_from(before_armored.as_str()).unwrap();
+        let old_message = keys
+            .sign_encrypt_sign("sub2", &[&before], b"before rotation".to_vec())
+            .unwrap();
+
+        assert!(
+            keys.rotate_subkey("main", "sub2", SubkeyKind::Encryption)
+                .is_err()
+        );
+        let rotated = keys
+            .rotate_subkey("main2", "sub2", SubkeyKind::Encryption)
+            .unwrap();
+        let rotated = PrivateKeys::try_from(rotated.as_str())
+            .unwrap()
+            .rotate_subkey("main2", "sub2", SubkeyKind::Signing)
+            .unwrap();
+        let keys = PrivateKeys::try_from(rotated.as_str()).unwrap();
+        let after = PublicKeys::try_from(keys.public_keys().as_str()).unwrap();
+        assert_ne!(
+            before.encryption_public().unwrap().fingerprint(),
+            after.encryption_public().unwrap().fingerprint()
+        );
+        assert_ne!(
+            before.get_signing_sub_key_fingerprint().unwrap(),
+            after.get_signing_sub_key_fingerprint().unwrap()
+        );
+
+        // 新しい署名サブキーで署名し、古い暗号化サブキー宛てのメッセージも復号できる
+        after
+            .verify(&keys.sign("sub2", b"hello".to_vec()).unwrap())
+            .unwrap();
+        let inner = xrypton_common::keys::PublicKeys::try_from(before_armored.as_str())
+            .unwrap()
+            .verify_and_extract(&old_message)
+            .unwrap();
+        let (plain, _, _) = keys.decrypt_from_bytes("sub2", &inner).unwrap();
+        assert_eq!(plain, b"before rotation");
+    }
+
+    #[test]
+    fn revocation_certificates() {
+        let (armored, certificate) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let keys = PrivateKeys::try_from(armored.as_str()).unwrap();
+        let public =
+            xrypton_common::keys::PublicKeys::try_from(keys.public_keys().as_str()).unwrap();
+        public.verify_revocation_certificate(&certificate).unwrap();
+        let revoked = xrypton_common::keys::PublicKeys::try_from(
+            public
+                .with_revocation_certificate(&certificate)
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        assert!(revoked.is_revoked());
+
+        let encryption_fingerprint = format!(
+            "{:X}",
+            PublicKeys::try_from(keys.public_keys().as_str())
+                .unwrap()
+                .encryption_public()
+                .unwrap()
+                .fingerprint()
+        );
+        assert!(
+            keys.create_subkey_revocation(
+                "wrong",
+                &encryption_fingerprint,
+                RevocationReason::Retired,
+                ""
+            )
+            .is_err()
+        );
+        let subkey_revocation = keys
+            .create_subkey_revocation(
+                "main",
+                &encryption_fingerprint,
+                RevocationReason::Superseded,
+                "rotated",
+            )
+            .unwrap();
+        // 主鍵の失効証明書としては受け付けない
+        assert!(
+            public
+                .verify_revocation_certificate(&subkey_revocation)
+                .is_err()
+        );
+        let updated = public
+            .with_subkey_revocation_certificate(&subkey_revocation)
+            .unwrap();
+        let (updated, _) = SignedPublicKey::from_string(&updated).unwrap();
+        assert!(xrypton_common::keys::valid_encryption_subkeys(&updated).is_empty());
+        assert!(!xrypton_common::keys::valid_signing_subkeys(&updated).is_empty());
+    }
+
+    #[test]
+    fn verifies_signatures_made_before_subkey_revocation() {
+        use xrypton_common::error::XryptonError;
+        use xrypton_common::keys::VerificationOutcome;
+
+        let (armored, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let keys = PrivateKeys::try_from(armored.as_str()).unwrap();
+        let public =
+            xrypton_common::keys::PublicKeys::try_from(keys.public_keys().as_str()).unwrap();
+        let signing_fingerprint = public.get_signing_sub_key_fingerprint().unwrap();
+        let message = keys.sign("sub", b"before revocation".to_vec()).unwrap();
+        // 失効署名の作成時刻を署名より後にする
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let revoke = |reason| {
+            let revocation = keys
+                .create_subkey_revocation("main", &signing_fingerprint, reason, "")
+                .unwrap();
+            xrypton_common::keys::PublicKeys::try_from(
+                public
+                    .with_subkey_revocation_certificate(&revocation)
+                    .unwrap()
+                    .as_str(),
+            )
+            .unwrap()
+        };
+
+        // 廃止による失効は失効前の署名を無効にしない
+        let (payload, report) = revoke(RevocationReason::Retired)
+            .verify_with_report(&message)
+            .unwrap();
+        assert_eq!(payload, b"before revocation");
+        assert!(matches!(
+            report.outcome,
+            VerificationOutcome::VerifiedHistorical(XryptonError::Revoked(_))
+        ));
+        assert!(report.ensure_verified().is_err());
+        report.ensure_valid_when_signed().unwrap();
+
+        // 漏洩による失効はそれ以前の署名も無効にする
+        let (_, report) = revoke(RevocationReason::Compromised)
+            .verify_with_report(&message)
+            .unwrap();
+        assert!(matches!(
+            report.outcome,
+            VerificationOutcome::SignerUnusable(XryptonError::Revoked(_))
+        ));
+    }
+
+    #[test]
+    fn decrypts_with_keyring() {
+        let (old, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let (new, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let old_keys = PrivateKeys::try_from(old.as_str()).unwrap();
+        let old_public = PublicKeys::try_from(old_keys.public_keys().as_str()).unwrap();
+        let message = old_keys
+            .sign_encrypt_sign("sub", &[&old_public], b"old".to_vec())
+            .unwrap();
+        let inner = xrypton_common::keys::PublicKeys::try_from(old_keys.public_keys().as_str())
+            .unwrap()
+            .verify_and_extract(&message)
+            .unwrap();
+
+        let new_keys = PrivateKeys::try_from(new.as_str()).unwrap();
+        assert!(new_keys.decrypt_from_bytes("sub", &inner).is_err());
+
+        let keyring = add_to_keyring("", &old).unwrap();
+        let new_keys = new_keys.with_keyring(&keyring).unwrap();
+        let ((plain, _, _), decryption_key) =
+            new_keys.decrypt_bytes_and_report("sub", &inner).unwrap();
+        assert_eq!(plain, b"old");
+        assert_eq!(
+            decryption_key,
+            format!(
+                "{:X}",
+                old_public.encryption_public().unwrap().fingerprint()
+            )
+        );
+    }
+
+    #[test]
+    fn changes_keyring_sub_passphrase() {
+        let (old, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let (new, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let old_keys = PrivateKeys::try_from(old.as_str()).unwrap();
+        let old_public = PublicKeys::try_from(old_keys.public_keys().as_str()).unwrap();
+        let message = old_keys
+            .sign_encrypt_sign("sub", &[&old_public], b"old".to_vec())
+            .unwrap();
+        let inner = xrypton_common::keys::PublicKeys::try_from(old_keys.public_keys().as_str())
+            .unwrap()
+            .verify_and_extract(&message)
+            .unwrap();
+
+        let new_keys = PrivateKeys::try_from(new.as_str()).unwrap();
+        let changed = new_keys.change_sub_passphrase("sub", "sub2").unwrap();
+        let keyring = add_to_keyring("", &old).unwrap();
+        assert!(change_keyring_sub_passphrase(&keyring, "wrong", "sub2").is_err());
+        let keyring = change_keyring_sub_passphrase(&keyring, "sub", "sub2").unwrap();
+
+        let new_keys = PrivateKeys::try_from(changed.as_str())
+            .unwrap()
+            .with_keyring(&keyring)
+            .unwrap();
+        assert!(new_keys.decrypt_bytes_and_report("sub", &inner).is_err());
+        let ((plain, _, _), _) = new_keys.decrypt_bytes_and_report("sub2", &inner).unwrap();
+        assert_eq!(plain, b"old");
+    }
+
+    #[test]
+    fn pads_inner_layer() {
+        assert_eq!(padme(256), 256);
+        assert_eq!(padme(1000), 1024);
+        assert_eq!(padme(100_000), 100_352);
+
+        let (armored, _) =
+            generate_keys("alice".into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+        let keys = PrivateKeys::try_from(armored.as_str()).unwrap();
+        let public = PublicKeys::try_from(keys.public_keys().as_str()).unwrap();
+        let outer =
+            xrypton_common::keys::PublicKeys::try_from(keys.public_keys().as_str()).unwrap();
+
+        // 短いメッセージは長さによらず同じサイズになる
+        let short = keys
+            .sign_encrypt_sign_bin("sub", &[&public], b"hi".to_vec())
+            .unwrap();
+        let longer = keys
+            .sign_encrypt_sign_bin("sub", &[&public], vec![b'a'; 100])
+            .unwrap();
+        let short = outer.verify_and_extract_from_bytes(&short).unwrap();
+        let longer = outer.verify_and_extract_from_bytes(&longer).unwrap();
+        assert_eq!(short.len(), longer.len());
+
+        // 受信側ではパディングが除去され、署名はデータに対して検証できる
+        let (plain, signature, _) = keys.decrypt_from_bytes("sub", &longer).unwrap();
+        assert_eq!(plain, vec![b'a'; 100]);
+        public
+            .verify_detached_signature(&signature.unwrap(), &plain)
+            .unwrap();
+
+        // パディング導入前の形式も復号できる
+        let mut legacy = MessageBuilder::from_bytes("", b"legacy".to_vec())
+            .seipd_v1(OsRng, crypto::sym::SymmetricKeyAlgorithm::AES256);
+        legacy.sign_with_subpackets(
+            &keys.signing_secret().key,
+            Password::from("sub"),
+            crypto::hash::HashAlgorithm::Sha512,
+            keys.sign_subpacket_config().unwrap(),
+        );
+        legacy
+            .encrypt_to_key(OsRng, public.encryption_public().unwrap())
+            .unwrap();
+        let legacy = legacy.to_vec(OsRng).unwrap();
+        let (plain, signature, _) = keys.decrypt_from_bytes("sub", &legacy).unwrap();
+        assert_eq!(plain, b"legacy");
+        assert!(signature.is_some());
+    }
 }
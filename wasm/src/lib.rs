@@ -4,6 +4,12 @@ use wasm_bindgen::prelude::*;
 
 mod backup;
 mod keys;
+mod ratchet;
+mod recovery;
+mod sender_key;
+mod stream;
+#[cfg(test)]
+mod test_utils;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -48,13 +54,28 @@ impl ReturnValue {
     }
 }
 
+/// 秘密鍵を生成する。
+/// `profile` は `"v4"`（既定）または `"v6"`（RFC 9580: Ed25519/X25519, SEIPDv2）。
 #[wasm_bindgen]
 pub fn generate_private_keys(
     user_id: String,
     main_passphrase: String,
     sub_passphrase: String,
+    profile: Option<String>,
 ) -> wasm_bindgen::JsValue {
-    let (keys, _subkeys) = match keys::generate_keys(user_id, main_passphrase, sub_passphrase) {
+    let generated = profile
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .and_then(|profile| {
+            keys::generate_keys(
+                user_id,
+                main_passphrase,
+                sub_passphrase,
+                profile.unwrap_or_default(),
+            )
+        });
+    let (keys, revocation) = match generated {
         Ok(v) => v,
         Err(e) => {
             return ReturnValue::Error {
@@ -64,11 +85,188 @@ pub fn generate_private_keys(
         }
     };
     ReturnValue::Ok {
-        value: vec![ResultData::String { data: keys }],
+        value: vec![
+            ResultData::String { data: keys },
+            ResultData::String { data: revocation },
+        ],
     }
     .to_value()
 }
 
+/// 主鍵の失効証明書を生成する。
+/// `reason`: "no_reason" | "superseded" | "compromised" | "retired"
+/// 返り値: [String(armored_signature)]
+#[wasm_bindgen]
+pub fn create_revocation_certificate(
+    private_key: String,
+    main_passphrase: &str,
+    reason: &str,
+    description: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(private_key)?;
+    let certificate = reason
+        .parse()
+        .and_then(|reason| keys.create_revocation_certificate(main_passphrase, reason, description))
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: certificate }],
+    }
+    .to_value())
+}
+
+/// 指定したサブキーの失効署名を生成する。
+/// 返り値: [String(armored_signature)]
+#[wasm_bindgen]
+pub fn create_subkey_revocation(
+    private_key: String,
+    main_passphrase: &str,
+    subkey_fingerprint: &str,
+    reason: &str,
+    description: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(private_key)?;
+    let certificate = reason
+        .parse()
+        .and_then(|reason| {
+            keys.create_subkey_revocation(main_passphrase, subkey_fingerprint, reason, description)
+        })
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: certificate }],
+    }
+    .to_value())
+}
+
+/// 保管していた失効署名を公開鍵に取り込む。
+///
+/// 主鍵の失効証明書は POST `/keys/{fingerprint}/revocation` でそのまま公開できる。
+/// サブキーの失効署名を取り込んだ公開鍵は PUT `/user/{id}/keys` で公開する。
+/// 返り値: [String(public_key)]
+#[wasm_bindgen]
+pub fn apply_revocation_certificate(public_key: String, certificate: &str) -> JsValue {
+    let applied =
+        xrypton_common::keys::PublicKeys::try_from(public_key.as_str()).and_then(|keys| {
+            keys.with_revocation_certificate(certificate)
+                .or_else(|_| keys.with_subkey_revocation_certificate(certificate))
+        });
+    match applied {
+        Ok(data) => ReturnValue::Ok {
+            value: vec![ResultData::String { data }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 既存の OpenPGP 秘密鍵をインポートし、不足しているサブキーを追加した秘密鍵を返す。
+#[wasm_bindgen]
+pub fn import_private_keys(
+    armored: String,
+    main_passphrase: String,
+    sub_passphrase: String,
+) -> wasm_bindgen::JsValue {
+    match keys::import_keys(&armored, &main_passphrase, &sub_passphrase) {
+        Ok(keys) => ReturnValue::Ok {
+            value: vec![ResultData::String { data: keys }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 秘密鍵と、PUT `/user/{id}/keys` に送る公開鍵を返す。
+fn updated_keys_value(private_key: String) -> JsValue {
+    match keys::PrivateKeys::try_from(private_key.as_str()) {
+        Ok(keys) => ReturnValue::Ok {
+            value: vec![
+                ResultData::String { data: private_key },
+                ResultData::String {
+                    data: keys.public_keys(),
+                },
+            ],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 主鍵のパスフレーズを変更する。
+/// 返り値: [String(private_key), String(public_key)]
+#[wasm_bindgen]
+pub fn change_main_passphrase(
+    private_key: String,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(private_key)?;
+    match keys.change_main_passphrase(old_passphrase, new_passphrase) {
+        Ok(updated) => Ok(updated_keys_value(updated)),
+        Err(e) => Err(ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()),
+    }
+}
+
+/// サブ鍵のパスフレーズを変更する。
+/// 返り値: [String(private_key), String(public_key)]
+#[wasm_bindgen]
+pub fn change_sub_passphrase(
+    private_key: String,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(private_key)?;
+    match keys.change_sub_passphrase(old_passphrase, new_passphrase) {
+        Ok(updated) => Ok(updated_keys_value(updated)),
+        Err(e) => Err(ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()),
+    }
+}
+
+/// 新しい署名サブキー（`kind = "signing"`）または暗号化サブキー（`kind = "encryption"`）を追加する。
+/// 返り値: [String(private_key), String(public_key)]
+#[wasm_bindgen]
+pub fn rotate_subkey(
+    private_key: String,
+    main_passphrase: &str,
+    sub_passphrase: &str,
+    kind: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(private_key)?;
+    match kind
+        .parse()
+        .and_then(|kind| keys.rotate_subkey(main_passphrase, sub_passphrase, kind))
+    {
+        Ok(updated) => Ok(updated_keys_value(updated)),
+        Err(e) => Err(ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()),
+    }
+}
+
 #[wasm_bindgen]
 pub fn backup_encrypt(
     payload_json: String,
@@ -94,32 +292,474 @@ pub fn backup_encrypt(
 }
 
 #[wasm_bindgen]
-pub fn backup_decrypt(
-    armored: String,
-    main_passphrase: String,
-    prf_output_b64: String,
-    credential_id_b64: String,
-) -> JsValue {
-    match backup::backup_decrypt(
-        &armored,
-        &main_passphrase,
-        &prf_output_b64,
-        &credential_id_b64,
-    ) {
-        Ok((payload_json, credential_id)) => ReturnValue::Ok {
-            value: vec![
-                ResultData::String { data: payload_json },
-                ResultData::String {
-                    data: credential_id,
-                },
-            ],
-        }
-        .to_value(),
-        Err(e) => ReturnValue::Error {
-            message: e.to_string(),
-        }
-        .to_value(),
+pub fn backup_decrypt(
+    armored: String,
+    main_passphrase: String,
+    prf_output_b64: String,
+    credential_id_b64: String,
+) -> JsValue {
+    match backup::backup_decrypt(
+        &armored,
+        &main_passphrase,
+        &prf_output_b64,
+        &credential_id_b64,
+    ) {
+        Ok((payload_json, credential_id)) => ReturnValue::Ok {
+            value: vec![
+                ResultData::String { data: payload_json },
+                ResultData::String {
+                    data: credential_id,
+                },
+            ],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 複数の解除方法で開けるバックアップ（v2）を作成する。
+/// 返り値: [String(envelope_json), String(recovery_code)...]
+#[wasm_bindgen]
+pub fn backup_v2_create(payload_json: String, methods_json: String) -> JsValue {
+    match backup::backup_v2_create(&payload_json, &methods_json) {
+        Ok((envelope, recovery_codes)) => ReturnValue::Ok {
+            value: std::iter::once(envelope)
+                .chain(recovery_codes)
+                .map(|data| ResultData::String { data })
+                .collect(),
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// v2 バックアップを開く。
+/// 返り値: [String(payload_json)]
+#[wasm_bindgen]
+pub fn backup_v2_decrypt(envelope_json: String, unlock_json: String) -> JsValue {
+    match backup::backup_v2_decrypt(&envelope_json, &unlock_json) {
+        Ok(payload_json) => ReturnValue::Ok {
+            value: vec![ResultData::String { data: payload_json }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// v2 バックアップに解除方法を追加する。
+/// 返り値: [String(envelope_json), String(recovery_code)?]
+#[wasm_bindgen]
+pub fn backup_v2_add_method(
+    envelope_json: String,
+    unlock_json: String,
+    method_json: String,
+) -> JsValue {
+    match backup::backup_v2_add_method(&envelope_json, &unlock_json, &method_json) {
+        Ok((envelope, recovery_code)) => ReturnValue::Ok {
+            value: std::iter::once(envelope)
+                .chain(recovery_code)
+                .map(|data| ResultData::String { data })
+                .collect(),
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// v2 バックアップから解除方法を削除する。
+/// 返り値: [String(envelope_json)]
+#[wasm_bindgen]
+pub fn backup_v2_remove_method(envelope_json: String, method_id: String) -> JsValue {
+    match backup::backup_v2_remove_method(&envelope_json, &method_id) {
+        Ok(envelope) => ReturnValue::Ok {
+            value: vec![ResultData::String { data: envelope }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// バックアップ取得時の所持証明に使う回復用公開鍵を返す。
+/// 返り値: [String(public_key_b64)]
+#[wasm_bindgen]
+pub fn backup_recovery_public_key(unlock_json: String) -> JsValue {
+    match backup::backup_recovery_public_key(&unlock_json) {
+        Ok(public_key) => ReturnValue::Ok {
+            value: vec![ResultData::String { data: public_key }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// バックアップ取得のチャレンジに署名する。
+/// 返り値: [String(public_key_b64), String(signature)]
+#[wasm_bindgen]
+pub fn backup_sign_retrieval_challenge(
+    unlock_json: String,
+    user_id: String,
+    challenge: String,
+) -> JsValue {
+    match backup::backup_sign_retrieval_challenge(&unlock_json, &user_id, &challenge) {
+        Ok((public_key, signature)) => ReturnValue::Ok {
+            value: vec![
+                ResultData::String { data: public_key },
+                ResultData::String { data: signature },
+            ],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// バックアップペイロードを保護者の間で `threshold`-of-N に分割する。
+/// `guardians_json` は `[{"user_id", "public_key"}]`。
+/// 返り値: [String(setup_json)]（サーバに登録する `payload_armored`・`threshold`・`shares`）
+#[wasm_bindgen]
+pub fn social_recovery_split(
+    payload_json: String,
+    threshold: u8,
+    guardians_json: String,
+) -> JsValue {
+    let setup = serde_json::from_str::<Vec<recovery::Guardian>>(&guardians_json)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))
+        .and_then(|guardians| recovery::split_recovery_secret(&payload_json, threshold, &guardians))
+        .and_then(|setup| {
+            serde_json::to_string(&setup).map_err(|e| Error::EncryptionError(e.to_string()))
+        });
+    match setup {
+        Ok(data) => ReturnValue::Ok {
+            value: vec![ResultData::String { data }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 保護者として自分宛てのシェアを復元者の回復鍵宛てに再暗号化する。
+/// 返り値: [String(share_armored)]
+#[wasm_bindgen]
+pub fn social_recovery_reencrypt_share(
+    private_key: String,
+    sub_passphrase: String,
+    share_armored: String,
+    recovery_public_key: String,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let recovery_public = get_public_keys(recovery_public_key)?;
+    let share =
+        recovery::reencrypt_share(&private, &sub_passphrase, &share_armored, &recovery_public)
+            .map_err(|e| {
+                ReturnValue::Error {
+                    message: e.to_string(),
+                }
+                .to_value()
+            })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: share }],
+    }
+    .to_value())
+}
+
+/// 保護者から返されたシェアを結合してバックアップペイロードを復元する。
+/// `shares_json` は `[{"public_key", "share_armored"}]`。
+/// 返り値: [String(payload_json)]
+#[wasm_bindgen]
+pub fn social_recovery_combine(
+    recovery_private_key: String,
+    sub_passphrase: String,
+    payload_armored: String,
+    shares_json: String,
+) -> Result<JsValue, JsValue> {
+    let recovery_private = get_private_keys(recovery_private_key)?;
+    let payload_json = serde_json::from_str::<Vec<recovery::ReturnedShare>>(&shares_json)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))
+        .and_then(|shares| {
+            recovery::combine_recovery_shares(
+                &recovery_private,
+                &sub_passphrase,
+                &payload_armored,
+                &shares,
+            )
+        })
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: payload_json }],
+    }
+    .to_value())
+}
+
+/// チャットの送信用センダーキーを新規作成する。`member_ids` は配布先のメンバー。
+/// 返り値: [String(state_json)]
+#[wasm_bindgen]
+pub fn sender_key_create(chat_id: String, member_ids: Vec<String>) -> Result<JsValue, JsValue> {
+    let state = sender_key::SenderKeyState::new(&chat_id, &member_ids);
+    let data = serde_json::to_string(&state)
+        .map_err(|e| error_value(Error::EncryptionError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data }],
+    }
+    .to_value())
+}
+
+/// 現在のメンバーが配布時と異なり、センダーキーのローテーションが必要か判定する。
+#[wasm_bindgen]
+pub fn sender_key_needs_rotation(state_json: String, member_ids: Vec<String>) -> bool {
+    serde_json::from_str::<sender_key::SenderKeyState>(&state_json)
+        .map(|state| state.needs_rotation(&member_ids))
+        .unwrap_or(true)
+}
+
+/// センダーキーを各メンバー宛てに暗号化する。
+/// `recipients_json` は `[{"user_id", "public_key"}]`。
+/// 返り値: [String(body_json)]（PUT `/chat/{chat_id}/sender-keys` のリクエストボディ）
+#[wasm_bindgen]
+pub fn sender_key_distribute(
+    private_key: String,
+    sub_passphrase: String,
+    state_json: String,
+    recipients_json: String,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let body = serde_json::from_str::<sender_key::SenderKeyState>(&state_json)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))
+        .and_then(|state| {
+            let recipients = serde_json::from_str::<Vec<sender_key::Recipient>>(&recipients_json)
+                .map_err(|e| Error::InvalidPayload(e.to_string()))?;
+            let distributions = state.distribute(&private, &sub_passphrase, &recipients)?;
+            serde_json::to_string(&serde_json::json!({
+                "key_id": state.key_id(),
+                "distributions": distributions,
+            }))
+            .map_err(|e| Error::EncryptionError(e.to_string()))
+        })
+        .map_err(error_value)?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: body }],
+    }
+    .to_value())
+}
+
+/// 自分宛ての配布メッセージを検証・復号し、送信者のセンダーキーを取り込む。
+/// 返り値: [String(received_key_json)]
+#[wasm_bindgen]
+pub fn sender_key_accept(
+    private_key: String,
+    sub_passphrase: String,
+    sender_public_key: String,
+    distribution: String,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let data = sender_key::ReceivedSenderKey::accept(
+        &private,
+        &sub_passphrase,
+        &sender_public_key,
+        &distribution,
+    )
+    .and_then(|key| serde_json::to_string(&key).map_err(|e| Error::DecryptionError(e.to_string())))
+    .map_err(error_value)?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data }],
+    }
+    .to_value())
+}
+
+/// センダーキーでメッセージを暗号化する。更新後の状態を必ず保存すること。
+/// 返り値: [String(updated_state_json), String(armored_message)]
+#[wasm_bindgen]
+pub fn sender_key_encrypt(
+    private_key: String,
+    sub_passphrase: String,
+    state_json: String,
+    data: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let mut state = serde_json::from_str::<sender_key::SenderKeyState>(&state_json)
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let message = state
+        .encrypt(&private, &sub_passphrase, data)
+        .map_err(error_value)?;
+    let state = serde_json::to_string(&state)
+        .map_err(|e| error_value(Error::EncryptionError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String { data: state },
+            ResultData::String { data: message },
+        ],
+    }
+    .to_value())
+}
+
+/// 受信済みのセンダーキー（`keys_json` は配列）でメッセージを復号する。
+/// 更新後の鍵を必ず保存すること。
+/// 返り値: [String(updated_keys_json), Base64(data)]
+#[wasm_bindgen]
+pub fn sender_key_decrypt(
+    keys_json: String,
+    sender_public_key: String,
+    message: String,
+) -> Result<JsValue, JsValue> {
+    let mut keys = serde_json::from_str::<Vec<sender_key::ReceivedSenderKey>>(&keys_json)
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let data = sender_key::decrypt_message(&mut keys, &sender_public_key, &message)
+        .map_err(error_value)?;
+    let keys = serde_json::to_string(&keys)
+        .map_err(|e| error_value(Error::DecryptionError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String { data: keys },
+            ResultData::Base64 {
+                data: STANDARD.encode(data),
+            },
+        ],
+    }
+    .to_value())
+}
+
+/// 前方秘匿セッション用のプレキーを生成する。
+/// `store_json` が未指定の場合は ID 鍵を新規作成する。署名付きプレキーは毎回更新される。
+/// 返り値: [String(store_json), String(upload_json)]（PUT `/user/{id}/prekeys` のリクエストボディ）
+#[wasm_bindgen]
+pub fn ratchet_generate_prekeys(
+    private_key: String,
+    sub_passphrase: String,
+    store_json: Option<String>,
+    one_time_count: u32,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let mut store = match store_json {
+        Some(json) => serde_json::from_str::<ratchet::PrekeyStore>(&json)
+            .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?,
+        None => ratchet::PrekeyStore::new(),
+    };
+    let upload = store
+        .generate(&private, &sub_passphrase, one_time_count)
+        .and_then(|upload| {
+            serde_json::to_string(&upload).map_err(|e| Error::SigningError(e.to_string()))
+        })
+        .map_err(error_value)?;
+    let store = serde_json::to_string(&store)
+        .map_err(|e| error_value(Error::KeyGenerationError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String { data: store },
+            ResultData::String { data: upload },
+        ],
+    }
+    .to_value())
+}
+
+/// 相手のプレキーバンドル（GET `/user/{id}/prekeys` のレスポンス）を検証してセッションを開始する。
+/// 返り値: [String(session_json)]
+#[wasm_bindgen]
+pub fn ratchet_initiate(
+    store_json: String,
+    peer_public_key: String,
+    bundle_json: String,
+) -> Result<JsValue, JsValue> {
+    let store = serde_json::from_str::<ratchet::PrekeyStore>(&store_json)
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let data = serde_json::from_str::<ratchet::FetchedBundle>(&bundle_json)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))
+        .and_then(|bundle| ratchet::Session::initiate(&store, &peer_public_key, &bundle))
+        .and_then(|session| {
+            serde_json::to_string(&session).map_err(|e| Error::EncryptionError(e.to_string()))
+        })
+        .map_err(error_value)?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data }],
+    }
+    .to_value())
+}
+
+/// セッションでメッセージを暗号化する。更新後のセッションを必ず保存すること。
+/// 返り値: [String(updated_session_json), String(armored_message)]
+#[wasm_bindgen]
+pub fn ratchet_encrypt(
+    private_key: String,
+    sub_passphrase: String,
+    session_json: String,
+    data: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys(private_key)?;
+    let mut session = serde_json::from_str::<ratchet::Session>(&session_json)
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let message = session
+        .encrypt(&private, &sub_passphrase, data)
+        .map_err(error_value)?;
+    let session = serde_json::to_string(&session)
+        .map_err(|e| error_value(Error::EncryptionError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String { data: session },
+            ResultData::String { data: message },
+        ],
+    }
+    .to_value())
+}
+
+/// セッションでメッセージを復号する。相手が開始した初回メッセージは `store_json` から受け入れる。
+/// 更新後のプレキーストアとセッションを必ず保存すること。
+/// 返り値: [String(updated_store_json), String(updated_session_json), Base64(data)]
+#[wasm_bindgen]
+pub fn ratchet_decrypt(
+    store_json: String,
+    session_json: Option<String>,
+    sender_public_key: String,
+    message: String,
+) -> Result<JsValue, JsValue> {
+    let mut store = serde_json::from_str::<ratchet::PrekeyStore>(&store_json)
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let session = session_json
+        .map(|json| serde_json::from_str::<ratchet::Session>(&json))
+        .transpose()
+        .map_err(|e| error_value(Error::InvalidPayload(e.to_string())))?;
+    let (session, data) =
+        ratchet::decrypt_message(&mut store, session, &sender_public_key, &message)
+            .map_err(error_value)?;
+    let store = serde_json::to_string(&store)
+        .map_err(|e| error_value(Error::DecryptionError(e.to_string())))?;
+    let session = serde_json::to_string(&session)
+        .map_err(|e| error_value(Error::DecryptionError(e.to_string())))?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String { data: store },
+            ResultData::String { data: session },
+            ResultData::Base64 {
+                data: STANDARD.encode(data),
+            },
+        ],
     }
+    .to_value())
 }
 
 fn get_private_keys(keys: String) -> Result<keys::PrivateKeys, JsValue> {
@@ -154,10 +794,14 @@ pub fn export_public_keys(keys: String) -> Result<JsValue, JsValue> {
 #[wasm_bindgen]
 pub fn get_signing_sub_key_id(public_keys: String) -> Result<JsValue, JsValue> {
     let keys = get_public_keys(public_keys)?;
+    let data = keys.get_signing_sub_key_id().map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
     Ok(ReturnValue::Ok {
-        value: vec![ResultData::String {
-            data: keys.get_signing_sub_key_id(),
-        }],
+        value: vec![ResultData::String { data }],
     }
     .to_value())
 }
@@ -174,10 +818,14 @@ pub fn get_primary_fingerprint(public_keys: String) -> Result<JsValue, JsValue>
 #[wasm_bindgen]
 pub fn get_signing_sub_key_fingerprint(public_keys: String) -> Result<JsValue, JsValue> {
     let keys = get_public_keys(public_keys)?;
+    let data = keys.get_signing_sub_key_fingerprint().map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
     Ok(ReturnValue::Ok {
-        value: vec![ResultData::String {
-            data: keys.get_signing_sub_key_fingerprint(),
-        }],
+        value: vec![ResultData::String { data }],
     }
     .to_value())
 }
@@ -311,6 +959,62 @@ pub fn sign(keys: String, sub_passphrase: &str, data: Vec<u8>) -> Result<JsValue
     }
     .to_value())
 }
+
+/// アカウント移行宣言（主鍵署名済み armored）を生成する。
+/// 返り値: [String(armored_statement)]
+#[wasm_bindgen]
+pub fn create_moved_statement(
+    keys: String,
+    main_passphrase: &str,
+    to_user_id: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(keys)?;
+    let statement = keys
+        .create_moved_statement(main_passphrase, to_user_id)
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String { data: statement }],
+    }
+    .to_value())
+}
+
+/// 鍵移行宣言（旧主鍵・新主鍵でそれぞれ署名済み armored）を生成する。
+/// 返り値: [String(old_key_statement), String(new_key_statement)]
+#[wasm_bindgen]
+pub fn create_key_transition_statement(
+    keys: String,
+    main_passphrase: &str,
+    new_keys: String,
+    new_main_passphrase: &str,
+) -> Result<JsValue, JsValue> {
+    let keys = get_private_keys(keys)?;
+    let new_keys = get_private_keys(new_keys)?;
+    let (old_statement, new_statement) = keys
+        .create_key_transition_statement(main_passphrase, &new_keys, new_main_passphrase)
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::String {
+                data: old_statement,
+            },
+            ResultData::String {
+                data: new_statement,
+            },
+        ],
+    }
+    .to_value())
+}
+
 /// 署名のみ（暗号化なし）を raw PGP バイト列で返す。
 /// 返り値: [Base64(raw_pgp_bytes)]
 #[wasm_bindgen]
@@ -427,26 +1131,21 @@ pub fn verify_detached_signature(
 /// 返り値: [Base64(inner_bytes), String(outer_fingerprint)]
 #[wasm_bindgen]
 pub fn unwrap_outer(public_key: String, outer_armored: &str) -> Result<JsValue, JsValue> {
-    let outer_fingerprint = xrypton_common::keys::extract_issuer_fingerprint(outer_armored)
-        .map_err(|e| {
+    let common_pk =
+        xrypton_common::keys::PublicKeys::try_from(public_key.as_str()).map_err(|e| {
             ReturnValue::Error {
                 message: e.to_string(),
             }
             .to_value()
         })?;
-    let common_pk =
-        xrypton_common::keys::PublicKeys::try_from(public_key.as_str()).map_err(|e| {
+    let (inner_bytes, outer_fingerprint) = common_pk
+        .verify_and_extract_with_signer(outer_armored)
+        .map_err(|e| {
             ReturnValue::Error {
                 message: e.to_string(),
             }
             .to_value()
         })?;
-    let inner_bytes = common_pk.verify_and_extract(outer_armored).map_err(|e| {
-        ReturnValue::Error {
-            message: e.to_string(),
-        }
-        .to_value()
-    })?;
     Ok(ReturnValue::Ok {
         value: vec![
             ResultData::Base64 {
@@ -489,6 +1188,197 @@ pub fn decrypt_bytes(
     Ok(ReturnValue::Ok { value: result }.to_value())
 }
 
+/// 過去の秘密鍵を含む鍵束も使って復号する。
+/// 返り値: [Base64(data), String(decryption_key_fingerprint), String(signature)?, String(fingerprint)...]
+#[wasm_bindgen]
+pub fn decrypt_with_keyring(
+    private_key: String,
+    keyring: &str,
+    sub_passphrase: &str,
+    data: &str,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys_with_keyring(private_key, keyring)?;
+    let decrypted = private
+        .decrypt_and_report(sub_passphrase, data)
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(decrypt_report_value(decrypted))
+}
+
+/// raw PGP bytes を鍵束も使って復号する（decrypt_with_keyring と同じ返り値形式）。
+#[wasm_bindgen]
+pub fn decrypt_bytes_with_keyring(
+    private_key: String,
+    keyring: &str,
+    sub_passphrase: &str,
+    data: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let private = get_private_keys_with_keyring(private_key, keyring)?;
+    let decrypted = private
+        .decrypt_bytes_and_report(sub_passphrase, &data)
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    Ok(decrypt_report_value(decrypted))
+}
+
+/// 秘密鍵を鍵束に追加する。`keyring` が空文字列の場合は新しい鍵束を作る。
+/// 返り値: [String(keyring)]
+#[wasm_bindgen]
+pub fn keyring_add(keyring: &str, private_key: &str) -> JsValue {
+    match keys::add_to_keyring(keyring, private_key) {
+        Ok(data) => ReturnValue::Ok {
+            value: vec![ResultData::String { data }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+/// 鍵束内の秘密鍵のサブ鍵パスフレーズを変更する。`change_sub_passphrase` と合わせて呼び出す。
+/// 返り値: [String(keyring)]
+#[wasm_bindgen]
+pub fn keyring_change_sub_passphrase(
+    keyring: &str,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> JsValue {
+    match keys::change_keyring_sub_passphrase(keyring, old_passphrase, new_passphrase) {
+        Ok(data) => ReturnValue::Ok {
+            value: vec![ResultData::String { data }],
+        }
+        .to_value(),
+        Err(e) => ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value(),
+    }
+}
+
+fn get_private_keys_with_keyring(
+    private_key: String,
+    keyring: &str,
+) -> Result<keys::PrivateKeys, JsValue> {
+    get_private_keys(private_key)?
+        .with_keyring(keyring)
+        .map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })
+}
+
+fn decrypt_report_value(
+    ((data, signature, fingerprints), decryption_key): (keys::DecryptResult, String),
+) -> JsValue {
+    let mut result = Vec::with_capacity(2 + fingerprints.len());
+    result.push(ResultData::Base64 {
+        data: URL_SAFE.encode(&data),
+    });
+    result.push(ResultData::String {
+        data: decryption_key,
+    });
+    if let Some(data) = signature {
+        result.push(ResultData::String { data });
+        for fingerprint in fingerprints {
+            result.push(ResultData::String { data: fingerprint });
+        }
+    }
+    ReturnValue::Ok { value: result }.to_value()
+}
+
+fn error_value(e: Error) -> JsValue {
+    ReturnValue::Error {
+        message: e.to_string(),
+    }
+    .to_value()
+}
+
+/// 大きなファイルをチャンク単位で暗号化する。
+///
+/// `header()` を先にアップロードし、`push` と `finish` が返すフレームを順に追記する。
+#[wasm_bindgen]
+pub struct StreamEncryptor(stream::StreamEncryptor);
+
+#[wasm_bindgen]
+impl StreamEncryptor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        private_key: String,
+        public_keys: Vec<String>,
+        sub_passphrase: &str,
+    ) -> Result<StreamEncryptor, JsValue> {
+        let private = get_private_keys(private_key)?;
+        let recipients: Vec<keys::PublicKeys> = public_keys
+            .iter()
+            .map(|k| keys::PublicKeys::try_from(k.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error_value)?;
+        let recipient_refs: Vec<&keys::PublicKeys> = recipients.iter().collect();
+        stream::StreamEncryptor::new(private, sub_passphrase, &recipient_refs)
+            .map(StreamEncryptor)
+            .map_err(error_value)
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        self.0.header().to_vec()
+    }
+
+    /// 平文を追加し、完成したフレームを返す（空の場合もある）。
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.0.push(data).map_err(error_value)
+    }
+
+    /// 残りのフレームと署名を含む最終フレームを返す。
+    pub fn finish(self) -> Result<Vec<u8>, JsValue> {
+        self.0.finish().map_err(error_value)
+    }
+}
+
+/// チャンク単位で暗号化されたファイルを復号する。
+///
+/// `push` が返す平文は各チャンクの認証済みだが、送信者の署名は `finish` で確定する。
+/// `finish` が失敗した場合は復号結果を破棄すること。
+#[wasm_bindgen]
+pub struct StreamDecryptor(stream::StreamDecryptor);
+
+#[wasm_bindgen]
+impl StreamDecryptor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        private_key: String,
+        sub_passphrase: &str,
+        sender_public_key: &str,
+        header: &[u8],
+    ) -> Result<StreamDecryptor, JsValue> {
+        let private = get_private_keys(private_key)?;
+        stream::StreamDecryptor::new(&private, sub_passphrase, sender_public_key, header)
+            .map(StreamDecryptor)
+            .map_err(error_value)
+    }
+
+    /// 暗号文を追加し、復号できたチャンクの平文を返す（空の場合もある）。
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.0.push(data).map_err(error_value)
+    }
+
+    /// 最終フレームの署名を検証済みであることを確認する。
+    pub fn finish(self) -> Result<(), JsValue> {
+        self.0.finish().map_err(error_value)
+    }
+}
+
 /// raw PGP バイト列から署名者のフィンガープリントを抽出する。
 /// 返り値: [String(fingerprint)]
 #[wasm_bindgen]
@@ -510,13 +1400,6 @@ pub fn extract_fingerprint_bytes(data: Vec<u8>) -> Result<JsValue, JsValue> {
 /// 返り値: [Base64(inner_bytes), String(outer_fingerprint)]
 #[wasm_bindgen]
 pub fn unwrap_outer_bytes(public_key: String, data: Vec<u8>) -> Result<JsValue, JsValue> {
-    let outer_fingerprint = xrypton_common::keys::extract_issuer_fingerprint_from_bytes(&data)
-        .map_err(|e| {
-            ReturnValue::Error {
-                message: e.to_string(),
-            }
-            .to_value()
-        })?;
     let common_pk =
         xrypton_common::keys::PublicKeys::try_from(public_key.as_str()).map_err(|e| {
             ReturnValue::Error {
@@ -524,14 +1407,20 @@ pub fn unwrap_outer_bytes(public_key: String, data: Vec<u8>) -> Result<JsValue,
             }
             .to_value()
         })?;
-    let inner_bytes = common_pk
-        .verify_and_extract_from_bytes(&data)
-        .map_err(|e| {
-            ReturnValue::Error {
-                message: e.to_string(),
-            }
-            .to_value()
-        })?;
+    let (inner_bytes, report) = common_pk.verify_bytes_with_report(&data).map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
+    // 受信済みメッセージは署名時点で有効だったデバイス鍵の署名も受け付ける
+    report.ensure_valid_when_signed().map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
+    let outer_fingerprint = report.signer_fingerprint.unwrap_or_default();
     Ok(ReturnValue::Ok {
         value: vec![
             ResultData::Base64 {
@@ -574,6 +1463,91 @@ pub fn verify_extract_string(public_key: String, armored: &str) -> Result<JsValu
     .to_value())
 }
 
+/// 検証レポートを JSON 文字列に変換する。
+fn report_to_json(report: &xrypton_common::keys::SignatureReport) -> String {
+    use xrypton_common::keys::{MessageLayer, VerificationOutcome};
+
+    let layers: Vec<serde_json::Value> = report
+        .layers
+        .iter()
+        .map(|layer| match layer {
+            MessageLayer::Signed => serde_json::json!({ "type": "signed" }),
+            MessageLayer::Compressed(algorithm) => {
+                serde_json::json!({ "type": "compressed", "algorithm": algorithm })
+            }
+            MessageLayer::Encrypted => serde_json::json!({ "type": "encrypted" }),
+            MessageLayer::Literal => serde_json::json!({ "type": "literal" }),
+        })
+        .collect();
+    let reason = match &report.outcome {
+        VerificationOutcome::SignerUnusable(e) | VerificationOutcome::VerifiedHistorical(e) => {
+            Some(e.to_string())
+        }
+        VerificationOutcome::BadSignature(reason) => Some(reason.clone()),
+        _ => None,
+    };
+    serde_json::json!({
+        "signer_fingerprint": report.signer_fingerprint,
+        "signer_key_id": report.signer_key_id,
+        "signer_user_id": report.signer_user_id,
+        "created_at": report.created_at.map(|t| t.to_rfc3339()),
+        "hash_algorithm": report.hash_algorithm,
+        "layers": layers,
+        "outcome": report.outcome.as_str(),
+        "reason": reason,
+    })
+    .to_string()
+}
+
+/// armored PGP メッセージの署名を検証し、ペイロードと検証レポートを返す。
+/// 検証失敗でもデータとレポートは返す。
+/// 返り値: [Base64(payload), String(report_json)]
+#[wasm_bindgen]
+pub fn verify_with_report(public_key: String, armored: &str) -> Result<JsValue, JsValue> {
+    let common_pk =
+        xrypton_common::keys::PublicKeys::try_from(public_key.as_str()).map_err(|e| {
+            ReturnValue::Error {
+                message: e.to_string(),
+            }
+            .to_value()
+        })?;
+    let (bytes, report) = common_pk.verify_with_report(armored).map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
+    Ok(ReturnValue::Ok {
+        value: vec![
+            ResultData::Base64 {
+                data: STANDARD.encode(&bytes),
+            },
+            ResultData::String {
+                data: report_to_json(&report),
+            },
+        ],
+    }
+    .to_value())
+}
+
+/// armored PGP メッセージの署名情報と層構造を検証なしで返す。
+/// 返り値: [String(report_json)]
+#[wasm_bindgen]
+pub fn inspect_message(armored: &str) -> Result<JsValue, JsValue> {
+    let report = xrypton_common::keys::inspect_message(armored).map_err(|e| {
+        ReturnValue::Error {
+            message: e.to_string(),
+        }
+        .to_value()
+    })?;
+    Ok(ReturnValue::Ok {
+        value: vec![ResultData::String {
+            data: report_to_json(&report),
+        }],
+    }
+    .to_value())
+}
+
 /// armored PGP メッセージからデータを抽出し、署名検証結果も返す。
 /// 検証失敗でもデータは返す。
 /// 返り値: [String(plaintext), String("true"|"false")]
@@ -0,0 +1,662 @@
+//! 1対1チャット向けの前方秘匿なセッション（X3DH + Double Ratchet）。
+//!
+//! 各ユーザは X25519 の ID 鍵・署名付きプレキー・ワンタイムプレキーを生成し、
+//! ID 鍵と署名付きプレキーを PGP 署名サブキーで署名した上でサーバに公開する。
+//! 送信側は相手のプレキーバンドルから共有鍵を導出し、以後はメッセージごとに鍵を更新する。
+//! 使用済みのメッセージ鍵は破棄されるため、長期鍵が漏洩しても過去のメッセージは復号できない。
+//!
+//! メッセージは通常のメッセージと同じく `Signed(RatchetEncrypted(Signed(Data)))` で、
+//! 外側の署名はサーバでも検証できる。
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::Error;
+use crate::keys::PrivateKeys;
+
+const RATCHET_VERSION: u8 = 1;
+const MESSAGE_TYPE: &str = "xrypton_ratchet_message";
+const BUNDLE_TYPE: &str = "xrypton_prekey_bundle";
+/// 1つのチェーンで飛ばせるメッセージ数の上限
+const MAX_SKIP: u32 = 1000;
+/// 順序が入れ替わったメッセージのために保持するメッセージ鍵の上限
+const MAX_SKIPPED_KEYS: usize = 2000;
+/// 配送が遅れた初回メッセージのために保持する過去の署名付きプレキーの数
+const MAX_OLD_SIGNED_PREKEYS: usize = 2;
+/// 一度に生成できるワンタイムプレキーの数
+pub const MAX_ONE_TIME_PREKEYS: u32 = 100;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KeyPair {
+    secret: String,
+    public: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Prekey {
+    id: u32,
+    #[serde(flatten)]
+    keys: KeyPair,
+}
+
+/// ローカルに保存するプレキーの秘密鍵
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PrekeyStore {
+    version: u8,
+    identity: KeyPair,
+    /// 新しいものが末尾
+    signed_prekeys: Vec<Prekey>,
+    one_time_prekeys: Vec<Prekey>,
+    next_prekey_id: u32,
+}
+
+/// サーバに公開するプレキー
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicPrekey {
+    pub id: u32,
+    pub key: String,
+}
+
+/// PGP 署名サブキーで署名するバンドルの内容
+#[derive(Serialize, Deserialize)]
+struct BundleStatement {
+    #[serde(rename = "type")]
+    kind: String,
+    version: u8,
+    identity_key: String,
+    signed_prekey: PublicPrekey,
+    created_at: String,
+}
+
+/// PUT `/user/{id}/prekeys` のリクエストボディ
+#[derive(Serialize)]
+pub struct PrekeyUpload {
+    pub signed_bundle: String,
+    pub one_time_prekeys: Vec<PublicPrekey>,
+}
+
+/// GET `/user/{id}/prekeys` のレスポンス
+#[derive(Deserialize)]
+pub struct FetchedBundle {
+    pub signed_bundle: String,
+    #[serde(default)]
+    pub one_time_prekey: Option<PublicPrekey>,
+}
+
+/// 相手が最初の応答を返すまでメッセージに添える X3DH の情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PrekeyHeader {
+    identity_key: String,
+    ephemeral_key: String,
+    signed_prekey_id: u32,
+    one_time_prekey_id: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    dh: String,
+    pn: u32,
+    n: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RatchetMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prekey: Option<PrekeyHeader>,
+    header: Header,
+    ciphertext: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    dh: String,
+    n: u32,
+    key: String,
+}
+
+/// Double Ratchet のセッション状態
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    version: u8,
+    local_identity: String,
+    remote_identity: String,
+    root_key: String,
+    dh: KeyPair,
+    remote_dh: Option<String>,
+    send_chain: Option<String>,
+    recv_chain: Option<String>,
+    send_n: u32,
+    recv_n: u32,
+    prev_n: u32,
+    #[serde(default)]
+    skipped: Vec<SkippedKey>,
+    /// 送信側: 応答を受け取るまで初回メッセージに添える X3DH の情報
+    #[serde(default)]
+    pending_prekey: Option<PrekeyHeader>,
+    /// 受信側: セッションを開始した相手の一時鍵（再送された初回メッセージの判定用）
+    #[serde(default)]
+    remote_ephemeral: Option<String>,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn generate_key_pair() -> KeyPair {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    KeyPair {
+        secret: STANDARD.encode(secret.to_bytes()),
+        public: STANDARD.encode(public.as_bytes()),
+    }
+}
+
+fn decode_32(b64: &str, what: &str) -> Result<[u8; 32], Error> {
+    STANDARD
+        .decode(b64)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| Error::InvalidPayload(format!("invalid {what}")))
+}
+
+fn dh(secret: &str, public: &str) -> Result<[u8; 32], Error> {
+    let secret = StaticSecret::from(decode_32(secret, "secret key")?);
+    let public = PublicKey::from(decode_32(public, "public key")?);
+    Ok(secret.diffie_hellman(&public).to_bytes())
+}
+
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) {
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, okm)
+        .expect("HKDF output length is valid");
+}
+
+/// ルート鍵を更新し、(新しいルート鍵, チェーン鍵) を返す。
+fn kdf_rk(root_key: &str, dh_out: &[u8]) -> Result<(String, String), Error> {
+    let mut okm = [0u8; 64];
+    hkdf(
+        &decode_32(root_key, "root key")?,
+        dh_out,
+        b"xrypton-ratchet-root",
+        &mut okm,
+    );
+    Ok((STANDARD.encode(&okm[..32]), STANDARD.encode(&okm[32..])))
+}
+
+/// チェーン鍵から (メッセージ鍵, 次のチェーン鍵) を導出する。
+fn kdf_ck(chain_key: &str) -> Result<([u8; 32], String), Error> {
+    let chain_key = decode_32(chain_key, "chain key")?;
+    let derive = |constant: u8| -> [u8; 32] {
+        let mut mac = HmacSha256::new_from_slice(&chain_key).expect("HMAC accepts any key length");
+        mac.update(&[constant]);
+        mac.finalize().into_bytes().into()
+    };
+    Ok((derive(0x01), STANDARD.encode(derive(0x02))))
+}
+
+/// X3DH の DH 出力から共有鍵を導出する。
+fn x3dh_secret(dh_outputs: &[[u8; 32]]) -> String {
+    let mut ikm = vec![0xffu8; 32];
+    for out in dh_outputs {
+        ikm.extend_from_slice(out);
+    }
+    let mut sk = [0u8; 32];
+    hkdf(&[0u8; 32], &ikm, b"xrypton-x3dh-v1", &mut sk);
+    STANDARD.encode(sk)
+}
+
+fn aad(sender_identity: &str, receiver_identity: &str, header: &Header) -> Vec<u8> {
+    format!(
+        "{MESSAGE_TYPE}\n{sender_identity}\n{receiver_identity}\n{}\n{}\n{}",
+        header.dh, header.pn, header.n
+    )
+    .into_bytes()
+}
+
+/// メッセージ鍵から AES-256-GCM の鍵とノンスを導出する。メッセージ鍵は一度しか使わない。
+fn message_cipher(message_key: &[u8; 32]) -> (Aes256Gcm, [u8; 12]) {
+    let mut okm = [0u8; 44];
+    hkdf(
+        &[0u8; 32],
+        message_key,
+        b"xrypton-ratchet-message",
+        &mut okm,
+    );
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&okm[..32]));
+    let nonce: [u8; 12] = okm[32..].try_into().expect("nonce length is 12");
+    (cipher, nonce)
+}
+
+impl PrekeyStore {
+    pub fn new() -> Self {
+        Self {
+            version: RATCHET_VERSION,
+            identity: generate_key_pair(),
+            signed_prekeys: Vec::new(),
+            one_time_prekeys: Vec::new(),
+            next_prekey_id: 1,
+        }
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_prekey_id;
+        self.next_prekey_id = self.next_prekey_id.wrapping_add(1).max(1);
+        id
+    }
+
+    /// 署名付きプレキーを更新し、ワンタイムプレキーを `one_time_count` 個追加する。
+    ///
+    /// 返り値はサーバに公開するバンドル（ID 鍵と署名付きプレキーは PGP 署名サブキーで署名する）。
+    pub fn generate(
+        &mut self,
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        one_time_count: u32,
+    ) -> Result<PrekeyUpload, Error> {
+        if one_time_count > MAX_ONE_TIME_PREKEYS {
+            return Err(Error::InvalidPayload(format!(
+                "at most {MAX_ONE_TIME_PREKEYS} one-time prekeys can be generated at once"
+            )));
+        }
+        let signed_prekey = Prekey {
+            id: self.next_id(),
+            keys: generate_key_pair(),
+        };
+        let statement = serde_json::to_vec(&BundleStatement {
+            kind: BUNDLE_TYPE.into(),
+            version: RATCHET_VERSION,
+            identity_key: self.identity.public.clone(),
+            signed_prekey: PublicPrekey {
+                id: signed_prekey.id,
+                key: signed_prekey.keys.public.clone(),
+            },
+            created_at: chrono::Utc::now().to_rfc3339(),
+        })
+        .map_err(|e| Error::SigningError(e.to_string()))?;
+        let signed_bundle = private_keys.sign(sub_passphrase, statement)?;
+
+        self.signed_prekeys.push(signed_prekey);
+        let excess = self
+            .signed_prekeys
+            .len()
+            .saturating_sub(MAX_OLD_SIGNED_PREKEYS + 1);
+        self.signed_prekeys.drain(..excess);
+
+        let one_time_prekeys = (0..one_time_count)
+            .map(|_| {
+                let prekey = Prekey {
+                    id: self.next_id(),
+                    keys: generate_key_pair(),
+                };
+                let public = PublicPrekey {
+                    id: prekey.id,
+                    key: prekey.keys.public.clone(),
+                };
+                self.one_time_prekeys.push(prekey);
+                public
+            })
+            .collect();
+
+        Ok(PrekeyUpload {
+            signed_bundle,
+            one_time_prekeys,
+        })
+    }
+}
+
+impl Session {
+    /// 相手のプレキーバンドルを検証してセッションを開始する（送信側）。
+    pub fn initiate(
+        store: &PrekeyStore,
+        peer_public_key: &str,
+        bundle: &FetchedBundle,
+    ) -> Result<Self, Error> {
+        let peer = xrypton_common::keys::PublicKeys::try_from(peer_public_key)
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+        let statement = peer
+            .verify_and_extract(&bundle.signed_bundle)
+            .map_err(|e| Error::VerificationError(e.to_string()))?;
+        let statement: BundleStatement = serde_json::from_slice(&statement)
+            .map_err(|e| Error::InvalidPayload(format!("invalid prekey bundle: {e}")))?;
+        if statement.kind != BUNDLE_TYPE || statement.version != RATCHET_VERSION {
+            return Err(Error::InvalidPayload("unsupported prekey bundle".into()));
+        }
+
+        let ephemeral = generate_key_pair();
+        let spk = &statement.signed_prekey.key;
+        let mut dh_outputs = vec![
+            dh(&store.identity.secret, spk)?,
+            dh(&ephemeral.secret, &statement.identity_key)?,
+            dh(&ephemeral.secret, spk)?,
+        ];
+        if let Some(opk) = &bundle.one_time_prekey {
+            dh_outputs.push(dh(&ephemeral.secret, &opk.key)?);
+        }
+        let shared = x3dh_secret(&dh_outputs);
+
+        let ratchet_key = generate_key_pair();
+        let (root_key, send_chain) = kdf_rk(&shared, &dh(&ratchet_key.secret, spk)?)?;
+        Ok(Self {
+            version: RATCHET_VERSION,
+            local_identity: store.identity.public.clone(),
+            remote_identity: statement.identity_key,
+            root_key,
+            dh: ratchet_key,
+            remote_dh: Some(spk.clone()),
+            send_chain: Some(send_chain),
+            recv_chain: None,
+            send_n: 0,
+            recv_n: 0,
+            prev_n: 0,
+            skipped: Vec::new(),
+            pending_prekey: Some(PrekeyHeader {
+                identity_key: store.identity.public.clone(),
+                ephemeral_key: ephemeral.public,
+                signed_prekey_id: statement.signed_prekey.id,
+                one_time_prekey_id: bundle.one_time_prekey.as_ref().map(|opk| opk.id),
+            }),
+            remote_ephemeral: None,
+        })
+    }
+
+    /// 初回メッセージの X3DH 情報からセッションを開始する（受信側）。
+    ///
+    /// 使用したワンタイムプレキーはストアから削除する。
+    fn respond(store: &mut PrekeyStore, prekey: &PrekeyHeader) -> Result<Self, Error> {
+        let spk = store
+            .signed_prekeys
+            .iter()
+            .find(|k| k.id == prekey.signed_prekey_id)
+            .cloned()
+            .ok_or_else(|| Error::DecryptionError("signed prekey not found".into()))?;
+        let mut dh_outputs = vec![
+            dh(&spk.keys.secret, &prekey.identity_key)?,
+            dh(&store.identity.secret, &prekey.ephemeral_key)?,
+            dh(&spk.keys.secret, &prekey.ephemeral_key)?,
+        ];
+        if let Some(id) = prekey.one_time_prekey_id {
+            let index = store
+                .one_time_prekeys
+                .iter()
+                .position(|k| k.id == id)
+                .ok_or_else(|| Error::DecryptionError("one-time prekey already used".into()))?;
+            let opk = store.one_time_prekeys.remove(index);
+            dh_outputs.push(dh(&opk.keys.secret, &prekey.ephemeral_key)?);
+        }
+
+        Ok(Self {
+            version: RATCHET_VERSION,
+            local_identity: store.identity.public.clone(),
+            remote_identity: prekey.identity_key.clone(),
+            root_key: x3dh_secret(&dh_outputs),
+            dh: spk.keys,
+            remote_dh: None,
+            send_chain: None,
+            recv_chain: None,
+            send_n: 0,
+            recv_n: 0,
+            prev_n: 0,
+            skipped: Vec::new(),
+            pending_prekey: None,
+            remote_ephemeral: Some(prekey.ephemeral_key.clone()),
+        })
+    }
+
+    /// メッセージを暗号化し、送信チェーンを進める。返り値は外側を署名した armored メッセージ。
+    pub fn encrypt(
+        &mut self,
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        data: Vec<u8>,
+    ) -> Result<String, Error> {
+        let send_chain = self
+            .send_chain
+            .as_deref()
+            .ok_or_else(|| Error::EncryptionError("session cannot send yet".into()))?;
+        let (message_key, next_chain) = kdf_ck(send_chain)?;
+        let header = Header {
+            dh: self.dh.public.clone(),
+            pn: self.prev_n,
+            n: self.send_n,
+        };
+
+        let inner = private_keys.sign_bytes(sub_passphrase, data)?;
+        let (cipher, nonce) = message_cipher(&message_key);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &inner,
+                    aad: &aad(&self.local_identity, &self.remote_identity, &header),
+                },
+            )
+            .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        let message = serde_json::to_vec(&RatchetMessage {
+            kind: MESSAGE_TYPE.into(),
+            version: RATCHET_VERSION,
+            prekey: self.pending_prekey.clone(),
+            header,
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+
+        self.send_chain = Some(next_chain);
+        self.send_n = self
+            .send_n
+            .checked_add(1)
+            .ok_or_else(|| Error::EncryptionError("sending chain is exhausted".into()))?;
+        private_keys.sign(sub_passphrase, message)
+    }
+
+    fn take_skipped_key(&mut self, header: &Header) -> Option<[u8; 32]> {
+        let index = self
+            .skipped
+            .iter()
+            .position(|k| k.dh == header.dh && k.n == header.n)?;
+        decode_32(&self.skipped.remove(index).key, "message key").ok()
+    }
+
+    fn skip_message_keys(&mut self, until: u32) -> Result<(), Error> {
+        let (Some(remote_dh), Some(mut chain)) = (self.remote_dh.clone(), self.recv_chain.clone())
+        else {
+            return Ok(());
+        };
+        if until.saturating_sub(self.recv_n) > MAX_SKIP {
+            return Err(Error::DecryptionError("too many skipped messages".into()));
+        }
+        while self.recv_n < until {
+            let (message_key, next) = kdf_ck(&chain)?;
+            self.skipped.push(SkippedKey {
+                dh: remote_dh.clone(),
+                n: self.recv_n,
+                key: STANDARD.encode(message_key),
+            });
+            chain = next;
+            self.recv_n += 1;
+        }
+        self.recv_chain = Some(chain);
+        // 古いものから捨てて上限を守る
+        let excess = self.skipped.len().saturating_sub(MAX_SKIPPED_KEYS);
+        self.skipped.drain(..excess);
+        Ok(())
+    }
+
+    /// 相手の新しいラチェット公開鍵で DH ラチェットを進める。
+    fn dh_ratchet(&mut self, header: &Header) -> Result<(), Error> {
+        self.prev_n = self.send_n;
+        self.send_n = 0;
+        self.recv_n = 0;
+        self.remote_dh = Some(header.dh.clone());
+        let (root_key, recv_chain) = kdf_rk(&self.root_key, &dh(&self.dh.secret, &header.dh)?)?;
+        self.dh = generate_key_pair();
+        let (root_key, send_chain) = kdf_rk(&root_key, &dh(&self.dh.secret, &header.dh)?)?;
+        self.root_key = root_key;
+        self.recv_chain = Some(recv_chain);
+        self.send_chain = Some(send_chain);
+        Ok(())
+    }
+
+    fn message_key(&mut self, header: &Header) -> Result<[u8; 32], Error> {
+        if let Some(key) = self.take_skipped_key(header) {
+            return Ok(key);
+        }
+        if self.remote_dh.as_deref() != Some(header.dh.as_str()) {
+            self.skip_message_keys(header.pn)?;
+            self.dh_ratchet(header)?;
+        }
+        self.skip_message_keys(header.n)?;
+        let recv_chain = self
+            .recv_chain
+            .as_deref()
+            .ok_or_else(|| Error::DecryptionError("no receiving chain".into()))?;
+        if header.n < self.recv_n {
+            return Err(Error::DecryptionError("message key already used".into()));
+        }
+        let (message_key, next) = kdf_ck(recv_chain)?;
+        self.recv_chain = Some(next);
+        self.recv_n += 1;
+        Ok(message_key)
+    }
+}
+
+/// セッションでメッセージを復号する。
+///
+/// `session` が `None` の場合や、相手が新しいセッションを開始した場合は
+/// 初回メッセージの X3DH 情報とプレキーストアからセッションを作る。
+/// 失敗した場合は `store` と `session` を更新しない。
+pub fn decrypt_message(
+    store: &mut PrekeyStore,
+    session: Option<Session>,
+    sender_public_key: &str,
+    armored: &str,
+) -> Result<(Session, Vec<u8>), Error> {
+    let sender = xrypton_common::keys::PublicKeys::try_from(sender_public_key)
+        .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    let envelope = sender
+        .verify_and_extract(armored)
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    let message: RatchetMessage = serde_json::from_slice(&envelope)
+        .map_err(|e| Error::InvalidPayload(format!("invalid ratchet message: {e}")))?;
+    if message.kind != MESSAGE_TYPE || message.version != RATCHET_VERSION {
+        return Err(Error::InvalidPayload("unsupported ratchet message".into()));
+    }
+
+    let mut new_store = None;
+    let mut session = match (session, &message.prekey) {
+        (Some(session), Some(prekey))
+            if session.remote_ephemeral.as_ref() != Some(&prekey.ephemeral_key) =>
+        {
+            // 相手が新しいセッションを開始した
+            let mut store_copy = store.clone();
+            let session = Session::respond(&mut store_copy, prekey)?;
+            new_store = Some(store_copy);
+            session
+        }
+        (Some(session), _) => session,
+        (None, Some(prekey)) => {
+            let mut store_copy = store.clone();
+            let session = Session::respond(&mut store_copy, prekey)?;
+            new_store = Some(store_copy);
+            session
+        }
+        (None, None) => return Err(Error::DecryptionError("session not found".into())),
+    };
+
+    let message_key = session.message_key(&message.header)?;
+    let ciphertext = STANDARD
+        .decode(&message.ciphertext)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let (cipher, nonce) = message_cipher(&message_key);
+    let inner = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &aad(
+                    &session.remote_identity,
+                    &session.local_identity,
+                    &message.header,
+                ),
+            },
+        )
+        .map_err(|_| Error::DecryptionError("ratchet message authentication failed".into()))?;
+    let (data, report) = sender
+        .verify_bytes_with_report(&inner)
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    report
+        .ensure_valid_when_signed()
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+
+    // 相手から応答が届いたので、以後は X3DH の情報を添えない
+    if message.prekey.is_none() {
+        session.pending_prekey = None;
+    }
+    if let Some(new_store) = new_store {
+        *store = new_store;
+    }
+    Ok((session, data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::keys;
+
+    #[test]
+    fn x3dh_and_double_ratchet() {
+        let (alice, alice_public) = keys("alice");
+        let (bob, bob_public) = keys("bob");
+        let mut alice_store = PrekeyStore::new();
+        let mut bob_store = PrekeyStore::new();
+        alice_store.generate(&alice, "sub", 0).unwrap();
+        let upload = bob_store.generate(&bob, "sub", 2).unwrap();
+
+        let bundle = FetchedBundle {
+            signed_bundle: upload.signed_bundle,
+            one_time_prekey: upload.one_time_prekeys.first().cloned(),
+        };
+        // 他人の鍵で署名されたバンドルは受け付けない
+        assert!(Session::initiate(&alice_store, &alice_public, &bundle).is_err());
+        let mut alice_session = Session::initiate(&alice_store, &bob_public, &bundle).unwrap();
+
+        let a1 = alice_session
+            .encrypt(&alice, "sub", b"a1".to_vec())
+            .unwrap();
+        let a2 = alice_session
+            .encrypt(&alice, "sub", b"a2".to_vec())
+            .unwrap();
+
+        let (bob_session, data) =
+            decrypt_message(&mut bob_store, None, &alice_public, &a2).unwrap();
+        assert_eq!(data, b"a2");
+        assert_eq!(bob_store.one_time_prekeys.len(), 1);
+        let (mut bob_session, data) =
+            decrypt_message(&mut bob_store, Some(bob_session), &alice_public, &a1).unwrap();
+        assert_eq!(data, b"a1");
+
+        let b1 = bob_session.encrypt(&bob, "sub", b"b1".to_vec()).unwrap();
+        let (mut alice_session, data) =
+            decrypt_message(&mut alice_store, Some(alice_session), &bob_public, &b1).unwrap();
+        assert_eq!(data, b"b1");
+        assert!(alice_session.pending_prekey.is_none());
+
+        let a3 = alice_session
+            .encrypt(&alice, "sub", b"a3".to_vec())
+            .unwrap();
+        let (bob_session, data) =
+            decrypt_message(&mut bob_store, Some(bob_session), &alice_public, &a3).unwrap();
+        assert_eq!(data, b"a3");
+
+        // 使用済みのメッセージ鍵では復号できない
+        assert!(decrypt_message(&mut bob_store, Some(bob_session), &alice_public, &a3).is_err());
+    }
+}
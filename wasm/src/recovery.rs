@@ -0,0 +1,303 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use pgp::composed::{ArmorOptions, MessageBuilder};
+use pgp::crypto::sym::SymmetricKeyAlgorithm;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+use crate::backup::{parse_payload, pgp_decrypt_with_password, pgp_encrypt_with_password};
+use crate::keys::{PrivateKeys, PublicKeys};
+
+const SHARE_VERSION: u8 = 1;
+/// 鍵暗号化鍵（ペイロードを暗号化する共通鍵）のバイト数
+const KEK_LEN: usize = 32;
+/// 分割できるシェアの最大数
+const MAX_SHARES: usize = 16;
+
+/// 保護者（ガーディアン）の情報
+#[derive(Debug, Deserialize)]
+pub struct Guardian {
+    pub user_id: String,
+    pub public_key: String,
+}
+
+/// 保護者ごとに暗号化したシェア
+#[derive(Debug, Serialize)]
+pub struct EncryptedShare {
+    pub guardian_id: String,
+    pub share_armored: String,
+}
+
+/// サーバに登録するソーシャルリカバリーの設定
+#[derive(Debug, Serialize)]
+pub struct RecoverySetup {
+    pub payload_armored: String,
+    pub threshold: u8,
+    pub shares: Vec<EncryptedShare>,
+}
+
+/// 復元時に保護者から返されたシェア
+#[derive(Debug, Deserialize)]
+pub struct ReturnedShare {
+    /// シェアを再暗号化した保護者の公開鍵（署名検証用）
+    pub public_key: String,
+    pub share_armored: String,
+}
+
+/// シェアの平文
+#[derive(Debug, Serialize, Deserialize)]
+struct SharePlain {
+    version: u8,
+    index: u8,
+    threshold: u8,
+    share: String,
+}
+
+/// GF(2^8)（既約多項式 x^8 + x^4 + x^3 + x + 1）での乗算
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// GF(2^8) での逆元（a^254）
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exp = 254u8;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// 秘密を `threshold`-of-`count` の Shamir シェアに分割する。返り値は (x座標, シェア)。
+fn split_secret(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<(u8, Vec<u8>)>, Error> {
+    if threshold < 2 || threshold > count || usize::from(count) > MAX_SHARES {
+        return Err(Error::InvalidPayload(format!(
+            "invalid threshold {threshold} of {count} shares"
+        )));
+    }
+    let mut shares: Vec<(u8, Vec<u8>)> = (1..=count)
+        .map(|x| (x, Vec::with_capacity(secret.len())))
+        .collect();
+    let mut coefficients = vec![0u8; usize::from(threshold)];
+    for &byte in secret {
+        coefficients[0] = byte;
+        OsRng.fill_bytes(&mut coefficients[1..]);
+        for (x, share) in &mut shares {
+            // ホーナー法で多項式を評価する
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0u8, |acc, &c| gf_mul(acc, *x) ^ c);
+            share.push(y);
+        }
+    }
+    Ok(shares)
+}
+
+/// Shamir シェアから秘密を復元する（x = 0 でのラグランジュ補間）。
+fn combine_shares(shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    let Some((_, first)) = shares.first() else {
+        return Err(Error::InvalidPayload("no shares".into()));
+    };
+    let len = first.len();
+    for (i, (x, share)) in shares.iter().enumerate() {
+        if *x == 0 || share.len() != len {
+            return Err(Error::InvalidPayload("malformed share".into()));
+        }
+        if shares[..i].iter().any(|(other, _)| other == x) {
+            return Err(Error::InvalidPayload("duplicate share".into()));
+        }
+    }
+
+    let mut secret = vec![0u8; len];
+    for (j, (xj, share)) in shares.iter().enumerate() {
+        // 基底多項式の x = 0 での値（GF(2^8) では減算は XOR）
+        let basis = shares
+            .iter()
+            .enumerate()
+            .filter(|(m, _)| *m != j)
+            .fold(1u8, |acc, (_, (xm, _))| {
+                gf_mul(acc, gf_mul(*xm, gf_inv(xm ^ xj)))
+            });
+        for (byte, &y) in secret.iter_mut().zip(share) {
+            *byte ^= gf_mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+fn build_kek_password(kek_b64: &str) -> String {
+    format!("xrypton-social-recovery-v1:{kek_b64}")
+}
+
+/// 保護者の暗号化サブキー宛てにシェアを暗号化する。
+fn encrypt_to_guardian(data: Vec<u8>, guardian: &PublicKeys) -> Result<String, Error> {
+    let mut builder =
+        MessageBuilder::from_bytes("", data).seipd_v1(OsRng, SymmetricKeyAlgorithm::AES256);
+    builder
+        .encrypt_to_key(OsRng, guardian.encryption_public()?)
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+    builder
+        .to_armored_string(OsRng, ArmorOptions::default())
+        .map_err(|e| Error::EncryptionError(e.to_string()))
+}
+
+/// バックアップペイロードを鍵暗号化鍵で暗号化し、鍵暗号化鍵を保護者に分割する。
+///
+/// `threshold` 人の保護者がシェアを返せば復元できる。
+pub fn split_recovery_secret(
+    payload_json: &str,
+    threshold: u8,
+    guardians: &[Guardian],
+) -> Result<RecoverySetup, Error> {
+    parse_payload(payload_json)?;
+    let count = u8::try_from(guardians.len())
+        .map_err(|_| Error::InvalidPayload("too many guardians".into()))?;
+    for (i, guardian) in guardians.iter().enumerate() {
+        if guardians[..i].iter().any(|g| g.user_id == guardian.user_id) {
+            return Err(Error::InvalidPayload(format!(
+                "duplicate guardian: {}",
+                guardian.user_id
+            )));
+        }
+    }
+
+    let mut kek = vec![0u8; KEK_LEN];
+    OsRng.fill_bytes(&mut kek);
+    let payload_armored = pgp_encrypt_with_password(
+        payload_json.as_bytes().to_vec(),
+        &build_kek_password(&STANDARD.encode(&kek)),
+    )?;
+
+    let shares = split_secret(&kek, threshold, count)?
+        .into_iter()
+        .zip(guardians)
+        .map(|((index, share), guardian)| {
+            let public_keys = PublicKeys::try_from(guardian.public_key.as_str())?;
+            let plain = serde_json::to_vec(&SharePlain {
+                version: SHARE_VERSION,
+                index,
+                threshold,
+                share: STANDARD.encode(share),
+            })
+            .map_err(|e| Error::EncryptionError(e.to_string()))?;
+            Ok(EncryptedShare {
+                guardian_id: guardian.user_id.clone(),
+                share_armored: encrypt_to_guardian(plain, &public_keys)?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(RecoverySetup {
+        payload_armored,
+        threshold,
+        shares,
+    })
+}
+
+/// 保護者が自分宛てのシェアを復号し、復元者の一時的な回復鍵宛てに署名・再暗号化する。
+pub fn reencrypt_share(
+    private_keys: &PrivateKeys,
+    sub_passphrase: &str,
+    share_armored: &str,
+    recovery_public_key: &PublicKeys,
+) -> Result<String, Error> {
+    let (plain, _, _) = private_keys.decrypt(sub_passphrase, share_armored)?;
+    // 自分宛てのシェアであることを確認してから渡す
+    serde_json::from_slice::<SharePlain>(&plain)
+        .map_err(|e| Error::InvalidPayload(format!("invalid share: {e}")))?;
+    private_keys.sign_encrypt_sign(sub_passphrase, &[recovery_public_key], plain)
+}
+
+/// 保護者から返されたシェアを回復鍵で復号・結合し、バックアップペイロードを復元する。
+pub fn combine_recovery_shares(
+    recovery_keys: &PrivateKeys,
+    sub_passphrase: &str,
+    payload_armored: &str,
+    returned: &[ReturnedShare],
+) -> Result<String, Error> {
+    let mut threshold = None;
+    let mut shares = Vec::with_capacity(returned.len());
+    for share in returned {
+        // 外側の署名で保護者が再暗号化したものか確認する
+        let guardian = xrypton_common::keys::PublicKeys::try_from(share.public_key.as_str())
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+        let inner = guardian
+            .verify_and_extract(&share.share_armored)
+            .map_err(|e| Error::VerificationError(e.to_string()))?;
+        let (plain, _, _) = recovery_keys.decrypt_from_bytes(sub_passphrase, &inner)?;
+        let plain: SharePlain = serde_json::from_slice(&plain)
+            .map_err(|e| Error::InvalidPayload(format!("invalid share: {e}")))?;
+        if plain.version != SHARE_VERSION {
+            return Err(Error::InvalidPayload("unsupported share version".into()));
+        }
+        if *threshold.get_or_insert(plain.threshold) != plain.threshold {
+            return Err(Error::InvalidPayload(
+                "shares have different thresholds".into(),
+            ));
+        }
+        let bytes = STANDARD
+            .decode(&plain.share)
+            .map_err(|e| Error::InvalidPayload(format!("invalid share: {e}")))?;
+        shares.push((plain.index, bytes));
+    }
+    let threshold = threshold.ok_or_else(|| Error::InvalidPayload("no shares".into()))?;
+    if shares.len() < usize::from(threshold) {
+        return Err(Error::InvalidPayload(format!(
+            "{threshold} shares are required, got {}",
+            shares.len()
+        )));
+    }
+
+    let kek = combine_shares(&shares)?;
+    let plain =
+        pgp_decrypt_with_password(payload_armored, &build_kek_password(&STANDARD.encode(kek)))?;
+    let payload_json =
+        String::from_utf8(plain).map_err(|e| Error::DecryptionError(e.to_string()))?;
+    parse_payload(&payload_json)?;
+    Ok(payload_json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_and_combine() {
+        let secret: Vec<u8> = (0..=255).collect();
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        assert_eq!(combine_shares(&shares[..3]).unwrap(), secret);
+        assert_eq!(combine_shares(&shares[2..]).unwrap(), secret);
+        assert_eq!(
+            combine_shares(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap(),
+            secret
+        );
+        // 閾値未満では復元できない
+        assert_ne!(combine_shares(&shares[..2]).unwrap(), secret);
+    }
+
+    #[test]
+    fn rejects_invalid_threshold() {
+        assert!(split_secret(b"secret", 1, 3).is_err());
+        assert!(split_secret(b"secret", 4, 3).is_err());
+    }
+}
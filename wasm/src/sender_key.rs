@@ -0,0 +1,509 @@
+//! 大人数のチャット向けのセンダーキー方式。
+//!
+//! 各メンバーは自分の送信用チェーン鍵を生成し、各メンバーの PGP 鍵宛てに一度だけ
+//! 配布する。メッセージはチェーン鍵から導出したメッセージ鍵で一度だけ暗号化するため、
+//! サイズと計算量がメンバー数に比例しない。メンバーが変わったら新しいチェーン鍵を作る。
+//!
+//! メッセージは通常のメッセージと同じく `Signed(SenderKeyEncrypted(Signed(Data)))` で、
+//! 外側の署名はサーバでも検証できる。
+
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::Error;
+use crate::keys::{PrivateKeys, PublicKeys};
+
+const SENDER_KEY_VERSION: u8 = 1;
+const MESSAGE_TYPE: &str = "xrypton_sender_key_message";
+const DISTRIBUTION_TYPE: &str = "xrypton_sender_key_distribution";
+/// 順序が入れ替わったメッセージのために保持するメッセージ鍵の上限
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+/// 送信側のセンダーキー
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SenderKeyState {
+    version: u8,
+    chat_id: String,
+    key_id: String,
+    chain_key: String,
+    iteration: u32,
+    /// 配布したメンバー（ソート済み）。メンバーが変わったらローテーションする
+    members: Vec<String>,
+}
+
+/// 受信側で保持する他メンバーのセンダーキー
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedSenderKey {
+    version: u8,
+    chat_id: String,
+    sender_fingerprint: String,
+    key_id: String,
+    chain_key: String,
+    iteration: u32,
+    /// まだ受信していないメッセージの鍵（iteration → メッセージ鍵）
+    #[serde(default)]
+    skipped: BTreeMap<u32, String>,
+}
+
+/// 配布先のメンバー
+#[derive(Debug, Deserialize)]
+pub struct Recipient {
+    pub user_id: String,
+    pub public_key: String,
+}
+
+/// メンバーごとの配布メッセージ（PUT `/chat/{chat_id}/sender-keys` の要素）
+#[derive(Debug, Serialize)]
+pub struct Distribution {
+    pub recipient_id: String,
+    pub distribution: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DistributionPlain {
+    #[serde(rename = "type")]
+    kind: String,
+    version: u8,
+    chat_id: String,
+    key_id: String,
+    chain_key: String,
+    iteration: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SenderKeyMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    version: u8,
+    chat_id: String,
+    key_id: String,
+    iteration: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// チェーン鍵から (メッセージ鍵, 次のチェーン鍵) を導出する。
+fn ratchet(chain_key: &[u8]) -> ([u8; 32], [u8; 32]) {
+    (hmac(chain_key, &[0x01]), hmac(chain_key, &[0x02]))
+}
+
+fn decode_key(b64: &str) -> Result<[u8; 32], Error> {
+    STANDARD
+        .decode(b64)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| Error::InvalidPayload("invalid sender key".into()))
+}
+
+fn aad(chat_id: &str, key_id: &str, iteration: u32) -> Vec<u8> {
+    format!("{MESSAGE_TYPE}\n{chat_id}\n{key_id}\n{iteration}").into_bytes()
+}
+
+fn normalize_members(members: &[String]) -> Vec<String> {
+    let mut members = members.to_vec();
+    members.sort();
+    members.dedup();
+    members
+}
+
+impl SenderKeyState {
+    /// 新しいチェーン鍵を生成する。
+    pub fn new(chat_id: &str, members: &[String]) -> Self {
+        let mut key_id = [0u8; 16];
+        OsRng.fill_bytes(&mut key_id);
+        let mut chain_key = [0u8; 32];
+        OsRng.fill_bytes(&mut chain_key);
+        Self {
+            version: SENDER_KEY_VERSION,
+            chat_id: chat_id.to_string(),
+            key_id: URL_SAFE_NO_PAD.encode(key_id),
+            chain_key: STANDARD.encode(chain_key),
+            iteration: 0,
+            members: normalize_members(members),
+        }
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// 配布時のメンバーと現在のメンバーが異なる場合はローテーションが必要。
+    pub fn needs_rotation(&self, members: &[String]) -> bool {
+        self.members != normalize_members(members)
+    }
+
+    /// 現在のチェーン鍵を各メンバーの PGP 鍵宛てに署名・暗号化する。
+    ///
+    /// 配布後に受信したメンバーは、配布時点以降のメッセージのみ復号できる。
+    pub fn distribute(
+        &self,
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        recipients: &[Recipient],
+    ) -> Result<Vec<Distribution>, Error> {
+        let plain = serde_json::to_vec(&DistributionPlain {
+            kind: DISTRIBUTION_TYPE.into(),
+            version: SENDER_KEY_VERSION,
+            chat_id: self.chat_id.clone(),
+            key_id: self.key_id.clone(),
+            chain_key: self.chain_key.clone(),
+            iteration: self.iteration,
+        })
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        recipients
+            .iter()
+            .map(|recipient| {
+                let public_keys = PublicKeys::try_from(recipient.public_key.as_str())?;
+                Ok(Distribution {
+                    recipient_id: recipient.user_id.clone(),
+                    distribution: private_keys.sign_encrypt_sign(
+                        sub_passphrase,
+                        &[&public_keys],
+                        plain.clone(),
+                    )?,
+                })
+            })
+            .collect()
+    }
+
+    /// メッセージを暗号化し、チェーン鍵を進める。返り値は外側を署名した armored メッセージ。
+    pub fn encrypt(
+        &mut self,
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        data: Vec<u8>,
+    ) -> Result<String, Error> {
+        let inner = private_keys.sign_bytes(sub_passphrase, data)?;
+        let (message_key, next_chain_key) = ratchet(&decode_key(&self.chain_key)?);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&message_key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &inner,
+                    aad: &aad(&self.chat_id, &self.key_id, self.iteration),
+                },
+            )
+            .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        let message = serde_json::to_vec(&SenderKeyMessage {
+            kind: MESSAGE_TYPE.into(),
+            version: SENDER_KEY_VERSION,
+            chat_id: self.chat_id.clone(),
+            key_id: self.key_id.clone(),
+            iteration: self.iteration,
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+
+        self.chain_key = STANDARD.encode(next_chain_key);
+        self.iteration = self
+            .iteration
+            .checked_add(1)
+            .ok_or_else(|| Error::EncryptionError("sender key is exhausted".into()))?;
+        private_keys.sign(sub_passphrase, message)
+    }
+}
+
+impl ReceivedSenderKey {
+    /// 配布メッセージを検証・復号して、送信者のセンダーキーを取り込む。
+    pub fn accept(
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        sender_public_key: &str,
+        distribution: &str,
+    ) -> Result<Self, Error> {
+        let sender = xrypton_common::keys::PublicKeys::try_from(sender_public_key)
+            .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+        let inner = sender
+            .verify_and_extract(distribution)
+            .map_err(|e| Error::VerificationError(e.to_string()))?;
+        let (plain, signature, _) = private_keys.decrypt_from_bytes(sub_passphrase, &inner)?;
+        let signature = signature
+            .ok_or_else(|| Error::VerificationError("distribution is not signed".into()))?;
+        PublicKeys::try_from(sender_public_key)?.verify_detached_signature(&signature, &plain)?;
+
+        let plain: DistributionPlain = serde_json::from_slice(&plain)
+            .map_err(|e| Error::InvalidPayload(format!("invalid distribution: {e}")))?;
+        if plain.kind != DISTRIBUTION_TYPE || plain.version != SENDER_KEY_VERSION {
+            return Err(Error::InvalidPayload(
+                "unsupported sender key distribution".into(),
+            ));
+        }
+        decode_key(&plain.chain_key)?;
+        Ok(Self {
+            version: SENDER_KEY_VERSION,
+            chat_id: plain.chat_id,
+            sender_fingerprint: sender.get_primary_fingerprint(),
+            key_id: plain.key_id,
+            chain_key: plain.chain_key,
+            iteration: plain.iteration,
+            skipped: BTreeMap::new(),
+        })
+    }
+
+    /// 指定した iteration のメッセージ鍵を返す。必要に応じてチェーン鍵を進める。
+    ///
+    /// 状態を変更するため、復号に成功するまではコピーに対して呼び出す。
+    fn message_key(&mut self, iteration: u32) -> Result<[u8; 32], Error> {
+        if iteration < self.iteration {
+            return self
+                .skipped
+                .remove(&iteration)
+                .ok_or_else(|| Error::DecryptionError("message key already used".into()))
+                .and_then(|key| decode_key(&key));
+        }
+        if (iteration - self.iteration) as usize > MAX_SKIPPED_KEYS {
+            return Err(Error::DecryptionError("too many skipped messages".into()));
+        }
+        let mut chain_key = decode_key(&self.chain_key)?;
+        while self.iteration < iteration {
+            let (message_key, next) = ratchet(&chain_key);
+            self.skipped
+                .insert(self.iteration, STANDARD.encode(message_key));
+            chain_key = next;
+            self.iteration += 1;
+        }
+        // 古いものから捨てて上限を守る
+        while self.skipped.len() > MAX_SKIPPED_KEYS {
+            self.skipped.pop_first();
+        }
+        let (message_key, next) = ratchet(&chain_key);
+        self.chain_key = STANDARD.encode(next);
+        self.iteration += 1;
+        Ok(message_key)
+    }
+}
+
+/// 受信済みのセンダーキーからメッセージの鍵IDに一致するものを選んで復号する。
+///
+/// 外側・内側の署名を送信者の公開鍵で検証し、平文を返す。
+/// 過去のメッセージも読めるよう、署名時点で有効だったデバイス鍵による署名を受け付ける。
+pub fn decrypt_message(
+    keys: &mut [ReceivedSenderKey],
+    sender_public_key: &str,
+    armored: &str,
+) -> Result<Vec<u8>, Error> {
+    let sender = xrypton_common::keys::PublicKeys::try_from(sender_public_key)
+        .map_err(|e| Error::KeyFormatError(e.to_string()))?;
+    let (envelope, report) = sender
+        .verify_with_report(armored)
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    report
+        .ensure_valid_when_signed()
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    let message: SenderKeyMessage = serde_json::from_slice(&envelope)
+        .map_err(|e| Error::InvalidPayload(format!("invalid sender key message: {e}")))?;
+    if message.kind != MESSAGE_TYPE || message.version != SENDER_KEY_VERSION {
+        return Err(Error::InvalidPayload(
+            "unsupported sender key message".into(),
+        ));
+    }
+
+    let fingerprint = sender.get_primary_fingerprint();
+    let key = keys
+        .iter_mut()
+        .find(|k| {
+            k.key_id == message.key_id
+                && k.chat_id == message.chat_id
+                && k.sender_fingerprint == fingerprint
+        })
+        .ok_or_else(|| Error::DecryptionError("sender key not found".into()))?;
+    // 認証に失敗したメッセージでチェーン鍵が進まないよう、コピーで導出して復号後に反映する
+    let mut advanced = key.clone();
+    let message_key = advanced.message_key(message.iteration)?;
+
+    let nonce: [u8; 12] = STANDARD
+        .decode(&message.nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or_else(|| Error::InvalidPayload("invalid nonce".into()))?;
+    let ciphertext = STANDARD
+        .decode(&message.ciphertext)
+        .map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    let inner = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&message_key))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &aad(&message.chat_id, &message.key_id, message.iteration),
+            },
+        )
+        .map_err(|_| Error::DecryptionError("sender key message authentication failed".into()))?;
+    *key = advanced;
+    let (data, report) = sender
+        .verify_bytes_with_report(&inner)
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    report
+        .ensure_valid_when_signed()
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::keys;
+
+    #[test]
+    fn distribute_encrypt_decrypt() {
+        let (alice, alice_public) = keys("alice");
+        let (bob, bob_public) = keys("bob");
+        let members = vec!["alice".to_string(), "bob".to_string()];
+
+        let mut state = SenderKeyState::new("chat", &members);
+        let distributions = state
+            .distribute(
+                &alice,
+                "sub",
+                &[Recipient {
+                    user_id: "bob".into(),
+                    public_key: bob_public,
+                }],
+            )
+            .unwrap();
+        let received =
+            ReceivedSenderKey::accept(&bob, "sub", &alice_public, &distributions[0].distribution)
+                .unwrap();
+        let mut received = vec![received];
+
+        let first = state.encrypt(&alice, "sub", b"first".to_vec()).unwrap();
+        let second = state.encrypt(&alice, "sub", b"second".to_vec()).unwrap();
+        let third = state.encrypt(&alice, "sub", b"third".to_vec()).unwrap();
+
+        // 順序が入れ替わっても復号でき、同じメッセージ鍵は再利用できない
+        assert_eq!(
+            decrypt_message(&mut received, &alice_public, &third).unwrap(),
+            b"third"
+        );
+        assert_eq!(
+            decrypt_message(&mut received, &alice_public, &first).unwrap(),
+            b"first"
+        );
+        assert_eq!(
+            decrypt_message(&mut received, &alice_public, &second).unwrap(),
+            b"second"
+        );
+        assert!(decrypt_message(&mut received, &alice_public, &second).is_err());
+
+        assert!(!state.needs_rotation(&["bob".into(), "alice".into()]));
+        assert!(state.needs_rotation(&members[..1]));
+    }
+
+    #[test]
+    fn forged_message_does_not_advance_chain() {
+        let (alice, alice_public) = keys("alice");
+        let (bob, bob_public) = keys("bob");
+        let members = vec!["alice".to_string(), "bob".to_string()];
+
+        let mut state = SenderKeyState::new("chat", &members);
+        let distributions = state
+            .distribute(
+                &alice,
+                "sub",
+                &[Recipient {
+                    user_id: "bob".into(),
+                    public_key: bob_public,
+                }],
+            )
+            .unwrap();
+        let mut received = vec![
+            ReceivedSenderKey::accept(&bob, "sub", &alice_public, &distributions[0].distribution)
+                .unwrap(),
+        ];
+
+        // 署名は正しいが認証タグが一致しない、先の iteration のメッセージ
+        let forged = serde_json::to_vec(&SenderKeyMessage {
+            kind: MESSAGE_TYPE.into(),
+            version: SENDER_KEY_VERSION,
+            chat_id: state.chat_id.clone(),
+            key_id: state.key_id.clone(),
+            iteration: 10,
+            nonce: STANDARD.encode([0u8; 12]),
+            ciphertext: STANDARD.encode([0u8; 32]),
+        })
+        .unwrap();
+        let forged = alice.sign("sub", forged).unwrap();
+        assert!(decrypt_message(&mut received, &alice_public, &forged).is_err());
+        assert_eq!(received[0].iteration, 0);
+        assert!(received[0].skipped.is_empty());
+
+        let first = state.encrypt(&alice, "sub", b"first".to_vec()).unwrap();
+        assert_eq!(
+            decrypt_message(&mut received, &alice_public, &first).unwrap(),
+            b"first"
+        );
+    }
+
+    #[test]
+    fn decrypts_history_after_device_rotation() {
+        use crate::keys::{PrivateKeys, RevocationReason, SubkeyKind};
+
+        let (alice, alice_public) = keys("alice");
+        let (bob, bob_public) = keys("bob");
+        let members = vec!["alice".to_string(), "bob".to_string()];
+
+        let mut state = SenderKeyState::new("chat", &members);
+        let distributions = state
+            .distribute(
+                &alice,
+                "sub",
+                &[Recipient {
+                    user_id: "bob".into(),
+                    public_key: bob_public,
+                }],
+            )
+            .unwrap();
+        let received =
+            ReceivedSenderKey::accept(&bob, "sub", &alice_public, &distributions[0].distribution)
+                .unwrap();
+        let mut received = vec![received];
+        let old = state.encrypt(&alice, "sub", b"old".to_vec()).unwrap();
+
+        // 失効署名の作成時刻を署名より後にする
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let old_fingerprint = xrypton_common::keys::PublicKeys::try_from(alice_public.as_str())
+            .unwrap()
+            .get_signing_sub_key_fingerprint()
+            .unwrap();
+        let rotated = PrivateKeys::try_from(
+            alice
+                .rotate_subkey("main", "sub", SubkeyKind::Signing)
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        let revocation = rotated
+            .create_subkey_revocation("main", &old_fingerprint, RevocationReason::Superseded, "")
+            .unwrap();
+        let rotated_public =
+            xrypton_common::keys::PublicKeys::try_from(rotated.public_keys().as_str())
+                .unwrap()
+                .with_subkey_revocation_certificate(&revocation)
+                .unwrap();
+
+        // 置き換えたデバイス鍵で署名された過去のメッセージも復号できる
+        assert_eq!(
+            decrypt_message(&mut received, &rotated_public, &old).unwrap(),
+            b"old"
+        );
+    }
+}
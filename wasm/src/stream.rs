@@ -0,0 +1,403 @@
+//! 大きなファイルをチャンク単位で暗号化・復号するストリーム形式。
+//!
+//! ヘッダは通常のメッセージと同じ `Signed(Encrypted(Signed(...)))` で、ファイル鍵を
+//! 受信者に渡す。本体は固定長チャンクごとの AES-256-GCM フレームの列で、最後の
+//! フレームに平文全体のハッシュに対する送信者の署名を含める。
+//!
+//! フレーム: `[種別 (1 byte)][長さ (u32 BE)][暗号文]`
+//! ノンス: `[ノンスプレフィックス (7 byte)][カウンタ (u32 BE)][最終フラグ (1 byte)]`
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::Error;
+use crate::keys::{PrivateKeys, PublicKeys};
+
+const STREAM_VERSION: u8 = 1;
+const STREAM_ALG: &str = "AES-256-GCM";
+/// 平文チャンクのサイズ
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const KEY_LEN: usize = 32;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const FRAME_HEADER_LEN: usize = 5;
+/// 最終フレーム（署名）の最大長
+const MAX_FINAL_FRAME_LEN: usize = 16 * 1024;
+
+const FRAME_DATA: u8 = 0;
+const FRAME_FINAL: u8 = 1;
+
+/// ヘッダ内で受信者に渡す情報
+#[derive(Serialize, Deserialize)]
+struct StreamHeader {
+    version: u8,
+    alg: String,
+    chunk_size: usize,
+    nonce_prefix: String,
+    key: String,
+}
+
+/// チャンクの暗号化・復号と署名対象のハッシュを管理する共通部分。
+struct StreamState {
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    chunk_size: usize,
+    /// ヘッダの SHA-256。各チャンクの AAD と署名対象に含め、ヘッダと本体を結び付ける
+    header_digest: [u8; 32],
+    counter: u32,
+    length: u64,
+    hasher: Sha512,
+}
+
+impl StreamState {
+    fn nonce(&self, frame_type: u8) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = frame_type;
+        nonce
+    }
+
+    fn next_counter(&mut self) -> Result<(), Error> {
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| Error::EncryptionError("stream is too long".into()))?;
+        Ok(())
+    }
+
+    /// 送信者が署名するマニフェスト。平文の長さ・チャンク数・ハッシュを含む。
+    fn manifest(&self) -> Vec<u8> {
+        let digest: String = self
+            .hasher
+            .clone()
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let header: String = self
+            .header_digest
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!(
+            "xrypton-stream-v{STREAM_VERSION}\n{header}\n{}\n{}\n{digest}",
+            self.length, self.counter
+        )
+        .into_bytes()
+    }
+}
+
+fn write_frame(out: &mut Vec<u8>, frame_type: u8, ciphertext: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(ciphertext.len())
+        .map_err(|_| Error::EncryptionError("frame is too large".into()))?;
+    out.push(frame_type);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(ciphertext);
+    Ok(())
+}
+
+/// ストリーム暗号化。`push` で受け取った平文をチャンクごとに暗号化して返す。
+pub struct StreamEncryptor {
+    private_keys: PrivateKeys,
+    sub_passphrase: String,
+    header: Vec<u8>,
+    state: StreamState,
+    buffer: Vec<u8>,
+}
+
+impl StreamEncryptor {
+    pub fn new(
+        private_keys: PrivateKeys,
+        sub_passphrase: &str,
+        recipients: &[&PublicKeys],
+    ) -> Result<Self, Error> {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        let header = serde_json::to_vec(&StreamHeader {
+            version: STREAM_VERSION,
+            alg: STREAM_ALG.into(),
+            chunk_size: CHUNK_SIZE,
+            nonce_prefix: STANDARD.encode(nonce_prefix),
+            key: STANDARD.encode(key),
+        })
+        .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        let header = private_keys.sign_encrypt_sign_bin(sub_passphrase, recipients, header)?;
+
+        Ok(Self {
+            state: StreamState {
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+                nonce_prefix,
+                chunk_size: CHUNK_SIZE,
+                header_digest: Sha256::digest(&header).into(),
+                counter: 0,
+                length: 0,
+                hasher: Sha512::new(),
+            },
+            private_keys,
+            sub_passphrase: sub_passphrase.to_string(),
+            header,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// 受信者がファイル鍵を取り出すためのヘッダ。本体より先にアップロードする。
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn seal(&mut self, frame_type: u8, plain: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        let nonce = self.state.nonce(frame_type);
+        let ciphertext = self
+            .state
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plain,
+                    aad: &self.state.header_digest,
+                },
+            )
+            .map_err(|e| Error::EncryptionError(e.to_string()))?;
+        write_frame(out, frame_type, &ciphertext)?;
+        self.state.next_counter()
+    }
+
+    /// 平文を追加し、完成したチャンクのフレームを返す。
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.state.hasher.update(data);
+        self.state.length += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+
+        let mut out = Vec::new();
+        let chunk_size = self.state.chunk_size;
+        let full = self.buffer.len() / chunk_size * chunk_size;
+        let pending: Vec<u8> = self.buffer.drain(..full).collect();
+        for chunk in pending.chunks(chunk_size) {
+            self.seal(FRAME_DATA, chunk, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// 残りの平文と、平文全体に対する署名を含む最終フレームを返す。
+    pub fn finish(mut self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            self.seal(FRAME_DATA, &rest, &mut out)?;
+        }
+        let signature = self
+            .private_keys
+            .sign_detached(&self.sub_passphrase, self.state.manifest())?;
+        self.seal(FRAME_FINAL, signature.as_bytes(), &mut out)?;
+        Ok(out)
+    }
+}
+
+/// ストリーム復号。`push` で受け取ったフレームを復号し、認証済みのチャンクを返す。
+///
+/// 各チャンクは AEAD で改ざんを検出するが、送信者の署名は最終フレームで検証するため、
+/// `finish` が成功するまで復号結果を確定したものとして扱ってはならない。
+pub struct StreamDecryptor {
+    sender: PublicKeys,
+    state: StreamState,
+    buffer: Vec<u8>,
+    verified: bool,
+}
+
+impl StreamDecryptor {
+    /// ヘッダの署名を検証し、ファイル鍵を取り出す。
+    pub fn new(
+        private_keys: &PrivateKeys,
+        sub_passphrase: &str,
+        sender_public_key: &str,
+        header: &[u8],
+    ) -> Result<Self, Error> {
+        let sender = PublicKeys::try_from(sender_public_key)?;
+        let (inner, report) = xrypton_common::keys::PublicKeys::try_from(sender_public_key)
+            .and_then(|keys| keys.verify_bytes_with_report(header))
+            .map_err(|e| Error::VerificationError(e.to_string()))?;
+        report
+            .ensure_valid_when_signed()
+            .map_err(|e| Error::VerificationError(e.to_string()))?;
+        let (plain, signature, _) = private_keys.decrypt_from_bytes(sub_passphrase, &inner)?;
+        let signature = signature
+            .ok_or_else(|| Error::VerificationError("stream header is not signed".into()))?;
+        sender.verify_detached_signature(&signature, &plain)?;
+
+        let header_plain: StreamHeader = serde_json::from_slice(&plain)
+            .map_err(|e| Error::InvalidPayload(format!("invalid stream header: {e}")))?;
+        if header_plain.version != STREAM_VERSION || header_plain.alg != STREAM_ALG {
+            return Err(Error::InvalidPayload("unsupported stream format".into()));
+        }
+        if header_plain.chunk_size == 0 || header_plain.chunk_size > CHUNK_SIZE * 16 {
+            return Err(Error::InvalidPayload("invalid chunk size".into()));
+        }
+        let key: [u8; KEY_LEN] = STANDARD
+            .decode(&header_plain.key)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| Error::InvalidPayload("invalid stream key".into()))?;
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = STANDARD
+            .decode(&header_plain.nonce_prefix)
+            .ok()
+            .and_then(|prefix| prefix.try_into().ok())
+            .ok_or_else(|| Error::InvalidPayload("invalid nonce prefix".into()))?;
+
+        Ok(Self {
+            sender,
+            state: StreamState {
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+                nonce_prefix,
+                chunk_size: header_plain.chunk_size,
+                header_digest: Sha256::digest(header).into(),
+                counter: 0,
+                length: 0,
+                hasher: Sha512::new(),
+            },
+            buffer: Vec::new(),
+            verified: false,
+        })
+    }
+
+    /// 暗号文を追加し、完成したフレームを復号した平文を返す。
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.buffer.extend_from_slice(data);
+        let mut out = Vec::new();
+        let mut offset = 0;
+        while self.buffer.len() - offset >= FRAME_HEADER_LEN {
+            if self.verified {
+                return Err(Error::DecryptionError("data after the final frame".into()));
+            }
+            let frame_type = self.buffer[offset];
+            let len_bytes: [u8; 4] = self.buffer[offset + 1..offset + FRAME_HEADER_LEN]
+                .try_into()
+                .unwrap();
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            let max_len = match frame_type {
+                FRAME_DATA => self.state.chunk_size + TAG_LEN,
+                FRAME_FINAL => MAX_FINAL_FRAME_LEN,
+                _ => return Err(Error::DecryptionError("unknown frame type".into())),
+            };
+            if len < TAG_LEN || len > max_len {
+                return Err(Error::DecryptionError("invalid frame length".into()));
+            }
+            let start = offset + FRAME_HEADER_LEN;
+            if self.buffer.len() - start < len {
+                break;
+            }
+
+            let nonce = self.state.nonce(frame_type);
+            let plain = self
+                .state
+                .cipher
+                .decrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &self.buffer[start..start + len],
+                        aad: &self.state.header_digest,
+                    },
+                )
+                .map_err(|_| Error::DecryptionError("chunk authentication failed".into()))?;
+            offset = start + len;
+
+            if frame_type == FRAME_DATA {
+                self.state.hasher.update(&plain);
+                self.state.length += plain.len() as u64;
+                self.state.next_counter()?;
+                out.extend_from_slice(&plain);
+            } else {
+                let signature = String::from_utf8(plain)
+                    .map_err(|e| Error::VerificationError(e.to_string()))?;
+                self.sender
+                    .verify_detached_signature(&signature, &self.state.manifest())?;
+                self.verified = true;
+            }
+        }
+        self.buffer.drain(..offset);
+        Ok(out)
+    }
+
+    /// 最終フレームの署名を検証済みで、余分なデータがないことを確認する。
+    pub fn finish(self) -> Result<(), Error> {
+        if !self.verified {
+            return Err(Error::VerificationError(
+                "stream is truncated: final frame is missing".into(),
+            ));
+        }
+        if !self.buffer.is_empty() {
+            return Err(Error::DecryptionError("data after the final frame".into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::keys;
+
+    fn encrypt(data: &[u8], piece: usize) -> (Vec<u8>, Vec<u8>, PrivateKeys, String) {
+        let (sender, sender_public) = keys("alice");
+        let (recipient, recipient_public) = keys("bob");
+        let recipient_public = PublicKeys::try_from(recipient_public.as_str()).unwrap();
+        let mut encryptor = StreamEncryptor::new(sender, "sub", &[&recipient_public]).unwrap();
+        let header = encryptor.header().to_vec();
+        let mut body = Vec::new();
+        for chunk in data.chunks(piece) {
+            body.extend(encryptor.push(chunk).unwrap());
+        }
+        body.extend(encryptor.finish().unwrap());
+        (header, body, recipient, sender_public)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3 + 123).map(|i| i as u8).collect();
+        let (header, body, recipient, sender_public) = encrypt(&data, 10_000);
+
+        let mut decryptor =
+            StreamDecryptor::new(&recipient, "sub", &sender_public, &header).unwrap();
+        let mut plain = Vec::new();
+        for chunk in body.chunks(7_777) {
+            plain.extend(decryptor.push(chunk).unwrap());
+        }
+        decryptor.finish().unwrap();
+        assert_eq!(plain, data);
+    }
+
+    #[test]
+    fn detects_tampering_and_truncation() {
+        let data = vec![42u8; CHUNK_SIZE * 2];
+        let (header, body, recipient, sender_public) = encrypt(&data, CHUNK_SIZE);
+
+        let mut tampered = body.clone();
+        tampered[FRAME_HEADER_LEN + 1] ^= 1;
+        let mut decryptor =
+            StreamDecryptor::new(&recipient, "sub", &sender_public, &header).unwrap();
+        assert!(decryptor.push(&tampered).is_err());
+
+        // 最終フレームを落とすと finish で検出する
+        let frame_len = FRAME_HEADER_LEN + CHUNK_SIZE + TAG_LEN;
+        let mut decryptor =
+            StreamDecryptor::new(&recipient, "sub", &sender_public, &header).unwrap();
+        decryptor.push(&body[..frame_len * 2]).unwrap();
+        assert!(decryptor.finish().is_err());
+
+        // チャンクの順序を入れ替えると認証に失敗する
+        let mut reordered = body[frame_len..frame_len * 2].to_vec();
+        reordered.extend_from_slice(&body[..frame_len]);
+        let mut decryptor =
+            StreamDecryptor::new(&recipient, "sub", &sender_public, &header).unwrap();
+        assert!(decryptor.push(&reordered).is_err());
+    }
+}
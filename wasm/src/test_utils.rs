@@ -0,0 +1,12 @@
+//! テスト用の共通ヘルパー
+
+use crate::keys::{KeyProfile, PrivateKeys, generate_keys};
+
+/// テスト用の鍵を生成し、秘密鍵と armored 形式の公開鍵を返す。
+pub(crate) fn keys(user_id: &str) -> (PrivateKeys, String) {
+    let (armored, _) =
+        generate_keys(user_id.into(), "main".into(), "sub".into(), KeyProfile::V4).unwrap();
+    let keys = PrivateKeys::try_from(armored.as_str()).unwrap();
+    let public = keys.public_keys();
+    (keys, public)
+}